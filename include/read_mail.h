@@ -0,0 +1,48 @@
+#ifndef READ_MAIL_H
+#define READ_MAIL_H
+
+/* cbindgen で作ったファイル。直接編集しない */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct ReadMailMessages ReadMailMessages;
+
+typedef struct ReadMailSession ReadMailSession;
+
+const char *read_mail_last_error(void);
+
+struct ReadMailSession *read_mail_connect(const char *host,
+                                          uint16_t port,
+                                          const char *user,
+                                          const char *password,
+                                          const char *selection);
+
+int read_mail_select(struct ReadMailSession *session, const char *folder);
+
+struct ReadMailMessages *read_mail_fetch_all(struct ReadMailSession *session);
+
+void read_mail_logout(struct ReadMailSession *session);
+
+size_t read_mail_messages_len(const struct ReadMailMessages *messages);
+
+uint32_t read_mail_message_uid(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_folder(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_id(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_from(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_subject(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_body(const struct ReadMailMessages *messages, size_t index);
+
+const char *read_mail_message_html(const struct ReadMailMessages *messages, size_t index);
+
+void read_mail_messages_free(struct ReadMailMessages *messages);
+
+#endif  /* READ_MAIL_H */
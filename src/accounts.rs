@@ -0,0 +1,141 @@
+// 複数のアカウントをまとめて読む（統合受信箱など）
+// アカウントごとに別のスレッドで同時に取得し、どのアカウントのメールかを付けて1つに並べる
+
+use std::error::Error;
+use std::thread;
+
+use crate::{MyMailbox, MyMessage, ReadOptions};
+
+#[derive(Debug, Clone)]
+pub struct AccountMessage {
+    account: usize,
+    label: String,
+    message: MyMessage,
+}
+
+impl AccountMessage {
+    // read_accounts に渡した mailboxes の中での位置
+    pub fn account(&self) -> usize {
+        self.account
+    }
+
+    // 「user@host」
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn message(&self) -> &MyMessage {
+        &self.message
+    }
+
+    pub fn into_message(self) -> MyMessage {
+        self.message
+    }
+}
+
+// すべてのアカウントのメールを、ReadOptions::sort_by の順に返す
+// sort_by がなければ Date ヘッダーの古い順（日時のないものは最後）
+// どれか1つのアカウントでも失敗すればエラーにする
+pub fn read_accounts(
+    mailboxes: &[MyMailbox],
+    options: &ReadOptions,
+) -> Result<Vec<AccountMessage>, Box<dyn Error>> {
+    // Box<dyn Error> はスレッドをまたげないので、文字列にして返す
+    let results = thread::scope(|scope| {
+        let handles = mailboxes
+            .iter()
+            .map(|mailbox| {
+                scope.spawn(move || {
+                    crate::read_mail_with_options(mailbox, options).map_err(|e| e.to_string())
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|x| {
+                x.join()
+                    .unwrap_or_else(|_| Err("thread panicked".to_string()))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut merged = Vec::new();
+    for (account, (mailbox, result)) in mailboxes.iter().zip(results).enumerate() {
+        let label = format!("{}@{}", mailbox.user, mailbox.host);
+        let messages = result.map_err(|e| format!("{}: {}", label, e))?;
+        merged.extend(messages.into_iter().map(|message| AccountMessage {
+            account,
+            label: label.clone(),
+            message,
+        }));
+    }
+    merge(&mut merged, options);
+    Ok(merged)
+}
+
+// アカウントごとの並びは読み出しのときにそろえてあるが、まとめたあとにもう一度並べ直す
+fn merge(messages: &mut [AccountMessage], options: &ReadOptions) {
+    match options.sort {
+        Some((key, order)) => crate::sort::sort_by_message(messages, |x| &x.message, key, order),
+        None => sort_by_date(messages),
+    }
+}
+
+fn sort_by_date(messages: &mut [AccountMessage]) {
+    messages.sort_by_key(|x| (x.message.date().is_none(), x.message.date()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Order, SortKey};
+
+    #[test]
+    fn merge_in_date_order() {
+        let message = |account: usize, date: &str| {
+            let raw = format!(
+                "From: a@example.com\r\n{}Subject: {}\r\n\r\n",
+                date, account
+            );
+            AccountMessage {
+                account,
+                label: String::new(),
+                message: crate::parse_fetched(raw.as_bytes(), "INBOX", 1, &ReadOptions::default())
+                    .unwrap(),
+            }
+        };
+        let mut messages = vec![
+            message(0, ""),
+            message(1, "Date: Tue, 2 Jan 2024 09:00:00 +0900\r\n"),
+            message(2, "Date: Mon, 1 Jan 2024 23:00:00 -0500\r\n"),
+        ];
+        merge(&mut messages, &ReadOptions::default());
+        let order = messages.iter().map(|x| x.account()).collect::<Vec<_>>();
+        assert_eq!(order, [1, 2, 0]);
+    }
+
+    #[test]
+    fn merge_by_sort_key() {
+        let message = |account: usize, subject: &str| {
+            let raw = format!(
+                "From: a@example.com\r\nSubject: {}\r\nDate: Mon, 1 Jan 2024 09:00:00 +0900\r\n\r\n",
+                subject
+            );
+            AccountMessage {
+                account,
+                label: String::new(),
+                message: crate::parse_fetched(raw.as_bytes(), "INBOX", 1, &ReadOptions::default())
+                    .unwrap(),
+            }
+        };
+        let mut messages = vec![
+            message(0, "gamma"),
+            message(1, "Re: Alpha"),
+            message(2, "beta"),
+        ];
+        let options = ReadOptions::default().sort_by(SortKey::Subject, Order::Descending);
+        merge(&mut messages, &options);
+        let order = messages.iter().map(|x| x.account()).collect::<Vec<_>>();
+        assert_eq!(order, [0, 2, 1]);
+    }
+}
@@ -0,0 +1,111 @@
+// 共有フォルダーのアクセス権（ACL 拡張、RFC 4314）
+
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox};
+
+// 1人（または「anyone」などのグループ）分のアクセス権
+// rights は「lrswipkxtea」のような1文字ずつの権限
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclEntry {
+    identifier: String,
+    rights: String,
+}
+
+impl AclEntry {
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    pub fn rights(&self) -> &str {
+        &self.rights
+    }
+
+    pub fn has_right(&self, right: char) -> bool {
+        self.rights.contains(right)
+    }
+}
+
+pub fn get_acl(mailbox: &MyMailbox, folder: &str) -> Result<Vec<AclEntry>, Box<dyn Error>> {
+    let mut imap_session = connect_acl(mailbox)?;
+    let lines =
+        imap_session.run_extension(&format!("GETACL {}", crate::imap_quote(folder)), &["ACL"])?;
+    imap_session.logout()?;
+
+    let mut entries = Vec::new();
+    for line in &lines {
+        entries.extend(parse_acl(&session::parse_values(line)));
+    }
+    Ok(entries)
+}
+
+// who のアクセス権を rights にする
+// 先頭が「+」「-」なら今の権限に追加・削除、空文字列なら DELETEACL で取り消す
+pub fn set_acl(
+    mailbox: &MyMailbox,
+    folder: &str,
+    who: &str,
+    rights: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut imap_session = connect_acl(mailbox)?;
+    let command = if rights.is_empty() {
+        format!(
+            "DELETEACL {} {}",
+            crate::imap_quote(folder),
+            crate::imap_quote(who)
+        )
+    } else {
+        format!(
+            "SETACL {} {} {}",
+            crate::imap_quote(folder),
+            crate::imap_quote(who),
+            crate::imap_quote(rights)
+        )
+    };
+    imap_session.run_command_and_check_ok(command)?;
+    imap_session.logout()?;
+    Ok(())
+}
+
+fn connect_acl(mailbox: &MyMailbox) -> Result<crate::MySession, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    if !imap_session.supports(Capability::Acl)? {
+        return Err("ACL is not supported by the server".into());
+    }
+    Ok(imap_session)
+}
+
+// 「* ACL INBOX taro lrswipkxtea anyone lr」の「* ACL」より後
+fn parse_acl(values: &[Value]) -> Vec<AclEntry> {
+    // 最初の値はフォルダー名
+    values
+        .get(1..)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|x| match x {
+            [identifier, rights] => Some(AclEntry {
+                identifier: identifier.as_str()?.to_string(),
+                rights: rights.as_str()?.to_string(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_acl_response() {
+        let values = session::parse_values(b"\"Shared/Sales\" taro lrswipkxtea \"anyone\" lr");
+        let entries = parse_acl(&values);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].identifier(), "taro");
+        assert!(entries[0].has_right('x'));
+        assert_eq!(entries[1].identifier(), "anyone");
+        assert_eq!(entries[1].rights(), "lr");
+        assert!(!entries[1].has_right('w'));
+    }
+}
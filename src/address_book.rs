@@ -0,0 +1,163 @@
+// 読んだメールの From・To・Cc から連絡先を集める（入力候補や CRM への取り込み用）
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use mailparse::{addrparse_header, parse_headers, MailAddr, MailHeaderMap, SingleInfo};
+
+use crate::{EmailAddress, MyMessage};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contact {
+    // 小文字にしたメールアドレス
+    address: String,
+    name: Option<String>,
+    count: usize,
+    last_seen: Option<DateTime<FixedOffset>>,
+}
+
+impl Contact {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    // 表示名（いちばん新しいメールに付いていたもの）
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // From・To・Cc に出てきた回数（1通の中で何度出てきても1回）
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // 出てきたメールのうち、いちばん新しい Date
+    pub fn last_seen(&self) -> Option<DateTime<FixedOffset>> {
+        self.last_seen
+    }
+}
+
+// 連絡先を、出てきた回数の多い順（同じならアドレス順）に返す
+pub fn collect_contacts(messages: &[MyMessage]) -> Vec<Contact> {
+    let mut contacts: HashMap<String, Contact> = HashMap::new();
+    for message in messages {
+        let mut seen = Vec::new();
+        for (name, address) in addresses(message) {
+            let key = address.to_lowercase();
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key.clone());
+
+            let date = message.date();
+            let contact = contacts.entry(key.clone()).or_insert_with(|| Contact {
+                address: key,
+                name: None,
+                count: 0,
+                last_seen: None,
+            });
+            contact.count += 1;
+            // 日時の分からないメールの表示名は、ほかになければ使う
+            let newer = match (date, contact.last_seen) {
+                (Some(x), Some(y)) => x >= y,
+                (Some(_), None) => true,
+                (None, _) => contact.name.is_none(),
+            };
+            if newer && name.is_some() {
+                contact.name = name;
+            }
+            contact.last_seen = contact.last_seen.max(date);
+        }
+    }
+
+    let mut contacts = contacts.into_values().collect::<Vec<_>>();
+    contacts.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.address.cmp(&b.address))
+    });
+    contacts
+}
+
+// From・To・Cc の（表示名, アドレス）
+fn addresses(message: &MyMessage) -> Vec<(Option<String>, String)> {
+    let headers = match parse_headers(message.raw()) {
+        Ok((headers, _)) => headers,
+        Err(_) => return Vec::new(),
+    };
+    let mut addresses = Vec::new();
+    for name in ["From", "To", "Cc"].iter() {
+        for header in headers.get_all_headers(name) {
+            let list = match addrparse_header(header) {
+                Ok(list) => list,
+                Err(_) => continue,
+            };
+            for addr in list.iter() {
+                match addr {
+                    MailAddr::Single(info) => addresses.extend(single(info)),
+                    MailAddr::Group(group) => {
+                        addresses.extend(group.addrs.iter().filter_map(single))
+                    }
+                }
+            }
+        }
+    }
+    addresses
+}
+
+fn single(info: &SingleInfo) -> Option<(Option<String>, String)> {
+    let address = EmailAddress::parse(&info.addr).ok()?;
+    let name = info
+        .display_name
+        .as_ref()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty());
+    Some((name, address.as_str().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    #[test]
+    fn count_addresses() {
+        let messages = [
+            "From: Taro <Taro@Example.com>\r\nTo: jiro@example.com, Team: hanako@example.com;\r\n\
+             Date: Mon, 1 Jan 2024 10:00:00 +0900\r\n",
+            "From: =?UTF-8?B?5aSq6YOO?= <taro@example.com>\r\nTo: taro@example.com\r\n\
+             Cc: Jiro <jiro@example.com>\r\nDate: Tue, 2 Jan 2024 10:00:00 +0900\r\n",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, headers)| {
+            let raw = format!("{}Subject: hi\r\n\r\nbody\r\n", headers);
+            crate::parse_fetched(
+                raw.as_bytes(),
+                "INBOX",
+                i as u32 + 1,
+                &ReadOptions::default(),
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+        let contacts = collect_contacts(&messages);
+        let summary = contacts
+            .iter()
+            .map(|x| (x.address(), x.name(), x.count()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("jiro@example.com", Some("Jiro"), 2),
+                ("taro@example.com", Some("太郎"), 2),
+                ("hanako@example.com", None, 1),
+            ]
+        );
+        assert_eq!(
+            contacts[1].last_seen().unwrap().to_rfc3339(),
+            "2024-01-02T10:00:00+09:00"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+// 受信日時ごとのフォルダー（「Archive/2024/05」など）へのアーカイブ
+// 必要なフォルダーはなければ作る
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+use chrono::{DateTime, Datelike, FixedOffset};
+
+use crate::{MyMailbox, MySession};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveScheme {
+    // 「Archive/2024」（中身はアーカイブのルートのフォルダー名）
+    Yearly(String),
+    // 「Archive/2024/05」
+    Monthly(String),
+}
+
+impl ArchiveScheme {
+    // ルートから順に、date のメールを入れるフォルダーまでの名前
+    fn folders(&self, date: &DateTime<FixedOffset>, delimiter: &str) -> Vec<String> {
+        let (root, mut parts) = match self {
+            ArchiveScheme::Yearly(root) => (root, vec![date.year().to_string()]),
+            ArchiveScheme::Monthly(root) => (
+                root,
+                vec![date.year().to_string(), format!("{:02}", date.month())],
+            ),
+        };
+        let mut folders = vec![root.clone()];
+        for part in parts.drain(..) {
+            let parent = folders.last().cloned().unwrap_or_default();
+            folders.push(format!("{}{}{}", parent, delimiter, part));
+        }
+        folders
+    }
+}
+
+// mailbox.selection のメールを、受信日時（INTERNALDATE）で分けて移動する
+// 戻り値は移動したメールの数
+pub fn archive(
+    mailbox: &MyMailbox,
+    uids: &[u32],
+    scheme: &ArchiveScheme,
+) -> Result<usize, Box<dyn Error>> {
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    uids.dedup();
+    if uids.is_empty() {
+        return Ok(0);
+    }
+
+    let mut imap_session = crate::connect(mailbox)?;
+    let (mut existing, delimiter) = list_folders(&mut imap_session)?;
+    imap_session.select(mailbox.selection)?;
+
+    // 移動先のフォルダーごとに UID をまとめる
+    let mut targets: BTreeMap<Vec<String>, Vec<u32>> = BTreeMap::new();
+    let throttle = imap_session.throttle().clone();
+    let fetches = throttle.run(|| imap_session.uid_fetch(crate::uid_set(&uids), "INTERNALDATE"))?;
+    for fetch in fetches.iter() {
+        if let (Some(uid), Some(date)) = (fetch.uid, fetch.internal_date()) {
+            targets
+                .entry(scheme.folders(&date, &delimiter))
+                .or_default()
+                .push(uid);
+        }
+    }
+
+    let mut moved = 0;
+    for (folders, mut uids) in targets {
+        for folder in &folders {
+            if existing.insert(folder.clone()) {
+                imap_session.create(folder)?;
+            }
+        }
+        let to = folders.last().ok_or("no archive folder")?;
+        uids.sort_unstable();
+        crate::move_uids(&mut imap_session, &crate::uid_set(&uids), to)?;
+        moved += uids.len();
+    }
+    imap_session.logout()?;
+    Ok(moved)
+}
+
+// すべてのフォルダー名と、階層の区切り文字
+pub(crate) fn list_folders(
+    imap_session: &mut MySession,
+) -> Result<(HashSet<String>, String), Box<dyn Error>> {
+    let names = imap_session.list(Some(""), Some("*"))?;
+    let delimiter = names
+        .iter()
+        .find_map(|x| x.delimiter())
+        .unwrap_or("/")
+        .to_string();
+    let folders = names.iter().map(|x| x.name().to_string()).collect();
+    Ok((folders, delimiter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_folder_names() {
+        let date = DateTime::parse_from_rfc3339("2024-05-17T09:30:00+09:00").unwrap();
+        assert_eq!(
+            ArchiveScheme::Monthly("Archive".to_string()).folders(&date, "/"),
+            ["Archive", "Archive/2024", "Archive/2024/05"]
+        );
+        assert_eq!(
+            ArchiveScheme::Yearly("INBOX.Archive".to_string()).folders(&date, "."),
+            ["INBOX.Archive", "INBOX.Archive.2024"]
+        );
+    }
+}
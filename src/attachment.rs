@@ -0,0 +1,390 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::lenient::{self, Warnings};
+use crate::ReadOptions;
+
+#[derive(Debug, Clone)]
+pub struct AttachmentInfo {
+    filename: Option<String>,
+    mimetype: String,
+    data: Vec<u8>,
+    sha256: [u8; 32],
+    md5: Option<[u8; 16]>,
+    // 検査で隔離されたときの理由（data は空になる）
+    quarantined: Option<String>,
+    // ReadOptions::large_parts_to_disk で書き出したファイル（data は空になる）
+    path: Option<PathBuf>,
+    file_size: u64,
+}
+
+impl AttachmentInfo {
+    pub(crate) fn new(filename: Option<String>, mimetype: String, data: Vec<u8>) -> Self {
+        Self {
+            filename,
+            mimetype,
+            sha256: Sha256::digest(&data).into(),
+            data,
+            md5: None,
+            quarantined: None,
+            path: None,
+            file_size: 0,
+        }
+    }
+
+    // ファイルに書き出した添付ファイル（ハッシュはファイルを少しずつ読んで計算する）
+    fn spilled(filename: Option<String>, mimetype: String, path: PathBuf) -> io::Result<Self> {
+        let mut hasher = Sha256::new();
+        hash_file(&path, |x| hasher.update(x))?;
+        Ok(Self {
+            filename,
+            mimetype,
+            data: Vec::new(),
+            sha256: hasher.finalize().into(),
+            md5: None,
+            quarantined: None,
+            file_size: fs::metadata(&path)?.len(),
+            path: Some(path),
+        })
+    }
+
+    // ReadOptions::attachment_md5 を指定したときだけ計算する
+    pub(crate) fn compute_md5(&mut self) {
+        let mut hasher = Md5::new();
+        match &self.path {
+            Some(path) => {
+                if hash_file(path, |x| hasher.update(x)).is_err() {
+                    return;
+                }
+            }
+            None => hasher.update(&self.data),
+        }
+        self.md5 = Some(hasher.finalize().into());
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn mimetype(&self) -> &str {
+        &self.mimetype
+    }
+
+    // Content-Transfer-Encoding を解除した中身
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // ファイルに書き出したものは、ファイルの大きさ
+    pub fn size(&self) -> usize {
+        match self.path {
+            Some(_) => self.file_size as usize,
+            None => self.data.len(),
+        }
+    }
+
+    // ReadOptions::large_parts_to_disk で書き出したファイル（data の代わりにこちらを読む）
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    // data の SHA-256（重複の検出や、脅威情報の照会に使う）
+    pub fn sha256(&self) -> &[u8; 32] {
+        &self.sha256
+    }
+
+    // SHA-256 の16進表記（小文字）
+    pub fn sha256_hex(&self) -> String {
+        hex(&self.sha256)
+    }
+
+    // data の MD5（ReadOptions::attachment_md5 を指定したときだけ）
+    pub fn md5(&self) -> Option<&[u8; 16]> {
+        self.md5.as_ref()
+    }
+
+    pub fn md5_hex(&self) -> Option<String> {
+        self.md5.as_ref().map(|x| hex(x))
+    }
+
+    // ReadOptions::scan_attachments で隔離された理由（隔離されたものは data が空で、ハッシュは元のまま）
+    pub fn quarantined(&self) -> Option<&str> {
+        self.quarantined.as_deref()
+    }
+}
+
+// 添付ファイルの検査結果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    // 中身を捨てて、ファイル名などだけを残す
+    Quarantine(String),
+    // メール全体をエラーにする
+    Reject(String),
+}
+
+fn hash_file<F: FnMut(&[u8])>(path: &Path, mut update: F) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(()),
+            n => update(&buffer[..n]),
+        }
+    }
+}
+
+type ScanFn = dyn Fn(&AttachmentInfo, &[u8]) -> Verdict + Send + Sync;
+
+// ウイルス対策ソフトや YARA などで、デコードした添付ファイルを1つずつ検査する
+#[derive(Clone)]
+pub(crate) struct Scanner(Arc<ScanFn>);
+
+impl fmt::Debug for Scanner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Scanner(..)")
+    }
+}
+
+impl Scanner {
+    pub(crate) fn new<F>(scan: F) -> Self
+    where
+        F: Fn(&AttachmentInfo, &[u8]) -> Verdict + Send + Sync + 'static,
+    {
+        Self(Arc::new(scan))
+    }
+
+    pub(crate) fn scan(&self, attachments: &mut [AttachmentInfo]) -> Result<(), Box<dyn Error>> {
+        for attachment in attachments {
+            // ファイルに書き出したものは、そのファイルの中身を検査する
+            let verdict = match &attachment.path {
+                Some(path) => (self.0)(attachment, &fs::read(path)?),
+                None => (self.0)(attachment, &attachment.data),
+            };
+            match verdict {
+                Verdict::Allow => {}
+                Verdict::Quarantine(reason) => {
+                    attachment.data = Vec::new();
+                    attachment.quarantined = Some(reason);
+                    // 書き出したファイルは消す（spill::clean_up）
+                    attachment.path = None;
+                }
+                Verdict::Reject(reason) => {
+                    let name = attachment.filename().unwrap_or(&attachment.mimetype);
+                    return Err(format!("attachment {} rejected: {}", name, reason).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// 本文として使ったパート以外で、添付ファイルとみなせるパートをすべて取り出す
+// （Content-Disposition: attachment か、ファイル名が付いているもの）
+// ReadOptions::attachments_matching に当てはまらないものはデコードしない
+// （winmail.dat は中身が当てはまるかもしれないので、あとで中身を絞り込む）
+pub(crate) fn collect_attachments(
+    parsed_mail: &ParsedMail,
+    text_mail: Option<&ParsedMail>,
+    options: &ReadOptions,
+    warnings: &mut Warnings,
+) -> Result<Vec<AttachmentInfo>, Box<dyn Error>> {
+    let mut attachments = Vec::new();
+    for part in crate::all_parts(parsed_mail) {
+        if !part.subparts.is_empty() || text_mail.is_some_and(|x| std::ptr::eq(part, x)) {
+            continue;
+        }
+        let disposition = part.get_content_disposition();
+        let filename = disposition
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"))
+            .cloned();
+        if disposition.disposition != DispositionType::Attachment && filename.is_none() {
+            continue;
+        }
+        let mimetype = &part.ctype.mimetype;
+        let tnef = cfg!(feature = "tnef") && mimetype == "application/ms-tnef";
+        if !tnef && !is_matching(&options.attachment_patterns, filename.as_deref(), mimetype) {
+            continue;
+        }
+        let spilled = part
+            .headers
+            .get_first_value(crate::spill::SPILLED_HEADER)
+            .map(PathBuf::from)
+            .filter(|x| options.spilled.contains(x));
+        if let Some(path) = spilled {
+            let mimetype = part.ctype.mimetype.clone();
+            attachments.push(AttachmentInfo::spilled(filename, mimetype, path)?);
+            continue;
+        }
+        let data = warnings.recover(part.get_body_raw().map_err(Into::into), || {
+            lenient::raw_body(part)
+        })?;
+        attachments.push(AttachmentInfo::new(
+            filename,
+            part.ctype.mimetype.clone(),
+            data,
+        ));
+    }
+    Ok(attachments)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// 「application/pdf」「image/*」のように「/」を含むものは MIME タイプ、
+// 「*.xlsx」のようなものはファイル名と比べる（大文字・小文字は区別しない、patterns が空ならすべて）
+pub(crate) fn is_matching(patterns: &[String], filename: Option<&str>, mimetype: &str) -> bool {
+    patterns.is_empty()
+        || patterns.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+            if pattern.contains('/') {
+                glob_match(&pattern, &mimetype.to_lowercase())
+            } else {
+                filename.is_some_and(|x| glob_match(&pattern, &x.to_lowercase()))
+            }
+        })
+}
+
+// 「*」は任意の文字列、「?」は任意の1文字
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // 最後に出てきた「*」の位置と、そのとき比べていた text の位置
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&x| x == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_attachment() {
+        let mut attachment =
+            AttachmentInfo::new(None, "text/plain".to_string(), b"hello\n".to_vec());
+        assert_eq!(
+            attachment.sha256_hex(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        assert_eq!(attachment.md5_hex(), None);
+        attachment.compute_md5();
+        assert_eq!(
+            attachment.md5_hex().as_deref(),
+            Some("b1946ac92492d2347c6235b4d2611184")
+        );
+    }
+
+    #[test]
+    fn scan_attachments() {
+        let scanner = Scanner::new(|_, data| {
+            if data.starts_with(b"MZ") {
+                Verdict::Quarantine("executable".to_string())
+            } else if data.starts_with(b"X5O!") {
+                Verdict::Reject("EICAR".to_string())
+            } else {
+                Verdict::Allow
+            }
+        });
+        let attachment = |name: &str, data: &[u8]| {
+            AttachmentInfo::new(
+                Some(name.to_string()),
+                "application/octet-stream".to_string(),
+                data.to_vec(),
+            )
+        };
+
+        let mut attachments = [
+            attachment("a.txt", b"hello"),
+            attachment("b.exe", b"MZ\x90"),
+        ];
+        scanner.scan(&mut attachments).unwrap();
+        assert_eq!(attachments[0].data(), b"hello");
+        assert_eq!(attachments[1].quarantined(), Some("executable"));
+        assert_eq!(attachments[1].size(), 0);
+
+        let mut attachments = [attachment("eicar.com", b"X5O!P%@AP")];
+        let error = scanner.scan(&mut attachments).unwrap_err();
+        assert_eq!(error.to_string(), "attachment eicar.com rejected: EICAR");
+        // large_parts_to_disk で書き出したものも、ファイルの中身で検査する
+        let dir = std::env::temp_dir().join(format!("read-mail-scan-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("b.exe");
+        fs::write(&path, b"MZ\x90").unwrap();
+        let spilled = AttachmentInfo::spilled(
+            Some("b.exe".to_string()),
+            "application/octet-stream".to_string(),
+            path.clone(),
+        )
+        .unwrap();
+        let mut attachments = [spilled];
+        scanner.scan(&mut attachments).unwrap();
+        assert_eq!(attachments[0].quarantined(), Some("executable"));
+        assert_eq!(attachments[0].path(), None);
+
+        fs::write(&path, b"X5O!P%@AP").unwrap();
+        let spilled = AttachmentInfo::spilled(
+            Some("eicar.com".to_string()),
+            "application/octet-stream".to_string(),
+            path,
+        )
+        .unwrap();
+        assert!(scanner.scan(&mut [spilled]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn match_attachment_patterns() {
+        let patterns = ["application/pdf".to_string(), "*.XLSX".to_string()];
+        assert!(is_matching(
+            &patterns,
+            Some("invoice.pdf"),
+            "application/pdf"
+        ));
+        assert!(is_matching(
+            &patterns,
+            Some("売上.xlsx"),
+            "application/octet-stream"
+        ));
+        assert!(!is_matching(&patterns, Some("logo.png"), "image/png"));
+        assert!(!is_matching(&patterns, None, "image/png"));
+        assert!(is_matching(&[], Some("logo.png"), "image/png"));
+        assert!(is_matching(&["image/*".to_string()], None, "image/png"));
+        assert!(is_matching(
+            &["report-????.csv".to_string()],
+            Some("report-2024.csv"),
+            "text/csv"
+        ));
+        assert!(!is_matching(
+            &["report-????.csv".to_string()],
+            Some("report-24.csv"),
+            "text/csv"
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+// ログインの方法（LOGIN コマンドか、AUTHENTICATE の SASL メカニズム）
+
+use std::error::Error;
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    // サーバーの AUTH= を見て選ぶ（CRAM-MD5、PLAIN、LOGIN の順）
+    Auto,
+    // LOGIN コマンド（パスワードをそのまま送る）
+    Login,
+    // PLAIN（RFC 4616、これもパスワードをそのまま送る）
+    Plain,
+    // CRAM-MD5（RFC 2195、パスワードそのものは送らない）
+    CramMd5,
+    // XOAUTH2（Gmail や Outlook.com、パスワードの代わりにアクセストークンを使う）
+    XOauth2,
+    // EXTERNAL（RFC 4422、TLS のクライアント証明書などで認証済みのとき）
+    External,
+}
+
+impl AuthMethod {
+    // AUTHENTICATE に渡す名前（LOGIN コマンドなら None）
+    pub(crate) fn mechanism(&self) -> Option<&'static str> {
+        match self {
+            AuthMethod::Auto | AuthMethod::Login => None,
+            AuthMethod::Plain => Some("PLAIN"),
+            AuthMethod::CramMd5 => Some("CRAM-MD5"),
+            AuthMethod::XOauth2 => Some("XOAUTH2"),
+            AuthMethod::External => Some("EXTERNAL"),
+        }
+    }
+
+    // パスワードをそのまま送る方法
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self, AuthMethod::Login | AuthMethod::Plain)
+    }
+
+    // Auto なら capabilities（大文字）から実際に使う方法を選ぶ
+    // forbid_plaintext のときはパスワードをそのまま送る方法を使わない
+    pub(crate) fn resolve(
+        self,
+        capabilities: &[String],
+        forbid_plaintext: bool,
+    ) -> Result<AuthMethod, Box<dyn Error>> {
+        let has = |x: &str| capabilities.iter().any(|c| c == x);
+        let method = match self {
+            AuthMethod::Auto => {
+                let candidates = [
+                    (AuthMethod::CramMd5, has("AUTH=CRAM-MD5")),
+                    (AuthMethod::Plain, has("AUTH=PLAIN")),
+                    (AuthMethod::Login, !has("LOGINDISABLED")),
+                ];
+                candidates
+                    .iter()
+                    .find(|(method, available)| {
+                        *available && !(forbid_plaintext && method.is_plaintext())
+                    })
+                    .map(|(method, _)| *method)
+                    .ok_or("no usable authentication method")?
+            }
+            method => method,
+        };
+        if forbid_plaintext && method.is_plaintext() {
+            return Err("plaintext authentication is forbidden".into());
+        }
+        Ok(method)
+    }
+}
+
+// imap クレートの AUTHENTICATE に渡す
+pub(crate) struct Authenticator<'a> {
+    pub(crate) method: AuthMethod,
+    pub(crate) user: &'a str,
+    pub(crate) password: &'a str,
+}
+
+impl imap::Authenticator for Authenticator<'_> {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Vec<u8> {
+        match self.method {
+            AuthMethod::CramMd5 => cram_md5(self.user, self.password, challenge).into_bytes(),
+            AuthMethod::XOauth2 => format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                self.user, self.password
+            )
+            .into_bytes(),
+            AuthMethod::Plain => format!("\0{}\0{}", self.user, self.password).into_bytes(),
+            // 認証済みの ID をそのまま使う
+            AuthMethod::External => Vec::new(),
+            AuthMethod::Auto | AuthMethod::Login => Vec::new(),
+        }
+    }
+}
+
+// 「ユーザー名 HMAC-MD5(パスワード, チャレンジ) の16進」
+fn cram_md5(user: &str, password: &str, challenge: &[u8]) -> String {
+    // HMAC はどんな長さの鍵でも受け付けるので失敗しない
+    let mut mac =
+        Hmac::<Md5>::new_from_slice(password.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(challenge);
+    let digest = mac.finalize().into_bytes();
+    let hex = digest
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    format!("{} {}", user, hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cram_md5_response() {
+        // RFC 2195 の例
+        assert_eq!(
+            cram_md5(
+                "tim",
+                "tanstaaftanstaaf",
+                b"<1896.697170952@postoffice.reston.mci.net>"
+            ),
+            "tim b913a602c7eda7a495b4e6e7334d3890"
+        );
+    }
+
+    #[test]
+    fn resolve_auto() {
+        let capabilities = ["IMAP4REV1".to_string(), "AUTH=PLAIN".to_string()];
+        let resolve = |method: AuthMethod, forbid| method.resolve(&capabilities, forbid).ok();
+        assert_eq!(resolve(AuthMethod::Auto, false), Some(AuthMethod::Plain));
+        assert_eq!(resolve(AuthMethod::Auto, true), None);
+        assert_eq!(resolve(AuthMethod::Login, true), None);
+        assert_eq!(
+            resolve(AuthMethod::XOauth2, true),
+            Some(AuthMethod::XOauth2)
+        );
+
+        let capabilities = ["AUTH=CRAM-MD5".to_string(), "AUTH=PLAIN".to_string()];
+        assert_eq!(
+            AuthMethod::Auto.resolve(&capabilities, true).ok(),
+            Some(AuthMethod::CramMd5)
+        );
+    }
+}
@@ -0,0 +1,344 @@
+// アカウント全体のバックアップと復元
+// フォルダーの構成・フラグ・受信日時・メールの中身をディレクトリに書き出し、
+// APPEND で作り直す（別のプロバイダーへの移行にも使える）
+//
+// dir/folders.txt        1行に1フォルダー「ディレクトリ名<TAB>区切り文字<TAB>フォルダー名」
+// dir/<名前>/index.txt 1行に1メール「UID<TAB>受信日時（RFC 3339）<TAB>フラグ（空白区切り）」
+// dir/<名前>/<UID>.eml メールの中身
+//
+// ディレクトリ名はフォルダー名の SHA-256 から決めるので、LIST の順が変わっても同じになる
+// （以前の、LIST の順の番号で書き出したバックアップも restore できる）
+// 大きなアカウントは backup_resumable で、途中で止まっても続きから書き出せる
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+use imap::types::{Flag, NameAttribute};
+use sha2::{Digest, Sha256};
+
+use crate::{MyMailbox, MySession};
+
+// 一度に FETCH するメールの数
+const BATCH: usize = 100;
+
+// backup_resumable がどこまで書き出したか（1バッチごとに渡される）
+// 文字列にして保存しておける（Display と parse）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    folder: String,
+    uid_validity: u32,
+    last_uid: u32,
+    batch: usize,
+}
+
+impl Checkpoint {
+    // Display で書いた「UIDVALIDITY<TAB>最後のUID<TAB>バッチの番号<TAB>フォルダー名」を読む
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let fields = text.splitn(4, '\t').collect::<Vec<_>>();
+        match fields[..] {
+            [uid_validity, last_uid, batch, folder] => Ok(Self {
+                folder: folder.to_string(),
+                uid_validity: uid_validity.parse()?,
+                last_uid: last_uid.parse()?,
+                batch: batch.parse()?,
+            }),
+            _ => Err(format!("invalid checkpoint: {}", text).into()),
+        }
+    }
+
+    // 書き出している途中のフォルダー（これより前のフォルダーは書き出し終えている）
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn uid_validity(&self) -> u32 {
+        self.uid_validity
+    }
+
+    // folder で書き出し終えた最後の UID
+    pub fn last_uid(&self) -> u32 {
+        self.last_uid
+    }
+
+    // folder で書き出し終えたバッチの数
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.uid_validity, self.last_uid, self.batch, self.folder
+        )
+    }
+}
+
+// すべてのフォルダーを dir に書き出し、書き出したメールの数を返す
+pub fn backup<P: AsRef<Path>>(mailbox: &MyMailbox, dir: P) -> Result<usize, Box<dyn Error>> {
+    backup_resumable(mailbox, dir, None, |_| Ok(()))
+}
+
+// backup と同じく書き出し、バッチ（BATCH 通）ごとに on_checkpoint を呼ぶ
+// 途中で止まったときは、最後の Checkpoint を resume に渡せば続きから書き出す
+// （返すのは、このとき書き出したメールの数）
+pub fn backup_resumable<P, F>(
+    mailbox: &MyMailbox,
+    dir: P,
+    resume: Option<&Checkpoint>,
+    mut on_checkpoint: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Checkpoint) -> Result<(), Box<dyn Error>>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let mut imap_session = crate::connect(mailbox)?;
+    let names = imap_session.list(Some(""), Some("*"))?;
+    let folders = names
+        .iter()
+        .filter(|x| !x.attributes().contains(&NameAttribute::NoSelect))
+        .map(|x| {
+            (
+                x.delimiter().unwrap_or("/").to_string(),
+                x.name().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // resume のフォルダーより前は書き出し終えている
+    // （そのあとで増えたフォルダーは、まだ書き出していないので書き出す）
+    let start = resume
+        .and_then(|x| folders.iter().position(|(_, folder)| *folder == x.folder))
+        .unwrap_or(0);
+    let mut list = String::new();
+    let mut count = 0;
+    for (i, (delimiter, folder)) in folders.iter().enumerate() {
+        let name = folder_dir_name(folder);
+        list.push_str(&format!("{}\t{}\t{}\n", name, delimiter, folder));
+        let folder_dir = dir.join(&name);
+        if i < start && folder_dir.join("index.txt").exists() {
+            continue;
+        }
+        fs::create_dir_all(&folder_dir)?;
+        let resume = resume.filter(|x| x.folder == *folder);
+        count += backup_folder(
+            &mut imap_session,
+            folder,
+            &folder_dir,
+            resume,
+            &mut on_checkpoint,
+        )?;
+    }
+    imap_session.logout()?;
+    fs::write(dir.join("folders.txt"), list)?;
+    Ok(count)
+}
+
+fn backup_folder<F>(
+    imap_session: &mut MySession,
+    folder: &str,
+    folder_dir: &Path,
+    resume: Option<&Checkpoint>,
+    on_checkpoint: &mut F,
+) -> Result<usize, Box<dyn Error>>
+where
+    F: FnMut(&Checkpoint) -> Result<(), Box<dyn Error>>,
+{
+    let selected = imap_session.examine(folder)?;
+    let uid_validity = selected.uid_validity.unwrap_or_default();
+    let mut checkpoint = match resume {
+        Some(resume) if resume.uid_validity == uid_validity => resume.clone(),
+        // UIDVALIDITY が変わっていれば、このフォルダーは始めから書き出す
+        _ => Checkpoint {
+            folder: folder.to_string(),
+            uid_validity,
+            last_uid: 0,
+            batch: 0,
+        },
+    };
+
+    // 前回、Checkpoint を渡す前に止まっていれば、その後に書いた行は消す
+    let index_path = folder_dir.join("index.txt");
+    let mut index = String::new();
+    if checkpoint.last_uid > 0 {
+        for line in fs::read_to_string(&index_path)?.lines() {
+            if parse_index_line(line)?.0 <= checkpoint.last_uid {
+                index.push_str(line);
+                index.push('\n');
+            }
+        }
+    }
+    fs::write(&index_path, &index)?;
+
+    let mut uids = crate::search_uids(imap_session)?;
+    uids.retain(|&x| x > checkpoint.last_uid);
+    // 取得するまでに消されたメールは書き出さないので、uids.len() とは限らない
+    let mut count = 0;
+    for chunk in uids.chunks(BATCH) {
+        let throttle = imap_session.throttle().clone();
+        let fetches = throttle.run(|| {
+            imap_session.uid_fetch(crate::uid_set(chunk), "(FLAGS INTERNALDATE BODY.PEEK[])")
+        })?;
+        let mut lines = String::new();
+        for fetch in fetches.iter() {
+            let (uid, body) = match (fetch.uid, fetch.body()) {
+                (Some(uid), Some(body)) => (uid, body),
+                _ => continue,
+            };
+            fs::write(folder_dir.join(format!("{}.eml", uid)), body)?;
+            let flags = fetch
+                .flags()
+                .iter()
+                .filter(|x| **x != Flag::Recent)
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            lines.push_str(&index_line(uid, fetch.internal_date(), &flags));
+            count += 1;
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&index_path)?
+            .write_all(lines.as_bytes())?;
+
+        checkpoint.last_uid = chunk[chunk.len() - 1];
+        checkpoint.batch += 1;
+        on_checkpoint(&checkpoint)?;
+    }
+    Ok(count)
+}
+
+// backup で書き出した dir の中身を mailbox のアカウントに作り直し、追加したメールの数を返す
+// フォルダーの区切り文字が違うサーバーでも、階層はそのまま保つ
+pub fn restore<P: AsRef<Path>>(dir: P, mailbox: &MyMailbox) -> Result<usize, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let list = fs::read_to_string(dir.join("folders.txt"))?;
+
+    let mut imap_session = crate::connect(mailbox)?;
+    let (mut existing, delimiter) = crate::archive::list_folders(&mut imap_session)?;
+
+    let mut count = 0;
+    for line in list.lines().filter(|x| !x.is_empty()) {
+        let fields = line.splitn(3, '\t').collect::<Vec<_>>();
+        let (dir_name, source_delimiter, name) = match fields[..] {
+            [dir_name, delimiter, name] => (dir_name, delimiter, name),
+            _ => return Err(format!("invalid folder line: {}", line).into()),
+        };
+        let folder = translate_folder(name, source_delimiter, &delimiter);
+        if existing.insert(folder.clone()) && !folder.eq_ignore_ascii_case("INBOX") {
+            imap_session.create(&folder)?;
+        }
+
+        let folder_dir = dir.join(dir_name);
+        let index = fs::read_to_string(folder_dir.join("index.txt"))?;
+        for line in index.lines().filter(|x| !x.is_empty()) {
+            let (uid, date, flags) = parse_index_line(line)?;
+            let content = fs::read(folder_dir.join(format!("{}.eml", uid)))?;
+            let flags = flags.into_iter().map(Flag::from).collect::<Vec<_>>();
+            imap_session.append_message(&folder, &content, &flags, date)?;
+            count += 1;
+        }
+    }
+    imap_session.logout()?;
+    Ok(count)
+}
+
+// フォルダー名には、ファイル名に使えない文字や、大文字・小文字だけが違うものがあるので、
+// SHA-256 の先頭 16 バイト（16進）にする
+fn folder_dir_name(folder: &str) -> String {
+    Sha256::digest(folder.as_bytes())[..16]
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+fn index_line(uid: u32, date: Option<DateTime<FixedOffset>>, flags: &[String]) -> String {
+    let date = date.map_or("-".to_string(), |x| x.to_rfc3339());
+    format!("{}\t{}\t{}\n", uid, date, flags.join(" "))
+}
+
+type IndexEntry = (u32, Option<DateTime<FixedOffset>>, Vec<String>);
+
+fn parse_index_line(line: &str) -> Result<IndexEntry, Box<dyn Error>> {
+    let fields = line.splitn(3, '\t').collect::<Vec<_>>();
+    match fields[..] {
+        [uid, date, flags] => {
+            let date = match date {
+                "-" => None,
+                x => Some(DateTime::parse_from_rfc3339(x)?),
+            };
+            let flags = flags.split(' ').filter(|x| !x.is_empty());
+            Ok((uid.parse()?, date, flags.map(str::to_string).collect()))
+        }
+        _ => Err(format!("invalid index line: {}", line).into()),
+    }
+}
+
+// 「INBOX.Work.2024」（区切り「.」）を「INBOX/Work/2024」（区切り「/」）のように置き換える
+pub(crate) fn translate_folder(name: &str, from: &str, to: &str) -> String {
+    if from.is_empty() || from == to {
+        name.to_string()
+    } else {
+        name.split(from).collect::<Vec<_>>().join(to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trip() {
+        let date = DateTime::parse_from_rfc3339("2024-05-17T09:30:00+09:00").unwrap();
+        let flags = ["\\Seen".to_string(), "$Label1".to_string()];
+        let line = index_line(42, Some(date), &flags);
+        assert_eq!(line, "42\t2024-05-17T09:30:00+09:00\t\\Seen $Label1\n");
+        assert_eq!(
+            parse_index_line(line.trim_end()).unwrap(),
+            (42, Some(date), flags.to_vec())
+        );
+        assert_eq!(parse_index_line("7\t-\t").unwrap(), (7, None, Vec::new()));
+        assert!(parse_index_line("7").is_err());
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let checkpoint = Checkpoint {
+            folder: "Archive\t2024".to_string(),
+            uid_validity: 7,
+            last_uid: 300,
+            batch: 3,
+        };
+        let text = checkpoint.to_string();
+        assert_eq!(text, "7\t300\t3\tArchive\t2024");
+        assert_eq!(Checkpoint::parse(&text).unwrap(), checkpoint);
+        assert!(Checkpoint::parse("7\t300\t3").is_err());
+    }
+
+    #[test]
+    fn name_folder_dirs() {
+        let name = folder_dir_name("Archive/2024");
+        assert_eq!(name, folder_dir_name("Archive/2024"));
+        assert_eq!(name.len(), 32);
+        assert!(name.bytes().all(|x| x.is_ascii_hexdigit()));
+        assert_ne!(folder_dir_name("Work"), folder_dir_name("work"));
+        assert_ne!(folder_dir_name("INBOX"), folder_dir_name("Sent"));
+    }
+
+    #[test]
+    fn translate_delimiter() {
+        assert_eq!(
+            translate_folder("INBOX.Work.2024", ".", "/"),
+            "INBOX/Work/2024"
+        );
+        assert_eq!(translate_folder("Sent", "/", "/"), "Sent");
+    }
+}
@@ -0,0 +1,113 @@
+// multipart/report（report-type=delivery-status）のエラーメール解析
+// https://tools.ietf.org/html/rfc3464
+
+use mailparse::{parse_headers, MailHeaderMap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BounceInfo {
+    action: String,
+    status: String,
+    recipient: String,
+    diagnostic: Option<String>,
+}
+
+impl BounceInfo {
+    // failed / delayed / delivered / relayed / expanded
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    // 「5.1.1」のような拡張ステータスコード
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    // 配送できなかった宛先（「rfc822;」は取り除く）
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    pub fn diagnostic(&self) -> Option<&str> {
+        self.diagnostic.as_deref()
+    }
+
+    // 恒久的なエラー（5.x.x）かどうか
+    pub fn is_permanent(&self) -> bool {
+        self.status.starts_with('5')
+    }
+}
+
+// message/delivery-status パートの本文を解析する
+// 宛先が複数ある場合は、最初の failed の宛先（なければ最初の宛先）を使う
+pub(crate) fn parse_delivery_status(text: &str) -> Option<BounceInfo> {
+    let text = text.replace("\r\n", "\n");
+
+    // 最初のブロックはメッセージ単位のフィールドなので読み飛ばす
+    let recipients = text
+        .split("\n\n")
+        .skip(1)
+        .filter_map(|block| {
+            let block = format!("{}\n\n", block.trim());
+            let (headers, _) = parse_headers(block.as_bytes()).ok()?;
+            let recipient = headers
+                .get_first_value("Final-Recipient")
+                .or_else(|| headers.get_first_value("Original-Recipient"))?;
+            Some(BounceInfo {
+                action: headers
+                    .get_first_value("Action")?
+                    .trim()
+                    .to_ascii_lowercase(),
+                status: headers
+                    .get_first_value("Status")?
+                    .split_whitespace()
+                    .next()?
+                    .to_string(),
+                recipient: strip_address_type(&recipient),
+                diagnostic: headers
+                    .get_first_value("Diagnostic-Code")
+                    .map(|x| strip_address_type(&x)),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    recipients
+        .iter()
+        .find(|x| x.action == "failed")
+        .or_else(|| recipients.first())
+        .cloned()
+}
+
+// 「rfc822; user@example.com」→「user@example.com」
+pub(crate) fn strip_address_type(value: &str) -> String {
+    match value.find(';') {
+        Some(i) => value[i + 1..].trim().to_string(),
+        None => value.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_failed_recipient() {
+        let text = "Reporting-MTA: dns; mx.example.com\r\n\
+                    Arrival-Date: Mon, 1 Jun 2020 10:00:00 +0900\r\n\
+                    \r\n\
+                    Final-Recipient: rfc822; ok@example.net\r\n\
+                    Action: delayed\r\n\
+                    Status: 4.4.7\r\n\
+                    \r\n\
+                    Final-Recipient: rfc822; nobody@example.net\r\n\
+                    Action: failed\r\n\
+                    Status: 5.1.1 (user unknown)\r\n\
+                    Diagnostic-Code: smtp; 550 5.1.1 User unknown\r\n";
+
+        let bounce = parse_delivery_status(text).unwrap();
+        assert_eq!(bounce.action(), "failed");
+        assert_eq!(bounce.status(), "5.1.1");
+        assert_eq!(bounce.recipient(), "nobody@example.net");
+        assert_eq!(bounce.diagnostic(), Some("550 5.1.1 User unknown"));
+        assert!(bounce.is_permanent());
+    }
+}
@@ -0,0 +1,265 @@
+// 取得したメールの SQLite キャッシュ
+// (フォルダー, UIDVALIDITY, UID) ごとに元のメールを保存しておき、2回目以降はまだ持っていない
+// メールだけをダウンロードする
+// 解析結果ではなく元のバイト列を持つ
+// 本文の選び方・文字コードの扱い・添付ファイルの絞り込み・stage などの結果は ReadOptions で変わるので、
+// 解析結果を持つと ReadOptions を変えるたびにキャッシュを作り直すことになる
+// 解析はダウンロードに比べて十分に速いので、毎回元のバイト列から解析し直す
+
+use std::error::Error;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    deadline, DeadlineExceeded, MessageError, MyMailbox, MyMessage, MySession, ReadOptions,
+    ResumeToken,
+};
+
+pub struct MessageCache {
+    conn: Connection,
+    max_bytes: Option<u64>,
+}
+
+impl MessageCache {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS folders (
+                 folder      TEXT PRIMARY KEY,
+                 uidvalidity INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS messages (
+                 folder      TEXT NOT NULL,
+                 uidvalidity INTEGER NOT NULL,
+                 uid         INTEGER NOT NULL,
+                 raw         BLOB NOT NULL,
+                 size        INTEGER NOT NULL,
+                 cached_at   INTEGER NOT NULL,
+                 PRIMARY KEY (folder, uidvalidity, uid)
+             );
+             CREATE INDEX IF NOT EXISTS messages_cached_at ON messages (cached_at);",
+        )?;
+        Ok(Self {
+            conn,
+            max_bytes: None,
+        })
+    }
+
+    // キャッシュの合計サイズの上限（超えたら古いものから消す）
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    // キャッシュしているメールの合計サイズ
+    pub fn total_bytes(&self) -> Result<u64, Box<dyn Error>> {
+        let total: i64 =
+            self.conn
+                .query_row("SELECT COALESCE(SUM(size), 0) FROM messages", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(total as u64)
+    }
+
+    pub fn clear(&self) -> Result<(), Box<dyn Error>> {
+        self.conn
+            .execute_batch("DELETE FROM messages; DELETE FROM folders;")?;
+        Ok(())
+    }
+
+    // UIDVALIDITY が変わっていたら、そのフォルダーのキャッシュは使えないので消す
+    fn validate(&self, folder: &str, uidvalidity: u32) -> Result<(), Box<dyn Error>> {
+        let cached: Option<u32> = self
+            .conn
+            .query_row(
+                "SELECT uidvalidity FROM folders WHERE folder = ?1",
+                params![folder],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if cached != Some(uidvalidity) {
+            self.conn
+                .execute("DELETE FROM messages WHERE folder = ?1", params![folder])?;
+            self.conn.execute(
+                "INSERT OR REPLACE INTO folders (folder, uidvalidity) VALUES (?1, ?2)",
+                params![folder, uidvalidity],
+            )?;
+        }
+        Ok(())
+    }
+
+    // サーバーから消えたメールをキャッシュからも消す
+    fn prune(&self, folder: &str, uids: &[u32]) -> Result<(), Box<dyn Error>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uid FROM messages WHERE folder = ?1")?;
+        let cached = stmt
+            .query_map(params![folder], |row| row.get::<_, u32>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for uid in cached {
+            if uids.binary_search(&uid).is_err() {
+                self.conn.execute(
+                    "DELETE FROM messages WHERE folder = ?1 AND uid = ?2",
+                    params![folder, uid],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get(
+        &self,
+        folder: &str,
+        uidvalidity: u32,
+        uid: u32,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT raw FROM messages WHERE folder = ?1 AND uidvalidity = ?2 AND uid = ?3",
+                params![folder, uidvalidity, uid],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn put(
+        &self,
+        folder: &str,
+        uidvalidity: u32,
+        uid: u32,
+        raw: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO messages (folder, uidvalidity, uid, raw, size, cached_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![folder, uidvalidity, uid, raw, raw.len() as i64, now],
+        )?;
+        Ok(())
+    }
+
+    // 上限を超えていたら、古いものから消す
+    fn enforce_limit(&self) -> Result<(), Box<dyn Error>> {
+        let max_bytes = match self.max_bytes {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let mut total = self.total_bytes()?;
+        if total <= max_bytes {
+            return Ok(());
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT folder, uidvalidity, uid, size FROM messages ORDER BY cached_at, uid",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (folder, uidvalidity, uid, size) in rows {
+            if total <= max_bytes {
+                break;
+            }
+            self.conn.execute(
+                "DELETE FROM messages WHERE folder = ?1 AND uidvalidity = ?2 AND uid = ?3",
+                params![folder, uidvalidity, uid],
+            )?;
+            total = total.saturating_sub(size as u64);
+        }
+        Ok(())
+    }
+}
+
+// read_mail_with_options と同じだが、キャッシュにないメールだけをダウンロードする
+pub fn read_mail_with_cache(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    cache: &MessageCache,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    crate::read_folders(mailbox, options, |imap_session, folder| {
+        fetch_folder_cached(imap_session, folder, options, cache)
+    })
+}
+
+fn fetch_folder_cached(
+    imap_session: &mut MySession,
+    folder: &str,
+    options: &ReadOptions,
+    cache: &MessageCache,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let selected = imap_session.select(folder)?;
+    let uidvalidity = selected.uid_validity.ok_or("no UIDVALIDITY")?;
+    cache.validate(folder, uidvalidity)?;
+
+    let mut uids = crate::search_uids(imap_session)?;
+    cache.prune(folder, &uids)?;
+
+    // fetch_folder と同じく、resume_from で続きから読み、締め切りを過ぎたら止める
+    let mut last_uid = options
+        .resume
+        .as_ref()
+        .map_or(0, |x| x.last_uid_in(folder, uidvalidity));
+    uids.retain(|&x| x > last_uid);
+
+    let mut messages = Vec::new();
+    for uid in uids {
+        if deadline::is_exceeded(options) {
+            cache.enforce_limit()?;
+            let resume = ResumeToken::new(folder, uidvalidity, last_uid);
+            return Err(DeadlineExceeded::new(messages, resume).into());
+        }
+        let raw = match cache.get(folder, uidvalidity, uid)? {
+            Some(raw) => raw,
+            None => {
+                let raw = crate::fetch_raw(imap_session, uid)
+                    .map_err(|e| MessageError::fetch(folder, uid, "UID FETCH", e))?;
+                cache.put(folder, uidvalidity, uid, &raw)?;
+                raw
+            }
+        };
+        let message = crate::parse_fetched(&raw, folder, uid, options);
+        messages.push(crate::count_parse(imap_session, message)?);
+        last_uid = uid;
+    }
+    cache.enforce_limit()?;
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidate_and_limit() {
+        let cache = MessageCache::open(":memory:").unwrap().max_bytes(10);
+
+        cache.validate("INBOX", 1).unwrap();
+        cache.put("INBOX", 1, 1, b"12345").unwrap();
+        cache.put("INBOX", 1, 2, b"12345").unwrap();
+        assert_eq!(cache.get("INBOX", 1, 1).unwrap().unwrap(), b"12345");
+
+        // 上限を超えたら古いもの（uid 1）から消える
+        cache.put("INBOX", 1, 3, b"12345").unwrap();
+        cache.enforce_limit().unwrap();
+        assert_eq!(cache.total_bytes().unwrap(), 10);
+        assert!(cache.get("INBOX", 1, 1).unwrap().is_none());
+
+        // サーバーから消えたもの
+        cache.prune("INBOX", &[3]).unwrap();
+        assert!(cache.get("INBOX", 1, 2).unwrap().is_none());
+
+        // UIDVALIDITY が変わったらフォルダーごと消える
+        cache.validate("INBOX", 2).unwrap();
+        assert_eq!(cache.total_bytes().unwrap(), 0);
+    }
+}
@@ -0,0 +1,123 @@
+// サーバーが対応している拡張（CAPABILITY）
+// 拡張のコマンドを試してエラーになるのを待つ代わりに、先にこれを見て使うかどうかを決める
+
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::MyMailbox;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Imap4rev1,
+    Imap4rev2,
+    Acl,
+    Condstore,
+    Esearch,
+    Id,
+    Idle,
+    ListStatus,
+    LiteralPlus,
+    LoginDisabled,
+    Move,
+    Namespace,
+    Quota,
+    SpecialUse,
+    UidPlus,
+    Xlist,
+    // AUTH=PLAIN などの SASL メカニズム（大文字）
+    Auth(String),
+    // 上のどれでもないもの（大文字）
+    Other(String),
+}
+
+impl Capability {
+    pub fn parse(text: &str) -> Self {
+        let text = text.to_ascii_uppercase();
+        match text.as_str() {
+            "IMAP4REV1" => Capability::Imap4rev1,
+            "IMAP4REV2" => Capability::Imap4rev2,
+            "ACL" => Capability::Acl,
+            "CONDSTORE" => Capability::Condstore,
+            "ESEARCH" => Capability::Esearch,
+            "ID" => Capability::Id,
+            "IDLE" => Capability::Idle,
+            "LIST-STATUS" => Capability::ListStatus,
+            "LITERAL+" => Capability::LiteralPlus,
+            "LOGINDISABLED" => Capability::LoginDisabled,
+            "MOVE" => Capability::Move,
+            "NAMESPACE" => Capability::Namespace,
+            "QUOTA" => Capability::Quota,
+            "SPECIAL-USE" => Capability::SpecialUse,
+            "UIDPLUS" => Capability::UidPlus,
+            "XLIST" => Capability::Xlist,
+            _ => match text.strip_prefix("AUTH=") {
+                Some(mechanism) => Capability::Auth(mechanism.to_string()),
+                None => Capability::Other(text),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    set: HashSet<Capability>,
+}
+
+impl Capabilities {
+    pub(crate) fn from_names<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Self {
+        Self {
+            set: names.into_iter().map(Capability::parse).collect(),
+        }
+    }
+
+    pub fn has(&self, capability: &Capability) -> bool {
+        self.set.contains(capability)
+    }
+
+    // 「QUOTA」「X-GM-EXT-1」のような名前で調べる（大文字・小文字は区別しない）
+    pub fn has_str(&self, name: &str) -> bool {
+        self.has(&Capability::parse(name))
+    }
+
+    // AUTHENTICATE で使える SASL メカニズム
+    pub fn auth_mechanisms(&self) -> Vec<&str> {
+        let mut mechanisms = self
+            .set
+            .iter()
+            .filter_map(|x| match x {
+                Capability::Auth(x) => Some(x.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        mechanisms.sort_unstable();
+        mechanisms
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Capability> {
+        self.set.iter()
+    }
+}
+
+// ログイン後のケーパビリティ（ログイン前とは違うことがある）
+pub fn capabilities(mailbox: &MyMailbox) -> Result<Capabilities, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    let capabilities = imap_session.capability_set()?.clone();
+    imap_session.logout()?;
+    Ok(capabilities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_capabilities() {
+        let capabilities = Capabilities::from_names(
+            "IMAP4rev1 IDLE auth=PLAIN AUTH=XOAUTH2 X-GM-EXT-1".split(' '),
+        );
+        assert!(capabilities.has(&Capability::Idle));
+        assert!(capabilities.has_str("x-gm-ext-1"));
+        assert!(!capabilities.has(&Capability::Move));
+        assert_eq!(capabilities.auth_mechanisms(), ["PLAIN", "XOAUTH2"]);
+    }
+}
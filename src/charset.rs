@@ -0,0 +1,106 @@
+// 本文の文字コード変換
+// 日本語のメールは宣言された charset と中身が違うこと（ISO-2022-JP なのに UTF-8 と書いてある等）が
+// よくあるので、指定による上書きと自動判定を用意する
+
+use std::error::Error;
+
+use encoding_rs::{Encoding, EUC_JP, ISO_2022_JP, SHIFT_JIS, UTF_8};
+use mailparse::ParsedMail;
+
+use crate::lenient::{self, Warnings};
+use crate::ReadOptions;
+
+// 寛容モードでは、読めなかった本文を元のバイト列のまま（U+FFFD に置き換えて）返す
+pub(crate) fn decode_body(
+    part: &ParsedMail,
+    options: &ReadOptions,
+    warnings: &mut Warnings,
+) -> Result<String, Box<dyn Error>> {
+    let text = decode(part, options);
+    warnings.recover(text, || lenient::raw_body_lossy(part))
+}
+
+fn decode(part: &ParsedMail, options: &ReadOptions) -> Result<String, Box<dyn Error>> {
+    if options.charset.is_none() && !options.detect_charset {
+        return Ok(part.get_body()?);
+    }
+    let bytes = part.get_body_raw()?;
+
+    // 指定された charset で読む
+    if let Some(label) = &options.charset {
+        let encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| format!("unknown charset: {}", label))?;
+        return Ok(encoding.decode_without_bom_handling(&bytes).0.into_owned());
+    }
+
+    // 宣言どおりに読めて、ほかの文字コードの特徴もなければそのまま使う
+    let declared = Encoding::for_label(part.ctype.charset.as_bytes());
+    if let Some(encoding) = declared {
+        if encoding == ISO_2022_JP || !has_iso_2022_jp_escape(&bytes) {
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(&bytes)
+            {
+                return Ok(text.into_owned());
+            }
+        }
+    }
+
+    let encoding = detect(&bytes).or(declared).unwrap_or(UTF_8);
+    Ok(encoding.decode_without_bom_handling(&bytes).0.into_owned())
+}
+
+// ISO-2022-JP → UTF-8 → Shift_JIS → EUC-JP の順に、エラーなく読めるものを探す
+fn detect(bytes: &[u8]) -> Option<&'static Encoding> {
+    if has_iso_2022_jp_escape(bytes) {
+        return Some(ISO_2022_JP);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Some(UTF_8);
+    }
+
+    let sjis = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes);
+    let euc = EUC_JP.decode_without_bom_handling_and_without_replacement(bytes);
+    match (sjis, euc) {
+        // EUC-JP の文字列は Shift_JIS の半角カナとしても読めてしまうことが多いので、
+        // 半角カナが少ないほうを選ぶ
+        (Some(sjis), Some(euc)) => {
+            if count_halfwidth_kana(&euc) < count_halfwidth_kana(&sjis) {
+                Some(EUC_JP)
+            } else {
+                Some(SHIFT_JIS)
+            }
+        }
+        (Some(_), None) => Some(SHIFT_JIS),
+        (None, Some(_)) => Some(EUC_JP),
+        (None, None) => None,
+    }
+}
+
+// ESC $ B / ESC $ @ / ESC ( J など
+fn has_iso_2022_jp_escape(bytes: &[u8]) -> bool {
+    bytes.windows(3).any(|x| {
+        matches!(
+            x,
+            [0x1B, b'$', b'B'] | [0x1B, b'$', b'@'] | [0x1B, b'(', b'J'] | [0x1B, b'(', b'I']
+        )
+    })
+}
+
+fn count_halfwidth_kana(text: &str) -> usize {
+    text.chars()
+        .filter(|&x| ('\u{FF61}'..='\u{FF9F}').contains(&x))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_japanese_encodings() {
+        let text = "こんにちは、世界。テスト";
+        for encoding in &[ISO_2022_JP, UTF_8, SHIFT_JIS, EUC_JP] {
+            let (bytes, _, _) = encoding.encode(text);
+            assert_eq!(detect(&bytes), Some(*encoding));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// 2つのアカウントのフォルダーを比べ（compare）、送り先に足りないメールを APPEND する（Comparison::sync）
+// 別のプロバイダーへの移行や、バックアップしたアカウントに漏れがないかを確かめるのに使う
+// メールは Message-ID で、Message-ID がなければ中身の SHA-256 で同じものとみなす
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use imap::types::{Flag, NameAttribute};
+use sha2::{Digest, Sha256};
+
+use crate::backup::translate_folder;
+use crate::processed::message_id;
+use crate::session::MySession;
+use crate::{MessageId, MyMailbox};
+
+// 一度に FETCH するメールの数（中身を取得するときは BATCH）
+const CHUNK: usize = 500;
+const BATCH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Id(MessageId),
+    Hash([u8; 32]),
+}
+
+// フォルダーごとの違い
+#[derive(Debug, Clone)]
+pub struct FolderDiff {
+    folder: String,
+    dest_folder: String,
+    dest_exists: bool,
+    uid_validity: u32,
+    missing: Vec<u32>,
+    extra: Vec<u32>,
+    matched: usize,
+}
+
+impl FolderDiff {
+    // source のフォルダー名
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    // dest でのフォルダー名（区切り文字を dest のものにしたもの）
+    pub fn dest_folder(&self) -> &str {
+        &self.dest_folder
+    }
+
+    // dest にこのフォルダーがあるか（なければ sync で作る）
+    pub fn dest_exists(&self) -> bool {
+        self.dest_exists
+    }
+
+    // source にあって dest にないメールの、source での UID
+    pub fn missing(&self) -> &[u32] {
+        &self.missing
+    }
+
+    // dest にあって source にないメールの、dest での UID（sync でも消さない）
+    pub fn extra(&self) -> &[u32] {
+        &self.extra
+    }
+
+    // 両方にあったメールの数
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+// compare の結果
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    folders: Vec<FolderDiff>,
+    extra_folders: Vec<String>,
+}
+
+impl Comparison {
+    // source のフォルダーごとの違い（source の LIST の順）
+    pub fn folders(&self) -> &[FolderDiff] {
+        &self.folders
+    }
+
+    // dest にだけあるフォルダー
+    pub fn extra_folders(&self) -> &[String] {
+        &self.extra_folders
+    }
+
+    // dest に足りないメールの数
+    pub fn missing_count(&self) -> usize {
+        self.folders.iter().map(|x| x.missing.len()).sum()
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.extra_folders.is_empty() && self.folders.iter().all(FolderDiff::is_identical)
+    }
+
+    // 足りないメールを、フラグと受信日時を保って dest に APPEND し、追加したメールの数を返す
+    // dest にだけあるメールやフォルダーはそのまま残す（片方向の同期）
+    // compare のあとで source の UIDVALIDITY が変わっていればエラーにする（比べ直す）
+    pub fn sync(&self, source: &MyMailbox, dest: &MyMailbox) -> Result<usize, Box<dyn Error>> {
+        let mut source_session = crate::connect(source)?;
+        let mut dest_session = crate::connect(dest)?;
+        let mut count = 0;
+        for diff in self.folders.iter().filter(|x| !x.missing.is_empty()) {
+            if !diff.dest_exists && !is_inbox(&diff.dest_folder) {
+                dest_session.create(&diff.dest_folder)?;
+            }
+            let selected = source_session.examine(&diff.folder)?;
+            if selected.uid_validity.unwrap_or_default() != diff.uid_validity {
+                return Err(format!("UIDVALIDITY of {} changed since compare", diff.folder).into());
+            }
+            for chunk in diff.missing.chunks(BATCH) {
+                let throttle = source_session.throttle().clone();
+                let fetches = throttle.run(|| {
+                    source_session.uid_fetch(
+                        crate::uid_set(chunk),
+                        "(UID FLAGS INTERNALDATE BODY.PEEK[])",
+                    )
+                })?;
+                for fetch in fetches.iter() {
+                    let body = match fetch.body() {
+                        Some(body) => body,
+                        None => continue,
+                    };
+                    let flags = fetch
+                        .flags()
+                        .iter()
+                        .filter(|x| **x != Flag::Recent)
+                        .map(|x| Flag::from(x.to_string()))
+                        .collect::<Vec<_>>();
+                    dest_session.append_message(
+                        &diff.dest_folder,
+                        body,
+                        &flags,
+                        fetch.internal_date(),
+                    )?;
+                    count += 1;
+                }
+            }
+        }
+        source_session.logout()?;
+        dest_session.logout()?;
+        Ok(count)
+    }
+}
+
+// source のすべてのフォルダーを dest の同じ名前のフォルダーと比べる
+pub fn compare(source: &MyMailbox, dest: &MyMailbox) -> Result<Comparison, Box<dyn Error>> {
+    let mut source_session = crate::connect(source)?;
+    let mut dest_session = crate::connect(dest)?;
+    let (source_folders, source_delimiter) = selectable_folders(&mut source_session)?;
+    let (dest_folders, dest_delimiter) = selectable_folders(&mut dest_session)?;
+
+    let mut folders = Vec::new();
+    let mut seen = HashSet::new();
+    for folder in &source_folders {
+        let dest_folder = translate_folder(folder, &source_delimiter, &dest_delimiter);
+        let existing = dest_folders
+            .iter()
+            .find(|x| **x == dest_folder || (is_inbox(x) && is_inbox(&dest_folder)));
+        let (uid_validity, source_keys) = folder_keys(&mut source_session, folder)?;
+        let dest_keys = match existing {
+            Some(existing) => {
+                seen.insert(existing.clone());
+                folder_keys(&mut dest_session, existing)?.1
+            }
+            None => Vec::new(),
+        };
+        let (missing, extra, matched) = diff(&source_keys, &dest_keys);
+        folders.push(FolderDiff {
+            folder: folder.clone(),
+            dest_folder: existing.cloned().unwrap_or(dest_folder),
+            dest_exists: existing.is_some(),
+            uid_validity,
+            missing,
+            extra,
+            matched,
+        });
+    }
+    source_session.logout()?;
+    dest_session.logout()?;
+
+    let extra_folders = dest_folders
+        .into_iter()
+        .filter(|x| !seen.contains(x))
+        .collect();
+    Ok(Comparison {
+        folders,
+        extra_folders,
+    })
+}
+
+// INBOX は大文字・小文字を区別しない（RFC 3501）
+fn is_inbox(folder: &str) -> bool {
+    folder.eq_ignore_ascii_case("INBOX")
+}
+
+// 選択できるフォルダー（LIST の順）と区切り文字
+fn selectable_folders(
+    imap_session: &mut MySession,
+) -> Result<(Vec<String>, String), Box<dyn Error>> {
+    let names = imap_session.list(Some(""), Some("*"))?;
+    let delimiter = names
+        .iter()
+        .find_map(|x| x.delimiter())
+        .unwrap_or("/")
+        .to_string();
+    let folders = names
+        .iter()
+        .filter(|x| !x.attributes().contains(&NameAttribute::NoSelect))
+        .map(|x| x.name().to_string())
+        .collect();
+    Ok((folders, delimiter))
+}
+
+// フォルダーのメールの UIDVALIDITY と、UID ごとのキー
+type FolderKeys = (u32, Vec<(u32, Key)>);
+
+// Message-ID のないメールだけ、中身を取得してハッシュを計算する
+fn folder_keys(imap_session: &mut MySession, folder: &str) -> Result<FolderKeys, Box<dyn Error>> {
+    let selected = imap_session.examine(folder)?;
+    let uid_validity = selected.uid_validity.unwrap_or_default();
+    let uids = crate::search_uids(imap_session)?;
+
+    let mut keys = Vec::new();
+    let mut no_id = Vec::new();
+    for chunk in uids.chunks(CHUNK) {
+        let throttle = imap_session.throttle().clone();
+        let fetches = throttle.run(|| {
+            imap_session.uid_fetch(
+                crate::uid_set(chunk),
+                "(UID BODY.PEEK[HEADER.FIELDS (MESSAGE-ID)])",
+            )
+        })?;
+        for fetch in fetches.iter() {
+            if let Some(uid) = fetch.uid {
+                let id =
+                    message_id(fetch.header().unwrap_or_default()).and_then(|x| MessageId::new(&x));
+                match id {
+                    Some(id) => keys.push((uid, Key::Id(id))),
+                    None => no_id.push(uid),
+                }
+            }
+        }
+    }
+    for chunk in no_id.chunks(BATCH) {
+        let throttle = imap_session.throttle().clone();
+        let fetches =
+            throttle.run(|| imap_session.uid_fetch(crate::uid_set(chunk), "(UID BODY.PEEK[])"))?;
+        for fetch in fetches.iter() {
+            if let (Some(uid), Some(body)) = (fetch.uid, fetch.body()) {
+                keys.push((uid, Key::Hash(Sha256::digest(body).into())));
+            }
+        }
+    }
+    keys.sort_unstable_by_key(|(uid, _)| *uid);
+    Ok((uid_validity, keys))
+}
+
+// source にだけあるメールの UID、dest にだけあるメールの UID、両方にあったメールの数
+// 同じキーのメールが何通かあれば、数の違いも足りない・余分とみなす
+fn diff(source: &[(u32, Key)], dest: &[(u32, Key)]) -> (Vec<u32>, Vec<u32>, usize) {
+    let mut remaining = HashMap::<&Key, Vec<u32>>::new();
+    for (uid, key) in dest.iter().rev() {
+        remaining.entry(key).or_default().push(*uid);
+    }
+    let mut missing = Vec::new();
+    let mut matched = 0;
+    for (uid, key) in source {
+        match remaining.get_mut(key).and_then(Vec::pop) {
+            Some(_) => matched += 1,
+            None => missing.push(*uid),
+        }
+    }
+    let mut extra = remaining.into_values().flatten().collect::<Vec<_>>();
+    extra.sort_unstable();
+    (missing, extra, matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(id: &str) -> Key {
+        Key::Id(MessageId::new(id).unwrap())
+    }
+
+    #[test]
+    fn diff_folders() {
+        let source = [
+            (1, id("<a@example.com>")),
+            (2, id("<b@example.com>")),
+            (3, id("<b@example.com>")),
+            (4, Key::Hash([1; 32])),
+            (5, id("<c@example.com>")),
+        ];
+        let dest = [
+            (10, id("a@EXAMPLE.com")),
+            (11, id("b@example.com")),
+            (12, Key::Hash([1; 32])),
+            (13, Key::Hash([2; 32])),
+        ];
+        let (missing, extra, matched) = diff(&source, &dest);
+        // b は source に 2 通、dest に 1 通
+        assert_eq!(missing, [3, 5]);
+        assert_eq!(extra, [13]);
+        assert_eq!(matched, 3);
+
+        let (missing, extra, matched) = diff(&source, &[]);
+        assert_eq!(missing, [1, 2, 3, 4, 5]);
+        assert!(extra.is_empty());
+        assert_eq!(matched, 0);
+    }
+}
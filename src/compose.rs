@@ -0,0 +1,371 @@
+// 送信するメールを組み立てる
+// 下書きフォルダーに \Draft 付きで APPEND しておけば、人が確認してから送ることができる
+
+use std::error::Error;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use imap::types::Flag;
+
+use crate::{AttachmentInfo, MyMailbox, MyMessage};
+
+// base64 の1行の長さ（RFC 2045 では 76 文字まで）
+const LINE_LENGTH: usize = 76;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Default)]
+pub struct MessageBuilder {
+    from: String,
+    to: Vec<String>,
+    cc: Vec<String>,
+    subject: String,
+    body: String,
+    headers: Vec<(String, String)>,
+    attachments: Vec<AttachmentInfo>,
+}
+
+impl MessageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // アドレスは「taro@example.com」か「山田 太郎 <taro@example.com>」の形式
+    pub fn from(mut self, from: &str) -> Self {
+        self.from = from.to_string();
+        self
+    }
+
+    pub fn to(mut self, to: &str) -> Self {
+        self.to.push(to.to_string());
+        self
+    }
+
+    pub fn cc(mut self, cc: &str) -> Self {
+        self.cc.push(cc.to_string());
+        self
+    }
+
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = subject.to_string();
+        self
+    }
+
+    // テキストの本文
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+
+    // In-Reply-To などのヘッダーを追加する（値は ASCII のみ）
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn attachment(mut self, filename: &str, mimetype: &str, data: &[u8]) -> Self {
+        self.attachments.push(AttachmentInfo::new(
+            Some(filename.to_string()),
+            mimetype.to_string(),
+            data.to_vec(),
+        ));
+        self
+    }
+
+    // RFC 5322 形式のメール（APPEND やそのまま SMTP で送れる）
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.from.is_empty() {
+            return Err("no From address".into());
+        }
+        let domain = self.from.rsplit('@').next().unwrap_or("localhost");
+        let domain = domain.trim_end_matches('>');
+
+        let mut out = Vec::new();
+        header(&mut out, "Date", &chrono::Local::now().to_rfc2822());
+        header(&mut out, "From", &encode_address(&self.from));
+        if !self.to.is_empty() {
+            let to = self
+                .to
+                .iter()
+                .map(|x| encode_address(x))
+                .collect::<Vec<_>>();
+            header(&mut out, "To", &to.join(",\r\n "));
+        }
+        if !self.cc.is_empty() {
+            let cc = self
+                .cc
+                .iter()
+                .map(|x| encode_address(x))
+                .collect::<Vec<_>>();
+            header(&mut out, "Cc", &cc.join(",\r\n "));
+        }
+        header(&mut out, "Subject", &encode_word(&self.subject));
+        header(
+            &mut out,
+            "Message-ID",
+            &format!("<{}@{}>", unique(), domain),
+        );
+        for (name, value) in &self.headers {
+            header(&mut out, name, value);
+        }
+        header(&mut out, "MIME-Version", "1.0");
+
+        if self.attachments.is_empty() {
+            text_part(&mut out, &self.body);
+            return Ok(out);
+        }
+
+        let boundary = format!("=_{}", unique());
+        header(
+            &mut out,
+            "Content-Type",
+            &format!("multipart/mixed; boundary=\"{}\"", boundary),
+        );
+        out.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
+        text_part(&mut out, &self.body);
+        for attachment in &self.attachments {
+            out.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
+            let filename = attachment.filename().unwrap_or("attachment");
+            header(&mut out, "Content-Type", attachment.mimetype());
+            header(
+                &mut out,
+                "Content-Disposition",
+                &format!("attachment; {}", filename_param(filename)),
+            );
+            // message/rfc822 は base64 にできない（RFC 2046）のでそのまま入れる
+            if attachment.mimetype() == "message/rfc822" {
+                let encoding = if attachment.data().is_ascii() {
+                    "7bit"
+                } else {
+                    "8bit"
+                };
+                header(&mut out, "Content-Transfer-Encoding", encoding);
+                out.extend_from_slice(b"\r\n");
+                out.extend_from_slice(attachment.data());
+            } else {
+                header(&mut out, "Content-Transfer-Encoding", "base64");
+                out.extend_from_slice(b"\r\n");
+                out.extend_from_slice(base64_lines(attachment.data()).as_bytes());
+            }
+        }
+        out.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        Ok(out)
+    }
+
+    // 下書きとして folder（「Drafts」など）に保存する
+    // サーバーが UIDPLUS に対応していれば、保存した下書きの UID を返す
+    pub fn save_draft(
+        &self,
+        mailbox: &MyMailbox,
+        folder: &str,
+    ) -> Result<Option<u32>, Box<dyn Error>> {
+        let raw = self.build()?;
+        let mut imap_session = crate::connect(mailbox)?;
+        let uid = imap_session.append_message(folder, &raw, &[Flag::Draft, Flag::Seen], None)?;
+        imap_session.logout()?;
+        Ok(uid)
+    }
+}
+
+// message を転送するメールを作る（元のメールはそのまま message/rfc822 で添付する）
+// 差出人は from で設定してから build・save_draft する
+// 添付ファイルを書き出したメール（MyMessage::is_spilled）は、元のメールが手元にないのでエラーにする
+pub fn forward(
+    message: &MyMessage,
+    to: &str,
+    comment: &str,
+) -> Result<MessageBuilder, Box<dyn Error>> {
+    if message.is_spilled() {
+        return Err("cannot forward a message whose parts were written to disk".into());
+    }
+    let subject = message.subject().trim_start();
+    let subject = if crate::subject::starts_with_prefix(subject, "fwd:") {
+        subject.to_string()
+    } else {
+        format!("Fwd: {}", subject)
+    };
+    let mut builder = MessageBuilder::new().to(to).subject(&subject).body(comment);
+    builder.attachments.push(AttachmentInfo::new(
+        Some("forwarded.eml".to_string()),
+        "message/rfc822".to_string(),
+        message.raw().to_vec(),
+    ));
+    Ok(builder)
+}
+
+pub(crate) fn header(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+}
+
+// 本文は ASCII だけならそのまま、それ以外は UTF-8 を base64 にする
+pub(crate) fn text_part(out: &mut Vec<u8>, body: &str) {
+    let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+    let long_line = body.split("\r\n").any(|x| x.len() > 998);
+    if body.is_ascii() && !long_line {
+        header(out, "Content-Type", "text/plain; charset=us-ascii");
+        header(out, "Content-Transfer-Encoding", "7bit");
+        out.extend_from_slice(format!("\r\n{}\r\n", body).as_bytes());
+    } else {
+        header(out, "Content-Type", "text/plain; charset=utf-8");
+        header(out, "Content-Transfer-Encoding", "base64");
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(base64_lines(body.as_bytes()).as_bytes());
+    }
+}
+
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut out = String::new();
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        // base64 の文字はすべて ASCII
+        out.push_str(std::str::from_utf8(line).unwrap_or_default());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// ASCII 以外を含むヘッダーは RFC 2047 の encoded-word にする
+// 1つの encoded-word が長くなりすぎないよう、文字の途中で切らずに分ける
+pub(crate) fn encode_word(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+    }
+    words.join("\r\n ")
+}
+
+// 「名前 <アドレス>」の名前の部分だけを encoded-word にする
+pub(crate) fn encode_address(address: &str) -> String {
+    match address.rfind('<') {
+        Some(i) if !address[..i].is_ascii() => {
+            let name = address[..i].trim().trim_matches('"');
+            format!("{} {}", encode_word(name), &address[i..])
+        }
+        _ => address.to_string(),
+    }
+}
+
+// 「名前 <アドレス>」のアドレスの部分
+pub(crate) fn bare_address(address: &str) -> &str {
+    match (address.rfind('<'), address.rfind('>')) {
+        (Some(start), Some(end)) if start < end => address[start + 1..end].trim(),
+        _ => address.trim(),
+    }
+}
+
+// ASCII 以外のファイル名は RFC 2231 の形式にする
+fn filename_param(filename: &str) -> String {
+    if filename.is_ascii() {
+        return format!("filename={}", crate::imap_quote(filename));
+    }
+    let encoded = filename
+        .bytes()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || b"-._~".contains(&x) {
+                (x as char).to_string()
+            } else {
+                format!("%{:02X}", x)
+            }
+        })
+        .collect::<String>();
+    format!("filename*=UTF-8''{}", encoded)
+}
+
+// Message-ID や boundary に使う、ほかと重ならない文字列
+pub(crate) fn unique() -> String {
+    let now = chrono::Utc::now();
+    format!(
+        "{}.{}.{}.{}",
+        now.timestamp(),
+        now.timestamp_subsec_nanos(),
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_and_parse_back() {
+        let raw = MessageBuilder::new()
+            .from("山田 太郎 <taro@example.com>")
+            .to("hanako@example.com")
+            .subject("見積書の送付について")
+            .body("お世話になっております。\n見積書を添付します。")
+            .attachment("見積書.pdf", "application/pdf", b"%PDF-1.4")
+            .build()
+            .unwrap();
+
+        let message = crate::parse(&raw, &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.from(), "taro@example.com");
+        assert_eq!(message.subject(), "見積書の送付について");
+        assert_eq!(
+            message.body(),
+            "お世話になっております。\r\n見積書を添付します。"
+        );
+        assert!(message.message_id().unwrap().ends_with("@example.com"));
+        assert_eq!(message.attachments().len(), 1);
+        assert_eq!(message.attachments()[0].filename(), Some("見積書.pdf"));
+        assert_eq!(message.attachments()[0].data(), b"%PDF-1.4");
+
+        assert!(MessageBuilder::new().build().is_err());
+    }
+
+    #[test]
+    fn forward_as_attachment() {
+        let original = "From: hanako@example.com\r\nSubject: Meeting\r\n\r\nSee you at 10.\r\n";
+        let message = crate::parse(original.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.raw(), original.as_bytes());
+
+        let raw = forward(&message, "jiro@example.com", "FYI")
+            .unwrap()
+            .from("taro@example.com")
+            .build()
+            .unwrap();
+        let forwarded = crate::parse(&raw, &crate::ReadOptions::default()).unwrap();
+        assert_eq!(forwarded.subject(), "Fwd: Meeting");
+        assert_eq!(forwarded.body(), "FYI");
+        assert_eq!(forwarded.attachments()[0].mimetype(), "message/rfc822");
+        let attached = forwarded.attachments()[0].data();
+        let attached = crate::parse(attached, &crate::ReadOptions::default()).unwrap();
+        assert_eq!(attached.subject(), "Meeting");
+        assert_eq!(attached.body(), "See you at 10.");
+        assert_eq!(forwarded.embedded_messages().len(), 1);
+        assert_eq!(
+            forwarded.embedded_messages()[0].from(),
+            "hanako@example.com"
+        );
+        assert_eq!(forwarded.embedded_messages()[0].subject(), "Meeting");
+
+        // 4 バイト目が文字の途中になる件名
+        let original = "From: hanako@example.com\r\nSubject: =?UTF-8?B?QULml6XmnKw=?=\r\n\r\nx\r\n";
+        let message = crate::parse(original.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        let raw = forward(&message, "jiro@example.com", "FYI")
+            .unwrap()
+            .from("taro@example.com")
+            .build()
+            .unwrap();
+        let forwarded = crate::parse(&raw, &crate::ReadOptions::default()).unwrap();
+        assert_eq!(forwarded.subject(), "Fwd: AB日本");
+
+        // raw がヘッダーだけのメールは転送しない
+        let mut message = message;
+        message.is_spilled = true;
+        assert!(forward(&message, "jiro@example.com", "FYI").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// メールを会話（スレッド）ごとにまとめる
+// Message-ID・In-Reply-To・References でつながるものを1つにし、
+// それらがない返信（「Re:」で始まる件名）は、接頭辞を除いた件名が同じ会話に入れる
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{EmailAddress, MessageId, MyMailbox, MyMessage, ReadOptions};
+
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    subject: String,
+    participants: Vec<EmailAddress>,
+    messages: Vec<MyMessage>,
+}
+
+impl Conversation {
+    // 最初のメールの件名（返信・転送の接頭辞などは除く）
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    // 差出人のメールアドレス（最初に出てきた順、大文字・小文字だけが違うものは重複とみなす）
+    pub fn participants(&self) -> &[EmailAddress] {
+        &self.participants
+    }
+
+    // 古い順
+    pub fn messages(&self) -> &[MyMessage] {
+        &self.messages
+    }
+
+    pub fn into_messages(self) -> Vec<MyMessage> {
+        self.messages
+    }
+
+    // いちばん新しいメールの日時
+    pub fn last_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.messages.iter().filter_map(MyMessage::date).max()
+    }
+
+    fn new(mut messages: Vec<MyMessage>) -> Self {
+        messages.sort_by(|a, b| match (a.date(), b.date()) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            (x, y) => y.is_some().cmp(&x.is_some()),
+        });
+        let subject = messages
+            .first()
+            .map(MyMessage::normalized_subject)
+            .unwrap_or_default();
+        let mut participants: Vec<EmailAddress> = Vec::new();
+        for address in messages.iter().filter_map(MyMessage::from_address) {
+            if !participants.contains(address) {
+                participants.push(address.clone());
+            }
+        }
+        Self {
+            subject,
+            participants,
+            messages,
+        }
+    }
+}
+
+// 読んだメールを会話にまとめて、新しいメールのある会話から順に返す
+pub fn read_conversations(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+) -> Result<Vec<Conversation>, Box<dyn Error>> {
+    Ok(conversations(crate::read_mail_with_options(
+        mailbox, options,
+    )?))
+}
+
+// 取得済みのメールを会話にまとめる
+pub fn conversations(messages: Vec<MyMessage>) -> Vec<Conversation> {
+    let mut groups = Groups::new(messages.len());
+
+    // 同じ Message-ID に触れるメールは同じ会話（途中のメールがなくても References でつながる）
+    let mut owners: HashMap<MessageId, usize> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let ids = message
+            .message_id()
+            .into_iter()
+            .chain(message.in_reply_to())
+            .chain(message.references().iter().map(String::as_str))
+            .filter_map(MessageId::new);
+        for id in ids {
+            match owners.get(&id) {
+                Some(&j) => groups.union(i, j),
+                None => {
+                    owners.insert(id, i);
+                }
+            }
+        }
+    }
+
+    // スレッドのヘッダーがない返信は件名でつなぐ
+    let mut subjects: HashMap<String, usize> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let subject = message.normalized_subject();
+        if subject.is_empty() {
+            continue;
+        }
+        let unthreaded = message.in_reply_to().is_none() && message.references().is_empty();
+        let is_reply = subject != message.subject().trim();
+        match subjects.get(&subject) {
+            Some(&j) if unthreaded && is_reply => groups.union(i, j),
+            Some(_) => {}
+            None => {
+                subjects.insert(subject, i);
+            }
+        }
+    }
+
+    let mut grouped: HashMap<usize, Vec<MyMessage>> = HashMap::new();
+    let mut order = Vec::new();
+    for (i, message) in messages.into_iter().enumerate() {
+        let root = groups.find(i);
+        grouped
+            .entry(root)
+            .or_insert_with(|| {
+                order.push(root);
+                Vec::new()
+            })
+            .push(message);
+    }
+
+    let mut conversations = order
+        .into_iter()
+        .filter_map(|root| grouped.remove(&root))
+        .map(Conversation::new)
+        .collect::<Vec<_>>();
+    // 日時のない会話は最後
+    conversations.sort_by(|a, b| match (a.last_date(), b.last_date()) {
+        (Some(x), Some(y)) => y.cmp(&x),
+        (x, y) => y.is_some().cmp(&x.is_some()),
+    });
+    conversations
+}
+
+// Union-Find
+struct Groups {
+    parents: Vec<usize>,
+}
+
+impl Groups {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let parent = self.parents[i];
+        if parent == i {
+            return i;
+        }
+        let root = self.find(parent);
+        self.parents[i] = root;
+        root
+    }
+
+    fn union(&mut self, i: usize, j: usize) {
+        let (i, j) = (self.find(i), self.find(j));
+        if i != j {
+            self.parents[i.max(j)] = i.min(j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(uid: u32, headers: &str) -> MyMessage {
+        let raw = format!("{}\r\n\r\nbody\r\n", headers);
+        crate::parse_fetched(raw.as_bytes(), "INBOX", uid, &ReadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn group_by_threading_headers_and_subject() {
+        let messages = vec![
+            message(
+                1,
+                "From: taro@example.com\r\nSubject: Lunch\r\nMessage-ID: <a@example.com>\r\n\
+                 Date: Mon, 1 Jan 2024 10:00:00 +0900",
+            ),
+            message(
+                2,
+                "From: jiro@example.com\r\nSubject: Invoice\r\nMessage-ID: <x@example.com>\r\n\
+                 Date: Mon, 1 Jan 2024 11:00:00 +0900",
+            ),
+            // b を持っていなくても、References の a でつながる
+            message(
+                3,
+                "From: hanako@example.com\r\nSubject: Re: Re: Lunch\r\nMessage-ID: <c@example.com>\r\n\
+                 In-Reply-To: <b@example.com>\r\nReferences: <a@example.com> <b@example.com>\r\n\
+                 Date: Mon, 1 Jan 2024 13:00:00 +0900",
+            ),
+            // スレッドのヘッダーがない返信
+            message(
+                4,
+                "From: taro@example.com\r\nSubject: AW: Invoice\r\n\
+                 Date: Mon, 1 Jan 2024 12:00:00 +0900",
+            ),
+            // 同じ件名でも返信でなければ別の会話
+            message(
+                5,
+                "From: saburo@example.com\r\nSubject: Lunch\r\n\
+                 Date: Mon, 1 Jan 2024 09:00:00 +0900",
+            ),
+            message(
+                6,
+                "From: Taro@Example.com\r\nSubject: Re: Lunch\r\nIn-Reply-To: <c@example.com>\r\n\
+                 Date: Mon, 1 Jan 2024 14:00:00 +0900",
+            ),
+        ];
+
+        let conversations = conversations(messages);
+        let uids = conversations
+            .iter()
+            .map(|x| x.messages().iter().map(MyMessage::uid).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(uids, [vec![1, 3, 6], vec![2, 4], vec![5]]);
+        assert_eq!(conversations[0].subject(), "Lunch");
+        let participants = conversations[0]
+            .participants()
+            .iter()
+            .map(EmailAddress::as_str)
+            .collect::<Vec<_>>();
+        // 大文字・小文字だけが違う Taro@Example.com は同じ人
+        assert_eq!(participants, ["taro@example.com", "hanako@example.com"]);
+    }
+}
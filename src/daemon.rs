@@ -0,0 +1,210 @@
+// 常駐して新着メールを処理し続ける（run_forever）
+// 接続・IDLE かポーリング・つなぎ直し・失敗したときの待ち時間の延長は Watcher に任せ、
+// SIGINT・SIGTERM を受けたら、処理中のメールを終えてから止まる
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::{MyMailbox, MyMessage, ReadOptions, Watcher};
+
+type ErrorHook<'a> = Box<dyn FnMut(&dyn Error) + 'a>;
+
+pub struct DaemonConfig<'a> {
+    mailbox: &'a MyMailbox<'a>,
+    options: ReadOptions,
+    interval: Duration,
+    max_backoff: Duration,
+    on_error: Option<ErrorHook<'a>>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl<'a> DaemonConfig<'a> {
+    pub fn new(mailbox: &'a MyMailbox<'a>, options: ReadOptions) -> Self {
+        Self {
+            mailbox,
+            options,
+            interval: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(600),
+            on_error: None,
+            stop: None,
+        }
+    }
+
+    // 確認の間隔（IDLE のときは待ち時間の上限）
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // つなぎ直すまでの待ち時間の上限（Watcher::max_backoff）
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    // 接続・取得・handler が失敗したとき（失敗しても止まらない）
+    pub fn on_error<F: FnMut(&dyn Error) + 'a>(mut self, hook: F) -> Self {
+        self.on_error = Some(Box::new(hook));
+        self
+    }
+
+    // シグナルのほかに、別のスレッドからこれを true にしても止まる
+    pub fn stop_when(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+}
+
+// 止められるまで、新着メールごとに handler を呼ぶ
+pub fn run_forever<'a, F>(config: DaemonConfig<'a>, mut handler: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&MyMessage) -> Result<(), Box<dyn Error>> + 'a,
+{
+    let on_error = Rc::new(RefCell::new(config.on_error));
+    let mut watcher = Watcher::new(config.mailbox, config.options)
+        .interval(config.interval)
+        .max_backoff(config.max_backoff);
+    {
+        let on_error = on_error.clone();
+        watcher.on_new_message(move |message| {
+            if let Err(e) = handler(message) {
+                if let Some(hook) = on_error.borrow_mut().as_mut() {
+                    hook(e.as_ref());
+                }
+            }
+        });
+    }
+    {
+        let on_error = on_error.clone();
+        watcher.on_error(move |e| {
+            if let Some(hook) = on_error.borrow_mut().as_mut() {
+                hook(e);
+            }
+        });
+    }
+
+    let signals = signal::Signals::install()?;
+    let stop = watcher.stop_handle();
+    let finished = Arc::new(AtomicBool::new(false));
+    let requested = config.stop.unwrap_or_default();
+    if requested.load(Ordering::SeqCst) {
+        stop.store(true, Ordering::SeqCst);
+    }
+    let stopper = {
+        let finished = finished.clone();
+        thread::spawn(move || {
+            while !finished.load(Ordering::SeqCst) {
+                if signal::received() || requested.load(Ordering::SeqCst) {
+                    stop.store(true, Ordering::SeqCst);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+        })
+    };
+
+    watcher.run();
+    finished.store(true, Ordering::SeqCst);
+    let _ = stopper.join();
+    drop(signals);
+    Ok(())
+}
+
+#[cfg(unix)]
+mod signal {
+    use std::error::Error;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn received() -> bool {
+        RECEIVED.load(Ordering::SeqCst)
+    }
+
+    // 元のハンドラーは drop で戻す
+    pub(super) struct Signals {
+        previous: Vec<(libc::c_int, libc::sighandler_t)>,
+    }
+
+    impl Signals {
+        pub(super) fn install() -> Result<Self, Box<dyn Error>> {
+            RECEIVED.store(false, Ordering::SeqCst);
+            let mut signals = Self {
+                previous: Vec::new(),
+            };
+            for &number in &[libc::SIGINT, libc::SIGTERM] {
+                let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                // SAFETY: on_signal は AtomicBool に書き込むだけなので、シグナルハンドラーから呼んでよい
+                let previous = unsafe { libc::signal(number, handler) };
+                if previous == libc::SIG_ERR {
+                    return Err(io::Error::last_os_error().into());
+                }
+                signals.previous.push((number, previous));
+            }
+            Ok(signals)
+        }
+    }
+
+    impl Drop for Signals {
+        fn drop(&mut self) {
+            for &(number, previous) in &self.previous {
+                // SAFETY: install で取っておいた、元のハンドラーに戻すだけ
+                unsafe {
+                    libc::signal(number, previous);
+                }
+            }
+        }
+    }
+}
+
+// シグナルを扱えないプラットフォームでは、stop_when でだけ止める
+#[cfg(not(unix))]
+mod signal {
+    use std::error::Error;
+
+    pub(super) fn received() -> bool {
+        false
+    }
+
+    pub(super) struct Signals;
+
+    impl Signals {
+        pub(super) fn install() -> Result<Self, Box<dyn Error>> {
+            Ok(Self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_before_connecting() {
+        let mailbox = MyMailbox::default();
+        let stop = Arc::new(AtomicBool::new(true));
+        let mut errors = 0;
+        let config = DaemonConfig::new(&mailbox, ReadOptions::default())
+            .interval(Duration::from_millis(10))
+            .on_error(|_| errors += 1)
+            .stop_when(stop);
+        let mut handled = 0;
+        run_forever(config, |_| {
+            handled += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(handled, 0);
+        assert_eq!(errors, 0);
+    }
+}
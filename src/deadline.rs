@@ -0,0 +1,144 @@
+// 全体の締め切り（ReadOptions::deadline）
+// ソケットのタイムアウトとは別に、締め切りを過ぎたら取得をやめて、それまでに読んだメールと
+// 続きを読むための ResumeToken を DeadlineExceeded で返す（downcast::<DeadlineExceeded>() で取り出す）
+
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+use crate::{MyMessage, ReadOptions};
+
+// どこまで読んだか（ReadOptions::resume_from に渡すと、その続きから読む）
+// 文字列にして保存しておける（Display と parse）
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeToken {
+    folder: String,
+    uid_validity: u32,
+    last_uid: u32,
+}
+
+impl ResumeToken {
+    pub(crate) fn new(folder: &str, uid_validity: u32, last_uid: u32) -> Self {
+        Self {
+            folder: folder.to_string(),
+            uid_validity,
+            last_uid,
+        }
+    }
+
+    // Display で書いた「UIDVALIDITY<TAB>最後のUID<TAB>フォルダー名」を読む
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let fields = text.splitn(3, '\t').collect::<Vec<_>>();
+        if fields.len() != 3 {
+            return Err(format!("invalid resume token: {}", text).into());
+        }
+        Ok(Self::new(fields[2], fields[0].parse()?, fields[1].parse()?))
+    }
+
+    // 止まったフォルダー（これより前のフォルダーは読み終えている）
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn uid_validity(&self) -> u32 {
+        self.uid_validity
+    }
+
+    // folder で最後に読んだ UID（まだ読んでいなければ 0）
+    pub fn last_uid(&self) -> u32 {
+        self.last_uid
+    }
+
+    // folder で読み終えた最後の UID（別のフォルダーか、UIDVALIDITY が変わっていれば 0）
+    pub(crate) fn last_uid_in(&self, folder: &str, uid_validity: u32) -> u32 {
+        if self.folder == folder && self.uid_validity == uid_validity {
+            self.last_uid
+        } else {
+            0
+        }
+    }
+}
+
+impl fmt::Display for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}",
+            self.uid_validity, self.last_uid, self.folder
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct DeadlineExceeded {
+    messages: Vec<MyMessage>,
+    resume: ResumeToken,
+}
+
+impl DeadlineExceeded {
+    pub(crate) fn new(messages: Vec<MyMessage>, resume: ResumeToken) -> Self {
+        Self { messages, resume }
+    }
+
+    // 締め切りまでに読めたメール
+    pub fn messages(&self) -> &[MyMessage] {
+        &self.messages
+    }
+
+    pub fn into_messages(self) -> Vec<MyMessage> {
+        self.messages
+    }
+
+    pub(crate) fn messages_mut(&mut self) -> &mut Vec<MyMessage> {
+        &mut self.messages
+    }
+
+    pub fn resume(&self) -> &ResumeToken {
+        &self.resume
+    }
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deadline exceeded after UID {} in {}",
+            self.resume.last_uid, self.resume.folder
+        )
+    }
+}
+
+impl Error for DeadlineExceeded {}
+
+// 締め切りを過ぎたか
+pub(crate) fn is_exceeded(options: &ReadOptions) -> bool {
+    options.deadline.is_some_and(|x| Instant::now() >= x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn resume_token() {
+        let token = ResumeToken::new("Archive\t2024", 7, 42);
+        let text = token.to_string();
+        assert_eq!(text, "7\t42\tArchive\t2024");
+        assert_eq!(ResumeToken::parse(&text).unwrap(), token);
+        assert!(ResumeToken::parse("7\t42").is_err());
+
+        assert_eq!(token.last_uid_in("Archive\t2024", 7), 42);
+        assert_eq!(token.last_uid_in("Archive\t2024", 8), 0);
+        assert_eq!(token.last_uid_in("INBOX", 7), 0);
+    }
+
+    #[test]
+    fn deadline() {
+        assert!(!is_exceeded(&ReadOptions::default()));
+        let past = ReadOptions::default().deadline(Instant::now());
+        assert!(is_exceeded(&past));
+        let future = ReadOptions::default().deadline(Instant::now() + Duration::from_secs(60));
+        assert!(!is_exceeded(&future));
+    }
+}
@@ -0,0 +1,124 @@
+// Message-ID による重複の除去
+// Gmail の「すべてのメール」や、複数のメーリングリストに投稿されたメールは
+// 複数のフォルダーに同じものが入っている
+
+use std::collections::{HashMap, HashSet};
+
+use sha2::{Digest, Sha256};
+
+use crate::{EmailAddress, MessageId, MyMessage};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dedup {
+    // 最初に見つかったものを残す
+    KeepFirst,
+    // このフォルダーにあるものを優先して残す（なければ最初のもの）
+    PreferFolder(String),
+}
+
+pub(crate) fn dedup(messages: Vec<MyMessage>, strategy: &Dedup) -> Vec<MyMessage> {
+    // Message-ID ごとに残すメッセージの位置を決める
+    let mut keep: HashMap<MessageId, usize> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let id = match message.id() {
+            Some(id) => id,
+            None => continue,
+        };
+        match keep.get(&id) {
+            None => {
+                keep.insert(id, i);
+            }
+            Some(&kept) => {
+                if let Dedup::PreferFolder(folder) = strategy {
+                    if message.folder() == folder && messages[kept].folder() != folder {
+                        keep.insert(id, i);
+                    }
+                }
+            }
+        }
+    }
+    let keep = keep.into_values().collect::<HashSet<_>>();
+
+    // Message-ID のないメールは比べようがないので、すべて残す
+    messages
+        .into_iter()
+        .enumerate()
+        .filter(|(i, x)| x.id().is_none() || keep.contains(i))
+        .map(|(_, x)| x)
+        .collect()
+}
+
+// find_duplicates で何を同じメールとみなすか
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateKey {
+    MessageId,
+    // 差出人・件名・本文が同じもの（Message-ID がない・付け直されたメールも見つかる）
+    Content,
+}
+
+// 同じメールが入っている場所（フォルダー, UID）の一覧
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    key: String,
+    locations: Vec<(String, u32)>,
+}
+
+impl DuplicateGroup {
+    // Message-ID、または内容の SHA-256（16進）
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn locations(&self) -> &[(String, u32)] {
+        &self.locations
+    }
+}
+
+// 2か所以上に入っているメールをグループにして返す（最初に見つかった順）
+pub fn find_duplicates(messages: &[MyMessage], by: DuplicateKey) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for message in messages {
+        let key = match by {
+            DuplicateKey::MessageId => match message.id() {
+                Some(id) => id.to_string(),
+                None => continue,
+            },
+            DuplicateKey::Content => content_hash(message),
+        };
+        let location = (message.folder().to_string(), message.uid());
+        match index.get(&key) {
+            Some(&i) => groups[i].locations.push(location),
+            None => {
+                index.insert(key.clone(), groups.len());
+                groups.push(DuplicateGroup {
+                    key,
+                    locations: vec![location],
+                });
+            }
+        }
+    }
+
+    groups.retain(|x| x.locations.len() > 1);
+    groups
+}
+
+// 実行するたびに変わらないので、保存しておいて別の実行の結果と比べられる
+// 区切りの 0 は、「ab」+「c」と「a」+「bc」を区別するため
+fn content_hash(message: &MyMessage) -> String {
+    let mut hasher = Sha256::new();
+    // 差出人は、アドレスとして読めれば大文字・小文字をそろえたもので比べる
+    let from = message
+        .from_address()
+        .map_or(message.from(), EmailAddress::key);
+    for field in [from, message.subject(), message.body()] {
+        hasher.update(field.as_bytes());
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
@@ -0,0 +1,332 @@
+// メールアドレスだけから IMAP サーバーの設定を探す
+// 大手のプロバイダーは組み込みの一覧から、それ以外は Thunderbird の autoconfig
+// （プロバイダー自身の autoconfig と Thunderbird の ISPDB）、DNS の SRV レコード（RFC 6186）、
+// 最後に「imap.ドメイン」に接続できるかを順に試す
+
+use std::error::Error;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{MyMailbox, Security};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSettings {
+    host: String,
+    port: u16,
+    security: Security,
+    username: String,
+    source: &'static str,
+}
+
+impl ServerSettings {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn security(&self) -> Security {
+        self.security
+    }
+
+    // ログインに使うユーザー名（多くはメールアドレスそのもの）
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    // どこで見つけたか（「provider」「autoconfig」「ispdb」「srv」「guess」）
+    pub fn source(&self) -> &str {
+        self.source
+    }
+
+    // この設定で接続する MyMailbox
+    pub fn mailbox<'a>(&'a self, password: &'a str) -> MyMailbox<'a> {
+        MyMailbox {
+            host: &self.host,
+            port: self.port,
+            user: &self.username,
+            password,
+            security: self.security,
+            ..MyMailbox::default()
+        }
+    }
+}
+
+pub fn discover(email: &str) -> Result<ServerSettings, Box<dyn Error>> {
+    let domain = email
+        .rsplit_once('@')
+        .map(|x| x.1.to_ascii_lowercase())
+        .filter(|x| !x.is_empty())
+        .ok_or("not an email address")?;
+
+    if let Some((host, port)) = known_provider(&domain) {
+        return Ok(settings(host, port, Security::Tls, email, "provider"));
+    }
+
+    let urls = [
+        (
+            format!(
+                "https://autoconfig.{}/mail/config-v1.1.xml?emailaddress={}",
+                domain, email
+            ),
+            "autoconfig",
+        ),
+        (
+            format!("https://autoconfig.thunderbird.net/v1.1/{}", domain),
+            "ispdb",
+        ),
+    ];
+    for (url, source) in &urls {
+        if let Some(found) = fetch_autoconfig(url, email, source) {
+            return Ok(found);
+        }
+    }
+
+    for (service, security) in [
+        ("_imaps._tcp", Security::Tls),
+        ("_imap._tcp", Security::StartTls),
+    ] {
+        if let Some((host, port)) = lookup_srv(&format!("{}.{}", service, domain)) {
+            return Ok(settings(&host, port, security, email, "srv"));
+        }
+    }
+
+    let host = format!("imap.{}", domain);
+    if reachable(&host, 993) {
+        return Ok(settings(&host, 993, Security::Tls, email, "guess"));
+    }
+    Err(format!("could not discover IMAP settings for {}", domain).into())
+}
+
+fn settings(
+    host: &str,
+    port: u16,
+    security: Security,
+    username: &str,
+    source: &'static str,
+) -> ServerSettings {
+    ServerSettings {
+        host: host.to_string(),
+        port,
+        security,
+        username: username.to_string(),
+        source,
+    }
+}
+
+fn known_provider(domain: &str) -> Option<(&'static str, u16)> {
+    let host = match domain {
+        "gmail.com" | "googlemail.com" => "imap.gmail.com",
+        "outlook.com" | "hotmail.com" | "live.com" | "msn.com" | "outlook.jp" | "hotmail.co.jp"
+        | "live.jp" => "outlook.office365.com",
+        "yahoo.com" | "ymail.com" => "imap.mail.yahoo.com",
+        "yahoo.co.jp" => "imap.mail.yahoo.co.jp",
+        "icloud.com" | "me.com" | "mac.com" => "imap.mail.me.com",
+        "aol.com" => "imap.aol.com",
+        "fastmail.com" | "fastmail.fm" => "imap.fastmail.com",
+        "gmx.com" | "gmx.net" | "gmx.de" => "imap.gmx.net",
+        "zoho.com" => "imap.zoho.com",
+        _ => return None,
+    };
+    Some((host, 993))
+}
+
+fn fetch_autoconfig(url: &str, email: &str, source: &'static str) -> Option<ServerSettings> {
+    let agent = ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(native_tls::TlsConnector::new().ok()?))
+        .timeout(TIMEOUT)
+        .build();
+    let xml = agent.get(url).call().ok()?.into_string().ok()?;
+    parse_autoconfig(&xml, email, source)
+}
+
+// <incomingServer type="imap"> のうち最初のもの（SSL を STARTTLS より優先する）
+fn parse_autoconfig(xml: &str, email: &str, source: &'static str) -> Option<ServerSettings> {
+    let (local, domain) = email.rsplit_once('@')?;
+    let expand = |x: &str| {
+        x.replace("%EMAILADDRESS%", email)
+            .replace("%EMAILLOCALPART%", local)
+            .replace("%EMAILDOMAIN%", domain)
+    };
+
+    let mut found = Vec::new();
+    for server in xml.split("<incomingServer").skip(1) {
+        let server = server.split("</incomingServer>").next()?;
+        let is_imap = server
+            .split('>')
+            .next()
+            .is_some_and(|x| x.contains("\"imap\""));
+        if !is_imap {
+            continue;
+        }
+        let security = match element(server, "socketType")?.as_str() {
+            "SSL" => Security::Tls,
+            "STARTTLS" => Security::StartTls,
+            _ => continue,
+        };
+        let host = expand(&element(server, "hostname")?);
+        let port = element(server, "port")?.parse().ok()?;
+        let username = element(server, "username").map_or(email.to_string(), |x| expand(&x));
+        found.push(settings(&host, port, security, &username, source));
+    }
+    found.sort_by_key(|x| x.security != Security::Tls);
+    found.into_iter().next()
+}
+
+fn element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(xml[start..end].trim().to_string())
+}
+
+fn reachable(host: &str, port: u16) -> bool {
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut x| x.next())
+        .is_some_and(|x| TcpStream::connect_timeout(&x, TIMEOUT).is_ok())
+}
+
+// /etc/resolv.conf の最初のネームサーバーに SRV を問い合わせ、優先度の最も高いものを返す
+fn lookup_srv(name: &str) -> Option<(String, u16)> {
+    let resolv = fs::read_to_string("/etc/resolv.conf").ok()?;
+    let nameserver = resolv
+        .lines()
+        .find_map(|x| x.trim().strip_prefix("nameserver"))?
+        .trim();
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.set_read_timeout(Some(TIMEOUT)).ok()?;
+    socket.send_to(&srv_query(name), (nameserver, 53)).ok()?;
+    let mut buf = [0; 4096];
+    let n = socket.recv(&mut buf).ok()?;
+    parse_srv(&buf[..n])
+}
+
+fn srv_query(name: &str) -> Vec<u8> {
+    // ID・再帰要求・質問1つ
+    let mut query = vec![0x52, 0x4d, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.').filter(|x| !x.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    // QTYPE SRV（33）・QCLASS IN（1）
+    query.extend_from_slice(&[0, 0, 33, 0, 1]);
+    query
+}
+
+fn parse_srv(response: &[u8]) -> Option<(String, u16)> {
+    let count = |at: usize| {
+        Some(u16::from_be_bytes([
+            *response.get(at)?,
+            *response.get(at + 1)?,
+        ]))
+    };
+    let questions = count(4)?;
+    let answers = count(6)?;
+
+    let mut position = 12;
+    for _ in 0..questions {
+        position = read_name(response, position)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        position = read_name(response, position)?.1;
+        let kind = count(position)?;
+        let length = count(position + 8)? as usize;
+        let data = position + 10;
+        if kind == 33 {
+            let priority = count(data)?;
+            let port = count(data + 4)?;
+            let (target, _) = read_name(response, data + 6)?;
+            // 「.」はサービスがないことを表す（RFC 2782）
+            if !target.is_empty() {
+                records.push((priority, target, port));
+            }
+        }
+        position = data + length;
+    }
+    records.sort_by_key(|x| x.0);
+    records
+        .into_iter()
+        .next()
+        .map(|(_, host, port)| (host, port))
+}
+
+// 圧縮（ポインター）を含む名前を読み、名前と、その次の位置を返す
+fn read_name(message: &[u8], mut position: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let length = *message.get(position)? as usize;
+        if length == 0 {
+            return Some((labels.join("."), end.unwrap_or(position + 1)));
+        }
+        if length & 0xc0 == 0xc0 {
+            end.get_or_insert(position + 2);
+            position = ((length & 0x3f) << 8) | *message.get(position + 1)? as usize;
+            continue;
+        }
+        let label = message.get(position + 1..position + 1 + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        position += 1 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_thunderbird_autoconfig() {
+        let xml = r#"<clientConfig version="1.1"><emailProvider id="example.com">
+  <incomingServer type="pop3"><hostname>pop.example.com</hostname><port>995</port>
+    <socketType>SSL</socketType></incomingServer>
+  <incomingServer type="imap"><hostname>mail.%EMAILDOMAIN%</hostname><port>143</port>
+    <socketType>STARTTLS</socketType><username>%EMAILLOCALPART%</username></incomingServer>
+  <incomingServer type="imap"><hostname>imap.example.com</hostname><port>993</port>
+    <socketType>SSL</socketType><username>%EMAILADDRESS%</username></incomingServer>
+</emailProvider></clientConfig>"#;
+        let found = parse_autoconfig(xml, "taro@example.com", "ispdb").unwrap();
+        assert_eq!(found.host(), "imap.example.com");
+        assert_eq!(found.port(), 993);
+        assert_eq!(found.security(), Security::Tls);
+        assert_eq!(found.username(), "taro@example.com");
+        assert_eq!(found.mailbox("secret").port, 993);
+    }
+
+    #[test]
+    fn parse_srv_response() {
+        let mut response = srv_query("_imaps._tcp.example.com");
+        response[2] = 0x81;
+        response[7] = 2;
+        // 名前は質問への圧縮（0xc00c）、優先度 10 と 0 の2つ
+        for (priority, host) in [(10u8, "backup"), (0, "imap")] {
+            response.extend_from_slice(&[0xc0, 0x0c, 0, 33, 0, 1, 0, 0, 0, 60]);
+            response.extend_from_slice(&[0, 6 + 1 + host.len() as u8 + 2]);
+            response.extend_from_slice(&[0, priority, 0, 0, 0x03, 0xe1]);
+            response.push(host.len() as u8);
+            response.extend_from_slice(host.as_bytes());
+            // 「example.com」は質問の中の位置（12 + 7 + 5）へのポインター
+            response.extend_from_slice(&[0xc0, 24]);
+        }
+        assert_eq!(
+            parse_srv(&response),
+            Some(("imap.example.com".to_string(), 993))
+        );
+    }
+
+    #[test]
+    fn known_providers() {
+        let found = discover("someone@gmail.com").unwrap();
+        assert_eq!(found.host(), "imap.gmail.com");
+        assert_eq!(found.source(), "provider");
+        assert!(discover("no-at-sign").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// 形の正しさを確かめたメールアドレス
+// 大文字・小文字を区別せずに比べるので、使う側でそれぞれ小文字にしたり確かめたりしなくて済む
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Clone)]
+pub struct EmailAddress {
+    // 書かれていたまま
+    address: String,
+    // 比べるときに使う、小文字にしたもの
+    key: String,
+    at: usize,
+}
+
+impl EmailAddress {
+    // RFC 5321・5322 の形（ローカル部は dot-atom か quoted-string、ドメインはホスト名か [IP]）か確かめる
+    // ドメインの国際化ドメイン名（U+0080 以上の文字）は受け付ける
+    pub fn parse(address: &str) -> Result<Self, Box<dyn Error>> {
+        let address = address.trim();
+        let invalid = |reason: &str| format!("invalid email address {:?}: {}", address, reason);
+        let at = address.rfind('@').ok_or_else(|| invalid("no @"))?;
+        let (local, domain) = (&address[..at], &address[at + 1..]);
+        if local.is_empty() || local.len() > 64 {
+            return Err(invalid("local part must be 1 to 64 octets").into());
+        }
+        if !is_local_part(local) {
+            return Err(invalid("bad local part").into());
+        }
+        if domain.is_empty() || domain.len() > 255 {
+            return Err(invalid("domain must be 1 to 255 octets").into());
+        }
+        if !is_domain(domain) {
+            return Err(invalid("bad domain").into());
+        }
+        Ok(Self {
+            address: address.to_string(),
+            key: address.to_lowercase(),
+            at,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.address
+    }
+
+    // 比べるときに使う、小文字にしたもの
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    // 「@」より前
+    pub fn local_part(&self) -> &str {
+        &self.address[..self.at]
+    }
+
+    // 「@」より後
+    pub fn domain(&self) -> &str {
+        &self.address[self.at + 1..]
+    }
+}
+
+impl PartialEq for EmailAddress {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for EmailAddress {}
+
+impl Hash for EmailAddress {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl PartialOrd for EmailAddress {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EmailAddress {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.address)
+    }
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || !c.is_ascii()
+}
+
+fn is_local_part(local: &str) -> bool {
+    if let Some(quoted) = local.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        // quoted-string（「\」のあとの1文字はそのまま）
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.next().is_none() => return false,
+                '\\' => {}
+                '"' => return false,
+                c if c.is_control() => return false,
+                _ => {}
+            }
+        }
+        return true;
+    }
+    local
+        .split('.')
+        .all(|x| !x.is_empty() && x.chars().all(is_atext))
+}
+
+fn is_domain(domain: &str) -> bool {
+    if let Some(literal) = domain.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        return !literal.is_empty()
+            && literal
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".:-".contains(c));
+    }
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    domain.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || !c.is_ascii())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_email_address() {
+        let address = EmailAddress::parse(" Taro.Yamada+news@Example.co.jp ").unwrap();
+        assert_eq!(address.as_str(), "Taro.Yamada+news@Example.co.jp");
+        assert_eq!(address.local_part(), "Taro.Yamada+news");
+        assert_eq!(address.domain(), "Example.co.jp");
+        assert_eq!(
+            address,
+            EmailAddress::parse("taro.yamada+news@example.CO.JP").unwrap()
+        );
+
+        for valid in [
+            "\"taro yamada\"@example.com",
+            "user@[192.0.2.1]",
+            "太郎@例え.jp",
+            "a@localhost",
+        ] {
+            assert!(EmailAddress::parse(valid).is_ok(), "{}", valid);
+        }
+        for invalid in [
+            "example.com",
+            "@example.com",
+            "taro@",
+            "taro..yamada@example.com",
+            ".taro@example.com",
+            "taro yamada@example.com",
+            "taro@-example.com",
+            "taro@example..com",
+            "taro@exa mple.com",
+        ] {
+            assert!(EmailAddress::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// メール1通の取得・解析に失敗したときのエラー
+// 何万通も読むときに、どのフォルダーのどの UID で何をしていたかが分かるようにする
+// （ほかと同じく Box<dyn Error> で返すので、downcast_ref::<MessageError>() で取り出す）
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct MessageError {
+    folder: String,
+    uid: u32,
+    // 失敗したコマンド（解析に失敗したときは None）
+    command: Option<String>,
+    source: Box<dyn Error>,
+}
+
+impl MessageError {
+    pub(crate) fn fetch(folder: &str, uid: u32, command: &str, source: Box<dyn Error>) -> Self {
+        Self {
+            folder: folder.to_string(),
+            uid,
+            command: Some(command.to_string()),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(folder: &str, uid: u32, source: Box<dyn Error>) -> Self {
+        Self {
+            folder: folder.to_string(),
+            uid,
+            command: None,
+            source,
+        }
+    }
+
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    // 解析に失敗した（取得はできた）
+    pub fn is_parse(&self) -> bool {
+        self.command.is_none()
+    }
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.command {
+            Some(command) => write!(
+                f,
+                "UID {} in {} failed on {}: {}",
+                self.uid, self.folder, command, self.source
+            ),
+            None => write!(
+                f,
+                "UID {} in {} failed to parse: {}",
+                self.uid, self.folder, self.source
+            ),
+        }
+    }
+}
+
+impl Error for MessageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_context() {
+        let error = MessageError::parse("INBOX", 4312, "missing Subject".into());
+        assert_eq!(
+            error.to_string(),
+            "UID 4312 in INBOX failed to parse: missing Subject"
+        );
+        assert!(error.is_parse());
+
+        let error: Box<dyn Error> =
+            MessageError::fetch("Archive", 7, "UID FETCH", "no body".into()).into();
+        let error = error.downcast_ref::<MessageError>().unwrap();
+        assert_eq!(error.command(), Some("UID FETCH"));
+        assert_eq!(error.source().unwrap().to_string(), "no body");
+        assert_eq!(
+            error.to_string(),
+            "UID 7 in Archive failed on UID FETCH: no body"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// 結果をまとめて返す検索（ESEARCH 拡張、RFC 4731）
+// 何十万通もあるフォルダーでも、UID を1つずつ並べずに「1:5000,5002」のような範囲や件数だけを受け取る
+
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox, MySession};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchSummary {
+    count: usize,
+    min: Option<u32>,
+    max: Option<u32>,
+    uid_set: String,
+}
+
+impl SearchSummary {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<u32> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    // 「1:5000,5002」のような UID set（当てはまるものがなければ空）
+    pub fn uid_set(&self) -> &str {
+        &self.uid_set
+    }
+
+    // UID set を1つずつに展開する（昇順）
+    pub fn uids(&self) -> Vec<u32> {
+        let mut uids = expand_uid_set(&self.uid_set);
+        uids.sort_unstable();
+        uids.dedup();
+        uids
+    }
+}
+
+// mailbox.selection で query（「UNSEEN」「FROM "taro"」など IMAP の検索条件）に当てはまるもの
+pub fn search_summary(mailbox: &MyMailbox, query: &str) -> Result<SearchSummary, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    imap_session.select(mailbox.selection)?;
+    let summary = search(&mut imap_session, query, "MIN MAX COUNT ALL")?;
+    imap_session.logout()?;
+    Ok(summary)
+}
+
+// 件数だけを数える（ESEARCH に対応していれば UID の一覧は受け取らない）
+pub fn count(mailbox: &MyMailbox, query: &str) -> Result<usize, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    imap_session.select(mailbox.selection)?;
+    let summary = search(&mut imap_session, query, "COUNT")?;
+    imap_session.logout()?;
+    Ok(summary.count)
+}
+
+// ESEARCH がなければ普通の UID SEARCH の結果からまとめる
+pub(crate) fn search(
+    imap_session: &mut MySession,
+    query: &str,
+    returns: &str,
+) -> Result<SearchSummary, Box<dyn Error>> {
+    if !imap_session.supports(Capability::Esearch)? {
+        let mut uids = imap_session
+            .uid_search(query)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+        return Ok(SearchSummary {
+            count: uids.len(),
+            min: uids.first().copied(),
+            max: uids.last().copied(),
+            uid_set: crate::uid_set(&uids),
+        });
+    }
+
+    let command = format!("UID SEARCH RETURN ({}) {}", returns, query);
+    let lines = imap_session.run_extension(&command, &["ESEARCH"])?;
+    Ok(lines
+        .first()
+        .map(|x| parse_esearch(&session::parse_values(x)))
+        .unwrap_or_default())
+}
+
+// 「* ESEARCH (TAG "a5") UID MIN 1 MAX 500 COUNT 20 ALL 1:5,7」の「* ESEARCH」より後
+fn parse_esearch(values: &[Value]) -> SearchSummary {
+    let mut summary = SearchSummary::default();
+    let items = values
+        .iter()
+        .filter(|x| !matches!(x, Value::List(_)))
+        .filter_map(Value::as_str)
+        .filter(|x| !x.eq_ignore_ascii_case("UID"))
+        .collect::<Vec<_>>();
+    for item in items.chunks(2) {
+        if let [key, value] = item {
+            match key.to_ascii_uppercase().as_str() {
+                "COUNT" => summary.count = value.parse().unwrap_or(0),
+                "MIN" => summary.min = value.parse().ok(),
+                "MAX" => summary.max = value.parse().ok(),
+                "ALL" => summary.uid_set = value.to_string(),
+                _ => {}
+            }
+        }
+    }
+    summary
+}
+
+// 「1:3,5」を [1, 2, 3, 5] にする（「5:3」のような逆順も受け付ける）
+pub(crate) fn expand_uid_set(uid_set: &str) -> Vec<u32> {
+    let mut uids = Vec::new();
+    for range in uid_set.split(',').filter(|x| !x.is_empty()) {
+        let mut ends = range.splitn(2, ':').filter_map(|x| x.parse::<u32>().ok());
+        match (ends.next(), ends.next()) {
+            (Some(start), Some(end)) => uids.extend(start.min(end)..=start.max(end)),
+            (Some(uid), None) => uids.push(uid),
+            _ => {}
+        }
+    }
+    uids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_esearch_response() {
+        let values = session::parse_values(b"(TAG \"a5\") UID MIN 1 MAX 500 COUNT 6 ALL 1:5,500");
+        let summary = parse_esearch(&values);
+        assert_eq!(summary.count(), 6);
+        assert_eq!(summary.min(), Some(1));
+        assert_eq!(summary.max(), Some(500));
+        assert_eq!(summary.uids(), [1, 2, 3, 4, 5, 500]);
+
+        // 当てはまるものがない
+        let summary = parse_esearch(&session::parse_values(b"(TAG \"a6\") UID"));
+        assert_eq!(summary, SearchSummary::default());
+    }
+}
@@ -0,0 +1,95 @@
+// フォルダーの変化を知らせるイベント
+// Watcher::events で受け取ると、新着・削除・フラグの変化をチャンネルで受け取れるので、
+// IDLE・ポーリング・差分同期を組み合わせなくてもフォルダーの写しを保てる
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::{MyMessage, MySession};
+
+#[derive(Debug, Clone)]
+pub enum MailboxEvent {
+    MessageAdded(Box<MyMessage>),
+    // 消えたメールの UID
+    MessageRemoved(u32),
+    // UID と変化した後のフラグ（"\\Seen" など）
+    FlagsChanged(u32, Vec<String>),
+}
+
+// 前回の確認時点での UID ごとのフラグ
+#[derive(Debug, Default)]
+pub(crate) struct FolderSnapshot {
+    flags: Option<BTreeMap<u32, Vec<String>>>,
+}
+
+impl FolderSnapshot {
+    // 最初の1回は記録するだけで、イベントは出さない
+    // 前回なかった UID（新着）は MessageAdded で知らせるので、ここでは出さない
+    pub(crate) fn update(&mut self, current: BTreeMap<u32, Vec<String>>) -> Vec<MailboxEvent> {
+        let mut events = Vec::new();
+        if let Some(previous) = &self.flags {
+            for (&uid, flags) in previous {
+                match current.get(&uid) {
+                    None => events.push(MailboxEvent::MessageRemoved(uid)),
+                    Some(x) if x != flags => {
+                        events.push(MailboxEvent::FlagsChanged(uid, x.clone()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.flags = Some(current);
+        events
+    }
+}
+
+// 選択中のフォルダーの、すべてのメールのフラグ
+pub(crate) fn fetch_flags(
+    imap_session: &mut MySession,
+) -> Result<BTreeMap<u32, Vec<String>>, Box<dyn Error>> {
+    let uids = crate::search_uids(imap_session)?;
+    let mut flags = BTreeMap::new();
+    if uids.is_empty() {
+        return Ok(flags);
+    }
+    let throttle = imap_session.throttle().clone();
+    let fetches = throttle.run(|| imap_session.uid_fetch(crate::uid_set(&uids), "FLAGS"))?;
+    for fetch in fetches.iter() {
+        if let Some(uid) = fetch.uid {
+            let mut names = fetch
+                .flags()
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            flags.insert(uid, names);
+        }
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_changes() {
+        let flags = |x: &[(u32, &[&str])]| {
+            x.iter()
+                .map(|(uid, names)| (*uid, names.iter().map(|x| x.to_string()).collect()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let mut snapshot = FolderSnapshot::default();
+        assert!(snapshot
+            .update(flags(&[(1, &[]), (2, &["\\Seen"]), (3, &[])]))
+            .is_empty());
+
+        let events = snapshot.update(flags(&[(1, &["\\Flagged"]), (3, &[]), (4, &[])]));
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            MailboxEvent::FlagsChanged(1, x) => assert_eq!(x, &["\\Flagged"]),
+            x => panic!("unexpected event: {:?}", x),
+        }
+        assert!(matches!(events[1], MailboxEvent::MessageRemoved(2)));
+    }
+}
@@ -0,0 +1,296 @@
+// C から使うための関数（extern "C"）
+// セッションとメールの一覧は中身の見えないポインターで渡し、使い終わったら
+// read_mail_logout・read_mail_messages_free で解放する
+// 失敗したときは NULL か -1 を返し、理由は read_mail_last_error で受け取る
+// ヘッダーは include/read_mail.h（cbindgen で作る）
+//
+// どの関数も、ポインターの引数は NULL か、この関数群が返してまだ解放していないもの、
+// 文字列は NUL で終わる UTF-8 でなければならない
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use crate::{MyMailbox, MyMessage, MySession, ReadOptions};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+pub struct ReadMailSession {
+    session: MySession,
+    folder: String,
+}
+
+pub struct ReadMailMessages {
+    messages: Vec<FfiMessage>,
+}
+
+// C に渡す文字列は、メールと同じだけ生きるように CString で持っておく
+struct FfiMessage {
+    uid: u32,
+    folder: CString,
+    message_id: Option<CString>,
+    from: CString,
+    subject: CString,
+    body: CString,
+    html: Option<CString>,
+}
+
+impl From<&MyMessage> for FfiMessage {
+    fn from(message: &MyMessage) -> Self {
+        Self {
+            uid: message.uid(),
+            folder: c_string(message.folder()),
+            message_id: message.message_id().map(c_string),
+            from: c_string(message.from()),
+            subject: c_string(message.subject()),
+            body: c_string(message.body()),
+            html: message.html().map(c_string),
+        }
+    }
+}
+
+// 途中に NUL があると C の文字列にならないので取り除く
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+fn set_error(error: Box<dyn Error>) {
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(c_string(&error.to_string())));
+}
+
+unsafe fn str_arg<'a>(value: *const c_char, name: &str) -> Result<&'a str, Box<dyn Error>> {
+    if value.is_null() {
+        return Err(format!("{} is NULL", name).into());
+    }
+    Ok(CStr::from_ptr(value).to_str()?)
+}
+
+fn into_messages(messages: &[MyMessage]) -> *mut ReadMailMessages {
+    Box::into_raw(Box::new(ReadMailMessages {
+        messages: messages.iter().map(FfiMessage::from).collect(),
+    }))
+}
+
+// 同じスレッドで最後に失敗した理由（なければ NULL）
+// 返した文字列は、次に失敗するまで有効
+#[no_mangle]
+pub extern "C" fn read_mail_last_error() -> *const c_char {
+    LAST_ERROR.with(|x| x.borrow().as_ref().map_or(ptr::null(), |x| x.as_ptr()))
+}
+
+// ログインして、selection（NULL なら INBOX）を選択したセッションを返す
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_connect(
+    host: *const c_char,
+    port: u16,
+    user: *const c_char,
+    password: *const c_char,
+    selection: *const c_char,
+) -> *mut ReadMailSession {
+    let connect = || -> Result<ReadMailSession, Box<dyn Error>> {
+        let folder = if selection.is_null() {
+            "INBOX"
+        } else {
+            str_arg(selection, "selection")?
+        };
+        let mailbox = MyMailbox {
+            host: str_arg(host, "host")?,
+            port,
+            user: str_arg(user, "user")?,
+            password: str_arg(password, "password")?,
+            selection: folder,
+            ..MyMailbox::default()
+        };
+        let mut session = crate::connect(&mailbox)?;
+        session.select(folder)?;
+        Ok(ReadMailSession {
+            session,
+            folder: folder.to_string(),
+        })
+    };
+    match connect() {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+// 別のフォルダーを選択する（成功すれば 0、失敗すれば -1）
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_select(
+    session: *mut ReadMailSession,
+    folder: *const c_char,
+) -> c_int {
+    let select = || -> Result<(), Box<dyn Error>> {
+        let session = session.as_mut().ok_or("session is NULL")?;
+        let folder = str_arg(folder, "folder")?;
+        session.session.select(folder)?;
+        session.folder = folder.to_string();
+        Ok(())
+    };
+    match select() {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+// 選択中のフォルダーのすべてのメールを読む
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_fetch_all(
+    session: *mut ReadMailSession,
+) -> *mut ReadMailMessages {
+    let fetch = || -> Result<Vec<MyMessage>, Box<dyn Error>> {
+        let session = session.as_mut().ok_or("session is NULL")?;
+        let mut messages = Vec::new();
+        for uid in crate::search_uids(&mut session.session)? {
+            messages.push(crate::fetch_message(
+                &mut session.session,
+                &session.folder,
+                uid,
+                &ReadOptions::default(),
+            )?);
+        }
+        Ok(messages)
+    };
+    match fetch() {
+        Ok(messages) => into_messages(&messages),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+// ログアウトしてセッションを解放する（NULL なら何もしない）
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_logout(session: *mut ReadMailSession) {
+    if !session.is_null() {
+        let mut session = Box::from_raw(session);
+        if let Err(e) = session.session.logout() {
+            set_error(e.into());
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_messages_len(messages: *const ReadMailMessages) -> usize {
+    messages.as_ref().map_or(0, |x| x.messages.len())
+}
+
+unsafe fn message<'a>(messages: *const ReadMailMessages, index: usize) -> Option<&'a FfiMessage> {
+    messages.as_ref()?.messages.get(index)
+}
+
+fn as_ptr(value: Option<&CString>) -> *const c_char {
+    value.map_or(ptr::null(), |x| x.as_ptr())
+}
+
+// 以下、index 番目のメールの各項目（範囲外なら 0 か NULL）
+// 文字列は read_mail_messages_free まで有効
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_uid(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> u32 {
+    message(messages, index).map_or(0, |x| x.uid)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_folder(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).map(|x| &x.folder))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_id(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).and_then(|x| x.message_id.as_ref()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_from(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).map(|x| &x.from))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_subject(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).map(|x| &x.subject))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_body(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).map(|x| &x.body))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_message_html(
+    messages: *const ReadMailMessages,
+    index: usize,
+) -> *const c_char {
+    as_ptr(message(messages, index).and_then(|x| x.html.as_ref()))
+}
+
+// NULL なら何もしない
+#[no_mangle]
+pub unsafe extern "C" fn read_mail_messages_free(messages: *mut ReadMailMessages) {
+    if !messages.is_null() {
+        drop(Box::from_raw(messages));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_handle() {
+        let raw = b"From: taro@example.com\r\nSubject: =?UTF-8?B?6KuL5rGC?=\r\n\r\nbody\r\n";
+        let message = crate::parse_fetched(raw, "INBOX", 7, &ReadOptions::default()).unwrap();
+        let messages = into_messages(&[message]);
+        unsafe {
+            assert_eq!(read_mail_messages_len(messages), 1);
+            assert_eq!(read_mail_message_uid(messages, 0), 7);
+            let subject = CStr::from_ptr(read_mail_message_subject(messages, 0));
+            assert_eq!(subject.to_str().unwrap(), "請求");
+            assert!(read_mail_message_html(messages, 0).is_null());
+            assert!(read_mail_message_body(messages, 1).is_null());
+            read_mail_messages_free(messages);
+            assert_eq!(read_mail_messages_len(ptr::null()), 0);
+        }
+    }
+
+    #[test]
+    fn report_errors() {
+        unsafe {
+            let session =
+                read_mail_connect(ptr::null(), 993, ptr::null(), ptr::null(), ptr::null());
+            assert!(session.is_null());
+            let error = CStr::from_ptr(read_mail_last_error());
+            assert_eq!(error.to_str().unwrap(), "host is NULL");
+            assert_eq!(read_mail_select(ptr::null_mut(), ptr::null()), -1);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// text/plain; format=flowed（RFC 3676）の本文を、送り手が折り返す前の行に戻す
+// 行末の空白は「ここで折り返した」印なので、次の行とつなげる（delsp=yes ならその空白も消す）
+
+// text は 1 つの text/plain パートの本文（改行は CRLF か LF のまま）
+pub(crate) fn unflow(text: &str, delsp: bool) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = String::with_capacity(text.len());
+    // つなげている途中の行（引用の深さと内容）
+    let mut paragraph: Option<(usize, String)> = None;
+    for line in text.lines() {
+        let depth = line.bytes().take_while(|&x| x == b'>').count();
+        let content = &line[depth..];
+        // 行頭の空白 1 つは、送り手が「>」や「From 」と区別するために足したもの
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        let is_signature = content == "-- ";
+        let flowed = content.ends_with(' ') && !is_signature;
+
+        // 引用の深さが変わったり、署名の区切りが来たりしたら、つなげるのをやめる
+        if let Some((current, _)) = &paragraph {
+            if *current != depth || is_signature {
+                let (current, joined) = paragraph.take().unwrap();
+                push_line(&mut out, current, &joined, newline);
+            }
+        }
+        let (_, joined) = paragraph.get_or_insert_with(|| (depth, String::new()));
+        if flowed && delsp {
+            joined.push_str(&content[..content.len() - 1]);
+        } else {
+            joined.push_str(content);
+        }
+        if !flowed {
+            let (current, joined) = paragraph.take().unwrap();
+            push_line(&mut out, current, &joined, newline);
+        }
+    }
+    if let Some((current, joined)) = paragraph {
+        push_line(&mut out, current, &joined, newline);
+    }
+    if !text.ends_with('\n') {
+        out.truncate(out.len() - newline.len().min(out.len()));
+    }
+    out
+}
+
+fn push_line(out: &mut String, depth: usize, content: &str, newline: &str) {
+    if depth > 0 {
+        out.push_str(&">".repeat(depth));
+        if !content.is_empty() {
+            out.push(' ');
+        }
+    }
+    out.push_str(content);
+    out.push_str(newline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unflow_text() {
+        let text = "This is a long \r\n\
+                    paragraph.\r\n\
+                    \r\n\
+                    >> quoted \r\n\
+                    >> text\r\n\
+                    > reply \r\n\
+                    next\r\n\
+                    \x20>From here\r\n\
+                    -- \r\n\
+                    Taro\r\n";
+        assert_eq!(
+            unflow(text, false),
+            "This is a long paragraph.\r\n\
+             \r\n\
+             >> quoted text\r\n\
+             > reply \r\n\
+             next\r\n\
+             >From here\r\n\
+             -- \r\n\
+             Taro\r\n"
+        );
+
+        // delsp=yes は、空白を入れずに分かち書きしない言語を折り返すのに使う
+        assert_eq!(unflow("日本語の \n文章です。", true), "日本語の文章です。");
+        assert_eq!(
+            unflow("日本語の \n文章です。", false),
+            "日本語の 文章です。"
+        );
+    }
+
+    #[test]
+    fn parse_flowed_body() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: s\r\n\
+                   Content-Type: text/plain; charset=utf-8; format=flowed; delsp=yes\r\n\
+                   \r\n\
+                   Hello \r\n\
+                   world\r\n";
+        let message = crate::parse(raw.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "Helloworld");
+
+        let options = crate::ReadOptions::default().keep_flowed(true);
+        let message = crate::parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body(), "Hello \r\nworld");
+    }
+}
@@ -0,0 +1,154 @@
+// フォルダーの一覧と、それぞれのメール数・未読数
+// LIST-STATUS（RFC 5819）に対応していれば1回のコマンドでまとめて取得する
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderInfo {
+    name: String,
+    delimiter: Option<String>,
+    attributes: Vec<String>,
+    messages: Option<u32>,
+    unseen: Option<u32>,
+}
+
+impl FolderInfo {
+    // SELECT などにそのまま使えるフォルダー名
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn delimiter(&self) -> Option<&str> {
+        self.delimiter.as_deref()
+    }
+
+    // 「\HasChildren」「\Noselect」「\Sent」などの属性
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    // 選択できないフォルダー（\Noselect）では None
+    pub fn messages(&self) -> Option<u32> {
+        self.messages
+    }
+
+    pub fn unseen(&self) -> Option<u32> {
+        self.unseen
+    }
+
+    fn selectable(&self) -> bool {
+        !self.attributes.iter().any(|x| {
+            x.eq_ignore_ascii_case("\\Noselect") || x.eq_ignore_ascii_case("\\NonExistent")
+        })
+    }
+}
+
+pub fn folders(mailbox: &MyMailbox) -> Result<Vec<FolderInfo>, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    let list_status = imap_session.supports(Capability::ListStatus)?;
+
+    let command = if list_status {
+        "LIST \"\" \"*\" RETURN (STATUS (MESSAGES UNSEEN))"
+    } else {
+        "LIST \"\" \"*\""
+    };
+    let lines = imap_session.run_extension(command, &["LIST", "STATUS"])?;
+
+    let mut folders = Vec::new();
+    let mut statuses = HashMap::new();
+    for line in &lines {
+        let values = session::parse_values(line);
+        // LIST の行は属性のリストから、STATUS の行はフォルダー名から始まる
+        if let Some(Value::List(_)) = values.first() {
+            folders.extend(parse_list(&values));
+        } else if let Some((name, status)) = parse_status(&values) {
+            statuses.insert(name, status);
+        }
+    }
+
+    // LIST-STATUS がなければフォルダーごとに STATUS を送る
+    if !list_status {
+        for folder in folders.iter().filter(|x| x.selectable()) {
+            let command = format!(
+                "STATUS {} (MESSAGES UNSEEN)",
+                crate::imap_quote(&folder.name)
+            );
+            for line in imap_session.run_extension(&command, &["STATUS"])? {
+                statuses.extend(parse_status(&session::parse_values(&line)));
+            }
+        }
+    }
+    imap_session.logout()?;
+
+    for folder in &mut folders {
+        if let Some(&(messages, unseen)) = statuses.get(&folder.name) {
+            folder.messages = messages;
+            folder.unseen = unseen;
+        }
+    }
+    Ok(folders)
+}
+
+// 「* LIST (\HasNoChildren) "/" "Sent"」の「* LIST」より後
+fn parse_list(values: &[Value]) -> Option<FolderInfo> {
+    match values {
+        [attributes, delimiter, name, ..] => Some(FolderInfo {
+            name: name.as_str()?.to_string(),
+            delimiter: delimiter.as_str().map(str::to_string),
+            attributes: attributes
+                .as_list()
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            messages: None,
+            unseen: None,
+        }),
+        _ => None,
+    }
+}
+
+type Status = (Option<u32>, Option<u32>);
+
+// 「* STATUS "Sent" (MESSAGES 12 UNSEEN 0)」の「* STATUS」より後
+fn parse_status(values: &[Value]) -> Option<(String, Status)> {
+    match values {
+        [name, items, ..] => {
+            let mut status = (None, None);
+            for item in items.as_list().chunks(2) {
+                if let [key, value] = item {
+                    let value = value.as_str().and_then(|x| x.parse().ok());
+                    match key.as_str().map(str::to_ascii_uppercase).as_deref() {
+                        Some("MESSAGES") => status.0 = value,
+                        Some("UNSEEN") => status.1 = value,
+                        _ => {}
+                    }
+                }
+            }
+            Some((name.as_str()?.to_string(), status))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list_status() {
+        let folder = parse_list(&session::parse_values(b"(\\Noselect) \".\" \"Public\"")).unwrap();
+        assert_eq!(folder.name(), "Public");
+        assert_eq!(folder.delimiter(), Some("."));
+        assert!(!folder.selectable());
+
+        let (name, status) =
+            parse_status(&session::parse_values(b"\"Sent\" (MESSAGES 12 UNSEEN 0)")).unwrap();
+        assert_eq!(name, "Sent");
+        assert_eq!(status, (Some(12), Some(0)));
+    }
+}
@@ -0,0 +1,339 @@
+// Microsoft Graph（Office 365）でメールを読む
+// IMAP が無効にされたテナントでも、OAuth のアクセストークンで同じ MyMessage を受け取れる
+// メールの中身は MIME（/messages/{id}/$value）で受け取り、mailparse で解析する
+
+use std::error::Error;
+use std::io::Read;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{MyMessage, ReadOptions, SecretString};
+
+const GRAPH: &str = "https://graph.microsoft.com/v1.0";
+// 一度に一覧で受け取る数
+const PAGE: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct GraphAccount {
+    token: SecretString,
+    // 「me」かユーザーの ID・メールアドレス（共有メールボックスなど）
+    user: String,
+    timeout: Duration,
+}
+
+impl GraphAccount {
+    // Mail.ReadWrite の権限を持つアクセストークン
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.into(),
+            user: "me".to_string(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    // サインインしたユーザー以外のメールボックスを読む
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = user.to_string();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        let user = match self.user.as_str() {
+            "me" => "me".to_string(),
+            x => format!("users/{}", x),
+        };
+        format!("{}/{}/{}", GRAPH, user, path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphFolder {
+    id: String,
+    name: String,
+    messages: u32,
+    unseen: u32,
+}
+
+impl GraphFolder {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn messages(&self) -> u32 {
+        self.messages
+    }
+
+    pub fn unseen(&self) -> u32 {
+        self.unseen
+    }
+}
+
+// Graph のメッセージ ID 付きの MyMessage（移動やフラグの変更に ID を使う）
+#[derive(Debug, Clone)]
+pub struct GraphMessage {
+    id: String,
+    message: MyMessage,
+}
+
+impl GraphMessage {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn into_message(self) -> MyMessage {
+        self.message
+    }
+}
+
+impl Deref for GraphMessage {
+    type Target = MyMessage;
+
+    fn deref(&self) -> &MyMessage {
+        &self.message
+    }
+}
+
+// 最上位のフォルダーの一覧
+pub fn graph_folders(account: &GraphAccount) -> Result<Vec<GraphFolder>, Box<dyn Error>> {
+    let client = Client::new(account)?;
+    let mut folders = Vec::new();
+    for page in client.pages(&account.url(&format!("mailFolders?$top={}", PAGE)))? {
+        folders.extend(parse_folders(&page));
+    }
+    Ok(folders)
+}
+
+// folder（表示名か「inbox」「sentitems」のような既知の名前）のメールを、古い順に読む
+// options.folders を指定すれば、folder の代わりにそれらを順に読む
+// Graph には UID がないので、MyMessage の uid はフォルダーの中での順番（1から）になる
+pub fn read_graph(
+    account: &GraphAccount,
+    folder: &str,
+    options: &ReadOptions,
+) -> Result<Vec<GraphMessage>, Box<dyn Error>> {
+    let client = Client::new(account)?;
+    let folders = if options.folders.is_empty() {
+        vec![folder.to_string()]
+    } else {
+        options.folders.clone()
+    };
+
+    let mut messages = Vec::new();
+    for folder in &folders {
+        let id = client.folder_id(folder)?;
+        let url = account.url(&format!(
+            "mailFolders/{}/messages?$select=id&$orderby=receivedDateTime&$top={}",
+            id, PAGE
+        ));
+        let mut uid = 0;
+        for page in client.pages(&url)? {
+            for value in page["value"].as_array().ok_or("no message list")? {
+                let id = value["id"].as_str().ok_or("no message id")?;
+                let raw = client.download(&account.url(&format!("messages/{}/$value", id)))?;
+                uid += 1;
+                let message = crate::parse_fetched(&raw, folder, uid, options)?;
+                if let Some(message) = options.process(message) {
+                    messages.push(GraphMessage {
+                        id: id.to_string(),
+                        message,
+                    });
+                }
+            }
+        }
+    }
+    Ok(messages)
+}
+
+// メッセージを folder に移動し、移動先での新しいメッセージ ID を返す
+pub fn move_graph_message(
+    account: &GraphAccount,
+    id: &str,
+    folder: &str,
+) -> Result<String, Box<dyn Error>> {
+    let client = Client::new(account)?;
+    let destination = client.folder_id(folder)?;
+    let moved = client.send(
+        "POST",
+        &account.url(&format!("messages/{}/move", id)),
+        json!({ "destinationId": destination }),
+    )?;
+    Ok(moved["id"].as_str().ok_or("no message id")?.to_string())
+}
+
+// 既読（IMAP の \Seen）にする・外す
+pub fn set_graph_read(account: &GraphAccount, id: &str, read: bool) -> Result<(), Box<dyn Error>> {
+    let client = Client::new(account)?;
+    client.send(
+        "PATCH",
+        &account.url(&format!("messages/{}", id)),
+        json!({ "isRead": read }),
+    )?;
+    Ok(())
+}
+
+// フラグ（IMAP の \Flagged）を付ける・外す
+pub fn set_graph_flagged(
+    account: &GraphAccount,
+    id: &str,
+    flagged: bool,
+) -> Result<(), Box<dyn Error>> {
+    let status = if flagged { "flagged" } else { "notFlagged" };
+    let client = Client::new(account)?;
+    client.send(
+        "PATCH",
+        &account.url(&format!("messages/{}", id)),
+        json!({ "flag": { "flagStatus": status } }),
+    )?;
+    Ok(())
+}
+
+struct Client<'a> {
+    account: &'a GraphAccount,
+    agent: ureq::Agent,
+}
+
+impl<'a> Client<'a> {
+    fn new(account: &'a GraphAccount) -> Result<Self, Box<dyn Error>> {
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(native_tls::TlsConnector::new()?))
+            .timeout(account.timeout)
+            .build();
+        Ok(Self { account, agent })
+    }
+
+    fn authorization(&self) -> SecretString {
+        format!("Bearer {}", self.account.token.expose()).into()
+    }
+
+    fn get(&self, url: &str) -> Result<Value, Box<dyn Error>> {
+        let response = self
+            .agent
+            .get(url)
+            .set("Authorization", self.authorization().expose())
+            .call()?
+            .into_string()?;
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    fn send(&self, method: &str, url: &str, body: Value) -> Result<Value, Box<dyn Error>> {
+        let response = self
+            .agent
+            .request(method, url)
+            .set("Authorization", self.authorization().expose())
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())?
+            .into_string()?;
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    // @odata.nextLink をたどって、すべてのページを返す
+    fn pages(&self, url: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mut pages = Vec::new();
+        let mut next = Some(url.to_string());
+        while let Some(url) = next {
+            let page = self.get(&url)?;
+            next = page["@odata.nextLink"].as_str().map(str::to_string);
+            pages.push(page);
+        }
+        Ok(pages)
+    }
+
+    fn download(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut raw = Vec::new();
+        self.agent
+            .get(url)
+            .set("Authorization", self.authorization().expose())
+            .call()?
+            .into_reader()
+            .read_to_end(&mut raw)?;
+        Ok(raw)
+    }
+
+    // 表示名が一致するフォルダーがなければ、既知の名前（「inbox」など）か ID とみなす
+    fn folder_id(&self, folder: &str) -> Result<String, Box<dyn Error>> {
+        let url = self.account.url(&format!("mailFolders?$top={}", PAGE));
+        for page in self.pages(&url)? {
+            if let Some(x) = find_folder(&parse_folders(&page), folder) {
+                return Ok(x.id.clone());
+            }
+        }
+        Ok(folder.to_ascii_lowercase())
+    }
+}
+
+fn parse_folders(page: &Value) -> Vec<GraphFolder> {
+    let count = |x: &Value| x.as_u64().unwrap_or(0) as u32;
+    page["value"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|x| {
+            Some(GraphFolder {
+                id: x["id"].as_str()?.to_string(),
+                name: x["displayName"].as_str()?.to_string(),
+                messages: count(&x["totalItemCount"]),
+                unseen: count(&x["unreadItemCount"]),
+            })
+        })
+        .collect()
+}
+
+fn find_folder<'f>(folders: &'f [GraphFolder], name: &str) -> Option<&'f GraphFolder> {
+    folders.iter().find(|x| x.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_folder_list() {
+        let page = json!({
+            "value": [
+                { "id": "AAMkAGI2", "displayName": "Inbox", "totalItemCount": 12, "unreadItemCount": 3 },
+                { "id": "AAMkAGI3", "displayName": "請求書", "totalItemCount": 2, "unreadItemCount": 0 },
+                { "displayName": "no id" },
+            ]
+        });
+        let folders = parse_folders(&page);
+        assert_eq!(folders.len(), 2);
+        assert_eq!(folders[0].messages(), 12);
+        assert_eq!(folders[0].unseen(), 3);
+        assert_eq!(
+            find_folder(&folders, "INBOX").map(GraphFolder::id),
+            Some("AAMkAGI2")
+        );
+        assert_eq!(
+            find_folder(&folders, "請求書").map(GraphFolder::id),
+            Some("AAMkAGI3")
+        );
+        assert_eq!(find_folder(&folders, "sentitems"), None);
+    }
+
+    #[test]
+    fn user_urls() {
+        let account = GraphAccount::new("token");
+        assert_eq!(
+            account.url("mailFolders"),
+            format!("{}/me/mailFolders", GRAPH)
+        );
+        let account = account.user("shared@example.com");
+        assert_eq!(
+            account.url("messages/1"),
+            format!("{}/users/shared@example.com/messages/1", GRAPH)
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// Content-Type ごとに登録した処理でパートをデコードし、その結果（Payload）をメールに付ける
+// application/json や text/csv のような業務用の添付ファイルを、MIME の構造が分かっているうちに読める
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use mailparse::{MailHeaderMap, ParsedMail};
+
+use crate::{attachment, lenient, ReadOptions};
+
+type Value = Arc<dyn Any + Send + Sync>;
+type HandleFn = dyn Fn(&[u8]) -> Result<Value, Box<dyn Error>> + Send + Sync;
+
+// ReadOptions::handle_part で登録した処理（pattern は「text/csv」「application/*+json」など）
+#[derive(Clone)]
+pub(crate) struct PartHandler {
+    pattern: String,
+    handle: Arc<HandleFn>,
+}
+
+impl fmt::Debug for PartHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartHandler")
+            .field("pattern", &self.pattern)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartHandler {
+    pub(crate) fn new<T, F>(pattern: &str, handle: F) -> Self
+    where
+        T: Any + Send + Sync,
+        F: Fn(&[u8]) -> Result<T, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        Self {
+            pattern: pattern.to_string(),
+            handle: Arc::new(move |data| Ok(Arc::new(handle(data)?) as Value)),
+        }
+    }
+
+    fn handles(&self, mimetype: &str) -> bool {
+        attachment::is_matching(std::slice::from_ref(&self.pattern), None, mimetype)
+    }
+}
+
+// 登録した処理がパートから作った値
+#[derive(Clone)]
+pub struct Payload {
+    mimetype: String,
+    filename: Option<String>,
+    value: Value,
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Payload")
+            .field("mimetype", &self.mimetype)
+            .field("filename", &self.filename)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Payload {
+    // 元のパートの MIME タイプ
+    pub fn mimetype(&self) -> &str {
+        &self.mimetype
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    // 処理が返した値（型が違えば None）
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+// すべてのパートについて、当てはまる処理を登録した順に呼ぶ
+// 処理のエラーは、寛容モードなら警告にしてそのパートを飛ばす
+pub(crate) fn run(
+    parts: &[&ParsedMail],
+    options: &ReadOptions,
+    warnings: &mut lenient::Warnings,
+) -> Result<Vec<Payload>, Box<dyn Error>> {
+    let mut payloads = Vec::new();
+    if options.part_handlers.is_empty() {
+        return Ok(payloads);
+    }
+    for part in parts.iter().filter(|x| x.subparts.is_empty()) {
+        let mimetype = &part.ctype.mimetype;
+        let handlers = options
+            .part_handlers
+            .iter()
+            .filter(|x| x.handles(mimetype))
+            .collect::<Vec<_>>();
+        if handlers.is_empty() {
+            continue;
+        }
+        let data = match body(part, options) {
+            Ok(data) => data,
+            Err(e) => {
+                let e = format!("{} part: {}", mimetype, e).into();
+                warnings.recover(Err(e), || ())?;
+                continue;
+            }
+        };
+        let filename = part
+            .get_content_disposition()
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"))
+            .cloned();
+        for handler in handlers {
+            let value = (handler.handle)(&data)
+                .map(Some)
+                .map_err(|e| format!("{} part handler: {}", mimetype, e).into());
+            if let Some(value) = warnings.recover(value, || None)? {
+                payloads.push(Payload {
+                    mimetype: mimetype.clone(),
+                    filename: filename.clone(),
+                    value,
+                });
+            }
+        }
+    }
+    Ok(payloads)
+}
+
+// デコードした中身（ReadOptions::large_parts_to_disk で書き出したパートはファイルから読む）
+fn body(part: &ParsedMail, options: &ReadOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    let spilled = part
+        .headers
+        .get_first_value(crate::spill::SPILLED_HEADER)
+        .map(PathBuf::from)
+        .filter(|x| options.spilled.contains(x));
+    match spilled {
+        Some(path) => Ok(fs::read(path)?),
+        None => Ok(part.get_body_raw()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ReadOptions;
+
+    #[test]
+    fn handle_parts() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: report\r\n\
+                   Content-Type: multipart/mixed; boundary=b\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   see attached\r\n\
+                   --b\r\n\
+                   Content-Type: text/csv; name=sales.csv\r\n\
+                   Content-Disposition: attachment; filename=sales.csv\r\n\
+                   \r\n\
+                   a,b\r\n\
+                   1,2\r\n\
+                   --b\r\n\
+                   Content-Type: application/json\r\n\
+                   \r\n\
+                   not json\r\n\
+                   --b--\r\n";
+
+        let options = ReadOptions::default()
+            .handle_part("text/csv", |data| {
+                let text = std::str::from_utf8(data)?;
+                Ok(text
+                    .lines()
+                    .map(|x| x.split(',').map(str::to_string).collect::<Vec<_>>())
+                    .collect::<Vec<_>>())
+            })
+            .handle_part("application/json", |data| {
+                if data.starts_with(b"{") {
+                    Ok(data.len())
+                } else {
+                    Err("invalid json".into())
+                }
+            });
+        let error = crate::parse(raw.as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "application/json part handler: invalid json"
+        );
+
+        let message = crate::parse(raw.as_bytes(), &options.lenient(true)).unwrap();
+        assert_eq!(message.payloads().len(), 1);
+        assert_eq!(message.payloads()[0].filename(), Some("sales.csv"));
+        assert_eq!(
+            message.payload::<Vec<Vec<String>>>().unwrap(),
+            &[vec!["a", "b"], vec!["1", "2"]]
+        );
+        assert_eq!(message.payload::<usize>(), None);
+        assert_eq!(
+            message.warnings(),
+            ["application/json part handler: invalid json"]
+        );
+    }
+}
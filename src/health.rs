@@ -0,0 +1,450 @@
+// 接続の確認（設定画面などで、どこでつまずいたかを利用者に示すため）
+// 名前解決・TCP 接続・TLS・ログイン・フォルダーの選択を順に試し、失敗したところで止める
+
+use std::error::Error;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::session::MyClient;
+use crate::transport::{self, Security, Transport};
+use crate::MyMailbox;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckStep {
+    Dns,
+    Tcp,
+    Tls,
+    Login,
+    Select,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    step: CheckStep,
+    elapsed: Duration,
+    // 成功したときは分かったこと（アドレスやメールの数）、失敗したときはエラー
+    result: Result<String, String>,
+}
+
+impl StepResult {
+    pub fn step(&self) -> CheckStep {
+        self.step
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.result.as_deref().ok()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.result.as_ref().err().map(String::as_str)
+    }
+}
+
+// サーバー証明書の内容（検証に失敗したときも、分かれば入れる）
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateInfo {
+    // 「C=JP, O=Example, CN=imap.example.com」のような形
+    subject: String,
+    issuer: String,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl CertificateInfo {
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn not_before(&self) -> Option<DateTime<Utc>> {
+        self.not_before
+    }
+
+    pub fn not_after(&self) -> Option<DateTime<Utc>> {
+        self.not_after
+    }
+
+    // 自己署名（発行者と主体が同じ）
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    port: u16,
+    security: Security,
+    // 試した順（失敗したところまで）
+    steps: Vec<StepResult>,
+    certificate: Option<CertificateInfo>,
+}
+
+impl HealthReport {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn security(&self) -> Security {
+        self.security
+    }
+
+    pub fn steps(&self) -> &[StepResult] {
+        &self.steps
+    }
+
+    pub fn certificate(&self) -> Option<&CertificateInfo> {
+        self.certificate.as_ref()
+    }
+
+    // すべて成功した
+    pub fn is_healthy(&self) -> bool {
+        self.steps
+            .last()
+            .is_some_and(|x| x.step == CheckStep::Select && x.is_ok())
+    }
+
+    // 失敗した段階
+    pub fn failed(&self) -> Option<&StepResult> {
+        self.steps.iter().find(|x| !x.is_ok())
+    }
+
+    // 成功すれば Some、失敗すれば記録して None
+    fn run<T, F>(&mut self, step: CheckStep, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Self) -> Result<(T, String), Box<dyn Error>>,
+    {
+        let start = Instant::now();
+        let result = f(self);
+        let elapsed = start.elapsed();
+        match result {
+            Ok((value, detail)) => {
+                self.steps.push(StepResult {
+                    step,
+                    elapsed,
+                    result: Ok(detail),
+                });
+                Some(value)
+            }
+            Err(e) => {
+                self.steps.push(StepResult {
+                    step,
+                    elapsed,
+                    result: Err(e.to_string()),
+                });
+                None
+            }
+        }
+    }
+}
+
+// mailbox の host・port を確かめる（fallback のサーバーは試さない）
+// Security::Auto なら 993 の TLS、143 の STARTTLS の順に試し、TCP 接続できたほうの結果を返す
+// tunnel を指定したときは、ログインとフォルダーの選択だけを確かめる
+pub fn check(mailbox: &MyMailbox) -> HealthReport {
+    let attempts = mailbox.security.attempts(mailbox.port);
+    let mut report = None;
+    for &(port, security) in &attempts {
+        let current = check_server(mailbox, port, security);
+        let connected = current
+            .steps
+            .iter()
+            .any(|x| x.step == CheckStep::Tcp && x.is_ok());
+        report = Some(current);
+        if connected || mailbox.tunnel.is_some() {
+            break;
+        }
+    }
+    report.unwrap_or_else(|| HealthReport {
+        port: mailbox.port,
+        security: mailbox.security,
+        steps: Vec::new(),
+        certificate: None,
+    })
+}
+
+fn check_server(mailbox: &MyMailbox, port: u16, security: Security) -> HealthReport {
+    let mut report = HealthReport {
+        port,
+        security,
+        steps: Vec::new(),
+        certificate: None,
+    };
+    let host = mailbox.host;
+
+    // トンネルはログインの段階で起動する
+    let (transport, starttls) = if mailbox.tunnel.is_some() {
+        (None, false)
+    } else {
+        let addresses = match report.run(CheckStep::Dns, |_| resolve(host, port)) {
+            Some(x) => x,
+            None => return report,
+        };
+        let tcp = match report.run(CheckStep::Tcp, |_| connect(&addresses)) {
+            Some(x) => x,
+            None => return report,
+        };
+        let starttls = security == Security::StartTls;
+        let tls = report.run(CheckStep::Tls, |report| {
+            if starttls {
+                transport::starttls(&tcp)?;
+            }
+            let address = tcp.peer_addr()?;
+            match transport::tls_connect(host, tcp) {
+                Ok(stream) => {
+                    let certificate = stream
+                        .peer_certificate()?
+                        .and_then(|x| parse_certificate(&x.to_der().ok()?));
+                    let detail = certificate
+                        .as_ref()
+                        .map_or_else(String::new, describe_certificate);
+                    report.certificate = certificate;
+                    Ok((stream, detail))
+                }
+                Err(e) => {
+                    // 検証しないでつなぎ直して、なぜ信頼できないのかを示せるようにする
+                    report.certificate = insecure_certificate(host, address, starttls);
+                    Err(e)
+                }
+            }
+        });
+        match tls {
+            Some(stream) => (Some(Transport::Tls(stream)), starttls),
+            None => return report,
+        }
+    };
+
+    // 挨拶（BYE などで断られることもある）とケーパビリティを読んでからログインする
+    let session = report.run(CheckStep::Login, |_| {
+        let transport = match transport {
+            Some(x) => x,
+            None => Transport::open(mailbox, host, port)?,
+        };
+        let client = MyClient::start(mailbox, transport, starttls, (port, security))?;
+        Ok((
+            client.login(mailbox)?,
+            format!("logged in as {}", mailbox.user),
+        ))
+    });
+    let mut session = match session {
+        Some(x) => x,
+        None => return report,
+    };
+
+    let folder = mailbox.selection;
+    report.run(CheckStep::Select, |_| {
+        let selected = session.select(folder)?;
+        Ok(((), format!("{}: {} messages", folder, selected.exists)))
+    });
+    let _ = session.logout();
+    report
+}
+
+fn resolve(host: &str, port: u16) -> Result<(Vec<SocketAddr>, String), Box<dyn Error>> {
+    let addresses = (host, port).to_socket_addrs()?.collect::<Vec<_>>();
+    if addresses.is_empty() {
+        return Err(format!("{} has no address", host).into());
+    }
+    let detail = addresses
+        .iter()
+        .map(|x| x.ip().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok((addresses, detail))
+}
+
+// 解決できたアドレスを順に試す
+fn connect(addresses: &[SocketAddr]) -> Result<(TcpStream, String), Box<dyn Error>> {
+    let mut errors = Vec::new();
+    for address in addresses {
+        match TcpStream::connect_timeout(address, TIMEOUT) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(TIMEOUT))?;
+                tcp.set_write_timeout(Some(TIMEOUT))?;
+                return Ok((tcp, address.to_string()));
+            }
+            Err(e) => errors.push(format!("{}: {}", address, e)),
+        }
+    }
+    Err(errors.join("; ").into())
+}
+
+fn insecure_certificate(
+    host: &str,
+    address: SocketAddr,
+    starttls: bool,
+) -> Option<CertificateInfo> {
+    let tcp = TcpStream::connect_timeout(&address, TIMEOUT).ok()?;
+    tcp.set_read_timeout(Some(TIMEOUT)).ok()?;
+    if starttls {
+        transport::starttls(&tcp).ok()?;
+    }
+    let stream = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .ok()?
+        .connect(host, tcp)
+        .ok()?;
+    parse_certificate(&stream.peer_certificate().ok()??.to_der().ok()?)
+}
+
+fn describe_certificate(certificate: &CertificateInfo) -> String {
+    let date = |x: Option<DateTime<Utc>>| x.map_or_else(|| "?".to_string(), |x| x.to_rfc3339());
+    format!(
+        "subject: {}; issuer: {}; valid: {} - {}",
+        certificate.subject,
+        certificate.issuer,
+        date(certificate.not_before),
+        date(certificate.not_after)
+    )
+}
+
+// DER の証明書から主体・発行者・有効期間を読む
+fn parse_certificate(der: &[u8]) -> Option<CertificateInfo> {
+    let (_, certificate, _) = read_tlv(der)?;
+    let (_, tbs, _) = read_tlv(certificate)?;
+    // version は省略されることがある（[0] のタグ）
+    let (tag, _, rest) = read_tlv(tbs)?;
+    let rest = if tag == 0xa0 { rest } else { tbs };
+    let (_, _, rest) = read_tlv(rest)?; // serialNumber
+    let (_, _, rest) = read_tlv(rest)?; // signature
+    let (_, issuer, rest) = read_tlv(rest)?;
+    let (_, validity, rest) = read_tlv(rest)?;
+    let (_, subject, _) = read_tlv(rest)?;
+    let (tag, not_before, rest) = read_tlv(validity)?;
+    let not_before = read_time(tag, not_before);
+    let (tag, not_after, _) = read_tlv(rest)?;
+    Some(CertificateInfo {
+        subject: read_name(subject),
+        issuer: read_name(issuer),
+        not_before,
+        not_after: read_time(tag, not_after),
+    })
+}
+
+// DER の TLV を1つ読む（タグ, 中身, 残り）
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let length = rest[..n].iter().fold(0, |a, &b| a << 8 | b as usize);
+        (length, &rest[n..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+// Name（SET の並び）を「C=JP, O=Example, CN=...」にする
+fn read_name(mut data: &[u8]) -> String {
+    let mut parts = Vec::new();
+    while let Some((_, set, rest)) = read_tlv(data) {
+        data = rest;
+        let mut attributes = set;
+        while let Some((_, attribute, rest)) = read_tlv(attributes) {
+            attributes = rest;
+            let (oid, value) = match read_tlv(attribute) {
+                Some((_, oid, value)) => (oid, value),
+                None => continue,
+            };
+            let value = match read_tlv(value) {
+                Some((_, value, _)) => String::from_utf8_lossy(value).into_owned(),
+                None => continue,
+            };
+            let name = match oid {
+                [0x55, 0x04, 0x03] => "CN".to_string(),
+                [0x55, 0x04, 0x06] => "C".to_string(),
+                [0x55, 0x04, 0x07] => "L".to_string(),
+                [0x55, 0x04, 0x08] => "ST".to_string(),
+                [0x55, 0x04, 0x0a] => "O".to_string(),
+                [0x55, 0x04, 0x0b] => "OU".to_string(),
+                _ => oid.iter().map(|x| format!("{:02x}", x)).collect(),
+            };
+            parts.push(format!("{}={}", name, value));
+        }
+    }
+    parts.join(", ")
+}
+
+// UTCTime（YYMMDDHHMMSSZ）か GeneralizedTime（YYYYMMDDHHMMSSZ）
+fn read_time(tag: u8, value: &[u8]) -> Option<DateTime<Utc>> {
+    let text = std::str::from_utf8(value).ok()?;
+    let text = match tag {
+        0x17 => {
+            let year = text.get(..2)?.parse::<u32>().ok()?;
+            format!("{}{}", if year < 50 { "20" } else { "19" }, text)
+        }
+        0x18 => text.to_string(),
+        _ => return None,
+    };
+    let time = NaiveDateTime::parse_from_str(&text, "%Y%m%d%H%M%SZ").ok()?;
+    Some(DateTime::from_naive_utc_and_offset(time, Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    #[test]
+    fn read_certificate() {
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(
+                "MIIBtzCCAVygAwIBAgIBATAKBggqhkjOPQQDAjA6MQswCQYDVQQGEwJKUDEQMA4GA1UECgwHRXhhbXBsZTEZ\
+                 MBcGA1UEAwwQaW1hcC5leGFtcGxlLmNvbTAeFw0yNjEwMTQwNDMzMjhaFw0zNjEwMTEwNDMzMjhaMDoxCzAJ\
+                 BgNVBAYTAkpQMRAwDgYDVQQKDAdFeGFtcGxlMRkwFwYDVQQDDBBpbWFwLmV4YW1wbGUuY29tMFkwEwYHKoZI\
+                 zj0CAQYIKoZIzj0DAQcDQgAEV2dP9bChOc1yqCKtrDotj5Hz4uN2RvuEs3E+KsQh2fIG0/65FMgvknnN6IKi\
+                 mVAmBpCvoZdWimILiws4o7IBtaNTMFEwHQYDVR0OBBYEFDYGwSATXkl1mPCCWlldg39psnH3MB8GA1UdIwQY\
+                 MBaAFDYGwSATXkl1mPCCWlldg39psnH3MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSQAwRgIhAOlS\
+                 aed71KIk18ji+Bv5aOWwbzP2HjNsGCk803EeOb5sAiEArejdssHZXU6VO2mEX+3uEQ8VrpR5dWjRMYKKWOLQ\
+                 gIE=",
+            )
+            .unwrap();
+        let certificate = parse_certificate(&der).unwrap();
+        assert_eq!(
+            certificate.subject(),
+            "C=JP, O=Example, CN=imap.example.com"
+        );
+        assert!(certificate.is_self_signed());
+        assert_eq!(
+            certificate.not_after().unwrap().to_rfc3339(),
+            "2036-10-11T04:33:28+00:00"
+        );
+    }
+
+    #[test]
+    fn stop_at_failed_step() {
+        let mailbox = MyMailbox {
+            host: "imap.invalid",
+            user: "taro",
+            password: "secret",
+            ..Default::default()
+        };
+        let report = check(&mailbox);
+        assert!(!report.is_healthy());
+        assert_eq!(report.steps().len(), 1);
+        assert_eq!(report.failed().map(|x| x.step()), Some(CheckStep::Dns));
+    }
+}
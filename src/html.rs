@@ -0,0 +1,41 @@
+// HTML 本文のサニタイズ（Web 画面にそのまま埋め込める形にする）
+
+use std::borrow::Cow;
+
+pub(crate) fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        // 本文中のインライン画像（cid:）は残す
+        .add_url_schemes(&["cid"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            // 外部の画像は開封確認（トラッキングピクセル）に使われるので読み込ませない
+            ("img", "src") if is_remote(value) => None,
+            _ => Some(Cow::from(value)),
+        })
+        .clean(html)
+        .to_string()
+}
+
+fn is_remote(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http:") || url.starts_with("https:") || url.starts_with("//")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_scripts_and_trackers() {
+        let html = "<p onclick=\"steal()\">Hello<script>alert(1)</script></p>\
+                    <img src=\"https://tracker.example.com/open.gif\" width=\"1\">\
+                    <img src=\"cid:logo@example.com\">\
+                    <a href=\"javascript:alert(1)\">link</a>";
+        let sanitized = sanitize(html);
+        assert!(!sanitized.contains("script"));
+        assert!(!sanitized.contains("onclick"));
+        assert!(!sanitized.contains("tracker.example.com"));
+        assert!(!sanitized.contains("javascript:"));
+        assert!(sanitized.contains("cid:logo@example.com"));
+        assert!(sanitized.contains("<p>Hello</p>"));
+    }
+}
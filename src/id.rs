@@ -0,0 +1,70 @@
+// クライアントの名乗り（ID 拡張、RFC 2971）
+// 163.com などはログイン後に ID を送らないと SELECT できない
+
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox, MySession};
+
+// サーバーが返した ID（「name」「version」「vendor」など）
+// MyMailbox::client_id を指定していればそれを名乗る
+pub fn server_id(mailbox: &MyMailbox) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    // crate::connect だと client_id があるときに ID を2回送ってしまう
+    let client = session::MyClient::connect(mailbox)?;
+    let mut imap_session = client.login(mailbox)?;
+    let id = send_id(&mut imap_session, mailbox.client_id)?;
+    imap_session.logout()?;
+    Ok(id)
+}
+
+// ID を送って、サーバーの ID を返す（ID に対応していなければ空）
+pub(crate) fn send_id(
+    imap_session: &mut MySession,
+    client_id: Option<(&str, &str)>,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if !imap_session.supports(Capability::Id)? {
+        return Ok(Vec::new());
+    }
+    let parameters = match client_id {
+        Some((name, version)) => format!(
+            "(\"name\" {} \"version\" {})",
+            crate::imap_quote(name),
+            crate::imap_quote(version)
+        ),
+        None => "NIL".to_string(),
+    };
+    let lines = imap_session.run_extension(&format!("ID {}", parameters), &["ID"])?;
+    Ok(lines
+        .first()
+        .map(|x| parse_id(&session::parse_values(x)))
+        .unwrap_or_default())
+}
+
+// 「* ID ("name" "Dovecot" "version" "2.3")」の「* ID」より後（NIL なら空）
+fn parse_id(values: &[Value]) -> Vec<(String, String)> {
+    values
+        .first()
+        .map(Value::as_list)
+        .unwrap_or_default()
+        .chunks(2)
+        .filter_map(|x| match x {
+            [key, value] => Some((key.as_str()?.to_string(), value.as_str()?.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_id_response() {
+        let values = session::parse_values(b"(\"name\" \"Dovecot\" \"support-url\" NIL)");
+        assert_eq!(
+            parse_id(&values),
+            [("name".to_string(), "Dovecot".to_string())]
+        );
+        assert!(parse_id(&session::parse_values(b"NIL")).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+// JMAP（RFC 8620・RFC 8621）でメールを読む
+// IMAP と同じ MyMessage を返す。一覧の取得（Email/query と Email/get）は1回の要求にまとめ、
+// メールの中身は blob としてダウンロードして mailparse で解析する
+
+use std::error::Error;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{MyMessage, ReadOptions, SecretString};
+
+const MAIL: &str = "urn:ietf:params:jmap:mail";
+// 一度に Email/query で受け取る数
+const PAGE: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct JmapAccount {
+    // 「https://api.fastmail.com/jmap/session」のようなセッションの URL
+    session_url: String,
+    authorization: SecretString,
+    timeout: Duration,
+}
+
+impl JmapAccount {
+    // API トークン（Bearer）で認証する
+    pub fn new(session_url: &str, token: &str) -> Self {
+        Self::with_authorization(session_url, format!("Bearer {}", token))
+    }
+
+    // ユーザー名とパスワード（Basic）で認証する
+    pub fn basic(session_url: &str, user: &str, password: &str) -> Self {
+        let credentials = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            format!("{}:{}", user, password),
+        );
+        Self::with_authorization(session_url, format!("Basic {}", credentials))
+    }
+
+    fn with_authorization(session_url: &str, authorization: String) -> Self {
+        Self {
+            session_url: session_url.to_string(),
+            authorization: authorization.into(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+// folder（名前か「INBOX」「Sent」のような役割）のメールを読む
+// options.folders を指定すれば、folder の代わりにそれらを順に読む
+// JMAP には UID がないので、MyMessage の uid はフォルダーの中での受信順（1から）になる
+pub fn read_jmap(
+    account: &JmapAccount,
+    folder: &str,
+    options: &ReadOptions,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let client = Client::connect(account)?;
+    let mailboxes = client.call("Mailbox/get", json!({ "properties": ["name", "role"] }))?;
+    let mailboxes = mailboxes["list"].as_array().ok_or("no mailbox list")?;
+
+    let folders = if options.folders.is_empty() {
+        vec![folder.to_string()]
+    } else {
+        options.folders.clone()
+    };
+    let mut messages = Vec::new();
+    for folder in &folders {
+        let id = find_mailbox(mailboxes, folder).ok_or(format!("no such mailbox: {}", folder))?;
+        for (i, blob_id) in client.blob_ids(id)?.iter().enumerate() {
+            let raw = client.download(blob_id)?;
+            let message = crate::parse_fetched(&raw, folder, i as u32 + 1, options)?;
+            messages.extend(options.process(message));
+        }
+    }
+
+    if let Some(strategy) = &options.dedup {
+        messages = crate::dedup::dedup(messages, strategy);
+    }
+    if let Some((key, order)) = options.sort {
+        crate::sort::sort(&mut messages, key, order);
+    }
+    Ok(messages)
+}
+
+struct Client<'a> {
+    account: &'a JmapAccount,
+    agent: ureq::Agent,
+    api_url: String,
+    download_url: String,
+    account_id: String,
+}
+
+impl<'a> Client<'a> {
+    fn connect(account: &'a JmapAccount) -> Result<Self, Box<dyn Error>> {
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(native_tls::TlsConnector::new()?))
+            .timeout(account.timeout)
+            .build();
+        let session = agent
+            .get(&account.session_url)
+            .set("Authorization", account.authorization.expose())
+            .call()?
+            .into_string()?;
+        let session: Value = serde_json::from_str(&session)?;
+        let (api_url, download_url, account_id) = parse_session(&session)?;
+        Ok(Self {
+            account,
+            agent,
+            api_url,
+            download_url,
+            account_id,
+        })
+    }
+
+    // メソッドを1つ呼んで、その結果を返す
+    fn call(&self, method: &str, arguments: Value) -> Result<Value, Box<dyn Error>> {
+        let mut responses = self.request(vec![(method, arguments)])?;
+        responses.pop().ok_or_else(|| "no method response".into())
+    }
+
+    // 複数のメソッドを1回の要求で呼ぶ（後のメソッドは前の結果を「#」付きの引数で参照できる）
+    fn request(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Value>, Box<dyn Error>> {
+        let calls = calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, (method, mut arguments))| {
+                arguments["accountId"] = json!(self.account_id);
+                json!([method, arguments, i.to_string()])
+            })
+            .collect::<Vec<_>>();
+        let body = json!({
+            "using": ["urn:ietf:params:jmap:core", MAIL],
+            "methodCalls": calls,
+        });
+        let response = self
+            .agent
+            .post(&self.api_url)
+            .set("Authorization", self.account.authorization.expose())
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())?
+            .into_string()?;
+        let response: Value = serde_json::from_str(&response)?;
+
+        let mut results = Vec::new();
+        for response in response["methodResponses"]
+            .as_array()
+            .ok_or("no method responses")?
+        {
+            match (response[0].as_str(), &response[1]) {
+                (Some("error"), error) => {
+                    return Err(format!("JMAP error: {}", error["type"]).into())
+                }
+                (_, arguments) => results.push(arguments.clone()),
+            }
+        }
+        Ok(results)
+    }
+
+    // フォルダーのメールの blobId を、古い順に
+    fn blob_ids(&self, mailbox_id: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut blob_ids = Vec::new();
+        loop {
+            let query = json!({
+                "filter": { "inMailbox": mailbox_id },
+                "sort": [{ "property": "receivedAt", "isAscending": true }],
+                "position": blob_ids.len(),
+                "limit": PAGE,
+            });
+            let get = json!({
+                "#ids": { "resultOf": "0", "name": "Email/query", "path": "/ids" },
+                "properties": ["blobId"],
+            });
+            let responses = self.request(vec![("Email/query", query), ("Email/get", get)])?;
+            let emails = responses
+                .get(1)
+                .and_then(|x| x["list"].as_array())
+                .ok_or("no email list")?;
+            if emails.is_empty() {
+                return Ok(blob_ids);
+            }
+            for email in emails {
+                blob_ids.push(email["blobId"].as_str().ok_or("no blobId")?.to_string());
+            }
+        }
+    }
+
+    fn download(&self, blob_id: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let url = download_url(&self.download_url, &self.account_id, blob_id);
+        let mut raw = Vec::new();
+        self.agent
+            .get(&url)
+            .set("Authorization", self.account.authorization.expose())
+            .call()?
+            .into_reader()
+            .read_to_end(&mut raw)?;
+        Ok(raw)
+    }
+}
+
+// セッションの apiUrl・downloadUrl と、メールのアカウントの ID
+fn parse_session(session: &Value) -> Result<(String, String, String), Box<dyn Error>> {
+    let field = |name: &str| {
+        session[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or(format!("no {} in JMAP session", name))
+    };
+    let account_id = session["primaryAccounts"][MAIL]
+        .as_str()
+        .ok_or("no mail account in JMAP session")?
+        .to_string();
+    Ok((field("apiUrl")?, field("downloadUrl")?, account_id))
+}
+
+// 名前が同じか、役割（role）が同じフォルダーの ID
+fn find_mailbox<'v>(mailboxes: &'v [Value], folder: &str) -> Option<&'v str> {
+    let by = |key: &str| {
+        mailboxes
+            .iter()
+            .find(|x| {
+                x[key]
+                    .as_str()
+                    .is_some_and(|x| x.eq_ignore_ascii_case(folder))
+            })
+            .and_then(|x| x["id"].as_str())
+    };
+    by("name").or_else(|| by("role"))
+}
+
+// downloadUrl は「.../{accountId}/{blobId}/{name}?type={type}」のような URI テンプレート
+fn download_url(template: &str, account_id: &str, blob_id: &str) -> String {
+    template
+        .replace("{accountId}", account_id)
+        .replace("{blobId}", blob_id)
+        .replace("{name}", "message.eml")
+        .replace("{type}", "message%2Frfc822")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_jmap_session() {
+        let session = json!({
+            "apiUrl": "https://jmap.example.com/api/",
+            "downloadUrl": "https://jmap.example.com/download/{accountId}/{blobId}/{name}?type={type}",
+            "primaryAccounts": { MAIL: "u123" },
+        });
+        let (api_url, template, account_id) = parse_session(&session).unwrap();
+        assert_eq!(api_url, "https://jmap.example.com/api/");
+        assert_eq!(account_id, "u123");
+        assert_eq!(
+            download_url(&template, &account_id, "Gb1"),
+            "https://jmap.example.com/download/u123/Gb1/message.eml?type=message%2Frfc822"
+        );
+        assert!(parse_session(&json!({ "apiUrl": "x" })).is_err());
+    }
+
+    #[test]
+    fn find_mailbox_by_name_or_role() {
+        let mailboxes = [
+            json!({ "id": "m1", "name": "受信箱", "role": "inbox" }),
+            json!({ "id": "m2", "name": "Archive", "role": null }),
+        ];
+        assert_eq!(find_mailbox(&mailboxes, "INBOX"), Some("m1"));
+        assert_eq!(find_mailbox(&mailboxes, "archive"), Some("m2"));
+        assert_eq!(find_mailbox(&mailboxes, "Sent"), None);
+    }
+}
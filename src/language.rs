@@ -0,0 +1,75 @@
+// 本文の言語を推定する（問い合わせを言語ごとの窓口に振り分けるためのもの）
+// 推定は whatlang に任せ、確からしさが低いもの（短すぎる本文など）は None にする
+
+// whatlang の Lang（code で ISO 639-3 のコードが分かる）
+pub use whatlang::Lang;
+
+// これより文字が少ない本文は推定しない
+const MIN_LETTERS: usize = 10;
+
+// whatlang の confidence（0〜1）がこれより低ければ分からないとみなす
+// （is_reliable はメールの数行の本文では厳しすぎる）
+const MIN_CONFIDENCE: f64 = 0.2;
+
+pub(crate) fn detect(text: &str) -> Option<Lang> {
+    if text.chars().filter(|c| c.is_alphabetic()).count() < MIN_LETTERS {
+        return None;
+    }
+    whatlang::detect(text)
+        .filter(|x| x.confidence() >= MIN_CONFIDENCE)
+        .map(|x| x.lang())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_languages() {
+        assert_eq!(
+            detect("お世話になっております。請求書の件でご連絡しました。"),
+            Some(Lang::Jpn)
+        );
+        assert_eq!(
+            detect("您好，关于发票的问题我想咨询一下。"),
+            Some(Lang::Cmn)
+        );
+        assert_eq!(
+            detect("안녕하세요, 청구서에 대해 문의드립니다."),
+            Some(Lang::Kor)
+        );
+        assert_eq!(
+            detect("Здравствуйте, у меня вопрос по счёту."),
+            Some(Lang::Rus)
+        );
+        assert_eq!(
+            detect("Hello, I have a question about the invoice you sent."),
+            Some(Lang::Eng)
+        );
+        assert_eq!(
+            detect("Guten Tag, ich habe eine Frage zu der Rechnung. Bitte helfen Sie mir."),
+            Some(Lang::Deu)
+        );
+        assert_eq!(
+            detect("Bonjour, j'ai une question sur la facture. Merci pour votre aide."),
+            Some(Lang::Fra)
+        );
+        assert_eq!(
+            detect("Hola, tengo una pregunta sobre la factura. Muchas gracias por su ayuda."),
+            Some(Lang::Spa)
+        );
+        assert_eq!(
+            detect("Ciao, ho una domanda sulla fattura. Grazie mille per il vostro aiuto."),
+            Some(Lang::Ita)
+        );
+        assert_eq!(
+            detect("Olá, tenho uma pergunta sobre a fatura. Muito obrigado pela sua ajuda."),
+            Some(Lang::Por)
+        );
+        assert_eq!(Lang::Jpn.code(), "jpn");
+        // 短すぎるものと、分からないもの
+        assert_eq!(detect("OK"), None);
+        assert_eq!(detect("12345 67890 !!!"), None);
+        assert_eq!(detect("xqzv bnmt kwrp"), None);
+    }
+}
@@ -0,0 +1,54 @@
+// 壊れたメールを読むときの寛容モード
+// 失敗した箇所は代わりの値（U+FFFD を含む文字列など）で埋めて、警告として記録する
+
+use std::error::Error;
+
+use mailparse::body::Body;
+use mailparse::ParsedMail;
+
+pub(crate) struct Warnings {
+    lenient: bool,
+    messages: Vec<String>,
+}
+
+impl Warnings {
+    pub(crate) fn new(lenient: bool) -> Self {
+        Self {
+            lenient,
+            messages: Vec::new(),
+        }
+    }
+
+    // 寛容モードでなければエラーをそのまま返す
+    pub(crate) fn recover<T>(
+        &mut self,
+        result: Result<T, Box<dyn Error>>,
+        fallback: impl FnOnce() -> T,
+    ) -> Result<T, Box<dyn Error>> {
+        match result {
+            Ok(x) => Ok(x),
+            Err(e) if self.lenient => {
+                self.messages.push(e.to_string());
+                Ok(fallback())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn into_messages(self) -> Vec<String> {
+        self.messages
+    }
+}
+
+// Content-Transfer-Encoding を解除できなかったときに使う、元のままの本文
+pub(crate) fn raw_body(part: &ParsedMail) -> Vec<u8> {
+    match part.get_body_encoded() {
+        Body::Base64(body) | Body::QuotedPrintable(body) => body.get_raw().to_vec(),
+        Body::SevenBit(body) | Body::EightBit(body) => body.get_raw().to_vec(),
+        Body::Binary(body) => body.get_raw().to_vec(),
+    }
+}
+
+pub(crate) fn raw_body_lossy(part: &ParsedMail) -> String {
+    String::from_utf8_lossy(&raw_body(part)).into_owned()
+}
@@ -1,6 +1,169 @@
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::ops::Range;
 
-use mailparse::{addrparse, parse_mail, MailAddr, MailHeaderMap};
+use chrono::{DateTime, FixedOffset};
+use mailparse::{
+    addrparse, addrparse_header, dateparse, msgidparse, parse_mail, DispositionType, MailAddr,
+    MailHeader, MailHeaderMap, ParsedMail,
+};
+
+mod accounts;
+mod acl;
+mod address_book;
+mod archive;
+mod attachment;
+mod auth;
+mod backup;
+mod bounce;
+#[cfg(feature = "cache")]
+mod cache;
+mod capability;
+mod charset;
+mod compare;
+mod compose;
+mod conversation;
+#[cfg(feature = "daemon")]
+mod daemon;
+mod deadline;
+mod dedup;
+#[cfg(feature = "autodiscover")]
+mod discover;
+mod email_address;
+mod error;
+mod esearch;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod flowed;
+mod folders;
+#[cfg(feature = "graph")]
+mod graph;
+mod handler;
+mod health;
+mod html;
+mod id;
+#[cfg(feature = "jmap")]
+mod jmap;
+#[cfg(feature = "language")]
+mod language;
+mod lenient;
+mod markdown;
+mod mdn;
+mod message_id;
+mod message_ref;
+mod metrics;
+mod mime_tree;
+mod namespace;
+mod normalize;
+mod options;
+mod pipeline;
+#[cfg(feature = "pop3")]
+mod pop3;
+mod processed;
+#[cfg(feature = "python")]
+mod python;
+mod quota;
+mod quote;
+#[cfg(feature = "smtp")]
+mod reply;
+mod retention;
+mod rules;
+#[cfg(feature = "search")]
+mod search;
+mod secret;
+mod session;
+mod signature;
+mod sort;
+mod special;
+mod spill;
+mod stats;
+mod subject;
+mod sync;
+mod throttle;
+#[cfg(feature = "tnef")]
+mod tnef;
+mod transport;
+mod uidplus;
+mod upload;
+mod uuencode;
+mod vcard;
+mod watcher;
+#[cfg(feature = "webhook")]
+mod webhook;
+mod wire;
+
+pub use accounts::{read_accounts, AccountMessage};
+pub use acl::{get_acl, set_acl, AclEntry};
+pub use address_book::{collect_contacts, Contact};
+pub use archive::{archive, ArchiveScheme};
+pub use attachment::{AttachmentInfo, Verdict};
+pub use auth::AuthMethod;
+pub use backup::{backup, backup_resumable, restore, Checkpoint};
+pub use bounce::BounceInfo;
+#[cfg(feature = "cache")]
+pub use cache::{read_mail_with_cache, MessageCache};
+pub use capability::{capabilities, Capabilities, Capability};
+pub use compare::{compare, Comparison, FolderDiff};
+pub use compose::{forward, MessageBuilder};
+pub use conversation::{conversations, read_conversations, Conversation};
+#[cfg(feature = "daemon")]
+pub use daemon::{run_forever, DaemonConfig};
+pub use deadline::{DeadlineExceeded, ResumeToken};
+pub use dedup::{find_duplicates, Dedup, DuplicateGroup, DuplicateKey};
+#[cfg(feature = "autodiscover")]
+pub use discover::{discover, ServerSettings};
+pub use email_address::EmailAddress;
+pub use error::MessageError;
+pub use esearch::{count, search_summary, SearchSummary};
+pub use events::MailboxEvent;
+pub use folders::{folders, FolderInfo};
+#[cfg(feature = "graph")]
+pub use graph::{
+    graph_folders, move_graph_message, read_graph, set_graph_flagged, set_graph_read, GraphAccount,
+    GraphFolder, GraphMessage,
+};
+pub use handler::Payload;
+pub use health::{check, CertificateInfo, CheckStep, HealthReport, StepResult};
+pub use id::server_id;
+#[cfg(feature = "jmap")]
+pub use jmap::{read_jmap, JmapAccount};
+#[cfg(feature = "language")]
+pub use language::Lang;
+pub use mdn::{Disposition, MdnBuilder, Receipt};
+pub use message_id::{MessageId, ReplyGraph};
+pub use message_ref::{for_each_message, parse_ref, MyMessageRef};
+pub use metrics::Metrics;
+pub use mime_tree::MimePart;
+pub use namespace::{namespaces, Namespace, Namespaces};
+pub use normalize::Normalize;
+pub use options::{ReadOptions, Trim};
+#[cfg(feature = "pop3")]
+pub use pop3::{read_new_pop3, read_pop3, FileUidlStore, UidlStore};
+pub use processed::{read_unprocessed, FileProcessedStore, ProcessedStore};
+pub use quota::{quota, Quota};
+#[cfg(feature = "smtp")]
+pub use reply::{reply, SmtpConfig};
+pub use retention::purge_older_than;
+pub use rules::{apply_rules, Action, Condition, Rule};
+#[cfg(feature = "search")]
+pub use search::{index_mailbox, search_local, SearchHit};
+pub use secret::{prompt_password, SecretString};
+pub use sort::{Order, SortKey};
+pub use special::{special_folders, SpecialFolders, SpecialUse};
+pub use stats::{stats, MailboxStats};
+pub use sync::{
+    diff_with_server, read_new_mail, FileSyncStore, MemorySyncStore, SyncState, SyncStore,
+};
+pub use throttle::{Throttle, ThrottleEvent};
+pub use transport::Security;
+pub use uidplus::{copy_messages, move_messages, UidMapping};
+pub use upload::upload;
+pub use vcard::VCard;
+pub use watcher::Watcher;
+#[cfg(feature = "webhook")]
+pub use webhook::Webhook;
+pub use wire::{Direction, WireLog};
 
 pub struct MyMailbox<'a> {
     host: &'a str,
@@ -8,6 +171,15 @@ pub struct MyMailbox<'a> {
     user: &'a str,
     password: &'a str,
     selection: &'a str,
+    client_id: Option<(&'a str, &'a str)>,
+    auth: AuthMethod,
+    forbid_plaintext: bool,
+    tunnel: Option<&'a str>,
+    fallbacks: Vec<(&'a str, u16)>,
+    security: Security,
+    metrics: Option<Metrics>,
+    wire_log: Option<WireLog>,
+    throttle: Throttle,
 }
 impl<'a> Default for MyMailbox<'a> {
     fn default() -> Self {
@@ -17,91 +189,949 @@ impl<'a> Default for MyMailbox<'a> {
             user: "",
             password: "",
             selection: "INBOX",
+            client_id: None,
+            auth: AuthMethod::Login,
+            forbid_plaintext: false,
+            tunnel: None,
+            fallbacks: Vec::new(),
+            security: Security::Tls,
+            metrics: None,
+            wire_log: None,
+            throttle: Throttle::default(),
+        }
+    }
+}
+impl<'a> MyMailbox<'a> {
+    // ログイン後に ID（RFC 2971）で名乗るクライアント名とバージョン
+    pub fn client_id(mut self, name: &'a str, version: &'a str) -> Self {
+        self.client_id = Some((name, version));
+        self
+    }
+
+    // ログインの方法（XOAUTH2 では password にアクセストークンを指定する）
+    pub fn auth(mut self, auth: AuthMethod) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    // パスワードをそのまま送るログイン（LOGIN、PLAIN）を使わない
+    pub fn forbid_plaintext(mut self, forbid: bool) -> Self {
+        self.forbid_plaintext = forbid;
+        self
+    }
+
+    // host・port に接続する代わりに、command（sh -c で実行する）の標準入出力で IMAP を話す
+    // 「ssh host dovecot --exec-mail imap」のように PREAUTH で始まるなら、ログインもしない
+    pub fn tunnel(mut self, command: &'a str) -> Self {
+        self.tunnel = Some(command);
+        self
+    }
+
+    // host・port に接続できないとき（または挨拶が OK でないとき）に、追加した順に試すサーバー
+    pub fn fallback(mut self, host: &'a str, port: u16) -> Self {
+        self.fallbacks.push((host, port));
+        self
+    }
+
+    // TLS の使い方（Security::Auto なら 993 の TLS、143 の STARTTLS の順に試す）
+    pub fn security(mut self, security: Security) -> Self {
+        self.security = security;
+        self
+    }
+
+    // コマンド数・受信バイト数・解析の成否・取得時間を metrics に数える
+    // 同じ Metrics を複数のメールボックスに渡せば、合計になる
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    // 送受信した IMAP の行を wire_log に渡す（パスワードなどは伏せる）
+    pub fn wire_log(mut self, wire_log: &WireLog) -> Self {
+        self.wire_log = Some(wire_log.clone());
+        self
+    }
+
+    // サーバーに制限されたときの待ち方（指定しなければ 30 秒から倍にしながら 5 回まで待つ）
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    // 接続を試すサーバー（host・port、続いて fallback）
+    pub(crate) fn servers(&self) -> Vec<(&'a str, u16)> {
+        if self.tunnel.is_some() {
+            return vec![(self.host, self.port)];
         }
+        let mut servers = vec![(self.host, self.port)];
+        servers.extend(self.fallbacks.iter().copied());
+        servers
     }
 }
-#[derive(Debug)]
+use session::MySession;
+
+#[derive(Debug, Clone)]
 pub struct MyMessage {
+    folder: String,
+    uid: u32,
+    message_id: Option<String>,
     from: String,
+    from_address: Option<EmailAddress>,
+    reply_to: Option<String>,
+    reply_to_address: Option<EmailAddress>,
+    to: Vec<EmailAddress>,
+    cc: Vec<EmailAddress>,
+    references: Vec<String>,
+    in_reply_to: Option<String>,
+    date: Option<DateTime<FixedOffset>>,
     subject: String,
     body: String,
+    is_truncated: bool,
+    html: Option<String>,
+    body_matches: Vec<Range<usize>>,
+    contacts: Vec<VCard>,
+    bounce: Option<BounceInfo>,
+    receipt: Option<Receipt>,
+    disposition_notification_to: Option<String>,
+    original_recipient: Option<String>,
+    attachments: Vec<AttachmentInfo>,
+    #[cfg(feature = "tnef")]
+    rtf_body: Option<String>,
+    #[cfg(feature = "language")]
+    language: Option<Lang>,
+    payloads: Vec<Payload>,
+    embedded_messages: Vec<MyMessage>,
+    mime_tree: MimePart,
+    warnings: Vec<String>,
+    annotations: BTreeMap<String, String>,
+    raw: Vec<u8>,
+    is_spilled: bool,
+}
+impl MyMessage {
+    // 取得元のフォルダー
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    // Message-ID（<> は取り除く）
+    pub fn message_id(&self) -> Option<&str> {
+        self.message_id.as_deref()
+    }
+
+    // 比べるときは文字列ではなくこちらを使う（ドメインの大文字・小文字をそろえる）
+    pub fn id(&self) -> Option<MessageId> {
+        self.message_id.as_deref().and_then(MessageId::new)
+    }
+
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    // from を EmailAddress にしたもの（寛容モードで読めなかった From なら None）
+    // 差出人を比べるときは文字列ではなくこちらを使う（大文字・小文字をそろえる）
+    pub fn from_address(&self) -> Option<&EmailAddress> {
+        self.from_address.as_ref()
+    }
+
+    // Reply-To のメールアドレス
+    pub fn reply_to(&self) -> Option<&str> {
+        self.reply_to.as_deref()
+    }
+
+    pub fn reply_to_address(&self) -> Option<&EmailAddress> {
+        self.reply_to_address.as_ref()
+    }
+
+    // To のメールアドレス（グループの中のものも含む、形の正しくないものは除く）
+    pub fn to(&self) -> &[EmailAddress] {
+        &self.to
+    }
+
+    // Cc のメールアドレス（To と同じ）
+    pub fn cc(&self) -> &[EmailAddress] {
+        &self.cc
+    }
+
+    // References の Message-ID（<> は取り除く、古い順）
+    pub fn references(&self) -> &[String] {
+        &self.references
+    }
+
+    // In-Reply-To の Message-ID（<> は取り除く）
+    pub fn in_reply_to(&self) -> Option<&str> {
+        self.in_reply_to.as_deref()
+    }
+
+    // Date ヘッダーの日時（なければ、または読めなければ None）
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    // 返信・転送の接頭辞（Re: Fwd: AW: 回答: など）とメーリングリストのタグ（[ml:123] など）を除いた件名
+    pub fn normalized_subject(&self) -> String {
+        subject::normalize(&self.subject)
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    // ReadOptions::max_body_len で本文を切り詰めたか
+    pub fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    // text/html のパートがあればその内容
+    pub fn html(&self) -> Option<&str> {
+        self.html.as_deref()
+    }
+
+    // ReadOptions::filter_body の正規表現に当てはまった、body の中の位置（バイト）
+    pub fn body_matches(&self) -> &[Range<usize>] {
+        &self.body_matches
+    }
+
+    // 引用部分（「> ...」や「On ... wrote:」、Outlook の区切り線以降）を除いた本文
+    pub fn body_without_quotes(&self) -> String {
+        quote::strip_quotes(&self.body)
+    }
+
+    // 添付された vCard（text/vcard）から取り出した連絡先
+    pub fn contacts(&self) -> &[VCard] {
+        &self.contacts
+    }
+
+    // エラーメール（multipart/report の delivery-status）の内容
+    pub fn bounce(&self) -> Option<&BounceInfo> {
+        self.bounce.as_ref()
+    }
+
+    // 開封確認（multipart/report の disposition-notification）の内容
+    pub fn receipt(&self) -> Option<&Receipt> {
+        self.receipt.as_ref()
+    }
+
+    // 開封確認を求めている（Disposition-Notification-To がある）なら、その宛先
+    // MdnBuilder で開封確認を作れる
+    pub fn disposition_notification_to(&self) -> Option<&str> {
+        self.disposition_notification_to.as_deref()
+    }
+
+    // Original-Recipient（「rfc822;」は取り除く）
+    pub fn original_recipient(&self) -> Option<&str> {
+        self.original_recipient.as_deref()
+    }
+
+    pub fn attachments(&self) -> &[AttachmentInfo] {
+        &self.attachments
+    }
+
+    // winmail.dat に入っていた RTF の本文
+    #[cfg(feature = "tnef")]
+    pub fn rtf_body(&self) -> Option<&str> {
+        self.rtf_body.as_deref()
+    }
+
+    // 本文の言語（短すぎたり分からなかったりすれば None）
+    #[cfg(feature = "language")]
+    pub fn language(&self) -> Option<Lang> {
+        self.language
+    }
+
+    // ReadOptions::handle_part で登録した処理が、パートから作った値
+    pub fn payloads(&self) -> &[Payload] {
+        &self.payloads
+    }
+
+    // payloads のうち、型が T の最初のもの
+    pub fn payload<T: std::any::Any>(&self) -> Option<&T> {
+        self.payloads.iter().find_map(Payload::downcast_ref)
+    }
+
+    // 添付されたメール（message/rfc822 のパート、転送されたメールなど）
+    // 同じ ReadOptions で読む（folder・uid は空）。読めなかったものは入らない
+    pub fn embedded_messages(&self) -> &[MyMessage] {
+        &self.embedded_messages
+    }
+
+    // MIME のパートの木（一番外側がメール全体）
+    pub fn mime_tree(&self) -> &MimePart {
+        &self.mime_tree
+    }
+
+    // 寛容モード（ReadOptions::lenient）で読み飛ばした箇所
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // サーバーから取得したままのメール（転送やバックアップに使う）
+    // ReadOptions::large_parts_to_disk でパートを書き出したメール（is_spilled）は、ヘッダーだけ
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    // ReadOptions::large_parts_to_disk で、大きな添付ファイルをファイルに書き出したか
+    // （raw には中身がないので、forward はできない）
+    pub fn is_spilled(&self) -> bool {
+        self.is_spilled
+    }
+
+    // Markdown にしたもの（ナレッジベースやチケット、静的サイトジェネレーターにそのまま入れられる）
+    // ヘッダーは front matter に、HTML 本文があれば Markdown に直し、添付ファイルはファイル名・大きさ・SHA-256 を並べる
+    pub fn to_markdown(&self) -> String {
+        markdown::render(self)
+    }
+
+    // ReadOptions::stage で加えた情報
+    pub fn annotation(&self, key: &str) -> Option<&str> {
+        self.annotations.get(key).map(String::as_str)
+    }
+
+    pub fn annotations(&self) -> &BTreeMap<String, String> {
+        &self.annotations
+    }
+
+    // 以下は ReadOptions::stage から、伏せ字にしたり情報を加えたりするのに使う
+    pub fn set_subject(&mut self, subject: String) {
+        self.subject = subject;
+    }
+
+    // body_matches は本文の位置なので、本文を変えたら空にする
+    pub fn set_body(&mut self, body: String) {
+        self.body = body;
+        self.body_matches.clear();
+    }
+
+    pub fn set_html(&mut self, html: Option<String>) {
+        self.html = html;
+    }
+
+    pub fn annotate(&mut self, key: &str, value: &str) {
+        self.annotations.insert(key.to_string(), value.to_string());
+    }
 }
 
 pub fn read_mail(mailbox: &MyMailbox) -> Result<Vec<MyMessage>, Box<dyn Error>> {
-    let tls = native_tls::TlsConnector::builder().build()?;
-    let client = imap::connect((mailbox.host, mailbox.port), mailbox.host, &tls)?;
+    read_mail_with_options(mailbox, &ReadOptions::default())
+}
+
+pub fn read_mail_with_options(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    read_folders(mailbox, options, |imap_session, folder| {
+        fetch_folder(imap_session, folder, options)
+    })
+}
+
+// 各フォルダーを fetch で読んで、結果をまとめる
+fn read_folders<F>(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    mut fetch: F,
+) -> Result<Vec<MyMessage>, Box<dyn Error>>
+where
+    F: FnMut(&mut MySession, &str) -> Result<Vec<MyMessage>, Box<dyn Error>>,
+{
+    let mut imap_session = connect(mailbox)?;
+
+    // フォルダーの指定がなければ mailbox.selection だけを読む
+    let folders = if options.folders.is_empty() {
+        vec![mailbox.selection.to_string()]
+    } else {
+        options.folders.clone()
+    };
+    // resume_from のフォルダーより前は読み終えている
+    let start = options
+        .resume
+        .as_ref()
+        .and_then(|x| folders.iter().position(|folder| *folder == x.folder()))
+        .unwrap_or(0);
+    let mut messages = Vec::new();
+    for folder in &folders[start..] {
+        let fetched = match fetch(&mut imap_session, folder) {
+            Ok(fetched) => fetched,
+            Err(e) => match e.downcast::<DeadlineExceeded>() {
+                // 締め切りまでに読んだ分もまとめて返す
+                Ok(mut exceeded) => {
+                    let fetched = std::mem::take(exceeded.messages_mut());
+                    messages.extend(fetched.into_iter().filter_map(|x| options.process(x)));
+                    imap_session.logout()?;
+                    *exceeded.messages_mut() = finish(messages, options);
+                    return Err(exceeded);
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        messages.extend(fetched.into_iter().filter_map(|x| options.process(x)));
+    }
+
+    // ログアウト
+    imap_session.logout()?;
+
+    Ok(finish(messages, options))
+}
+
+// 重複を除いて、並べ替える
+fn finish(mut messages: Vec<MyMessage>, options: &ReadOptions) -> Vec<MyMessage> {
+    if let Some(strategy) = &options.dedup {
+        messages = dedup::dedup(messages, strategy);
+    }
+    if let Some((key, order)) = options.sort {
+        sort::sort(&mut messages, key, order);
+    }
+    messages
+}
+
+// 実際に接続できた（ポート, 方法）を返す（ログインはしない）
+// Security::Auto で試した結果を、次からの設定に使える
+pub fn detect_security(mailbox: &MyMailbox) -> Result<(u16, Security), Box<dyn Error>> {
+    Ok(session::MyClient::connect(mailbox)?.negotiated())
+}
+
+fn connect(mailbox: &MyMailbox) -> Result<MySession, Box<dyn Error>> {
+    let client = session::MyClient::connect(mailbox)?;
 
     // ログイン
-    let mut imap_session = client
-        .login(mailbox.user, mailbox.password)
-        .map_err(|e| e.0)?;
+    let mut imap_session = client.login(mailbox)?;
 
+    // 名乗らないと使わせてくれないサーバーがある
+    if mailbox.client_id.is_some() {
+        id::send_id(&mut imap_session, mailbox.client_id)?;
+    }
+
+    Ok(imap_session)
+}
+
+fn fetch_folder(
+    imap_session: &mut MySession,
+    folder: &str,
+    options: &ReadOptions,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
     // メールボックスを選択
-    imap_session.select(mailbox.selection)?;
+    let selected = imap_session.select(folder)?;
+    let uid_validity = selected.uid_validity.unwrap_or_default();
 
-    // 全 uid を取得
-    let uids = imap_session.uid_search("ALL")?;
+    // resume_from で続きから読むときは、読み終えた UID を飛ばす
+    let mut last_uid = options
+        .resume
+        .as_ref()
+        .map_or(0, |x| x.last_uid_in(folder, uid_validity));
+    let mut uids = search_uids(imap_session)?;
+    uids.retain(|&x| x > last_uid);
 
     // 各 uid から MyMessage（from, subject, body）を抽出
-    let messages = uids
+    let mut messages = Vec::new();
+    for uid in uids {
+        if deadline::is_exceeded(options) {
+            let resume = ResumeToken::new(folder, uid_validity, last_uid);
+            return Err(DeadlineExceeded::new(messages, resume).into());
+        }
+        messages.push(fetch_message(imap_session, folder, uid, options)?);
+        last_uid = uid;
+    }
+
+    Ok(messages)
+}
+
+// IMAP コマンドの引数に使う quoted string
+fn imap_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// 昇順の UID を「1:3,5,7:9」のような UID set にまとめる
+fn uid_set(uids: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &uid in uids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == uid => *end = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
+    ranges
         .iter()
-        .map(|uid| {
-            //（"RFC822"ではなく）"BODY.PEEK[]" を使うことにより既読にしない
-            let messages = imap_session
-                .uid_fetch(uid.to_string(), "BODY.PEEK[]")
-                .unwrap();
-            let message = messages.iter().next().unwrap();
-            parse(message.body().unwrap()).unwrap()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}:{}", start, end)
+            }
         })
-        .collect::<Vec<MyMessage>>();
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    // ログアウト
-    imap_session.logout()?;
+// MOVE（RFC 6851）が使えなければ COPY してから削除する
+// UIDPLUS に対応していれば、移動先での UID を返す
+fn move_uids(
+    imap_session: &mut MySession,
+    uid_set: &str,
+    to: &str,
+) -> Result<Option<UidMapping>, Box<dyn Error>> {
+    if imap_session.supports(Capability::Move)? {
+        let ((), codes) = imap_session.with_response_codes(|imap_session| {
+            imap_session.uid_mv(uid_set, to)?;
+            Ok(())
+        })?;
+        Ok(codes.iter().find_map(|x| uidplus::parse_copyuid(x)))
+    } else {
+        let mapping = uidplus::copy_uids(imap_session, uid_set, to)?;
+        delete_uids(imap_session, uid_set)?;
+        Ok(mapping)
+    }
+}
 
-    Ok(messages)
+// UID EXPUNGE（UIDPLUS、RFC 4315）が使えれば指定したメールだけを消す
+// 使えなければ、ほかのクライアントが \Deleted を付けたメールまで消さないよう、
+// そのフラグをいったん外して EXPUNGE し、付け直す
+fn delete_uids(imap_session: &mut MySession, uid_set: &str) -> Result<(), Box<dyn Error>> {
+    imap_session.uid_store(uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+    if imap_session.supports(Capability::UidPlus)? {
+        imap_session.uid_expunge(uid_set)?;
+        return Ok(());
+    }
+
+    let targets = esearch::expand_uid_set(uid_set);
+    let mut others = imap_session
+        .uid_search("DELETED")?
+        .into_iter()
+        .filter(|x| !targets.contains(x))
+        .collect::<Vec<_>>();
+    if others.is_empty() {
+        imap_session.expunge()?;
+        return Ok(());
+    }
+    others.sort_unstable();
+    let others = crate::uid_set(&others);
+    imap_session.uid_store(&others, "-FLAGS.SILENT (\\Deleted)")?;
+    let expunged = imap_session.expunge();
+    imap_session.uid_store(&others, "+FLAGS.SILENT (\\Deleted)")?;
+    expunged?;
+    Ok(())
+}
+
+// 全 uid を取得（重複を除くときに「最初のもの」が決まるように昇順に並べる）
+// ESEARCH が使えれば範囲で受け取る
+fn search_uids(imap_session: &mut MySession) -> Result<Vec<u32>, Box<dyn Error>> {
+    Ok(esearch::search(imap_session, "ALL", "ALL")?.uids())
+}
+
+fn fetch_raw(imap_session: &mut MySession, uid: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    //（"RFC822"ではなく）"BODY.PEEK[]" を使うことにより既読にしない
+    let throttle = imap_session.throttle().clone();
+    let messages = throttle.run(|| imap_session.uid_fetch(uid.to_string(), "BODY.PEEK[]"))?;
+    if let Some(metrics) = imap_session.metrics() {
+        metrics.add_fetch(start.elapsed());
+    }
+    let message = messages.iter().next().ok_or("no message")?;
+    Ok(message.body().ok_or("no body")?.to_vec())
+}
+
+// 取得して解析する
+fn fetch_message(
+    imap_session: &mut MySession,
+    folder: &str,
+    uid: u32,
+    options: &ReadOptions,
+) -> Result<MyMessage, Box<dyn Error>> {
+    if let Some((threshold, dir)) = &options.large_parts {
+        let spilled = spill::fetch(imap_session, folder, uid, *threshold, dir)
+            .map_err(|e| MessageError::fetch(folder, uid, "UID FETCH", e))?;
+        if let Some(spilled) = spilled {
+            let options = ReadOptions {
+                spilled: spilled.paths.clone(),
+                ..options.clone()
+            };
+            // 組み立て直したメールは、書き出したファイルを指しているので raw には残さない
+            let message = parse_fetched(&spilled.raw, folder, uid, &options).map(|mut x| {
+                x.raw = spilled.header.clone();
+                x.is_spilled = true;
+                x
+            });
+            spill::clean_up(&spilled.paths, message.as_ref().ok());
+            return count_parse(imap_session, message);
+        }
+    }
+    let raw = fetch_raw(imap_session, uid)
+        .map_err(|e| MessageError::fetch(folder, uid, "UID FETCH", e))?;
+    count_parse(imap_session, parse_fetched(&raw, folder, uid, options))
+}
+
+// 解析の成否を metrics に数える
+fn count_parse(
+    imap_session: &MySession,
+    message: Result<MyMessage, Box<dyn Error>>,
+) -> Result<MyMessage, Box<dyn Error>> {
+    if let Some(metrics) = imap_session.metrics() {
+        metrics.add_parse(message.is_ok());
+    }
+    message
+}
+
+fn parse_fetched(
+    raw_data: &[u8],
+    folder: &str,
+    uid: u32,
+    options: &ReadOptions,
+) -> Result<MyMessage, Box<dyn Error>> {
+    let mut message = parse(raw_data, options).map_err(|e| MessageError::parse(folder, uid, e))?;
+    message.folder = folder.to_string();
+    message.uid = uid;
+    Ok(message)
 }
 
-fn parse(raw_data: &[u8]) -> Result<MyMessage, Box<dyn Error>> {
+fn parse(raw_data: &[u8], options: &ReadOptions) -> Result<MyMessage, Box<dyn Error>> {
     let parsed_mail = parse_mail(raw_data)?;
     let headers = &parsed_mail.headers;
 
+    let mut warnings = lenient::Warnings::new(options.lenient);
+
     // 差出アドレス（メールアドレスのみ）
-    let from = match &addrparse(&headers.get_first_value("From").ok_or("no From header(1)")?)?
-        .first()
-        .ok_or("no From header(2)")?
-    {
-        MailAddr::Single(info) => info.addr.to_string(),
-        _ => return Err("no From header(3)".into()),
+    let from = || -> Result<String, Box<dyn Error>> {
+        match &addrparse(&headers.get_first_value("From").ok_or("no From header(1)")?)?
+            .first()
+            .ok_or("no From header(2)")?
+        {
+            MailAddr::Single(info) => Ok(info.addr.to_string()),
+            _ => Err("no From header(3)".into()),
+        }
     };
+    let from = warnings.recover(from(), || {
+        headers.get_first_value("From").unwrap_or_default()
+    })?;
+    let from_address = EmailAddress::parse(&from).ok();
+
+    // Message-ID
+    let message_id = headers
+        .get_first_value("Message-ID")
+        .map(|x| {
+            x.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .filter(|x| !x.is_empty());
+
+    // 返信先（Reply-To）
+    let reply_to = headers
+        .get_first_value("Reply-To")
+        .and_then(|x| addrparse(&x).ok())
+        .and_then(|x| match x.first() {
+            Some(MailAddr::Single(info)) => Some(info.addr.to_string()),
+            _ => None,
+        });
+    let reply_to_address = reply_to
+        .as_deref()
+        .and_then(|x| EmailAddress::parse(x).ok());
+
+    // 宛先（To・Cc）
+    let to = header_addresses(headers, "To");
+    let cc = header_addresses(headers, "Cc");
+
+    // References（壊れている場合は空にする）
+    let references = headers
+        .get_first_value("References")
+        .and_then(|x| msgidparse(&x).ok())
+        .map(|x| x.to_vec())
+        .unwrap_or_default();
+    let in_reply_to = headers
+        .get_first_value("In-Reply-To")
+        .and_then(|x| msgidparse(&x).ok())
+        .and_then(|x| x.first().cloned());
+
+    // 日時（RFC 2822 として読めなければ mailparse の寛容な解析で UTC として読む）
+    let date = headers.get_first_value("Date").and_then(|x| {
+        DateTime::parse_from_rfc2822(x.trim()).ok().or_else(|| {
+            let timestamp = dateparse(&x).ok()?;
+            Some(DateTime::from_timestamp(timestamp, 0)?.fixed_offset())
+        })
+    });
 
     // 件名
     let subject = headers
         .get_first_value("Subject")
-        .ok_or("no Subject header")?;
+        .ok_or_else(|| "no Subject header".into());
+    let subject = warnings.recover(subject, String::new)?;
 
     // 本文
-    // subparts がある場合は、最初の「mimetype: "text/plain"」になっているパートを使う
+    // subparts がある場合は、入れ子の multipart も含めて、添付ファイルではない最初の text/plain を使う
+    // （text/plain でも、添付されたログファイルなどは本文にしない。そういうものしかなければ本文は空）
     // https://docs.rs/mailparse/0.13.0/mailparse/struct.ParsedMail.html
     // subparts: Vec<ParsedMail<'a>>
     // The subparts of this message or subpart. This vector is only non-empty if ctype.mimetype starts with "multipart/".
     let text_mail = if parsed_mail.subparts.is_empty() {
-        &parsed_mail
+        Ok(Some(&parsed_mail).filter(|x| is_body_part(x)))
     } else {
-        parsed_mail
-            .subparts
-            .iter()
-            .find(|&x| x.ctype.mimetype == "text/plain")
-            .ok_or("no text/plain parts")?
+        let texts = all_parts(&parsed_mail)
+            .into_iter()
+            .filter(|x| x.ctype.mimetype == "text/plain")
+            .collect::<Vec<_>>();
+        if texts.is_empty() {
+            Err("no text/plain parts".into())
+        } else {
+            Ok(texts.into_iter().find(|x| is_body_part(x)))
+        }
+    };
+    let text_mail = warnings.recover(text_mail, || None)?;
+    // 本文に埋め込まれた uuencode は、本文から除いて添付ファイルにする
+    let mut uuencoded = Vec::new();
+    let mut body = match text_mail {
+        Some(text_mail) => {
+            let body = charset::decode_body(text_mail, options, &mut warnings)?;
+            let (body, files) = uuencode::extract(&body);
+            uuencoded = files;
+            // format=flowed（RFC 3676）は、送り手が折り返した行をつなげる
+            let params = &text_mail.ctype.params;
+            let is_flowed = text_mail.ctype.mimetype == "text/plain"
+                && params
+                    .get("format")
+                    .is_some_and(|x| x.eq_ignore_ascii_case("flowed"));
+            let body = if is_flowed && !options.keep_flowed {
+                let delsp = params
+                    .get("delsp")
+                    .is_some_and(|x| x.eq_ignore_ascii_case("yes"));
+                flowed::unflow(&body, delsp)
+            } else {
+                body
+            };
+            match options.trim {
+                Trim::Keep => body,
+                Trim::End => body.trim_end().to_string(),
+                Trim::Both => body.trim().to_string(),
+            }
+        }
+        None => String::new(),
+    };
+    if options.strip_signature {
+        body = signature::strip_signature(&body);
+    }
+    body = options.normalize.apply(body);
+    let mut is_truncated = false;
+    if let Some(max_len) = options.max_body_len {
+        if let Some((end, _)) = body.char_indices().nth(max_len) {
+            body.truncate(end);
+            is_truncated = true;
+        }
+    }
+    let body_matches = match &options.body_filter {
+        Some(regex) => regex.find_iter(&body).map(|x| x.range()).collect(),
+        None => Vec::new(),
     };
-    let body = text_mail.get_body()?.trim_end().to_string();
+    #[cfg(feature = "language")]
+    let language = language::detect(&body);
+
+    // HTML 本文（multipart/alternative などに入っている最初の text/html）
+    let mut html = None;
+    for part in all_parts(&parsed_mail) {
+        if part.ctype.mimetype == "text/html" && is_body_part(part) {
+            let body = charset::decode_body(part, options, &mut warnings)?;
+            html = Some(if options.sanitize_html {
+                html::sanitize(&body)
+            } else {
+                body
+            });
+            break;
+        }
+    }
+
+    // 連絡先（text/vcard のパートをすべて読む）
+    let mut contacts = Vec::new();
+    for part in all_parts(&parsed_mail) {
+        if is_vcard(&part.ctype.mimetype) {
+            let text = warnings.recover(part.get_body().map_err(Into::into), String::new)?;
+            contacts.extend(vcard::parse_vcards(&text));
+        }
+    }
+
+    // エラーメール（multipart/report; report-type=delivery-status）
+    let bounce = if parsed_mail.ctype.mimetype == "multipart/report" {
+        match all_parts(&parsed_mail)
+            .into_iter()
+            .find(|x| x.ctype.mimetype == "message/delivery-status")
+        {
+            Some(part) => {
+                let text = warnings.recover(part.get_body().map_err(Into::into), String::new)?;
+                bounce::parse_delivery_status(&text)
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    // 開封確認（multipart/report; report-type=disposition-notification）
+    let receipt = if parsed_mail.ctype.mimetype == "multipart/report" {
+        match all_parts(&parsed_mail)
+            .into_iter()
+            .find(|x| x.ctype.mimetype == "message/disposition-notification")
+        {
+            Some(part) => {
+                let text = warnings.recover(part.get_body().map_err(Into::into), String::new)?;
+                mdn::parse_disposition_notification(&text)
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+    let disposition_notification_to = headers
+        .get_first_value("Disposition-Notification-To")
+        .and_then(|x| addrparse(&x).ok())
+        .and_then(|x| match x.first() {
+            Some(MailAddr::Single(info)) => Some(info.addr.to_string()),
+            _ => None,
+        });
+    let original_recipient = headers
+        .get_first_value("Original-Recipient")
+        .map(|x| bounce::strip_address_type(&x));
+
+    // 添付ファイル
+    #[allow(unused_mut)]
+    let mut attachments =
+        attachment::collect_attachments(&parsed_mail, text_mail, options, &mut warnings)?;
+
+    // winmail.dat（application/ms-tnef）は中身の添付ファイルに置き換える
+    #[cfg(feature = "tnef")]
+    let mut rtf_body = None;
+    #[cfg(feature = "tnef")]
+    {
+        let mut decoded = Vec::new();
+        for attachment in attachments {
+            if attachment.mimetype() == "application/ms-tnef" && attachment.path().is_none() {
+                // 読めない winmail.dat は（寛容モードなら）そのまま残す
+                match warnings.recover(tnef::decode(attachment.data()).map(Some), || None)? {
+                    Some(tnef) => {
+                        rtf_body = rtf_body.or(tnef.rtf_body);
+                        decoded.extend(tnef.attachments);
+                    }
+                    None => decoded.push(attachment),
+                }
+            } else {
+                decoded.push(attachment);
+            }
+        }
+        attachments = decoded;
+        attachments.retain(|x| {
+            attachment::is_matching(&options.attachment_patterns, x.filename(), x.mimetype())
+        });
+    }
+    attachments.extend(uuencoded.into_iter().filter(|x| {
+        attachment::is_matching(&options.attachment_patterns, x.filename(), x.mimetype())
+    }));
+    if options.attachment_md5 {
+        attachments.iter_mut().for_each(AttachmentInfo::compute_md5);
+    }
+    if let Some(scanner) = &options.scanner {
+        scanner.scan(&mut attachments)?;
+    }
+
+    // ReadOptions::handle_part で登録した処理
+    let payloads = handler::run(&all_parts(&parsed_mail), options, &mut warnings)?;
+
+    // 添付されたメール（message/rfc822）
+    // 読めないものがあっても、外側のメールは読めているのでエラーにはしない
+    let mut embedded_messages = Vec::new();
+    for part in all_parts(&parsed_mail) {
+        if part.ctype.mimetype == "message/rfc822" {
+            let raw = warnings.recover(part.get_body_raw().map_err(Into::into), Vec::new)?;
+            if let Ok(message) = parse(&raw, options) {
+                embedded_messages.push(message);
+            }
+        }
+    }
 
     Ok(MyMessage {
+        folder: String::new(),
+        uid: 0,
+        message_id,
         from,
+        from_address,
+        reply_to,
+        reply_to_address,
+        to,
+        cc,
+        references,
+        in_reply_to,
+        date,
         subject,
         body,
+        is_truncated,
+        html,
+        body_matches,
+        contacts,
+        bounce,
+        receipt,
+        disposition_notification_to,
+        original_recipient,
+        attachments,
+        #[cfg(feature = "tnef")]
+        rtf_body,
+        #[cfg(feature = "language")]
+        language,
+        payloads,
+        embedded_messages,
+        mime_tree: mime_tree::build(&parsed_mail),
+        warnings: warnings.into_messages(),
+        annotations: BTreeMap::new(),
+        raw: raw_data.to_vec(),
+        is_spilled: false,
     })
 }
 
+// name のヘッダー（複数あればすべて）のメールアドレス
+fn header_addresses(headers: &[MailHeader], name: &str) -> Vec<EmailAddress> {
+    let mut addresses = Vec::new();
+    for header in headers.get_all_headers(name) {
+        let list = match addrparse_header(header) {
+            Ok(list) => list,
+            Err(_) => continue,
+        };
+        for addr in list.iter() {
+            let infos = match addr {
+                MailAddr::Single(info) => std::slice::from_ref(info),
+                MailAddr::Group(group) => &group.addrs[..],
+            };
+            addresses.extend(
+                infos
+                    .iter()
+                    .filter_map(|x| EmailAddress::parse(&x.addr).ok()),
+            );
+        }
+    }
+    addresses
+}
+
+// 入れ子になった multipart も含めて、すべてのパートを順にたどる
+fn all_parts<'a, 'b>(mail: &'b ParsedMail<'a>) -> Vec<&'b ParsedMail<'a>> {
+    let mut parts = vec![mail];
+    for subpart in &mail.subparts {
+        parts.extend(all_parts(subpart));
+    }
+    parts
+}
+
+// 本文として読むパートか
+// Content-Disposition: attachment か、ファイル名が付いていれば（inline でも）添付ファイルとみなす
+fn is_body_part(part: &ParsedMail) -> bool {
+    let disposition = part.get_content_disposition();
+    disposition.disposition != DispositionType::Attachment
+        && !disposition.params.contains_key("filename")
+        && !part.ctype.params.contains_key("name")
+}
+
+fn is_vcard(mimetype: &str) -> bool {
+    matches!(mimetype, "text/vcard" | "text/x-vcard" | "text/directory")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +1151,413 @@ mod tests {
             println!("message: {:?}", message);
         }
     }
+
+    #[test]
+    fn parse_misdeclared_charset() {
+        let mut raw = b"From: taro@example.com\r\n\
+                        Subject: charset\r\n\
+                        Content-Type: text/plain; charset=utf-8\r\n\
+                        \r\n"
+            .to_vec();
+        // 「日本語」を ISO-2022-JP で
+        raw.extend(b"\x1b$BF|K\\8l\x1b(B\r\n");
+
+        let message = parse(&raw, &ReadOptions::default()).unwrap();
+        assert_ne!(message.body(), "日本語");
+
+        let options = ReadOptions::default().detect_charset(true);
+        let message = parse(&raw, &options).unwrap();
+        assert_eq!(message.body(), "日本語");
+
+        let options = ReadOptions::default().charset("iso-2022-jp");
+        let message = parse(&raw, &options).unwrap();
+        assert_eq!(message.body(), "日本語");
+    }
+
+    #[test]
+    fn read_through_preauth_tunnel() {
+        // PREAUTH で始まり、最低限のコマンドにだけ答える IMAP サーバーのふり
+        let server = r#"printf '* PREAUTH [CAPABILITY IMAP4rev1] ready\r\n'
+while read -r tag command rest; do
+  case $command in
+    LOGIN*) printf '%s BAD LOGIN must not be sent\r\n' "$tag" ;;
+    CAPABILITY*) printf '* CAPABILITY IMAP4rev1\r\n%s OK done\r\n' "$tag" ;;
+    SELECT*) printf '* 0 EXISTS\r\n%s OK [READ-WRITE] done\r\n' "$tag" ;;
+    UID*) printf '* SEARCH\r\n%s OK done\r\n' "$tag" ;;
+    LOGOUT*) printf '* BYE logging out\r\n%s OK done\r\n' "$tag"; exit ;;
+    *) printf '%s OK done\r\n' "$tag" ;;
+  esac
+done"#;
+        let mailbox = MyMailbox::default().tunnel(server);
+        let messages = read_mail(&mailbox).unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn try_fallback_servers() {
+        // どちらのポートでも待っていないので、両方の失敗が報告される
+        let mailbox = MyMailbox {
+            host: "127.0.0.1",
+            port: 1,
+            ..MyMailbox::default()
+        }
+        .fallback("127.0.0.1", 2);
+        let error = read_mail(&mailbox).unwrap_err().to_string();
+        assert!(error.starts_with("127.0.0.1:1: "));
+        assert!(error.contains("; 127.0.0.1:2: "));
+    }
+
+    #[test]
+    fn compress_uid_set() {
+        assert_eq!(uid_set(&[1, 2, 3, 5, 7, 8, 9, 20]), "1:3,5,7:9,20");
+        assert_eq!(uid_set(&[42]), "42");
+    }
+
+    #[test]
+    fn dedup_by_message_id() {
+        let message = |folder: &str, uid: u32, id: &str| {
+            let raw = format!(
+                "From: taro@example.com\r\nSubject: s\r\nMessage-ID: {}\r\n\r\nbody\r\n",
+                id
+            );
+            let mut message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+            message.folder = folder.to_string();
+            message.uid = uid;
+            message
+        };
+        let messages = || {
+            vec![
+                message("[Gmail]/All Mail", 1, "<a@example.com>"),
+                message("[Gmail]/All Mail", 2, "<b@example.com>"),
+                message("INBOX", 10, "<a@example.com>"),
+                message("INBOX", 11, ""),
+                message("INBOX", 12, ""),
+            ]
+        };
+
+        let kept = dedup::dedup(messages(), &Dedup::KeepFirst);
+        let kept = kept.iter().map(|x| x.uid()).collect::<Vec<_>>();
+        assert_eq!(kept, [1, 2, 11, 12]);
+
+        let kept = dedup::dedup(messages(), &Dedup::PreferFolder("INBOX".to_string()));
+        assert_eq!(kept[0].message_id(), Some("b@example.com"));
+        let kept = kept.iter().map(|x| x.uid()).collect::<Vec<_>>();
+        assert_eq!(kept, [2, 10, 11, 12]);
+
+        let groups = find_duplicates(&messages(), DuplicateKey::MessageId);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].key(), "a@example.com");
+        assert_eq!(
+            groups[0].locations(),
+            [
+                ("[Gmail]/All Mail".to_string(), 1),
+                ("INBOX".to_string(), 10)
+            ]
+        );
+
+        // 内容で比べると、すべて同じメール
+        let groups = find_duplicates(&messages(), DuplicateKey::Content);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].locations().len(), 5);
+        assert_eq!(groups[0].key().len(), 64);
+    }
+
+    #[test]
+    fn parse_addresses_once() {
+        let raw = "From: Taro <Taro@Example.com>\r\n\
+                   Reply-To: support@example.com\r\n\
+                   To: jiro@example.com, Team: hanako@example.com, saburo@example.com;\r\n\
+                   Cc: Shiro <shiro@example.com>\r\n\
+                   Subject: s\r\n\
+                   \r\n\
+                   body\r\n";
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(
+            message.from_address(),
+            Some(&EmailAddress::parse("taro@example.com").unwrap())
+        );
+        assert_eq!(
+            message.reply_to_address().map(EmailAddress::as_str),
+            Some("support@example.com")
+        );
+        let to = message
+            .to()
+            .iter()
+            .map(EmailAddress::as_str)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to,
+            [
+                "jiro@example.com",
+                "hanako@example.com",
+                "saburo@example.com"
+            ]
+        );
+        let cc = message
+            .cc()
+            .iter()
+            .map(EmailAddress::as_str)
+            .collect::<Vec<_>>();
+        assert_eq!(cc, ["shiro@example.com"]);
+
+        // 差出人の大文字・小文字だけが違っても、内容が同じなら同じメール
+        let other = raw.replace("Taro@Example.com", "taro@example.com");
+        let other = parse(other.as_bytes(), &ReadOptions::default()).unwrap();
+        let groups = find_duplicates(&[message, other], DuplicateKey::Content);
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[test]
+    fn parse_broken_message_leniently() {
+        let raw = "From: undisclosed\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   Content-Transfer-Encoding: base64\r\n\
+                   \r\n\
+                   !!! not base64 !!!\r\n";
+
+        assert!(parse(raw.as_bytes(), &ReadOptions::default()).is_err());
+
+        let options = ReadOptions::default().lenient(true);
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.from(), "undisclosed");
+        assert_eq!(message.subject(), "");
+        assert_eq!(message.body(), "!!! not base64 !!!");
+        assert_eq!(message.warnings().len(), 3);
+    }
+
+    #[test]
+    fn parse_sanitized_html() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: html\r\n\
+                   Content-Type: multipart/alternative; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   Hello\r\n\
+                   --b\r\n\
+                   Content-Type: text/html\r\n\
+                   \r\n\
+                   <p>Hello<script>alert(1)</script></p>\r\n\
+                   --b--\r\n";
+
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert!(message.html().unwrap().contains("<script>"));
+
+        let options = ReadOptions::default().sanitize_html(true);
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body(), "Hello");
+        assert_eq!(message.html().unwrap().trim(), "<p>Hello</p>");
+    }
+
+    #[test]
+    fn filter_decoded_body() {
+        // 「請求書番号 1234、5678」を base64 にしたもの
+        let raw = "From: taro@example.com\r\n\
+                   Subject: invoice\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   Content-Transfer-Encoding: base64\r\n\
+                   \r\n\
+                   6KuL5rGC5pu455Wq5Y+3IDEyMzTjgIE1Njc4\r\n";
+
+        let options = ReadOptions::default().filter_body(regex::Regex::new(r"\d{4}").unwrap());
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body_matches(), [16..20, 23..27]);
+        assert_eq!(&message.body()[16..20], "1234");
+        assert!(options.keeps(&message));
+
+        let options = ReadOptions::default().filter_body(regex::Regex::new("領収書").unwrap());
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert!(!options.keeps(&message));
+        assert!(ReadOptions::default().keeps(&message));
+    }
+
+    #[test]
+    fn trim_and_truncate_body() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: hi\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   \r\n\
+                   \r\n  こんにちは、太郎です。  \r\n\r\n";
+        let body = |options: ReadOptions| {
+            let message = parse(raw.as_bytes(), &options).unwrap();
+            (message.body().to_string(), message.is_truncated())
+        };
+
+        assert_eq!(
+            body(ReadOptions::default()),
+            ("\r\n  こんにちは、太郎です。".to_string(), false)
+        );
+        assert_eq!(
+            body(ReadOptions::default().trim(Trim::Keep)),
+            ("\r\n  こんにちは、太郎です。  \r\n\r\n".to_string(), false)
+        );
+        assert_eq!(
+            body(ReadOptions::default().trim(Trim::Both).max_body_len(5)),
+            ("こんにちは".to_string(), true)
+        );
+        assert_eq!(
+            body(ReadOptions::default().trim(Trim::Both).max_body_len(11)),
+            ("こんにちは、太郎です。".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn parse_vcard_attachment() {
+        let raw = "From: Taro <taro@example.com>\r\n\
+                   Subject: contact\r\n\
+                   Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   see attached\r\n\
+                   --b\r\n\
+                   Content-Type: text/vcard; name=\"taro.vcf\"\r\n\
+                   Content-Disposition: attachment; filename=\"taro.vcf\"\r\n\
+                   \r\n\
+                   BEGIN:VCARD\r\n\
+                   VERSION:3.0\r\n\
+                   FN:Taro\r\n\
+                   EMAIL:taro@example.com\r\n\
+                   END:VCARD\r\n\
+                   --b--\r\n";
+
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "see attached");
+        assert_eq!(message.contacts().len(), 1);
+        assert_eq!(message.contacts()[0].name(), Some("Taro"));
+        assert_eq!(message.contacts()[0].emails(), ["taro@example.com"]);
+    }
+
+    #[test]
+    fn parse_with_strip_signature() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: hi\r\n\
+                   \r\n\
+                   Hello.\r\n\
+                   -- \r\n\
+                   Taro\r\n";
+
+        let options = ReadOptions::default();
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body(), "Hello.\r\n-- \r\nTaro");
+
+        let options = ReadOptions::default().strip_signature(true);
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body(), "Hello.");
+    }
+
+    #[test]
+    fn parse_bounce() {
+        let raw = "From: MAILER-DAEMON@mx.example.com\r\n\
+                   Subject: Undelivered Mail Returned to Sender\r\n\
+                   Content-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   This is the mail system.\r\n\
+                   --b\r\n\
+                   Content-Type: message/delivery-status\r\n\
+                   \r\n\
+                   Reporting-MTA: dns; mx.example.com\r\n\
+                   \r\n\
+                   Final-Recipient: rfc822; nobody@example.net\r\n\
+                   Action: failed\r\n\
+                   Status: 5.1.1\r\n\
+                   --b--\r\n";
+
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "This is the mail system.");
+        let bounce = message.bounce().unwrap();
+        assert_eq!(bounce.recipient(), "nobody@example.net");
+        assert_eq!(bounce.status(), "5.1.1");
+    }
+
+    #[test]
+    fn parse_embedded_messages() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: Fwd: report\r\n\
+                   Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   see attached\r\n\
+                   --b\r\n\
+                   Content-Type: message/rfc822\r\n\
+                   \r\n\
+                   From: hanako@example.net\r\n\
+                   Subject: report\r\n\
+                   Message-ID: <r1@example.net>\r\n\
+                   \r\n\
+                   disk is full\r\n\
+                   --b\r\n\
+                   Content-Type: message/rfc822\r\n\
+                   \r\n\
+                   Subject: no sender\r\n\
+                   \r\n\
+                   broken\r\n\
+                   --b--\r\n";
+
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "see attached");
+        // From のないメールは読めないので入らない
+        assert_eq!(message.embedded_messages().len(), 1);
+        let embedded = &message.embedded_messages()[0];
+        assert_eq!(embedded.from(), "hanako@example.net");
+        assert_eq!(embedded.message_id(), Some("r1@example.net"));
+        assert_eq!(embedded.body(), "disk is full");
+
+        let options = ReadOptions::default().lenient(true);
+        let message = parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.embedded_messages().len(), 2);
+        assert_eq!(message.embedded_messages()[1].subject(), "no sender");
+    }
+
+    #[test]
+    fn skip_attached_text_for_body() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: build failed\r\n\
+                   Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain; name=\"build.log\"\r\n\
+                   Content-Disposition: inline; filename=\"build.log\"\r\n\
+                   \r\n\
+                   error: linking failed\r\n\
+                   --b\r\n\
+                   Content-Type: multipart/alternative; boundary=\"a\"\r\n\
+                   \r\n\
+                   --a\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   See the log.\r\n\
+                   --a\r\n\
+                   Content-Type: text/html\r\n\
+                   \r\n\
+                   <p>See the log.</p>\r\n\
+                   --a--\r\n\
+                   --b--\r\n";
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "See the log.");
+        assert_eq!(message.html(), Some("<p>See the log.</p>\r\n"));
+        assert_eq!(message.attachments().len(), 1);
+        assert_eq!(message.attachments()[0].filename(), Some("build.log"));
+
+        // 添付された text/plain しかなければ、本文は空
+        let raw = "From: taro@example.com\r\n\
+                   Subject: log\r\n\
+                   Content-Type: text/plain\r\n\
+                   Content-Disposition: attachment; filename=\"build.log\"\r\n\
+                   \r\n\
+                   error: linking failed\r\n";
+        let message = parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "");
+        assert_eq!(
+            message.attachments()[0].data(),
+            b"error: linking failed\r\n"
+        );
+    }
 }
@@ -0,0 +1,535 @@
+// メールを Markdown にする（MyMessage::to_markdown）
+// ヘッダーは先頭の front matter（YAML）に、HTML 本文は Markdown に直し、添付ファイルは最後に一覧にする
+
+use crate::MyMessage;
+
+pub(crate) fn render(message: &MyMessage) -> String {
+    let mut out = String::from("---\n");
+    field(&mut out, "subject", message.subject());
+    field(&mut out, "from", message.from());
+    if let Some(reply_to) = message.reply_to() {
+        field(&mut out, "reply_to", reply_to);
+    }
+    if let Some(date) = message.date() {
+        field(&mut out, "date", &date.to_rfc3339());
+    }
+    if let Some(id) = message.message_id() {
+        field(&mut out, "message_id", id);
+    }
+    if let Some(id) = message.in_reply_to() {
+        field(&mut out, "in_reply_to", id);
+    }
+    if !message.references().is_empty() {
+        out.push_str("references:\n");
+        for id in message.references() {
+            out.push_str(&format!("  - {}\n", quote(id)));
+        }
+    }
+    if !message.folder().is_empty() {
+        field(&mut out, "folder", message.folder());
+        out.push_str(&format!("uid: {}\n", message.uid()));
+    }
+    out.push_str("---\n\n");
+
+    // HTML があればそちらのほうが、見出しやリンクなどが残る
+    let body = match message.html() {
+        Some(html) => html_to_markdown(html),
+        None => message.body().replace("\r\n", "\n"),
+    };
+    let body = body.trim();
+    if !body.is_empty() {
+        out.push_str(body);
+        out.push_str("\n\n");
+    }
+
+    if !message.attachments().is_empty() {
+        out.push_str("## Attachments\n\n");
+        for (i, attachment) in message.attachments().iter().enumerate() {
+            // リンクは Markdown と同じ場所に添付ファイルを置いたときの相対パス
+            // （ReadOptions::large_parts_to_disk で書き出したものは、そのファイル）
+            let name = attachment
+                .filename()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("attachment-{}", i + 1));
+            let target = match attachment.path() {
+                Some(path) => path.display().to_string(),
+                None => name.clone(),
+            };
+            out.push_str(&format!(
+                "- [{}]({}) ({}, {} bytes, sha256 `{}`",
+                escape(&name),
+                encode_url(&target),
+                attachment.mimetype(),
+                attachment.size(),
+                attachment.sha256_hex()
+            ));
+            if let Some(reason) = attachment.quarantined() {
+                out.push_str(&format!(", quarantined: {}", escape(reason)));
+            }
+            out.push_str(")\n");
+        }
+        out.push('\n');
+    }
+
+    let len = out.trim_end().len();
+    out.truncate(len);
+    out.push('\n');
+    out
+}
+
+fn field(out: &mut String, key: &str, value: &str) {
+    out.push_str(&format!("{}: {}\n", key, quote(value)));
+}
+
+// YAML のダブルクォートの文字列
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// 本文中で Markdown の記号として読まれてしまう文字
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// リンク先に使えない文字（空白、括弧、ASCII 以外など）は %XX にする（「/」はそのまま）
+fn encode_url(url: &str) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for &b in url.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/:#?=&%".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+// HTML を Markdown にする
+// 先に ammonia でサニタイズしておくと、タグや属性の書き方がそろうので、簡単な読み方で済む
+pub(crate) fn html_to_markdown(html: &str) -> String {
+    let html = crate::html::sanitize(html);
+    let mut writer = Writer::default();
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        writer.text(&decode_entities(&rest[..start]));
+        let end = match tag_end(&rest[start..]) {
+            Some(end) => start + end,
+            None => break,
+        };
+        writer.tag(&rest[start + 1..end]);
+        rest = &rest[end + 1..];
+    }
+    writer.text(&decode_entities(rest));
+    writer.out
+}
+
+// 「<」から始まるタグの、閉じる「>」の位置（引用符の中の「>」は飛ばす）
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// タグの名前（小文字）と属性
+fn parse_tag(tag: &str) -> (bool, String, Vec<(String, String)>) {
+    let tag = tag.trim().trim_end_matches('/');
+    let (closing, tag) = match tag.strip_prefix('/') {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    };
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = tag[..name_end].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, next) = match after.chars().next() {
+                Some(q @ '"') | Some(q @ '\'') => match after[1..].find(q) {
+                    Some(end) => (&after[1..end + 1], &after[end + 2..]),
+                    None => (&after[1..], ""),
+                },
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = next.trim_start();
+        }
+        if !key.is_empty() {
+            attributes.push((key, value));
+        }
+    }
+    (closing, name, attributes)
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').filter(|&x| x <= 10).map(|end| {
+            let name = &rest[1..end];
+            let c = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match name.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(number) => number.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            (c, end)
+        });
+        match entity {
+            Some((Some(c), end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// タグと文字を順に受け取って Markdown を書く
+#[derive(Default)]
+struct Writer {
+    out: String,
+    // 次の文字の前に入れる改行の数（2 なら空行を入れる）
+    breaks: usize,
+    // 改行を入れると決めてからの、一番浅い引用の深さ（空行の「>」の数）
+    break_quote: Option<usize>,
+    // 次の文字の前に空白を入れるか
+    space: bool,
+    // 次の行の先頭に入れる「- 」「## 」など
+    marker: String,
+    quote: usize,
+    // ul なら None、ol なら今の番号
+    lists: Vec<Option<usize>>,
+    pre: bool,
+    // a のリンク先と、リンクの文字の始まり
+    links: Vec<(Option<String>, usize)>,
+    cells: usize,
+    rows: usize,
+}
+
+impl Writer {
+    fn block(&mut self, breaks: usize) {
+        self.breaks = self.breaks.max(breaks);
+        self.break_quote = Some(self.break_quote.map_or(self.quote, |x| x.min(self.quote)));
+        self.space = false;
+    }
+
+    // 行の先頭の「> 」とリストの字下げ
+    fn prefix(&self) -> String {
+        let indent = self.lists.len().saturating_sub(1);
+        format!("{}{}", "> ".repeat(self.quote), "    ".repeat(indent))
+    }
+
+    // 文字を書く前に、たまっている改行と空白を書く
+    fn start_inline(&mut self) {
+        if self.breaks > 0 && !self.out.is_empty() {
+            let blank = "> ".repeat(self.break_quote.unwrap_or(self.quote));
+            for _ in 1..self.breaks {
+                self.out.push('\n');
+                self.out.push_str(blank.trim_end());
+            }
+            self.out.push('\n');
+            self.out.push_str(&self.prefix());
+        } else if self.out.is_empty() {
+            self.out.push_str(&self.prefix());
+        } else if self.space {
+            self.out.push(' ');
+        }
+        self.breaks = 0;
+        self.break_quote = None;
+        self.space = false;
+        let marker = std::mem::take(&mut self.marker);
+        self.out.push_str(&marker);
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.pre {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.out.push('\n');
+                    self.out.push_str(&self.prefix());
+                }
+                self.out.push_str(line);
+            }
+            return;
+        }
+        // 空白（改行も）は続いていても 1 つにする
+        for (i, word) in text.split(char::is_whitespace).enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+            if !word.is_empty() {
+                self.start_inline();
+                self.out.push_str(&escape(word));
+            }
+        }
+    }
+
+    fn tag(&mut self, tag: &str) {
+        let (closing, name, attributes) = parse_tag(tag);
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(x, _)| x == key)
+                .map(|(_, value)| value.clone())
+        };
+        match (closing, name.as_str()) {
+            (false, "p") | (false, "div") | (false, "table") => self.block(2),
+            (true, "p") | (true, "div") => self.block(2),
+            (_, "h1") | (_, "h2") | (_, "h3") | (_, "h4") | (_, "h5") | (_, "h6") => {
+                self.block(2);
+                if !closing {
+                    let level = name[1..].parse().unwrap_or(1);
+                    self.marker = format!("{} ", "#".repeat(level));
+                }
+            }
+            (_, "br") if self.pre => self.out.push('\n'),
+            (_, "br") => {
+                self.out.push('\\');
+                self.block(1);
+            }
+            (_, "hr") => {
+                self.block(2);
+                self.marker = "---".to_string();
+                self.start_inline();
+                self.block(2);
+            }
+            (_, "strong") | (_, "b") => self.emphasis("**", closing),
+            (_, "em") | (_, "i") => self.emphasis("*", closing),
+            (_, "code") if !self.pre => self.emphasis("`", closing),
+            (false, "pre") => {
+                self.block(2);
+                self.start_inline();
+                self.out.push_str("```\n");
+                self.out.push_str(&self.prefix());
+                self.pre = true;
+            }
+            (true, "pre") => {
+                if !self.out.ends_with('\n') {
+                    self.out.push('\n');
+                    self.out.push_str(&self.prefix());
+                }
+                self.out.push_str("```");
+                self.pre = false;
+                self.block(2);
+            }
+            (false, "blockquote") => {
+                self.block(2);
+                self.quote += 1;
+            }
+            (true, "blockquote") => {
+                self.quote = self.quote.saturating_sub(1);
+                self.block(2);
+            }
+            (false, "ul") | (false, "ol") => {
+                self.block(if self.lists.is_empty() { 2 } else { 1 });
+                self.lists.push(if name == "ol" { Some(0) } else { None });
+            }
+            (true, "ul") | (true, "ol") => {
+                self.lists.pop();
+                self.block(if self.lists.is_empty() { 2 } else { 1 });
+            }
+            (false, "li") => {
+                self.block(1);
+                self.marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", number)
+                    }
+                    _ => "- ".to_string(),
+                };
+            }
+            (false, "tr") => {
+                self.block(1);
+                self.cells = 0;
+                self.marker = "|".to_string();
+            }
+            (false, "td") | (false, "th") => {
+                self.space = false;
+                self.start_inline();
+                self.out.push(' ');
+                self.cells += 1;
+            }
+            (true, "td") | (true, "th") => self.out.push_str(" |"),
+            (true, "tr") => {
+                // 最初の行を見出しにする（Markdown の表には見出しの行が要る）
+                self.rows += 1;
+                if self.rows == 1 && self.cells > 0 {
+                    self.out.push('\n');
+                    self.out.push_str(&self.prefix());
+                    self.out.push('|');
+                    self.out.push_str(&" --- |".repeat(self.cells));
+                }
+            }
+            (true, "table") => {
+                self.rows = 0;
+                self.block(2);
+            }
+            (false, "a") => {
+                self.start_inline();
+                self.links.push((attribute("href"), self.out.len()));
+            }
+            (true, "a") => {
+                if let Some((Some(href), start)) = self.links.pop() {
+                    let text = self.out.split_off(start);
+                    let text = if text.trim().is_empty() {
+                        escape(&href)
+                    } else {
+                        text
+                    };
+                    self.out
+                        .push_str(&format!("[{}]({})", text, encode_url(&href)));
+                }
+            }
+            (false, "img") => {
+                if let Some(src) = attribute("src") {
+                    self.start_inline();
+                    let alt = attribute("alt").unwrap_or_default();
+                    self.out
+                        .push_str(&format!("![{}]({})", escape(&alt), encode_url(&src)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn emphasis(&mut self, mark: &str, closing: bool) {
+        if closing {
+            self.out.push_str(mark);
+        } else {
+            self.start_inline();
+            self.out.push_str(mark);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    #[test]
+    fn convert_html() {
+        let html = "<h2>Report</h2>\
+                    <p>Hello <b>world</b>, see <a href=\"https://example.com/a b\">the site</a>.<br>\
+                    Line 2 &amp; more</p>\
+                    <ul><li>one</li><li>two<ol><li>a</li><li>b</li></ol></li></ul>\
+                    <blockquote><p>quoted *text*</p></blockquote>\
+                    <pre>let x = 1;\n  y</pre>\
+                    <table><tr><th>name</th><th>value</th></tr><tr><td>a</td><td>1</td></tr></table>\
+                    <script>alert(1)</script>";
+        assert_eq!(
+            html_to_markdown(html),
+            "## Report\n\
+             \n\
+             Hello **world**, see [the site](https://example.com/a%20b).\\\n\
+             Line 2 & more\n\
+             \n\
+             - one\n\
+             - two\n\
+             \x20   1. a\n\
+             \x20   2. b\n\
+             \n\
+             > quoted \\*text\\*\n\
+             \n\
+             ```\n\
+             let x = 1;\n\
+             \x20 y\n\
+             ```\n\
+             \n\
+             | name | value |\n\
+             | --- | --- |\n\
+             | a | 1 |"
+        );
+    }
+
+    #[test]
+    fn render_message() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: \"Weekly\" report\r\n\
+                   Date: Mon, 1 Apr 2024 09:00:00 +0900\r\n\
+                   Message-ID: <r1@example.com>\r\n\
+                   Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+                   \r\n\
+                   --b\r\n\
+                   Content-Type: text/plain\r\n\
+                   \r\n\
+                   All good.\r\n\
+                   --b\r\n\
+                   Content-Type: text/csv\r\n\
+                   Content-Disposition: attachment; filename=\"week 14.csv\"\r\n\
+                   \r\n\
+                   a,b\r\n\
+                   --b--\r\n";
+        let message = crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        let markdown = message.to_markdown();
+        let expected = format!(
+            "---\n\
+             subject: \"\\\"Weekly\\\" report\"\n\
+             from: \"taro@example.com\"\n\
+             date: \"2024-04-01T09:00:00+09:00\"\n\
+             message_id: \"r1@example.com\"\n\
+             ---\n\
+             \n\
+             All good.\n\
+             \n\
+             ## Attachments\n\
+             \n\
+             - [week 14.csv](week%2014.csv) (text/csv, 5 bytes, sha256 `{}`)\n",
+            message.attachments()[0].sha256_hex()
+        );
+        assert_eq!(markdown, expected);
+    }
+}
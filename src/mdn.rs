@@ -0,0 +1,303 @@
+// 開封確認（MDN、multipart/report の disposition-notification）の解析と作成
+// https://tools.ietf.org/html/rfc8098
+
+use std::error::Error;
+
+use mailparse::{parse_headers, MailHeaderMap};
+
+use crate::bounce::strip_address_type;
+use crate::compose;
+use crate::MyMessage;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt {
+    disposition: String,
+    action_mode: String,
+    sending_mode: String,
+    final_recipient: String,
+    original_recipient: Option<String>,
+    original_message_id: Option<String>,
+    reporting_ua: Option<String>,
+}
+
+impl Receipt {
+    // displayed / deleted / dispatched / processed
+    pub fn disposition(&self) -> &str {
+        &self.disposition
+    }
+
+    // 人が操作したのではなく、自動で処理・送信されたか
+    pub fn is_automatic(&self) -> bool {
+        self.action_mode == "automatic-action" || self.sending_mode == "mdn-sent-automatically"
+    }
+
+    // 開封確認を送ってきた宛先（「rfc822;」は取り除く）
+    pub fn final_recipient(&self) -> &str {
+        &self.final_recipient
+    }
+
+    pub fn original_recipient(&self) -> Option<&str> {
+        self.original_recipient.as_deref()
+    }
+
+    // 開封確認の対象になったメールの Message-ID（<> は取り除く）
+    pub fn original_message_id(&self) -> Option<&str> {
+        self.original_message_id.as_deref()
+    }
+
+    // 開封確認を作ったソフトウェア
+    pub fn reporting_ua(&self) -> Option<&str> {
+        self.reporting_ua.as_deref()
+    }
+}
+
+// message/disposition-notification パートの本文を解析する
+pub(crate) fn parse_disposition_notification(text: &str) -> Option<Receipt> {
+    let text = format!("{}\r\n\r\n", text.trim());
+    let (headers, _) = parse_headers(text.as_bytes()).ok()?;
+
+    // 「manual-action/MDN-sent-manually; displayed/error」
+    let disposition = headers.get_first_value("Disposition")?;
+    let (modes, kind) = disposition.split_once(';')?;
+    let (action_mode, sending_mode) = modes.split_once('/').unwrap_or((modes, ""));
+    let kind = kind.split('/').next()?.trim().to_ascii_lowercase();
+    if kind.is_empty() {
+        return None;
+    }
+
+    Some(Receipt {
+        disposition: kind,
+        action_mode: action_mode.trim().to_ascii_lowercase(),
+        sending_mode: sending_mode.trim().to_ascii_lowercase(),
+        final_recipient: strip_address_type(&headers.get_first_value("Final-Recipient")?),
+        original_recipient: headers
+            .get_first_value("Original-Recipient")
+            .map(|x| strip_address_type(&x)),
+        original_message_id: headers.get_first_value("Original-Message-ID").map(|x| {
+            x.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        }),
+        reporting_ua: headers
+            .get_first_value("Reporting-UA")
+            .map(|x| x.trim().to_string()),
+    })
+}
+
+// 作る開封確認の内容
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Disposition {
+    // 表示した
+    Displayed,
+    // 読まずに削除した
+    Deleted,
+    // 印刷・転送など、表示したかは分からない
+    Dispatched,
+    // 表示せずに処理した（自動処理の受信箱など）
+    Processed,
+}
+
+impl Disposition {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Displayed => "displayed",
+            Self::Deleted => "deleted",
+            Self::Dispatched => "dispatched",
+            Self::Processed => "processed",
+        }
+    }
+}
+
+// message（Disposition-Notification-To のあるメール）への開封確認を作る
+#[derive(Debug, Clone)]
+pub struct MdnBuilder<'a> {
+    message: &'a MyMessage,
+    from: String,
+    disposition: Disposition,
+    automatic: bool,
+}
+
+impl<'a> MdnBuilder<'a> {
+    // from は開封確認を送る側（message を受け取った）のアドレス
+    pub fn new(message: &'a MyMessage, from: &str) -> Self {
+        Self {
+            message,
+            from: from.to_string(),
+            disposition: Disposition::Displayed,
+            automatic: false,
+        }
+    }
+
+    // 既定では Disposition::Displayed
+    pub fn disposition(mut self, disposition: Disposition) -> Self {
+        self.disposition = disposition;
+        self
+    }
+
+    // 人が確認せずに自動で送る（既定では、人が送ると決めたものとして作る）
+    pub fn automatic(mut self, automatic: bool) -> Self {
+        self.automatic = automatic;
+        self
+    }
+
+    // RFC 5322 形式のメール（Disposition-Notification-To の宛先に送る）
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let to = self
+            .message
+            .disposition_notification_to()
+            .ok_or("no Disposition-Notification-To header")?;
+        let from = compose::bare_address(&self.from);
+        if from.is_empty() {
+            return Err("no From address".into());
+        }
+        let domain = from.rsplit('@').next().unwrap_or("localhost");
+        let boundary = format!("=_{}", compose::unique());
+
+        let mut out = Vec::new();
+        compose::header(&mut out, "Date", &chrono::Local::now().to_rfc2822());
+        compose::header(&mut out, "From", &compose::encode_address(&self.from));
+        compose::header(&mut out, "To", to);
+        compose::header(
+            &mut out,
+            "Subject",
+            &compose::encode_word(&format!("Read: {}", self.message.subject())),
+        );
+        compose::header(
+            &mut out,
+            "Message-ID",
+            &format!("<{}@{}>", compose::unique(), domain),
+        );
+        if let Some(id) = self.message.message_id() {
+            compose::header(&mut out, "In-Reply-To", &format!("<{}>", id));
+            compose::header(&mut out, "References", &format!("<{}>", id));
+        }
+        compose::header(&mut out, "MIME-Version", "1.0");
+        compose::header(
+            &mut out,
+            "Content-Type",
+            &format!(
+                "multipart/report; report-type=disposition-notification; boundary=\"{}\"",
+                boundary
+            ),
+        );
+
+        // 人が読むための説明
+        out.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
+        let explanation = format!(
+            "The message sent to {} with subject \"{}\" has been {}.\n\
+             This is no guarantee that the message has been read or understood.",
+            from,
+            self.message.subject(),
+            self.disposition.as_str()
+        );
+        compose::text_part(&mut out, &explanation);
+
+        // 機械が読むための内容
+        out.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
+        compose::header(&mut out, "Content-Type", "message/disposition-notification");
+        out.extend_from_slice(b"\r\n");
+        compose::header(&mut out, "Reporting-UA", "read-mail");
+        if let Some(recipient) = self.message.original_recipient() {
+            compose::header(
+                &mut out,
+                "Original-Recipient",
+                &format!("rfc822;{}", recipient),
+            );
+        }
+        compose::header(&mut out, "Final-Recipient", &format!("rfc822;{}", from));
+        if let Some(id) = self.message.message_id() {
+            compose::header(&mut out, "Original-Message-ID", &format!("<{}>", id));
+        }
+        let mode = if self.automatic {
+            "automatic-action/MDN-sent-automatically"
+        } else {
+            "manual-action/MDN-sent-manually"
+        };
+        compose::header(
+            &mut out,
+            "Disposition",
+            &format!("{}; {}", mode, self.disposition.as_str()),
+        );
+
+        // 元のメールのヘッダー
+        out.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
+        compose::header(&mut out, "Content-Type", "text/rfc822-headers");
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(original_headers(self.message.raw()));
+        out.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        Ok(out)
+    }
+}
+
+// 空行までのヘッダー（最後の改行は含めない）
+fn original_headers(raw: &[u8]) -> &[u8] {
+    let end = raw
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .or_else(|| raw.windows(2).position(|x| x == b"\n\n"))
+        .unwrap_or(raw.len());
+    &raw[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    #[test]
+    fn generate_and_parse_back() {
+        let raw = "From: taro@example.com\r\n\
+                   To: hanako@example.net\r\n\
+                   Subject: =?UTF-8?B?5aWR57SE5pu4?=\r\n\
+                   Message-ID: <abc@example.com>\r\n\
+                   Disposition-Notification-To: Taro <taro@example.com>\r\n\
+                   Original-Recipient: rfc822;info@example.net\r\n\
+                   \r\n\
+                   please confirm\r\n";
+        let message = crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        assert_eq!(
+            message.disposition_notification_to(),
+            Some("taro@example.com")
+        );
+        assert_eq!(message.original_recipient(), Some("info@example.net"));
+
+        let mdn = MdnBuilder::new(&message, "花子 <hanako@example.net>")
+            .disposition(Disposition::Processed)
+            .automatic(true)
+            .build()
+            .unwrap();
+        let receipt = crate::parse(&mdn, &ReadOptions::default()).unwrap();
+        assert_eq!(receipt.from(), "hanako@example.net");
+        assert_eq!(receipt.subject(), "Read: 契約書");
+        assert_eq!(receipt.in_reply_to(), Some("abc@example.com"));
+        assert!(receipt.body().contains("has been processed"));
+
+        let receipt = receipt.receipt().unwrap();
+        assert_eq!(receipt.disposition(), "processed");
+        assert!(receipt.is_automatic());
+        assert_eq!(receipt.final_recipient(), "hanako@example.net");
+        assert_eq!(receipt.original_recipient(), Some("info@example.net"));
+        assert_eq!(receipt.original_message_id(), Some("abc@example.com"));
+        assert_eq!(receipt.reporting_ua(), Some("read-mail"));
+
+        let request = crate::parse(
+            b"From: a@example.com\r\nSubject: s\r\n\r\nx",
+            &ReadOptions::default(),
+        )
+        .unwrap();
+        assert!(MdnBuilder::new(&request, "b@example.com").build().is_err());
+    }
+
+    #[test]
+    fn parse_manual_receipt() {
+        let text = "Reporting-UA: Mail.app\r\n\
+                    Final-Recipient: rfc822; hanako@example.net\r\n\
+                    Disposition: manual-action/MDN-sent-manually; displayed\r\n";
+        let receipt = parse_disposition_notification(text).unwrap();
+        assert_eq!(receipt.disposition(), "displayed");
+        assert!(!receipt.is_automatic());
+        assert_eq!(receipt.original_message_id(), None);
+        assert_eq!(parse_disposition_notification("Reporting-UA: x\r\n"), None);
+    }
+}
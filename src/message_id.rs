@@ -0,0 +1,192 @@
+// Message-ID を比べるための型と、返信のつながり（ReplyGraph）
+// 「<a@Example.COM>」と「a@example.com」のような表記の違いを、使う側で毎回吸収しなくて済むようにする
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::MyMessage;
+
+// <> と前後の空白を除き、「@」より後（ドメイン）を小文字にした Message-ID
+// 「@」より前は大文字・小文字を区別する（RFC 5322 では区別しないとは決まっていない）
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId(String);
+
+impl MessageId {
+    // 空なら None
+    pub fn new(id: &str) -> Option<Self> {
+        let id = id.trim();
+        let id = id.strip_prefix('<').unwrap_or(id);
+        let id = id.strip_suffix('>').unwrap_or(id).trim();
+        if id.is_empty() {
+            return None;
+        }
+        Some(Self(match id.rsplit_once('@') {
+            Some((left, right)) => format!("{}@{}", left, right.to_lowercase()),
+            None => id.to_string(),
+        }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // 「@」より前（「@」がなければ全体）
+    pub fn local_part(&self) -> &str {
+        self.0.rsplit_once('@').map_or(&self.0, |(x, _)| x)
+    }
+
+    // 「@」より後（小文字）
+    pub fn domain(&self) -> Option<&str> {
+        self.0.rsplit_once('@').map(|(_, x)| x)
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// メールの返信のつながり
+// 親は In-Reply-To、なければ References を新しいほうから見て、messages の中にある最初のもの
+#[derive(Debug, Clone, Default)]
+pub struct ReplyGraph {
+    // Message-ID ごとの messages の中での位置（同じものがあれば最初のもの）
+    indices: HashMap<MessageId, usize>,
+    parents: HashMap<MessageId, MessageId>,
+    children: HashMap<MessageId, Vec<MessageId>>,
+    // messages の順
+    order: Vec<MessageId>,
+}
+
+impl ReplyGraph {
+    pub fn new(messages: &[MyMessage]) -> Self {
+        let mut graph = Self::default();
+        for (i, message) in messages.iter().enumerate() {
+            if let Some(id) = message.id() {
+                if !graph.indices.contains_key(&id) {
+                    graph.indices.insert(id.clone(), i);
+                    graph.order.push(id);
+                }
+            }
+        }
+
+        for message in messages {
+            let id = match message.id() {
+                Some(id) if !graph.parents.contains_key(&id) => id,
+                _ => continue,
+            };
+            let candidates = message
+                .in_reply_to()
+                .into_iter()
+                .chain(message.references().iter().rev().map(String::as_str))
+                .filter_map(MessageId::new);
+            for parent in candidates {
+                // 壊れたヘッダーで輪にならないようにする
+                if graph.indices.contains_key(&parent) && !graph.is_ancestor(&id, &parent) {
+                    graph
+                        .children
+                        .entry(parent.clone())
+                        .or_default()
+                        .push(id.clone());
+                    graph.parents.insert(id, parent);
+                    break;
+                }
+            }
+        }
+        graph
+    }
+
+    // id が ancestor 自身か、その祖先か
+    fn is_ancestor(&self, id: &MessageId, ancestor: &MessageId) -> bool {
+        let mut current = Some(ancestor);
+        while let Some(x) = current {
+            if x == id {
+                return true;
+            }
+            current = self.parents.get(x);
+        }
+        false
+    }
+
+    // id のメールの、new に渡した messages の中での位置
+    pub fn index(&self, id: &MessageId) -> Option<usize> {
+        self.indices.get(id).copied()
+    }
+
+    pub fn parent(&self, id: &MessageId) -> Option<&MessageId> {
+        self.parents.get(id)
+    }
+
+    // 返信（messages の順）
+    pub fn children(&self, id: &MessageId) -> &[MessageId] {
+        self.children.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    // 親のないメール（messages の順）
+    pub fn roots(&self) -> Vec<&MessageId> {
+        self.order
+            .iter()
+            .filter(|x| !self.parents.contains_key(*x))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    fn message(headers: &str) -> MyMessage {
+        let raw = format!(
+            "From: taro@example.com\r\nSubject: s\r\n{}\r\n\r\nbody\r\n",
+            headers
+        );
+        crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap()
+    }
+
+    fn id(id: &str) -> MessageId {
+        MessageId::new(id).unwrap()
+    }
+
+    #[test]
+    fn normalize_message_id() {
+        assert_eq!(id(" <Abc.1@Example.COM> "), id("Abc.1@example.com"));
+        assert_ne!(id("Abc.1@example.com"), id("abc.1@example.com"));
+        assert_eq!(id("<Abc.1@Example.COM>").as_str(), "Abc.1@example.com");
+        assert_eq!(id("<Abc.1@Example.COM>").local_part(), "Abc.1");
+        assert_eq!(id("<Abc.1@Example.COM>").domain(), Some("example.com"));
+        assert_eq!(id("no-at-sign").domain(), None);
+        assert_eq!(MessageId::new("<>"), None);
+    }
+
+    #[test]
+    fn build_reply_graph() {
+        let messages = vec![
+            message("Message-ID: <a@example.com>"),
+            // In-Reply-To の b は持っていないので、References の a が親になる
+            message(
+                "Message-ID: <c@example.com>\r\nIn-Reply-To: <b@example.com>\r\n\
+                 References: <a@Example.com> <b@example.com>",
+            ),
+            message("Message-ID: <d@example.com>\r\nIn-Reply-To: <c@EXAMPLE.com>"),
+            // 輪になるヘッダー
+            message("Message-ID: <x@example.com>\r\nIn-Reply-To: <y@example.com>"),
+            message("Message-ID: <y@example.com>\r\nIn-Reply-To: <x@example.com>"),
+        ];
+        let graph = ReplyGraph::new(&messages);
+
+        assert_eq!(
+            graph.parent(&id("c@example.com")),
+            Some(&id("a@example.com"))
+        );
+        assert_eq!(graph.children(&id("c@example.com")), [id("d@example.com")]);
+        assert_eq!(graph.index(&id("d@example.com")), Some(2));
+        assert_eq!(
+            graph.parent(&id("x@example.com")),
+            Some(&id("y@example.com"))
+        );
+        assert_eq!(graph.parent(&id("y@example.com")), None);
+        assert_eq!(graph.roots(), [&id("a@example.com"), &id("y@example.com")]);
+    }
+}
@@ -0,0 +1,339 @@
+// 取得したメールのバッファを借りたままの MyMessage（何万通も書き出すときのためのもの）
+// From・件名・本文は、エンコードされていなければバッファの一部をそのまま使い、
+// MIME エンコードや base64 などを解除する必要があるときだけ String を作る
+// for_each_message は、まとめて FETCH した応答のバッファをメールごとに写さずに渡す
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::str;
+
+use chrono::{DateTime, FixedOffset};
+use mailparse::{addrparse, parse_content_type, parse_header, parse_mail, MailAddr};
+
+use crate::{MessageError, MyMailbox, MyMessage, ReadOptions};
+
+// 一度に FETCH するメールの数
+const BATCH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct MyMessageRef<'a> {
+    uid: u32,
+    message_id: Option<&'a str>,
+    from: Cow<'a, str>,
+    date: Option<DateTime<FixedOffset>>,
+    subject: Cow<'a, str>,
+    body: Cow<'a, str>,
+    raw: &'a [u8],
+}
+
+impl<'a> MyMessageRef<'a> {
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    // Message-ID（<> は取り除く）
+    pub fn message_id(&self) -> Option<&'a str> {
+        self.message_id
+    }
+
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.date
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    // 最初の text/plain パート（末尾の空白は取り除く、ReadOptions は使わない）
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    // 添付ファイルなども含めて解析し直す
+    pub fn to_message(&self, options: &ReadOptions) -> Result<MyMessage, Box<dyn Error>> {
+        let mut message = crate::parse(self.raw, options)?;
+        message.uid = self.uid;
+        Ok(message)
+    }
+}
+
+// raw を借りたまま、From・件名・本文などを取り出す
+pub fn parse_ref(raw: &[u8]) -> Result<MyMessageRef<'_>, Box<dyn Error>> {
+    let (headers, body_start) = split_header(raw);
+
+    let from = find_header(headers, "From").ok_or("no From header(1)")?;
+    let from = address(from)?;
+    let subject = find_header(headers, "Subject").ok_or("no Subject header")?;
+    let subject = header_text(subject)?;
+    let message_id = find_header(headers, "Message-ID")
+        .and_then(|x| str::from_utf8(x).ok())
+        .map(|x| x.trim().trim_start_matches('<').trim_end_matches('>'))
+        .filter(|x| !x.is_empty() && !x.contains('\n'));
+    let date = find_header(headers, "Date")
+        .and_then(|x| str::from_utf8(x).ok())
+        .and_then(|x| DateTime::parse_from_rfc2822(x.trim()).ok());
+
+    let body = body(raw, headers, &raw[body_start..])?;
+    Ok(MyMessageRef {
+        uid: 0,
+        message_id,
+        from,
+        date,
+        subject,
+        body,
+        raw,
+    })
+}
+
+// folder のすべてのメールを、BATCH 通ずつ取得して f に渡す（渡したメールの数を返す）
+pub fn for_each_message<F>(
+    mailbox: &MyMailbox,
+    folder: &str,
+    mut f: F,
+) -> Result<usize, Box<dyn Error>>
+where
+    F: FnMut(MyMessageRef) -> Result<(), Box<dyn Error>>,
+{
+    let mut imap_session = crate::connect(mailbox)?;
+    imap_session.examine(folder)?;
+    let uids = crate::search_uids(&mut imap_session)?;
+
+    let mut count = 0;
+    for chunk in uids.chunks(BATCH) {
+        let throttle = imap_session.throttle().clone();
+        let fetches =
+            throttle.run(|| imap_session.uid_fetch(crate::uid_set(chunk), "BODY.PEEK[]"))?;
+        for fetch in fetches.iter() {
+            let (uid, raw) = match (fetch.uid, fetch.body()) {
+                (Some(uid), Some(raw)) => (uid, raw),
+                _ => continue,
+            };
+            let mut message = parse_ref(raw).map_err(|e| MessageError::parse(folder, uid, e))?;
+            message.uid = uid;
+            f(message)?;
+            count += 1;
+        }
+    }
+    imap_session.logout()?;
+    Ok(count)
+}
+
+// ヘッダー部分と、本文の始まる位置
+fn split_header(raw: &[u8]) -> (&[u8], usize) {
+    let mut start = 0;
+    while start < raw.len() {
+        let end = line_end(raw, start);
+        if matches!(&raw[start..end], b"\r\n" | b"\n") {
+            return (&raw[..end], end);
+        }
+        start = end;
+    }
+    (raw, raw.len())
+}
+
+// 最初の name ヘッダーの値（折り返しも含む）
+fn find_header<'a>(headers: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    let mut start = 0;
+    while start < headers.len() {
+        let end = line_end(headers, start);
+        let line = &headers[start..end];
+        if line.len() > name.len()
+            && line[name.len()] == b':'
+            && line[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+        {
+            // 空白で始まる行は前の行の続き
+            let mut value_end = end;
+            while value_end < headers.len() && matches!(headers[value_end], b' ' | b'\t') {
+                value_end = line_end(headers, value_end);
+            }
+            let value = &headers[start + name.len() + 1..value_end];
+            return Some(trim_newline(value));
+        }
+        start = end;
+    }
+    None
+}
+
+fn line_end(bytes: &[u8], start: usize) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&x| x == b'\n')
+        .map_or(bytes.len(), |x| start + x + 1)
+}
+
+fn trim_newline(value: &[u8]) -> &[u8] {
+    let end = value
+        .iter()
+        .rposition(|x| !matches!(x, b'\r' | b'\n'))
+        .map_or(0, |x| x + 1);
+    &value[..end]
+}
+
+// 折り返しも MIME エンコード（=?UTF-8?B?...?=）もなければ、そのまま使う
+fn header_text(value: &[u8]) -> Result<Cow<'_, str>, Box<dyn Error>> {
+    let plain = !value.contains(&b'\n') && !value.windows(2).any(|x| x == b"=?");
+    match str::from_utf8(value) {
+        Ok(text) if plain => Ok(Cow::Borrowed(text.trim())),
+        _ => {
+            let mut line = b"X: ".to_vec();
+            line.extend(value);
+            Ok(Cow::Owned(parse_header(&line)?.0.get_value()))
+        }
+    }
+}
+
+// 「Taro <taro@example.com>」や「taro@example.com」のメールアドレス
+fn address(value: &[u8]) -> Result<Cow<'_, str>, Box<dyn Error>> {
+    if let Cow::Borrowed(text) = header_text(value)? {
+        let simple = !text.contains([',', '"', '(', ';', '\\']);
+        if simple {
+            let addr = match (text.find('<'), text.rfind('>')) {
+                (Some(open), Some(close)) if open < close => Some(&text[open + 1..close]),
+                (None, None) if !text.contains(char::is_whitespace) => Some(text),
+                _ => None,
+            };
+            if let Some(addr) = addr.filter(|x| !x.is_empty()) {
+                return Ok(Cow::Borrowed(addr));
+            }
+        }
+    }
+    let text = header_text(value)?;
+    match addrparse(&text)?.first().ok_or("no From header(2)")? {
+        MailAddr::Single(info) => Ok(Cow::Owned(info.addr.clone())),
+        _ => Err("no From header(3)".into()),
+    }
+}
+
+// UTF-8（か US-ASCII）で、base64 などでもない単一パートなら、そのまま使う
+// そうでなければ MyMessage と同じように、最初の text/plain パートをデコードする
+fn body<'a>(raw: &'a [u8], headers: &[u8], body: &'a [u8]) -> Result<Cow<'a, str>, Box<dyn Error>> {
+    let content_type = find_header(headers, "Content-Type")
+        .map(|x| parse_content_type(&String::from_utf8_lossy(x)))
+        .unwrap_or_else(|| parse_content_type(""));
+    let encoding = find_header(headers, "Content-Transfer-Encoding")
+        .map(|x| String::from_utf8_lossy(x).trim().to_ascii_lowercase());
+    let plain_encoding = matches!(encoding.as_deref(), None | Some("7bit" | "8bit" | "binary"));
+    let utf8 = matches!(
+        content_type.charset.to_ascii_lowercase().as_str(),
+        "utf-8" | "utf8" | "us-ascii"
+    );
+    if !content_type.mimetype.starts_with("multipart/") && plain_encoding && utf8 {
+        if let Ok(text) = str::from_utf8(body) {
+            return Ok(Cow::Borrowed(text.trim_end()));
+        }
+    }
+
+    let parsed_mail = parse_mail(raw)?;
+    let text_mail = if parsed_mail.subparts.is_empty() {
+        Some(&parsed_mail)
+    } else {
+        parsed_mail
+            .subparts
+            .iter()
+            .find(|x| x.ctype.mimetype == "text/plain")
+    };
+    match text_mail {
+        Some(part) => Ok(Cow::Owned(part.get_body()?.trim_end().to_string())),
+        None => Err("no text/plain parts".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn is_borrowed(text: Cow<str>) -> bool {
+        matches!(text, Cow::Borrowed(_))
+    }
+
+    #[test]
+    fn borrow_plain_fields() {
+        let raw = b"From: Taro <taro@example.com>\r\nSubject: Lunch today\r\n\
+                    Message-ID: <a@example.com>\r\nDate: Mon, 1 Jan 2024 10:00:00 +0900\r\n\
+                    Content-Type: text/plain; charset=utf-8\r\n\r\n\
+                    \xe3\x81\x93\xe3\x82\x93\xe3\x81\xab\xe3\x81\xa1\xe3\x81\xaf\r\n\r\n";
+        let message = parse_ref(raw).unwrap();
+        assert_eq!(message.from(), "taro@example.com");
+        assert_eq!(message.subject(), "Lunch today");
+        assert_eq!(message.body(), "こんにちは");
+        assert_eq!(message.message_id(), Some("a@example.com"));
+        assert!(message.date().is_some());
+        assert!(is_borrowed(message.from));
+        assert!(is_borrowed(message.subject));
+        assert!(is_borrowed(message.body));
+    }
+
+    #[test]
+    fn decode_encoded_fields() {
+        let raw = b"From: \"Yamada, Taro\" <taro@example.com>\r\n\
+                    Subject: =?UTF-8?B?6KuL5rGC5pu4?=\r\n\
+                    Content-Type: multipart/alternative; boundary=b\r\n\r\n\
+                    --b\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                    Content-Transfer-Encoding: base64\r\n\r\n5pys5paH\r\n\
+                    --b--\r\n";
+        let message = parse_ref(raw).unwrap();
+        assert_eq!(message.from(), "taro@example.com");
+        assert_eq!(message.subject(), "請求書");
+        assert_eq!(message.body(), "本文");
+        assert!(!is_borrowed(message.subject.clone()));
+
+        let owned = message.to_message(&ReadOptions::default()).unwrap();
+        assert_eq!(
+            (owned.from(), owned.subject(), owned.body()),
+            (message.from(), message.subject(), message.body())
+        );
+    }
+
+    // 借りる方と MyMessage を作る方の速さを比べる（cargo test -- --nocapture で表示）
+    #[test]
+    fn bench_parse_ref() {
+        let messages = (0..2000)
+            .map(|i| {
+                format!(
+                    "From: Sender {0} <sender{0}@example.com>\r\nSubject: Report {0}\r\n\
+                     Message-ID: <{0}@example.com>\r\nDate: Mon, 1 Jan 2024 10:00:00 +0900\r\n\
+                     Content-Type: text/plain; charset=utf-8\r\n\r\n{1}\r\n",
+                    i,
+                    "The quarterly numbers are attached. ".repeat(20)
+                )
+                .into_bytes()
+            })
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let mut borrowed = 0;
+        for raw in &messages {
+            let message = parse_ref(raw).unwrap();
+            borrowed += message.body().len();
+            assert!(is_borrowed(message.body));
+        }
+        let borrowed_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut owned = 0;
+        for raw in &messages {
+            owned += crate::parse(raw, &ReadOptions::default())
+                .unwrap()
+                .body()
+                .len();
+        }
+        let owned_time = start.elapsed();
+
+        assert_eq!(borrowed, owned);
+        println!(
+            "parse_ref: {:?}, parse: {:?} ({} messages)",
+            borrowed_time,
+            owned_time,
+            messages.len()
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// 常駐して読み続けるときに、Prometheus などへ出すための数値
+// MyMailbox::metrics で渡すと、そのメールボックスでの接続すべてについて数える
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// 取得にかかった時間の区切り（秒、Prometheus の既定と同じ）
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct Counters {
+    commands_sent: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    messages_parsed: AtomicU64,
+    parse_failures: AtomicU64,
+    // 区切りごとの件数（最後は 10 秒を超えたもの）
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_micros: AtomicU64,
+}
+
+// clone しても同じ数値を共有する（スレッドをまたいでも使える）
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // サーバーに送ったコマンドの数
+    pub fn commands_sent(&self) -> u64 {
+        self.counters.commands_sent.load(Ordering::Relaxed)
+    }
+
+    // サーバーから受け取ったバイト数
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.counters.bytes_downloaded.load(Ordering::Relaxed)
+    }
+
+    // 解析できたメールの数
+    pub fn messages_parsed(&self) -> u64 {
+        self.counters.messages_parsed.load(Ordering::Relaxed)
+    }
+
+    // 解析に失敗したメールの数
+    pub fn parse_failures(&self) -> u64 {
+        self.counters.parse_failures.load(Ordering::Relaxed)
+    }
+
+    // メール1通の取得（UID FETCH）にかかった時間の（上限の秒数, それ以下の件数）
+    // 件数は累積で、最後の上限は f64::INFINITY
+    pub fn fetch_latency(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS
+            .iter()
+            .chain(&[f64::INFINITY])
+            .zip(&self.counters.latency_buckets)
+            .map(|(&bound, count)| {
+                total += count.load(Ordering::Relaxed);
+                (bound, total)
+            })
+            .collect()
+    }
+
+    // 取得にかかった時間の合計
+    pub fn fetch_latency_sum(&self) -> Duration {
+        Duration::from_micros(self.counters.latency_micros.load(Ordering::Relaxed))
+    }
+
+    // Prometheus のテキスト形式（/metrics の応答にそのまま使える）
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let counters = [
+            (
+                "read_mail_commands_sent_total",
+                "IMAP commands sent.",
+                self.commands_sent(),
+            ),
+            (
+                "read_mail_bytes_downloaded_total",
+                "Bytes received from the server.",
+                self.bytes_downloaded(),
+            ),
+            (
+                "read_mail_messages_parsed_total",
+                "Messages parsed successfully.",
+                self.messages_parsed(),
+            ),
+            (
+                "read_mail_parse_failures_total",
+                "Messages that failed to parse.",
+                self.parse_failures(),
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            let _ = writeln!(text, "{} {}", name, value);
+        }
+
+        let name = "read_mail_fetch_latency_seconds";
+        let _ = writeln!(text, "# HELP {} Time taken to fetch one message.", name);
+        let _ = writeln!(text, "# TYPE {} histogram", name);
+        let latency = self.fetch_latency();
+        for &(bound, count) in &latency {
+            let le = if bound.is_infinite() {
+                "+Inf".to_string()
+            } else {
+                bound.to_string()
+            };
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        let count = latency.last().map_or(0, |x| x.1);
+        let _ = writeln!(
+            text,
+            "{}_sum {}",
+            name,
+            self.fetch_latency_sum().as_secs_f64()
+        );
+        let _ = writeln!(text, "{}_count {}", name, count);
+        text
+    }
+
+    pub(crate) fn add_command(&self) {
+        self.counters.commands_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes(&self, bytes: usize) {
+        self.counters
+            .bytes_downloaded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_parse(&self, ok: bool) {
+        let counter = if ok {
+            &self.counters.messages_parsed
+        } else {
+            &self.counters.parse_failures
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_fetch(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|&x| seconds <= x)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counters.latency_buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .latency_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+// 「a1 SELECT INBOX」のように、タグで始まる行ならコマンド
+// （APPEND で続けて送るメールの中身などは数えない）
+pub(crate) fn is_command(line: &[u8]) -> bool {
+    match line.iter().position(|&x| x == b' ') {
+        Some(end) => end > 0 && line[..end].iter().all(u8::is_ascii_alphanumeric),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_prometheus() {
+        let metrics = Metrics::new();
+        metrics.clone().add_command();
+        metrics.add_command();
+        metrics.add_bytes(1024);
+        metrics.add_parse(true);
+        metrics.add_parse(false);
+        metrics.add_fetch(Duration::from_millis(30));
+        metrics.add_fetch(Duration::from_secs(20));
+
+        assert_eq!(metrics.commands_sent(), 2);
+        assert_eq!(metrics.fetch_latency()[3], (0.05, 1));
+        assert_eq!(metrics.fetch_latency().last(), Some(&(f64::INFINITY, 2)));
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("read_mail_commands_sent_total 2\n"));
+        assert!(text.contains("read_mail_bytes_downloaded_total 1024\n"));
+        assert!(text.contains("read_mail_parse_failures_total 1\n"));
+        assert!(text.contains("read_mail_fetch_latency_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(text.contains("read_mail_fetch_latency_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("read_mail_fetch_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("read_mail_fetch_latency_seconds_sum 20.03\n"));
+        assert!(text.contains("read_mail_fetch_latency_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn detect_command_lines() {
+        assert!(is_command(b"a12 UID FETCH 1 BODY.PEEK[]\r\n"));
+        assert!(!is_command(b"From: taro@example.com\r\n"));
+        assert!(!is_command(b"\r\n"));
+    }
+}
@@ -0,0 +1,199 @@
+// メールの MIME パートの木（MyMessage::mime_tree）
+// 本文や添付ファイルの選び方を自分で決めたいときに、raw を解析し直さずに使う
+
+use std::collections::BTreeMap;
+
+use mailparse::body::Body;
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail};
+
+#[derive(Debug, Clone)]
+pub struct MimePart {
+    path: String,
+    mimetype: String,
+    params: BTreeMap<String, String>,
+    disposition: Option<String>,
+    filename: Option<String>,
+    headers: Vec<(String, String)>,
+    size: usize,
+    children: Vec<MimePart>,
+}
+
+impl MimePart {
+    // IMAP の BODY[...] で使うパートの番号（「1」「2.1」など、一番外側は空）
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Content-Type（小文字、なければ text/plain）
+    pub fn mimetype(&self) -> &str {
+        &self.mimetype
+    }
+
+    // Content-Type のパラメーター（名前は小文字）
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn params(&self) -> &BTreeMap<String, String> {
+        &self.params
+    }
+
+    // Content-Disposition の種類（「inline」「attachment」など小文字、ヘッダーがなければ None）
+    pub fn disposition(&self) -> Option<&str> {
+        self.disposition.as_deref()
+    }
+
+    // Content-Disposition の filename、なければ Content-Type の name
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    // このパートのヘッダー（名前と、デコードした値、書かれていた順）
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    // 名前が name の最初のヘッダー（大文字・小文字は区別しない）
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // 本文のバイト数（Content-Transfer-Encoding を解除する前、multipart なら中のパートも含む）
+    // ReadOptions::large_parts_to_disk で書き出したパートは 0
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // multipart の中のパート
+    pub fn children(&self) -> &[MimePart] {
+        &self.children
+    }
+
+    // 自分と、その中のすべてのパート（深さ優先、メールに書かれていた順）
+    pub fn parts(&self) -> Vec<&MimePart> {
+        let mut parts = vec![self];
+        for child in &self.children {
+            parts.extend(child.parts());
+        }
+        parts
+    }
+
+    // path のパート
+    pub fn find(&self, path: &str) -> Option<&MimePart> {
+        self.parts().into_iter().find(|x| x.path == path)
+    }
+}
+
+pub(crate) fn build(mail: &ParsedMail) -> MimePart {
+    build_part(mail, String::new())
+}
+
+fn build_part(part: &ParsedMail, path: String) -> MimePart {
+    let content_disposition = part.get_content_disposition();
+    let disposition = part
+        .headers
+        .get_first_value("Content-Disposition")
+        .map(|_| match &content_disposition.disposition {
+            DispositionType::Inline => "inline".to_string(),
+            DispositionType::Attachment => "attachment".to_string(),
+            DispositionType::FormData => "form-data".to_string(),
+            DispositionType::Extension(x) => x.clone(),
+        });
+    let filename = content_disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned();
+    let size = match part.get_body_encoded() {
+        Body::Base64(body) | Body::QuotedPrintable(body) => body.get_raw().len(),
+        Body::SevenBit(body) | Body::EightBit(body) => body.get_raw().len(),
+        Body::Binary(body) => body.get_raw().len(),
+    };
+    let children = part
+        .subparts
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let number = (i + 1).to_string();
+            let path = if path.is_empty() {
+                number
+            } else {
+                format!("{}.{}", path, number)
+            };
+            build_part(x, path)
+        })
+        .collect();
+
+    MimePart {
+        path,
+        mimetype: part.ctype.mimetype.clone(),
+        params: part.ctype.params.clone(),
+        disposition,
+        filename,
+        headers: part
+            .headers
+            .iter()
+            .map(|x| (x.get_key(), x.get_value()))
+            .collect(),
+        size,
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ReadOptions;
+
+    #[test]
+    fn build_mime_tree() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: s\r\n\
+                   Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+                   \r\n\
+                   --outer\r\n\
+                   Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+                   \r\n\
+                   --inner\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   \r\n\
+                   hello\r\n\
+                   --inner\r\n\
+                   Content-Type: text/html\r\n\
+                   \r\n\
+                   <p>hello</p>\r\n\
+                   --inner--\r\n\
+                   --outer\r\n\
+                   Content-Type: application/pdf\r\n\
+                   Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+                   Content-Transfer-Encoding: base64\r\n\
+                   \r\n\
+                   JVBERi0xLjQ=\r\n\
+                   --outer--\r\n";
+        let message = crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        let tree = message.mime_tree();
+        assert_eq!(tree.path(), "");
+        assert_eq!(tree.mimetype(), "multipart/mixed");
+        assert_eq!(tree.param("Boundary"), Some("outer"));
+        assert_eq!(tree.header("subject"), Some("s"));
+        assert_eq!(tree.disposition(), None);
+        assert_eq!(
+            tree.parts().iter().map(|x| x.path()).collect::<Vec<_>>(),
+            ["", "1", "1.1", "1.2", "2"]
+        );
+
+        let text = tree.find("1.1").unwrap();
+        assert_eq!(text.mimetype(), "text/plain");
+        assert_eq!(text.param("charset"), Some("utf-8"));
+        assert_eq!(text.size(), "hello\r\n".len());
+        assert!(text.children().is_empty());
+
+        let pdf = tree.find("2").unwrap();
+        assert_eq!(pdf.disposition(), Some("attachment"));
+        assert_eq!(pdf.filename(), Some("report.pdf"));
+        assert_eq!(pdf.size(), "JVBERi0xLjQ=\r\n".len());
+        assert!(tree.find("3").is_none());
+    }
+}
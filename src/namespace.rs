@@ -0,0 +1,118 @@
+// フォルダー名の名前空間（NAMESPACE 拡張、RFC 2342）
+// 「INBOX.」で始まる Cyrus や Courier、「/」区切りの Dovecot など、サーバーごとの違いを吸収する
+
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox};
+
+// 接頭辞と階層の区切り文字（区切りのないサーバーでは None）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    prefix: String,
+    delimiter: Option<String>,
+}
+
+impl Namespace {
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn delimiter(&self) -> Option<&str> {
+        self.delimiter.as_deref()
+    }
+
+    // ["Projects", "2024"] のような階層から、この名前空間でのフォルダー名を作る
+    pub fn folder(&self, path: &[&str]) -> String {
+        format!(
+            "{}{}",
+            self.prefix,
+            path.join(self.delimiter.as_deref().unwrap_or(""))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Namespaces {
+    personal: Vec<Namespace>,
+    other_users: Vec<Namespace>,
+    shared: Vec<Namespace>,
+}
+
+impl Namespaces {
+    // 自分のフォルダー
+    pub fn personal(&self) -> &[Namespace] {
+        &self.personal
+    }
+
+    // ほかのユーザーのフォルダー
+    pub fn other_users(&self) -> &[Namespace] {
+        &self.other_users
+    }
+
+    // 共有フォルダー
+    pub fn shared(&self) -> &[Namespace] {
+        &self.shared
+    }
+}
+
+pub fn namespaces(mailbox: &MyMailbox) -> Result<Namespaces, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    if !imap_session.supports(Capability::Namespace)? {
+        return Err("NAMESPACE is not supported by the server".into());
+    }
+    let lines = imap_session.run_extension("NAMESPACE", &["NAMESPACE"])?;
+    imap_session.logout()?;
+
+    let line = lines.first().ok_or("no NAMESPACE response")?;
+    Ok(parse_namespaces(&session::parse_values(line)))
+}
+
+// 「* NAMESPACE (("" "/")) (("Other Users/" "/")) NIL」の「* NAMESPACE」より後
+fn parse_namespaces(values: &[Value]) -> Namespaces {
+    let list = |i: usize| {
+        values
+            .get(i)
+            .map(Value::as_list)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|x| match x.as_list() {
+                [prefix, delimiter, ..] => Some(Namespace {
+                    prefix: prefix.as_str()?.to_string(),
+                    delimiter: delimiter.as_str().map(str::to_string),
+                }),
+                _ => None,
+            })
+            .collect()
+    };
+    Namespaces {
+        personal: list(0),
+        other_users: list(1),
+        shared: list(2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_namespace_response() {
+        let values = session::parse_values(
+            b"((\"INBOX.\" \".\")) ((\"user.\" \".\")) ((\"\" \".\" \"X-PARAM\" (\"x\")))",
+        );
+        let namespaces = parse_namespaces(&values);
+        assert_eq!(namespaces.personal()[0].prefix(), "INBOX.");
+        assert_eq!(
+            namespaces.personal()[0].folder(&["Projects", "2024"]),
+            "INBOX.Projects.2024"
+        );
+        assert_eq!(namespaces.other_users()[0].prefix(), "user.");
+        assert_eq!(namespaces.shared()[0].delimiter(), Some("."));
+
+        let values = session::parse_values(b"((\"\" \"/\")) NIL NIL");
+        let namespaces = parse_namespaces(&values);
+        assert_eq!(namespaces.personal()[0].folder(&["Archive"]), "Archive");
+        assert!(namespaces.shared().is_empty());
+    }
+}
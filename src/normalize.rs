@@ -0,0 +1,135 @@
+// 本文（MyMessage::body）の正規化
+// 改行を LF にそろえる・Unicode NFC にする・ノーブレークスペースを普通の空白にする・タブを空白にする
+// 検索やテキスト処理をする側で、それぞれやり直さなくて済むようにする
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+
+// 既定ではなにもしない
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalize {
+    lf: bool,
+    nfc: bool,
+    collapse_nbsp: bool,
+    tab_width: Option<usize>,
+}
+
+impl Normalize {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // CRLF と CR だけの改行を LF にする
+    pub fn lf(mut self, lf: bool) -> Self {
+        self.lf = lf;
+        self
+    }
+
+    // 「か」と結合用の濁点（U+3099）のような組み合わせを、NFC の1文字（「が」）にする
+    pub fn nfc(mut self, nfc: bool) -> Self {
+        self.nfc = nfc;
+        self
+    }
+
+    // ノーブレークスペース（U+00A0 など）を含む空白の並びを、空白1つにする
+    // HTML メールから作られた本文の「&nbsp;&nbsp;」などを普通の空白にする
+    pub fn collapse_nbsp(mut self, collapse_nbsp: bool) -> Self {
+        self.collapse_nbsp = collapse_nbsp;
+        self
+    }
+
+    // タブを、次の width の倍数の桁までの空白にする
+    pub fn expand_tabs(mut self, width: usize) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    pub(crate) fn apply(&self, body: String) -> String {
+        let mut body = body;
+        if self.lf {
+            body = body.replace("\r\n", "\n").replace('\r', "\n");
+        }
+        if self.nfc {
+            body = ComposingNormalizerBorrowed::new_nfc()
+                .normalize(&body)
+                .into_owned();
+        }
+        if self.collapse_nbsp {
+            body = collapse_nbsp(&body);
+        }
+        if let Some(width) = self.tab_width {
+            body = expand_tabs(&body, width);
+        }
+        body
+    }
+}
+
+fn is_nbsp(c: char) -> bool {
+    matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+fn collapse_nbsp(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut run = String::new();
+    let flush = |run: &mut String, result: &mut String| {
+        if run.chars().any(is_nbsp) {
+            result.push(' ');
+        } else {
+            result.push_str(run);
+        }
+        run.clear();
+    };
+    for c in text.chars() {
+        if c == ' ' || is_nbsp(c) {
+            run.push(c);
+        } else {
+            flush(&mut run, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut run, &mut result);
+    result
+}
+
+fn expand_tabs(text: &str, width: usize) -> String {
+    if width == 0 {
+        return text.replace('\t', "");
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut column = 0;
+    for c in text.chars() {
+        match c {
+            '\t' => {
+                let spaces = width - column % width;
+                result.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
+            }
+            '\n' | '\r' => {
+                result.push(c);
+                column = 0;
+            }
+            _ => {
+                result.push(c);
+                column += 1;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_body() {
+        let body = "a\tbc\td\r\nか\u{3099}\u{a0}\u{a0}x  y\ret\u{a0}z".to_string();
+        assert_eq!(Normalize::new().apply(body.clone()), body);
+
+        let normalize = Normalize::new()
+            .lf(true)
+            .nfc(true)
+            .collapse_nbsp(true)
+            .expand_tabs(4);
+        assert_eq!(normalize.apply(body), "a   bc  d\nが x  y\net z");
+    }
+}
@@ -0,0 +1,210 @@
+use std::any::Any;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use regex::Regex;
+
+use crate::attachment::Scanner;
+use crate::handler::PartHandler;
+use crate::pipeline::{self, Stage};
+use crate::{AttachmentInfo, Dedup, MyMessage, Normalize, Order, ResumeToken, SortKey, Verdict};
+
+// 本文の前後の空白の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Trim {
+    // そのまま残す（アーカイブ向け）
+    Keep,
+    // 末尾の空白と改行だけを取り除く
+    #[default]
+    End,
+    // 先頭と末尾の空白と改行を取り除く
+    Both,
+}
+
+// read_mail_with_options に渡す、取得・解析ごとのオプション
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    pub(crate) strip_signature: bool,
+    pub(crate) sanitize_html: bool,
+    pub(crate) charset: Option<String>,
+    pub(crate) detect_charset: bool,
+    pub(crate) lenient: bool,
+    pub(crate) keep_flowed: bool,
+    pub(crate) folders: Vec<String>,
+    pub(crate) dedup: Option<Dedup>,
+    pub(crate) sort: Option<(SortKey, Order)>,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) resume: Option<ResumeToken>,
+    pub(crate) body_filter: Option<Regex>,
+    pub(crate) attachment_patterns: Vec<String>,
+    pub(crate) attachment_md5: bool,
+    pub(crate) scanner: Option<Scanner>,
+    pub(crate) part_handlers: Vec<PartHandler>,
+    pub(crate) normalize: Normalize,
+    pub(crate) trim: Trim,
+    pub(crate) max_body_len: Option<usize>,
+    pub(crate) large_parts: Option<(usize, PathBuf)>,
+    // 解析するメールについて、spill が書き出したファイル
+    pub(crate) spilled: Vec<PathBuf>,
+    pub(crate) stages: Vec<Stage>,
+}
+
+impl ReadOptions {
+    // 本文末尾の署名（「-- 」以降など）を取り除く
+    pub fn strip_signature(mut self, strip_signature: bool) -> Self {
+        self.strip_signature = strip_signature;
+        self
+    }
+
+    // HTML 本文からスクリプトや外部画像、危険な属性を取り除く
+    pub fn sanitize_html(mut self, sanitize_html: bool) -> Self {
+        self.sanitize_html = sanitize_html;
+        self
+    }
+
+    // 本文の charset を宣言に関係なくこの文字コードとして読む（"iso-2022-jp" など）
+    pub fn charset(mut self, charset: &str) -> Self {
+        self.charset = Some(charset.to_string());
+        self
+    }
+
+    // 宣言された charset で読めない（または明らかに違う）ときは文字コードを自動判定する
+    pub fn detect_charset(mut self, detect_charset: bool) -> Self {
+        self.detect_charset = detect_charset;
+        self
+    }
+
+    // ヘッダーや本文が読めなくてもエラーにせず、U+FFFD などで埋めて警告に記録する
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    // format=flowed の本文を、つなげずに送られてきた折り返しのまま残す
+    pub fn keep_flowed(mut self, keep_flowed: bool) -> Self {
+        self.keep_flowed = keep_flowed;
+        self
+    }
+
+    // MyMailbox の selection の代わりに、これらのフォルダーを順に読む
+    pub fn folders(mut self, folders: &[&str]) -> Self {
+        self.folders = folders.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
+    // Message-ID が同じメールを1通にまとめる
+    pub fn dedup(mut self, dedup: Dedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    // 結果を key の順に並べ替える（指定しなければフォルダーごとの UID 順）
+    pub fn sort_by(mut self, key: SortKey, order: Order) -> Self {
+        self.sort = Some((key, order));
+        self
+    }
+
+    // deadline を過ぎたら取得をやめ、それまでに読んだメールを DeadlineExceeded のエラーで返す
+    // （read_mail_with_options と read_new_mail、read_new_mail は読んだところまで store に保存する）
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // DeadlineExceeded::resume で止まったところの続きから読む
+    pub fn resume_from(mut self, resume: &ResumeToken) -> Self {
+        self.resume = Some(resume.clone());
+        self
+    }
+
+    // threshold バイトを超えるパートは、メモリに読み込まずに dir のファイルに書き出す
+    // （添付ファイルなら AttachmentInfo::path で分かる、MyMessage::raw からは中身が除かれる）
+    pub fn large_parts_to_disk(mut self, threshold: usize, dir: &Path) -> Self {
+        self.large_parts = Some((threshold, dir.to_path_buf()));
+        self
+    }
+
+    // 本文の前後の空白の扱い（既定では Trim::End）
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    // 本文を先頭から max_len 文字までにする（切り詰めたかは MyMessage::is_truncated で分かる）
+    pub fn max_body_len(mut self, max_len: usize) -> Self {
+        self.max_body_len = Some(max_len);
+        self
+    }
+
+    // 本文（MyMessage::body）の改行・Unicode の正規化形・空白をそろえる
+    pub fn normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    // 本文（デコードしたテキスト）が regex に当てはまるメールだけを返す
+    // サーバーの SEARCH TEXT は charset によっては当てにならないので、手元で探す
+    // 当てはまった位置は MyMessage::body_matches で分かる
+    pub fn filter_body(mut self, regex: Regex) -> Self {
+        self.body_filter = Some(regex);
+        self
+    }
+
+    // これらに当てはまる添付ファイルだけをデコードする（本文に埋め込まれた画像などは読まない）
+    // 「application/pdf」「image/*」は MIME タイプ、「*.xlsx」はファイル名と比べる
+    pub fn attachments_matching(mut self, patterns: &[&str]) -> Self {
+        self.attachment_patterns = patterns.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
+    // 添付ファイルの SHA-256 に加えて MD5 も計算する（MD5 でしか引けない脅威情報もある）
+    pub fn attachment_md5(mut self, attachment_md5: bool) -> Self {
+        self.attachment_md5 = attachment_md5;
+        self
+    }
+
+    // デコードした添付ファイルごとに scan を呼び、隔離（Verdict::Quarantine）や
+    // メール全体のエラー（Verdict::Reject）にできるようにする
+    pub fn scan_attachments<F>(mut self, scan: F) -> Self
+    where
+        F: Fn(&AttachmentInfo, &[u8]) -> Verdict + Send + Sync + 'static,
+    {
+        self.scanner = Some(Scanner::new(scan));
+        self
+    }
+
+    // MIME タイプが pattern（「text/csv」「application/*+json」など）に当てはまるパートを、
+    // デコードした中身で handle に渡し、返した値を MyMessage::payloads に付ける
+    pub fn handle_part<T, F>(mut self, pattern: &str, handle: F) -> Self
+    where
+        T: Any + Send + Sync,
+        F: Fn(&[u8]) -> Result<T, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.part_handlers.push(PartHandler::new(pattern, handle));
+        self
+    }
+
+    // 取得して解析したメールを、登録した順にこれらの stage に通す
+    // stage が None を返したメールは結果に含めない
+    pub fn stage<F>(mut self, stage: F) -> Self
+    where
+        F: Fn(MyMessage) -> Option<MyMessage> + Send + Sync + 'static,
+    {
+        self.stages.push(Stage::new(stage));
+        self
+    }
+
+    // filter_body を指定したときは、当てはまったメールだけを残す
+    pub(crate) fn keeps(&self, message: &MyMessage) -> bool {
+        self.body_filter.is_none() || !message.body_matches.is_empty()
+    }
+
+    // keeps で残したメールを stage に通す
+    pub(crate) fn process(&self, message: MyMessage) -> Option<MyMessage> {
+        if !self.keeps(&message) {
+            return None;
+        }
+        pipeline::run(&self.stages, message)
+    }
+}
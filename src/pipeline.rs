@@ -0,0 +1,69 @@
+// 取得したメールに順に通す処理（ReadOptions::stage で登録する）
+// 情報の追加・絞り込み・伏せ字などを、使う側のループに毎回書かずに組み合わせられる
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::MyMessage;
+
+type StageFn = dyn Fn(MyMessage) -> Option<MyMessage> + Send + Sync;
+
+// None を返せば、そのメールは結果に含めない（後の stage にも渡さない）
+#[derive(Clone)]
+pub(crate) struct Stage(Arc<StageFn>);
+
+impl fmt::Debug for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Stage(..)")
+    }
+}
+
+impl Stage {
+    pub(crate) fn new<F>(stage: F) -> Self
+    where
+        F: Fn(MyMessage) -> Option<MyMessage> + Send + Sync + 'static,
+    {
+        Self(Arc::new(stage))
+    }
+}
+
+// 登録した順に通す
+pub(crate) fn run(stages: &[Stage], message: MyMessage) -> Option<MyMessage> {
+    stages
+        .iter()
+        .try_fold(message, |message, stage| (stage.0)(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    #[test]
+    fn run_stages() {
+        let raw = "From: taro@example.com\r\n\
+                   Subject: card 4111-1111-1111-1111\r\n\
+                   \r\n\
+                   hello\r\n";
+        let message = crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+
+        let redact = Stage::new(|mut message: MyMessage| {
+            let subject = message.subject().replace("4111-1111-1111-1111", "****");
+            message.set_subject(subject);
+            message.annotate("redacted", "subject");
+            Some(message)
+        });
+        let only_taro = Stage::new(|message: MyMessage| {
+            Some(message).filter(|x| x.from() == "taro@example.com")
+        });
+        let only_jiro = Stage::new(|message: MyMessage| {
+            Some(message).filter(|x| x.from() == "jiro@example.com")
+        });
+
+        let message = run(&[redact.clone(), only_taro], message.clone()).unwrap();
+        assert_eq!(message.subject(), "card ****");
+        assert_eq!(message.annotation("redacted"), Some("subject"));
+        assert!(run(&[redact, only_jiro], message.clone()).is_none());
+        assert!(run(&[], message).is_some());
+    }
+}
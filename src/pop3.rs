@@ -0,0 +1,343 @@
+// POP3（RFC 1939）でメールを読む（POP3 しか使えない古いプロバイダー用）
+// 接続は IMAP と同じく TLS（POP3S、普通はポート 995）か tunnel のコマンドで、
+// MyMailbox の host・port・user・password を使う
+// UIDL を覚えておけば、前回までに読んだメールを飛ばして新しいメールだけを読める
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use crate::transport::Transport;
+use crate::{MyMailbox, MyMessage, ReadOptions};
+
+// 読んだメールの UIDL を覚えておく
+pub trait UidlStore {
+    fn contains(&mut self, uidl: &str) -> Result<bool, Box<dyn Error>>;
+    fn insert(&mut self, uidl: &str) -> Result<(), Box<dyn Error>>;
+}
+
+// プロセスの中だけで保持する
+impl UidlStore for HashSet<String> {
+    fn contains(&mut self, uidl: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(HashSet::contains(self, uidl))
+    }
+
+    fn insert(&mut self, uidl: &str) -> Result<(), Box<dyn Error>> {
+        HashSet::insert(self, uidl.to_string());
+        Ok(())
+    }
+}
+
+// 1行に1つ UIDL を書いたテキストファイル
+#[derive(Debug)]
+pub struct FileUidlStore {
+    path: PathBuf,
+    uidls: Option<HashSet<String>>,
+}
+
+impl FileUidlStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            uidls: None,
+        }
+    }
+
+    fn uidls(&mut self) -> Result<&mut HashSet<String>, Box<dyn Error>> {
+        if self.uidls.is_none() {
+            let text = match fs::read_to_string(&self.path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            self.uidls = Some(text.lines().map(str::to_string).collect());
+        }
+        Ok(self.uidls.get_or_insert_with(HashSet::new))
+    }
+}
+
+impl UidlStore for FileUidlStore {
+    fn contains(&mut self, uidl: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(HashSet::contains(self.uidls()?, uidl))
+    }
+
+    fn insert(&mut self, uidl: &str) -> Result<(), Box<dyn Error>> {
+        if HashSet::insert(self.uidls()?, uidl.to_string()) {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", uidl)?;
+        }
+        Ok(())
+    }
+}
+
+// すべてのメールを読む（サーバーからは消さない）
+// MyMessage の folder は「INBOX」、uid はメッセージ番号になる
+pub fn read_pop3(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    read_pop3_with(mailbox, options, None)
+}
+
+// store にない UIDL のメールだけを読み、読んだものを store に加える
+pub fn read_new_pop3(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    store: &mut dyn UidlStore,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    read_pop3_with(mailbox, options, Some(store))
+}
+
+fn read_pop3_with(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    store: Option<&mut dyn UidlStore>,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let mut pop3 = connect(mailbox)?;
+    pop3.command(&format!("USER {}", mailbox.user))?;
+    pop3.command(&format!("PASS {}", mailbox.password))?;
+
+    let mut messages = read_all(&mut pop3, options, store)?;
+    if let Some(strategy) = &options.dedup {
+        messages = crate::dedup::dedup(messages, strategy);
+    }
+    if let Some((key, order)) = options.sort {
+        crate::sort::sort(&mut messages, key, order);
+    }
+    Ok(messages)
+}
+
+// 読んだメールの UIDL は、QUIT まで済んでから store に加える
+// （途中で失敗すれば何も加えないので、次に read_new_pop3 したときに読み直せる）
+fn read_all<S: Read + Write>(
+    pop3: &mut Pop3<S>,
+    options: &ReadOptions,
+    mut store: Option<&mut dyn UidlStore>,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let mut messages = Vec::new();
+    let mut read = Vec::new();
+    for (number, uidl) in pop3.uidl()? {
+        if let Some(store) = store.as_mut() {
+            if store.contains(&uidl)? {
+                continue;
+            }
+        }
+        let raw = pop3.retr(number)?;
+        let message = crate::parse_fetched(&raw, "INBOX", number, options)?;
+        messages.extend(options.process(message));
+        read.push(uidl);
+    }
+    pop3.command("QUIT")?;
+
+    if let Some(store) = store {
+        for uidl in &read {
+            store.insert(uidl)?;
+        }
+    }
+    Ok(messages)
+}
+
+// IMAP と同じく、接続できなければ fallback のサーバーを順に試す
+fn connect(mailbox: &MyMailbox) -> Result<Pop3<Transport>, Box<dyn Error>> {
+    let mut errors = Vec::new();
+    for (host, port) in mailbox.servers() {
+        match Transport::open(mailbox, host, port).and_then(Pop3::connect) {
+            Ok(pop3) => return Ok(pop3),
+            Err(e) => errors.push(format!("{}:{}: {}", host, port, e)),
+        }
+    }
+    Err(errors.join("; ").into())
+}
+
+struct Pop3<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Pop3<S> {
+    // 「+OK」の挨拶を読む
+    fn connect(stream: S) -> Result<Self, Box<dyn Error>> {
+        let mut pop3 = Self {
+            stream: BufReader::new(stream),
+        };
+        pop3.status()?;
+        Ok(pop3)
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, Box<dyn Error>> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.status()
+    }
+
+    // 「+OK ...」なら「+OK 」より後を、「-ERR ...」ならエラーを返す
+    fn status(&mut self) -> Result<String, Box<dyn Error>> {
+        let line = self.read_line()?;
+        let line = String::from_utf8_lossy(&line);
+        match line.strip_prefix("+OK") {
+            Some(rest) => Ok(rest.trim().to_string()),
+            None => Err(format!("POP3 error: {}", line.trim()).into()),
+        }
+    }
+
+    // 「.」だけの行までの複数行の応答（行頭の「..」は「.」に戻す）
+    fn multiline(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(data);
+            }
+            data.extend_from_slice(line.strip_prefix(b".").unwrap_or(&line));
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line)? == 0 {
+            return Err("POP3 connection closed".into());
+        }
+        Ok(line)
+    }
+
+    // （メッセージ番号, UIDL）の一覧
+    fn uidl(&mut self) -> Result<Vec<(u32, String)>, Box<dyn Error>> {
+        self.command("UIDL")?;
+        let data = self.multiline()?;
+        let mut list = Vec::new();
+        for line in String::from_utf8_lossy(&data).lines() {
+            let mut words = line.split_whitespace();
+            if let (Some(number), Some(uidl)) = (words.next(), words.next()) {
+                list.push((number.parse()?, uidl.to_string()));
+            }
+        }
+        Ok(list)
+    }
+
+    fn retr(&mut self, number: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.command(&format!("RETR {}", number))?;
+        self.multiline()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pop3_commands() {
+        let response = "+OK POP3 ready\r\n\
+                        +OK\r\n\
+                        1 whqtswO00WBw418f9t5JxYwZ\r\n\
+                        2 QhdPYR:00WBw1Ph7x7\r\n\
+                        .\r\n\
+                        +OK 48 octets\r\n\
+                        Subject: test\r\n\
+                        \r\n\
+                        ..hidden\r\n\
+                        .\r\n\
+                        -ERR no such message\r\n";
+        let stream = MockStream {
+            input: Cursor::new(response.as_bytes().to_vec()),
+            output: Vec::new(),
+        };
+        let mut pop3 = Pop3::connect(stream).unwrap();
+        assert_eq!(
+            pop3.uidl().unwrap(),
+            [
+                (1, "whqtswO00WBw418f9t5JxYwZ".to_string()),
+                (2, "QhdPYR:00WBw1Ph7x7".to_string())
+            ]
+        );
+        assert_eq!(
+            pop3.retr(1).unwrap(),
+            b"Subject: test\r\n\r\n.hidden\r\n".to_vec()
+        );
+        assert!(pop3.retr(3).is_err());
+        assert_eq!(
+            pop3.stream.get_ref().output,
+            b"UIDL\r\nRETR 1\r\nRETR 3\r\n".to_vec()
+        );
+    }
+
+    fn mock(response: &str) -> Pop3<MockStream> {
+        let stream = MockStream {
+            input: Cursor::new(response.as_bytes().to_vec()),
+            output: Vec::new(),
+        };
+        Pop3::connect(stream).unwrap()
+    }
+
+    #[test]
+    fn store_uidls_after_quit() {
+        let uidl = "+OK POP3 ready\r\n\
+                    +OK\r\n\
+                    1 a\r\n\
+                    2 b\r\n\
+                    .\r\n\
+                    +OK\r\n\
+                    From: taro@example.com\r\n\
+                    Subject: one\r\n\
+                    \r\n\
+                    1\r\n\
+                    .\r\n";
+        let options = ReadOptions::default();
+
+        // 2通目で失敗したら、1通目も store に加えない
+        let mut store = HashSet::new();
+        let mut pop3 = mock(&format!("{}-ERR no such message\r\n", uidl));
+        assert!(read_all(&mut pop3, &options, Some(&mut store)).is_err());
+        assert!(store.is_empty());
+
+        let response = format!(
+            "{}+OK\r\nFrom: taro@example.com\r\nSubject: two\r\n\r\n2\r\n.\r\n+OK bye\r\n",
+            uidl
+        );
+        let mut pop3 = mock(&response);
+        let messages = read_all(&mut pop3, &options, Some(&mut store)).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(UidlStore::contains(&mut store, "a").unwrap());
+        assert!(UidlStore::contains(&mut store, "b").unwrap());
+        assert_eq!(
+            pop3.stream.get_ref().output,
+            b"UIDL\r\nRETR 1\r\nRETR 2\r\nQUIT\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn uidl_store() {
+        let mut store = HashSet::new();
+        assert!(!UidlStore::contains(&mut store, "a").unwrap());
+        UidlStore::insert(&mut store, "a").unwrap();
+        assert!(UidlStore::contains(&mut store, "a").unwrap());
+    }
+}
@@ -0,0 +1,190 @@
+// 処理済みのメールを覚えておき、次の実行では飛ばす
+// 共有のメールボックスでフラグを変えられなくても、cron で何度動かしても同じメールを二度処理しない
+// メールは Message-ID で、Message-ID がなければ「フォルダー<TAB>UIDVALIDITY<TAB>UID」で見分ける
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use mailparse::{parse_headers, MailHeaderMap};
+
+use crate::session::MySession;
+use crate::{MyMailbox, MyMessage, ReadOptions};
+
+// 一度に Message-ID を取得するメールの数
+const CHUNK: usize = 500;
+
+// 処理済みのメールのキーを覚えておく
+pub trait ProcessedStore {
+    fn contains(&mut self, key: &str) -> Result<bool, Box<dyn Error>>;
+    fn insert(&mut self, key: &str) -> Result<(), Box<dyn Error>>;
+}
+
+// プロセスの中だけで保持する
+impl ProcessedStore for HashSet<String> {
+    fn contains(&mut self, key: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(HashSet::contains(self, key))
+    }
+
+    fn insert(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
+        HashSet::insert(self, key.to_string());
+        Ok(())
+    }
+}
+
+// 1行に1つキーを書いたテキストファイル
+#[derive(Debug)]
+pub struct FileProcessedStore {
+    path: PathBuf,
+    keys: Option<HashSet<String>>,
+}
+
+impl FileProcessedStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            keys: None,
+        }
+    }
+
+    fn keys(&mut self) -> Result<&mut HashSet<String>, Box<dyn Error>> {
+        if self.keys.is_none() {
+            let text = match fs::read_to_string(&self.path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            self.keys = Some(text.lines().map(str::to_string).collect());
+        }
+        Ok(self.keys.get_or_insert_with(HashSet::new))
+    }
+}
+
+impl ProcessedStore for FileProcessedStore {
+    fn contains(&mut self, key: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(HashSet::contains(self.keys()?, key))
+    }
+
+    fn insert(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
+        if HashSet::insert(self.keys()?, key.to_string()) {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{}", key)?;
+        }
+        Ok(())
+    }
+}
+
+// store にないメールだけを読み、読んだものを store に加える
+// 読んだ時点で処理済みにするので、そのあと処理に失敗しても次の実行では読まない
+pub fn read_unprocessed(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    store: &mut dyn ProcessedStore,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    crate::read_folders(mailbox, options, |imap_session, folder| {
+        fetch_unprocessed(imap_session, folder, options, store)
+    })
+}
+
+fn fetch_unprocessed(
+    imap_session: &mut MySession,
+    folder: &str,
+    options: &ReadOptions,
+    store: &mut dyn ProcessedStore,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let selected = imap_session.select(folder)?;
+    let uid_validity = selected.uid_validity.ok_or("no UIDVALIDITY")?;
+    let uids = crate::search_uids(imap_session)?;
+
+    // 本文を取得する前に、Message-ID だけで処理済みかどうかを調べる
+    let mut keys = Vec::new();
+    for chunk in uids.chunks(CHUNK) {
+        let throttle = imap_session.throttle().clone();
+        let fetches = throttle.run(|| {
+            imap_session.uid_fetch(
+                crate::uid_set(chunk),
+                "(UID BODY.PEEK[HEADER.FIELDS (MESSAGE-ID)])",
+            )
+        })?;
+        for fetch in fetches.iter() {
+            if let Some(uid) = fetch.uid {
+                let message_id = message_id(fetch.header().unwrap_or_default());
+                keys.push((uid, key(folder, uid_validity, uid, message_id.as_deref())));
+            }
+        }
+    }
+    keys.sort_unstable();
+
+    let mut messages = Vec::new();
+    for (uid, key) in keys {
+        if store.contains(&key)? {
+            continue;
+        }
+        messages.push(crate::fetch_message(imap_session, folder, uid, options)?);
+        store.insert(&key)?;
+    }
+    Ok(messages)
+}
+
+// MyMessage::message_id と同じく、<> を外した Message-ID
+pub(crate) fn message_id(header: &[u8]) -> Option<String> {
+    let (headers, _) = parse_headers(header).ok()?;
+    headers
+        .get_first_value("Message-ID")
+        .map(|x| {
+            x.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .filter(|x| !x.is_empty() && !x.contains(['\r', '\n']))
+}
+
+fn key(folder: &str, uid_validity: u32, uid: u32, message_id: Option<&str>) -> String {
+    match message_id {
+        Some(message_id) => message_id.to_string(),
+        None => format!("{}\t{}\t{}", folder, uid_validity, uid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn processed_keys() {
+        let header = b"Message-ID: <abc@example.com>\r\n\r\n";
+        let id = message_id(header);
+        assert_eq!(id.as_deref(), Some("abc@example.com"));
+        assert_eq!(key("INBOX", 7, 3, id.as_deref()), "abc@example.com");
+        assert_eq!(message_id(b"\r\n"), None);
+        assert_eq!(key("INBOX", 7, 3, None), "INBOX\t7\t3");
+    }
+
+    #[test]
+    fn file_processed_store() {
+        let path =
+            std::env::temp_dir().join(format!("read-mail-processed-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileProcessedStore::new(&path);
+        assert!(!store.contains("abc@example.com").unwrap());
+        store.insert("abc@example.com").unwrap();
+        store.insert("INBOX\t7\t3").unwrap();
+        store.insert("abc@example.com").unwrap();
+
+        let mut store = FileProcessedStore::new(&path);
+        assert!(store.contains("abc@example.com").unwrap());
+        assert!(store.contains("INBOX\t7\t3").unwrap());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "abc@example.com\nINBOX\t7\t3\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
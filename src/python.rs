@@ -0,0 +1,221 @@
+// PyO3 による Python モジュール（read_mail）
+// maturin でビルドすると、Python から read_mail・Session・Message を使える
+//
+//   import read_mail
+//   mailbox = read_mail.Mailbox("imap.example.com", "user", "password")
+//   for message in read_mail.read_mail(mailbox):
+//       print(message.subject)
+
+use std::error::Error;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::{MyMailbox, MyMessage, MySession, SecretString};
+
+fn py_err(e: Box<dyn Error>) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+// MyMailbox は文字列を借りるので、Python 側には持ち主になる構造体を渡す
+#[pyclass(name = "Mailbox", from_py_object)]
+#[derive(Debug, Clone)]
+struct PyMailbox {
+    host: String,
+    port: u16,
+    user: String,
+    password: SecretString,
+    selection: String,
+}
+
+#[pymethods]
+impl PyMailbox {
+    #[new]
+    #[pyo3(signature = (host, user, password, port = 993, selection = "INBOX"))]
+    fn new(host: &str, user: &str, password: &str, port: u16, selection: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            user: user.to_string(),
+            password: password.into(),
+            selection: selection.to_string(),
+        }
+    }
+}
+
+impl PyMailbox {
+    fn mailbox(&self) -> MyMailbox<'_> {
+        MyMailbox {
+            host: &self.host,
+            port: self.port,
+            user: &self.user,
+            password: self.password.expose(),
+            selection: &self.selection,
+            ..MyMailbox::default()
+        }
+    }
+}
+
+#[pyclass(name = "Message", frozen)]
+struct PyMessage(MyMessage);
+
+#[pymethods]
+impl PyMessage {
+    #[getter]
+    fn folder(&self) -> &str {
+        self.0.folder()
+    }
+
+    #[getter]
+    fn uid(&self) -> u32 {
+        self.0.uid()
+    }
+
+    #[getter]
+    fn message_id(&self) -> Option<&str> {
+        self.0.message_id()
+    }
+
+    // from は Python の予約語なので from_
+    #[getter]
+    fn from_(&self) -> &str {
+        self.0.from()
+    }
+
+    #[getter]
+    fn subject(&self) -> &str {
+        self.0.subject()
+    }
+
+    #[getter]
+    fn body(&self) -> &str {
+        self.0.body()
+    }
+
+    #[getter]
+    fn html(&self) -> Option<&str> {
+        self.0.html()
+    }
+
+    // （ファイル名, MIME タイプ, 中身）のリスト
+    #[getter]
+    fn attachments<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> Vec<(Option<String>, String, Bound<'py, PyBytes>)> {
+        self.0
+            .attachments()
+            .iter()
+            .map(|x| {
+                (
+                    x.filename().map(str::to_string),
+                    x.mimetype().to_string(),
+                    PyBytes::new(py, x.data()),
+                )
+            })
+            .collect()
+    }
+
+    #[getter]
+    fn raw<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.0.raw())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Message {} {:?}>", self.0.uid(), self.0.subject())
+    }
+}
+
+// 接続したままフォルダーを選んで、UID を指定して取得する
+// imap のセッションはスレッドをまたげないので unsendable
+#[pyclass(name = "Session", unsendable)]
+struct PySession {
+    session: Option<MySession>,
+    folder: String,
+}
+
+#[pymethods]
+impl PySession {
+    // ffi の read_mail_connect と同じく、mailbox の selection を選択しておく
+    #[new]
+    fn new(mailbox: &PyMailbox) -> PyResult<Self> {
+        let mut session = crate::connect(&mailbox.mailbox()).map_err(py_err)?;
+        session
+            .select(&mailbox.selection)
+            .map_err(|e| py_err(e.into()))?;
+        Ok(Self {
+            session: Some(session),
+            folder: mailbox.selection.clone(),
+        })
+    }
+
+    fn select(&mut self, folder: &str) -> PyResult<()> {
+        self.session()?
+            .select(folder)
+            .map_err(|e| py_err(e.into()))?;
+        self.folder = folder.to_string();
+        Ok(())
+    }
+
+    // 選択中のフォルダーの UID（昇順）
+    fn uids(&mut self) -> PyResult<Vec<u32>> {
+        crate::search_uids(self.session()?).map_err(py_err)
+    }
+
+    fn fetch(&mut self, uid: u32) -> PyResult<PyMessage> {
+        let folder = self.folder.clone();
+        crate::fetch_message(self.session()?, &folder, uid, &Default::default())
+            .map(PyMessage)
+            .map_err(py_err)
+    }
+
+    fn logout(&mut self) -> PyResult<()> {
+        if let Some(mut session) = self.session.take() {
+            session.logout().map_err(|e| py_err(e.into()))?;
+        }
+        Ok(())
+    }
+}
+
+impl PySession {
+    fn session(&mut self) -> PyResult<&mut MySession> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| PyRuntimeError::new_err("session is logged out"))
+    }
+}
+
+// selection のすべてのメールを読む（取得と解析の間は GIL を手放す）
+#[pyfunction(name = "read_mail")]
+fn py_read_mail(py: Python<'_>, mailbox: PyMailbox) -> PyResult<Vec<PyMessage>> {
+    let messages = py
+        .detach(|| crate::read_mail(&mailbox.mailbox()).map_err(|e| e.to_string()))
+        .map_err(PyRuntimeError::new_err)?;
+    Ok(messages.into_iter().map(PyMessage).collect())
+}
+
+#[pymodule]
+#[pyo3(name = "read_mail")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMailbox>()?;
+    m.add_class::<PyMessage>()?;
+    m.add_class::<PySession>()?;
+    m.add_function(wrap_pyfunction!(py_read_mail, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mailbox_defaults() {
+        let mailbox = PyMailbox::new("imap.example.com", "user", "password", 993, "INBOX");
+        let mailbox = mailbox.mailbox();
+        assert_eq!(mailbox.host, "imap.example.com");
+        assert_eq!(mailbox.port, 993);
+        assert_eq!(mailbox.selection, "INBOX");
+        assert!(mailbox.tunnel.is_none());
+    }
+}
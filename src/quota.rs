@@ -0,0 +1,104 @@
+// ストレージの使用量と上限（QUOTA 拡張、RFC 2087 / RFC 9208）
+// 容量がいっぱいになるとメールが届かなくなるので、監視に使う
+
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quota {
+    root: String,
+    resource: String,
+    usage: u64,
+    limit: u64,
+}
+
+impl Quota {
+    // クォータルート（サーバーによっては空文字列）
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    // 「STORAGE」（KB 単位）や「MESSAGE」（通数）
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    pub fn usage(&self) -> u64 {
+        self.usage
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    // 使用率（%）
+    pub fn percent(&self) -> f64 {
+        if self.limit == 0 {
+            100.0
+        } else {
+            self.usage as f64 * 100.0 / self.limit as f64
+        }
+    }
+}
+
+// mailbox.selection のフォルダーにかかっているクォータをすべて返す
+pub fn quota(mailbox: &MyMailbox) -> Result<Vec<Quota>, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    if !imap_session.supports(Capability::Quota)? {
+        return Err("QUOTA is not supported by the server".into());
+    }
+    let lines = imap_session.run_extension(
+        &format!("GETQUOTAROOT {}", crate::imap_quote(mailbox.selection)),
+        &["QUOTAROOT", "QUOTA"],
+    )?;
+    imap_session.logout()?;
+
+    // 「* QUOTAROOT」はフォルダーとルートの対応なので使わない
+    let mut quotas = Vec::new();
+    for line in &lines {
+        quotas.extend(parse_quota(&session::parse_values(line)));
+    }
+    Ok(quotas)
+}
+
+// 「* QUOTA "" (STORAGE 10 512 MESSAGE 3 1000)」の「* QUOTA」より後
+fn parse_quota(values: &[Value]) -> Vec<Quota> {
+    let (root, resources) = match values {
+        [root, resources] if root.as_str().is_some() => {
+            (root.as_str().unwrap_or_default(), resources)
+        }
+        _ => return Vec::new(),
+    };
+    resources
+        .as_list()
+        .chunks(3)
+        .filter_map(|x| match x {
+            [resource, usage, limit] => Some(Quota {
+                root: root.to_string(),
+                resource: resource.as_str()?.to_ascii_uppercase(),
+                usage: usage.as_str()?.parse().ok()?,
+                limit: limit.as_str()?.parse().ok()?,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quota_response() {
+        let values = session::parse_values(b"\"\" (STORAGE 10 512 MESSAGE 3 1000)");
+        let quotas = parse_quota(&values);
+        assert_eq!(quotas.len(), 2);
+        assert_eq!(quotas[0].resource(), "STORAGE");
+        assert_eq!(quotas[0].usage(), 10);
+        assert_eq!(quotas[0].limit(), 512);
+        assert_eq!(quotas[1].resource(), "MESSAGE");
+        assert!((quotas[1].percent() - 0.3).abs() < 1e-9);
+    }
+}
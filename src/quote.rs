@@ -0,0 +1,121 @@
+// 返信メールの本文から、引用部分（前のメールの内容）を取り除く
+
+// Outlook などの区切り線（これ以降はすべて前のメールの内容）
+const SEPARATORS: &[&str] = &[
+    "-----Original Message-----",
+    "----- Original Message -----",
+    "-----Ursprüngliche Nachricht-----",
+    "-----元のメッセージ-----",
+    "----- 元のメッセージ -----",
+    "---------- Forwarded message ---------",
+];
+
+// 残した行は、それぞれの改行（CRLF か LF）ごとそのまま返す
+pub(crate) fn strip_quotes(body: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim();
+
+        // 区切り線以降は捨てる
+        if SEPARATORS.iter().any(|x| trimmed.eq_ignore_ascii_case(x))
+            || is_outlook_underline(trimmed)
+        {
+            break;
+        }
+
+        // 「> ...」の引用行
+        if trimmed.starts_with('>') {
+            continue;
+        }
+
+        // 「On Mon, Jan 1, 2020 at 10:00 AM Foo <foo@example.com> wrote:」
+        // 「2020年1月1日(水) 10:00 Foo <foo@example.com>:」
+        if is_preamble(trimmed) {
+            // 長い前置きは「wrote:」だけが次の行に折り返されることがある
+            if trimmed.eq_ignore_ascii_case("wrote:") {
+                if let Some(last) = lines.last() {
+                    if last.trim_start().starts_with("On ") {
+                        lines.pop();
+                    }
+                }
+            }
+            continue;
+        }
+
+        lines.push(line);
+    }
+
+    // 前置きや引用の前後に残った空行を取り除く
+    while let Some(last) = lines.last() {
+        if last.trim().is_empty() {
+            lines.pop();
+        } else {
+            break;
+        }
+    }
+
+    // 最後の行の改行は含めない
+    let mut text = lines.concat();
+    let len = text.trim_end_matches(['\r', '\n']).len();
+    text.truncate(len);
+    text
+}
+
+fn is_preamble(line: &str) -> bool {
+    (line.starts_with("On ") && line.ends_with("wrote:"))
+        || line.eq_ignore_ascii_case("wrote:")
+        || line.ends_with("書きました:")
+        || line.ends_with("書きました：")
+        || (line.starts_with("Am ") && line.ends_with("schrieb:"))
+        || (line.starts_with("Le ") && line.ends_with("a écrit :"))
+        || is_japanese_date_preamble(line)
+}
+
+// Gmail の日本語表示「2020年1月1日(水) 10:00 Foo <foo@example.com>:」
+fn is_japanese_date_preamble(line: &str) -> bool {
+    line.contains('年') && line.contains('日') && (line.ends_with(">:") || line.ends_with(">："))
+}
+
+// Outlook の「________________________________」（この後に From: などが続く）
+fn is_outlook_underline(line: &str) -> bool {
+    line.len() >= 20 && line.chars().all(|x| x == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_gmail_style_quotes() {
+        let body = "Thanks, that works.\n\
+                    \n\
+                    On Mon, Jun 1, 2020 at 10:00 AM Taro <taro@example.com>\n\
+                    wrote:\n\
+                    \n\
+                    > Could you try again?\n\
+                    >\n\
+                    > > older text\n";
+        assert_eq!(strip_quotes(body), "Thanks, that works.");
+    }
+
+    #[test]
+    fn strip_interleaved_and_outlook_quotes() {
+        let body = "2020年6月1日(月) 10:00 Taro <taro@example.com>:\n\
+                    > first question\n\
+                    first answer\n\
+                    > second question\n\
+                    second answer\n\
+                    \n\
+                    -----Original Message-----\n\
+                    From: Taro\n\
+                    Sent: Monday\n";
+        assert_eq!(strip_quotes(body), "first answer\nsecond answer");
+    }
+
+    #[test]
+    fn keep_line_endings() {
+        let body = "first\r\nsecond\nthird\r\n\r\n> quoted\r\n";
+        assert_eq!(strip_quotes(body), "first\r\nsecond\nthird");
+    }
+}
@@ -0,0 +1,137 @@
+// 受信したメールへの返信を SMTP（lettre）で送る
+// 件名に「Re: 」を付け、In-Reply-To・References を設定してスレッドがつながるようにする
+
+use std::error::Error;
+use std::time::Duration;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::{MyMessage, SecretString};
+
+// 送信に使う SMTP サーバー
+// ポート 465 は最初から TLS、それ以外（587 など）は STARTTLS で接続する
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    host: String,
+    port: u16,
+    user: String,
+    password: SecretString,
+    from: String,
+    timeout: Duration,
+}
+
+impl SmtpConfig {
+    // from は返信の差出人（「山田 太郎 <taro@example.com>」の形式も可）
+    pub fn new(host: &str, user: &str, password: &str, from: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port: 465,
+            user: user.to_string(),
+            password: password.into(),
+            from: from.to_string(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+// message に body（テキスト）で返信する
+// 宛先は Reply-To があればそのアドレス、なければ From
+pub fn reply(message: &MyMessage, body: &str, smtp: &SmtpConfig) -> Result<(), Box<dyn Error>> {
+    let email = build_reply(message, body, smtp.from.parse()?)?;
+
+    let builder = if smtp.port == 465 {
+        SmtpTransport::relay(&smtp.host)?
+    } else {
+        SmtpTransport::starttls_relay(&smtp.host)?
+    };
+    let transport = builder
+        .port(smtp.port)
+        .credentials(Credentials::new(
+            smtp.user.clone(),
+            smtp.password.expose().to_string(),
+        ))
+        .timeout(Some(smtp.timeout))
+        .build();
+    transport.send(&email)?;
+    Ok(())
+}
+
+fn build_reply(message: &MyMessage, body: &str, from: Mailbox) -> Result<Message, Box<dyn Error>> {
+    let to = message.reply_to().unwrap_or_else(|| message.from());
+    let mut builder = Message::builder()
+        .from(from)
+        .to(to.parse()?)
+        .subject(reply_subject(message.subject()));
+
+    // References は元のメールの References の後ろに、元のメールの Message-ID を足す
+    if let Some(id) = message.message_id() {
+        let references = message
+            .references()
+            .iter()
+            .map(String::as_str)
+            .chain(Some(id))
+            .map(|x| format!("<{}>", x))
+            .collect::<Vec<_>>()
+            .join(" ");
+        builder = builder
+            .in_reply_to(format!("<{}>", id))
+            .references(references);
+    }
+
+    Ok(builder
+        .header(ContentType::TEXT_PLAIN)
+        .body(body.to_string())?)
+}
+
+// すでに「Re:」（「RE:」「re:」も）で始まっていれば重ねない
+fn reply_subject(subject: &str) -> String {
+    let trimmed = subject.trim_start();
+    if crate::subject::starts_with_prefix(trimmed, "re:") {
+        trimmed.to_string()
+    } else {
+        format!("Re: {}", trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_reply_headers() {
+        let raw = "From: taro@example.com\r\n\
+                   Reply-To: Support <support@example.com>\r\n\
+                   Message-ID: <2@example.com>\r\n\
+                   References: <1@example.com>\r\n\
+                   Subject: Question\r\n\
+                   \r\n\
+                   Hello\r\n";
+        let message = crate::parse(raw.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.reply_to(), Some("support@example.com"));
+        assert_eq!(message.references(), ["1@example.com"]);
+
+        let email = build_reply(&message, "Thanks", "bot@example.com".parse().unwrap()).unwrap();
+        let text = String::from_utf8(email.formatted()).unwrap();
+        assert!(text.contains("To: support@example.com\r\n"));
+        assert!(text.contains("Subject: Re: Question\r\n"));
+        assert!(text.contains("In-Reply-To: <2@example.com>\r\n"));
+        assert!(text.contains("References: <1@example.com> <2@example.com>\r\n"));
+
+        assert_eq!(reply_subject("RE: Question"), "RE: Question");
+        assert_eq!(reply_subject("A日本の件"), "Re: A日本の件");
+        assert_eq!(reply_subject("日本"), "Re: 日本");
+    }
+}
@@ -0,0 +1,57 @@
+// 保存期間を過ぎたメールの削除
+// 受信日（INTERNALDATE）が指定した期間より前のメールを探し、確認してから消せるように
+// dry_run ではどのメールが消えるかだけを返す
+
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone};
+
+use crate::MyMailbox;
+
+// folder にある、受信してから age より経ったメールの UID を返す
+// dry_run が false なら、それらを削除（EXPUNGE）する
+pub fn purge_older_than(
+    mailbox: &MyMailbox,
+    folder: &str,
+    age: Duration,
+    dry_run: bool,
+) -> Result<Vec<u32>, Box<dyn Error>> {
+    let query = format!("BEFORE {}", before_date(chrono::Local::now(), age)?);
+
+    let mut imap_session = crate::connect(mailbox)?;
+    if dry_run {
+        imap_session.examine(folder)?;
+    } else {
+        imap_session.select(folder)?;
+    }
+    let uids = crate::esearch::search(&mut imap_session, &query, "ALL")?.uids();
+    if !dry_run && !uids.is_empty() {
+        crate::delete_uids(&mut imap_session, &crate::uid_set(&uids))?;
+    }
+    imap_session.logout()?;
+    Ok(uids)
+}
+
+// SEARCH の BEFORE に使う「1-Feb-2024」の形の日付
+// BEFORE は日付単位なので、この日より前（この日は含まない）が対象になる
+fn before_date<Tz: TimeZone>(now: DateTime<Tz>, age: Duration) -> Result<String, Box<dyn Error>>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let date = now - chrono::Duration::from_std(age)?;
+    Ok(date.format("%-d-%b-%Y").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_before_date() {
+        let now = DateTime::parse_from_rfc3339("2024-03-01T09:00:00+09:00").unwrap();
+        let days = |n: u64| Duration::from_secs(n * 24 * 60 * 60);
+        assert_eq!(before_date(now, days(30)).unwrap(), "31-Jan-2024");
+        assert_eq!(before_date(now, days(0)).unwrap(), "1-Mar-2024");
+    }
+}
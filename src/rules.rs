@@ -0,0 +1,212 @@
+// Sieve のような振り分けルール
+// 条件はできるだけ IMAP の SEARCH に変換してサーバー側で絞り込み、
+// 移動・フラグ・削除も UID を指定してサーバー上で実行する
+
+use std::error::Error;
+use std::fmt;
+
+use crate::{Capability, MyMailbox, MyMessage, MySession, ReadOptions};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    // 差出人（From）にこの文字列を含む
+    From(String),
+    // 件名にこの文字列を含む
+    Subject(String),
+    // サイズ（バイト）がこれより大きい / 小さい
+    LargerThan(u32),
+    SmallerThan(u32),
+    // フラグ（"\\Seen" や "$Label1"）が付いている / 付いていない
+    HasFlag(String),
+    NotFlag(String),
+}
+
+pub enum Action {
+    // フォルダーへ移動する
+    Move(String),
+    // フラグを付ける
+    AddFlag(String),
+    // 削除する（\Deleted を付けて EXPUNGE）
+    Delete,
+    // 取得・解析したメールを渡して呼び出す
+    Callback(Box<dyn Fn(&MyMessage)>),
+}
+
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Move(folder) => f.debug_tuple("Move").field(folder).finish(),
+            Action::AddFlag(flag) => f.debug_tuple("AddFlag").field(flag).finish(),
+            Action::Delete => f.write_str("Delete"),
+            Action::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+// 条件はすべてを満たすもの（AND）、動作は追加した順に実行する
+#[derive(Debug, Default)]
+pub struct Rule {
+    conditions: Vec<Condition>,
+    actions: Vec<Action>,
+}
+
+impl Rule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn then(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+}
+
+// ルールを順に適用し、ルールごとに当てはまったメールの数を返す
+// 前のルールで移動・削除されたメールは、後のルールには当てはまらない
+pub fn apply_rules(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    rules: &[Rule],
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut counts = vec![0; rules.len()];
+    crate::read_folders(mailbox, options, |imap_session, folder| {
+        imap_session.select(folder)?;
+        for (rule, count) in rules.iter().zip(counts.iter_mut()) {
+            *count += apply_rule(imap_session, folder, options, rule)?;
+        }
+        Ok(Vec::new())
+    })?;
+    Ok(counts)
+}
+
+fn apply_rule(
+    imap_session: &mut MySession,
+    folder: &str,
+    options: &ReadOptions,
+    rule: &Rule,
+) -> Result<usize, Box<dyn Error>> {
+    let literal_plus = imap_session.supports(Capability::LiteralPlus)?;
+    let mut uids = imap_session
+        .uid_search(search_query(&rule.conditions, literal_plus))?
+        .into_iter()
+        .collect::<Vec<_>>();
+    if uids.is_empty() {
+        return Ok(0);
+    }
+    uids.sort_unstable();
+    let uid_set = crate::uid_set(&uids);
+
+    for action in &rule.actions {
+        match action {
+            Action::Move(to) => {
+                crate::move_uids(imap_session, &uid_set, to)?;
+            }
+            Action::AddFlag(flag) => {
+                imap_session.uid_store(&uid_set, format!("+FLAGS.SILENT ({})", flag))?;
+            }
+            Action::Delete => crate::delete_uids(imap_session, &uid_set)?,
+            Action::Callback(callback) => {
+                for &uid in &uids {
+                    callback(&crate::fetch_message(imap_session, folder, uid, options)?);
+                }
+            }
+        }
+    }
+
+    Ok(uids.len())
+}
+
+// 条件を UID SEARCH の検索条件に変換する
+// LITERAL+ が使えれば、ASCII 以外の文字列は quoted string ではなくリテラルで送る
+fn search_query(conditions: &[Condition], literal_plus: bool) -> String {
+    let string = |x: &str| {
+        if literal_plus && !x.is_ascii() {
+            crate::session::literal(x)
+        } else {
+            crate::imap_quote(x)
+        }
+    };
+    let criteria = conditions
+        .iter()
+        .map(|condition| match condition {
+            Condition::From(x) => format!("FROM {}", string(x)),
+            Condition::Subject(x) => format!("SUBJECT {}", string(x)),
+            Condition::LargerThan(x) => format!("LARGER {}", x),
+            Condition::SmallerThan(x) => format!("SMALLER {}", x),
+            Condition::HasFlag(x) => flag_criterion(x, true),
+            Condition::NotFlag(x) => flag_criterion(x, false),
+        })
+        .collect::<Vec<_>>();
+
+    let mut query = if criteria.is_empty() {
+        "ALL".to_string()
+    } else {
+        criteria.join(" ")
+    };
+    // 日本語などで検索するときは charset の指定が必要
+    if !query.is_ascii() {
+        query = format!("CHARSET UTF-8 {}", query);
+    }
+    query
+}
+
+fn flag_criterion(flag: &str, set: bool) -> String {
+    let system = match flag.to_ascii_lowercase().as_str() {
+        "\\seen" => Some(("SEEN", "UNSEEN")),
+        "\\answered" => Some(("ANSWERED", "UNANSWERED")),
+        "\\flagged" => Some(("FLAGGED", "UNFLAGGED")),
+        "\\deleted" => Some(("DELETED", "UNDELETED")),
+        "\\draft" => Some(("DRAFT", "UNDRAFT")),
+        _ => None,
+    };
+    match (system, set) {
+        (Some((x, _)), true) => x.to_string(),
+        (Some((_, x)), false) => x.to_string(),
+        (None, true) => format!("KEYWORD {}", flag),
+        (None, false) => format!("UNKEYWORD {}", flag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_search_query() {
+        assert_eq!(search_query(&[], false), "ALL");
+
+        let rule = Rule::new()
+            .when(Condition::From("news@example.com".to_string()))
+            .when(Condition::LargerThan(1_000_000))
+            .when(Condition::NotFlag("\\Seen".to_string()))
+            .when(Condition::HasFlag("$Newsletter".to_string()))
+            .then(Action::Move("News".to_string()));
+        assert_eq!(
+            search_query(rule.conditions(), false),
+            "FROM \"news@example.com\" LARGER 1000000 UNSEEN KEYWORD $Newsletter"
+        );
+
+        let conditions = [Condition::Subject("請求書".to_string())];
+        assert_eq!(
+            search_query(&conditions, false),
+            "CHARSET UTF-8 SUBJECT \"請求書\""
+        );
+        assert_eq!(
+            search_query(&conditions, true),
+            "CHARSET UTF-8 SUBJECT {9+}\r\n請求書"
+        );
+    }
+}
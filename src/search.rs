@@ -0,0 +1,346 @@
+// 取得したメールの全文検索（tantivy）
+// index_mailbox でインデックスを作成・更新し、search_local でオフラインに検索する
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{QueryParser, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, STORED, STRING,
+};
+use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+use tantivy::{Index, IndexWriter, TantivyDocument, Term};
+
+use crate::{deadline, DeadlineExceeded, MyMailbox, MyMessage, ReadOptions, ResumeToken};
+
+const TOKENIZER: &str = "read_mail";
+const WRITER_MEMORY: usize = 50_000_000;
+
+// 検索結果（フォルダーと UID、関連度の高い順）
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    folder: String,
+    uid: u32,
+    score: f32,
+}
+
+impl SearchHit {
+    pub fn folder(&self) -> &str {
+        &self.folder
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+// メールボックスの内容でインデックスを作成・更新する
+// すでにインデックスにあるメールはダウンロードせず、サーバーから消えたメールはインデックスからも消す
+// 戻り値は新しく追加したメールの数
+// ReadOptions::deadline を過ぎたら、それまでに追加した分をコミットして DeadlineExceeded を返す
+// （resume_from に渡せば続きから追加する）
+pub fn index_mailbox<P: AsRef<Path>>(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    index_dir: P,
+) -> Result<usize, Box<dyn Error>> {
+    let (index, fields) = open_index(index_dir.as_ref())?;
+    let mut writer: IndexWriter = index.writer(WRITER_MEMORY)?;
+    let searcher = index.reader()?.searcher();
+    let mut added = 0;
+
+    let result = crate::read_folders(mailbox, options, |imap_session, folder| {
+        let selected = imap_session.select(folder)?;
+        let uid_validity = selected.uid_validity.ok_or("no UIDVALIDITY")?;
+        let uids = crate::search_uids(imap_session)?;
+
+        // このフォルダーについて、インデックスにあるもの
+        let query = TermQuery::new(
+            Term::from_field_text(fields.folder, folder),
+            IndexRecordOption::Basic,
+        );
+        let mut indexed = HashSet::new();
+        for address in searcher.search(&query, &DocSetCollector)? {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            if let Some(key) = doc.get_first(fields.key).and_then(|x| x.as_str()) {
+                indexed.insert(key.to_string());
+            }
+        }
+
+        let keys = uids
+            .iter()
+            .map(|&uid| (uid, key(folder, uid_validity, uid)))
+            .collect::<Vec<_>>();
+        let current = keys.iter().map(|(_, x)| x.as_str()).collect::<HashSet<_>>();
+        for key in &indexed {
+            if !current.contains(key.as_str()) {
+                writer.delete_term(Term::from_field_text(fields.key, key));
+            }
+        }
+        // resume_from で続きから追加するときは、追加し終えた UID を飛ばす
+        let resumed = options
+            .resume
+            .as_ref()
+            .map_or(0, |x| x.last_uid_in(folder, uid_validity));
+        let mut last_uid = resumed;
+        for (uid, key) in keys.into_iter().filter(|(uid, _)| *uid > resumed) {
+            if indexed.contains(&key) {
+                last_uid = uid;
+                continue;
+            }
+            if deadline::is_exceeded(options) {
+                let resume = ResumeToken::new(folder, uid_validity, last_uid);
+                return Err(DeadlineExceeded::new(Vec::new(), resume).into());
+            }
+            let message = crate::fetch_message(imap_session, folder, uid, options)?;
+            add_document(&writer, &fields, &key, &message)?;
+            added += 1;
+            last_uid = uid;
+        }
+
+        // メッセージ自体は返さない（インデックスを作るだけ）
+        Ok(Vec::new())
+    });
+
+    writer.commit()?;
+    result?;
+    Ok(added)
+}
+
+// 「invoice 2023」「請求書」のような検索語で、関連度の高い順に limit 件まで返す
+// 複数の語はすべてを含むもの（AND）を探す
+pub fn search_local<P: AsRef<Path>>(
+    index_dir: P,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let (index, fields) = open_index(index_dir.as_ref())?;
+    let searcher = index.reader()?.searcher();
+
+    let mut parser = QueryParser::for_index(&index, vec![fields.from, fields.subject, fields.body]);
+    parser.set_conjunction_by_default();
+    let query = parser.parse_query(query)?;
+
+    let mut hits = Vec::new();
+    for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+        let doc: TantivyDocument = searcher.doc(address)?;
+        let folder = doc.get_first(fields.folder).and_then(|x| x.as_str());
+        let uid = doc.get_first(fields.uid).and_then(|x| x.as_u64());
+        if let (Some(folder), Some(uid)) = (folder, uid) {
+            hits.push(SearchHit {
+                folder: folder.to_string(),
+                uid: uid as u32,
+                score,
+            });
+        }
+    }
+    Ok(hits)
+}
+
+struct Fields {
+    key: Field,
+    folder: Field,
+    uid: Field,
+    from: Field,
+    subject: Field,
+    body: Field,
+}
+
+fn open_index(index_dir: &Path) -> Result<(Index, Fields), Box<dyn Error>> {
+    let text = TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(TOKENIZER)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    );
+    let mut builder = Schema::builder();
+    builder.add_text_field("key", STRING | STORED);
+    builder.add_text_field("folder", STRING | STORED);
+    builder.add_u64_field("uid", STORED);
+    builder.add_text_field("from", text.clone());
+    builder.add_text_field("subject", text.clone());
+    builder.add_text_field("body", text);
+    let schema = builder.build();
+
+    fs::create_dir_all(index_dir)?;
+    let index = Index::open_or_create(MmapDirectory::open(index_dir)?, schema)?;
+    index.tokenizers().register(TOKENIZER, MailTokenizer);
+
+    let schema = index.schema();
+    let fields = Fields {
+        key: schema.get_field("key")?,
+        folder: schema.get_field("folder")?,
+        uid: schema.get_field("uid")?,
+        from: schema.get_field("from")?,
+        subject: schema.get_field("subject")?,
+        body: schema.get_field("body")?,
+    };
+    Ok((index, fields))
+}
+
+// UIDVALIDITY が変われば別のメールとみなす
+fn key(folder: &str, uid_validity: u32, uid: u32) -> String {
+    format!("{}\0{}\0{}", folder, uid_validity, uid)
+}
+
+fn add_document(
+    writer: &IndexWriter,
+    fields: &Fields,
+    key: &str,
+    message: &MyMessage,
+) -> Result<(), Box<dyn Error>> {
+    let mut doc = TantivyDocument::default();
+    doc.add_text(fields.key, key);
+    doc.add_text(fields.folder, message.folder());
+    doc.add_u64(fields.uid, message.uid() as u64);
+    doc.add_text(fields.from, message.from());
+    doc.add_text(fields.subject, message.subject());
+    doc.add_text(fields.body, message.body());
+    writer.add_document(doc)?;
+    Ok(())
+}
+
+// 英数字は単語ごと、日本語（かな・漢字）は分かち書きできないので2文字ずつ区切る
+#[derive(Clone)]
+struct MailTokenizer;
+
+struct MailTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Tokenizer for MailTokenizer {
+    type TokenStream<'a> = MailTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> MailTokenStream {
+        MailTokenStream {
+            tokens: tokenize(text),
+            index: 0,
+        }
+    }
+}
+
+impl TokenStream for MailTokenStream {
+    fn advance(&mut self) -> bool {
+        self.index += 1;
+        self.index <= self.tokens.len()
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let end_of = |i: usize| chars.get(i).map_or(text.len(), |x| x.0);
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        if is_cjk(c) {
+            let mut j = i;
+            while j < chars.len() && is_cjk(chars[j].1) {
+                j += 1;
+            }
+            // 1文字だけならそのまま、2文字以上なら重なりのある2文字ずつ
+            if j - i == 1 {
+                push_token(&mut tokens, text, offset, end_of(j));
+            } else {
+                for (k, &(from, _)) in chars.iter().enumerate().take(j - 1).skip(i) {
+                    push_token(&mut tokens, text, from, end_of(k + 2));
+                }
+            }
+            i = j;
+        } else if c.is_alphanumeric() {
+            let mut j = i;
+            while j < chars.len() && chars[j].1.is_alphanumeric() && !is_cjk(chars[j].1) {
+                j += 1;
+            }
+            push_token(&mut tokens, text, offset, end_of(j));
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+fn push_token(tokens: &mut Vec<Token>, text: &str, from: usize, to: usize) {
+    tokens.push(Token {
+        offset_from: from,
+        offset_to: to,
+        position: tokens.len(),
+        text: text[from..to].to_lowercase(),
+        position_length: 1,
+    });
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // ひらがな・カタカナ
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}' // 漢字
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF66}'..='\u{FF9F}' // 半角カナ
+        | '\u{AC00}'..='\u{D7AF}') // ハングル
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_mixed_text() {
+        let tokens = tokenize("Invoice 2023年の請求書");
+        let texts = tokens.iter().map(|x| x.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["invoice", "2023", "年の", "の請", "請求", "求書"]);
+    }
+
+    #[test]
+    fn index_and_search() {
+        let dir = std::env::temp_dir().join(format!("read-mail-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let (index, fields) = open_index(&dir).unwrap();
+        let mut writer: IndexWriter = index.writer(WRITER_MEMORY).unwrap();
+        let messages = [
+            (1, "Invoice 2023", "Please find the invoice attached."),
+            (2, "Lunch", "See you at noon."),
+            (3, "請求書の送付", "2023年分の請求書をお送りします。"),
+        ];
+        for (uid, subject, body) in messages.iter() {
+            let raw = format!(
+                "From: taro@example.com\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+                subject, body
+            );
+            let message =
+                crate::parse_fetched(raw.as_bytes(), "INBOX", *uid, &ReadOptions::default())
+                    .unwrap();
+            add_document(&writer, &fields, &key("INBOX", 1, *uid), &message).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let hits = search_local(&dir, "invoice 2023", 10).unwrap();
+        assert_eq!(hits.iter().map(|x| x.uid()).collect::<Vec<_>>(), [1]);
+
+        let hits = search_local(&dir, "請求書", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].folder(), "INBOX");
+        assert_eq!(hits[0].uid(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+// パスワードやトークンを持つ文字列
+// Debug では中身を出さず、捨てるときにメモリを 0 で上書きするので、
+// ログやコアダンプにパスワードが残りにくい
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{compiler_fence, Ordering};
+
+#[derive(Clone, Default, PartialEq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    // 中身（MyMailbox の password に渡すときなど、必要なときだけ取り出す）
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // 0 は UTF-8 としても正しいので、String のまま上書きしてよい
+        // 最適化で消されないように volatile で書く
+        unsafe {
+            for byte in self.0.as_bytes_mut() {
+                std::ptr::write_volatile(byte, 0);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+// 端末に prompt を出して、入力を表示せずにパスワードを読む
+pub fn prompt_password(prompt: &str) -> Result<SecretString, Box<dyn Error>> {
+    let mut stderr = io::stderr();
+    stderr.write_all(prompt.as_bytes())?;
+    stderr.flush()?;
+
+    let echo = Echo::off();
+    let mut line = SecretString::default();
+    let read = io::stdin().lock().read_line(&mut line.0);
+    drop(echo);
+    stderr.write_all(b"\n")?;
+    read?;
+
+    let length = line.0.trim_end_matches(&['\r', '\n'][..]).len();
+    line.0.truncate(length);
+    Ok(line)
+}
+
+// 端末の表示（エコー）を止めておき、drop で戻す
+struct Echo {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    disabled: bool,
+}
+
+impl Echo {
+    #[cfg(unix)]
+    fn off() -> Self {
+        Self {
+            disabled: stty("-echo"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn off() -> Self {
+        Self { disabled: false }
+    }
+}
+
+impl Drop for Echo {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.disabled {
+            stty("echo");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn stty(mode: &str) -> bool {
+    std::fs::File::open("/dev/tty")
+        .and_then(|tty| {
+            std::process::Command::new("stty")
+                .arg(mode)
+                .stdin(tty)
+                .status()
+        })
+        .is_ok_and(|x| x.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hide_secret() {
+        let secret = SecretString::from("パスワード");
+        assert_eq!(secret.expose(), "パスワード");
+        assert_eq!(format!("{:?}", secret), "SecretString(***)");
+    }
+}
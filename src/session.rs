@@ -0,0 +1,796 @@
+// IMAP の接続
+// imap クレートは QUOTA や NAMESPACE などの拡張の応答を解釈できず、受け取るとエラーになって
+// 以降のやり取りもずれてしまう。そこで接続を包んで、そうした応答の行を imap クレートに渡る前に横取りする
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use imap::extensions::idle::SetReadTimeout;
+
+use crate::auth::Authenticator;
+use crate::capability::{Capabilities, Capability};
+use crate::metrics::Metrics;
+use crate::throttle::Throttle;
+use crate::transport::{Security, Transport};
+use crate::wire::WireTap;
+use crate::MyMailbox;
+
+// 「COPYUID 38505 304 3956」のような応答コードの中身
+type ResponseCode = Vec<u8>;
+
+#[derive(Debug, Default)]
+struct Capture {
+    // 横取りする応答の名前（「QUOTA」など、大文字）
+    names: Vec<String>,
+    lines: Vec<Vec<u8>>,
+    // OK 応答の応答コード（「[COPYUID ...]」の中身）を集める
+    record_codes: bool,
+    codes: Vec<ResponseCode>,
+    // PREAUTH のときは imap クレートが送る LOGIN をサーバーに送らず、自分で OK を返す
+    fake_login: bool,
+}
+
+pub(crate) struct CaptureStream<S: Read + Write> {
+    inner: BufReader<S>,
+    capture: Arc<Mutex<Capture>>,
+    // imap クレートに渡す途中の行
+    pending: Vec<u8>,
+    position: usize,
+    // fake_login のときに送らなかった行
+    written: Vec<u8>,
+    // 次に書く内容が行の始めか（コマンドを数えるため）
+    line_start: bool,
+    metrics: Option<Metrics>,
+    wire: Option<WireTap>,
+}
+
+impl<S: Read + Write> CaptureStream<S> {
+    fn new(inner: S, capture: Arc<Mutex<Capture>>) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            capture,
+            pending: Vec::new(),
+            position: 0,
+            written: Vec::new(),
+            line_start: true,
+            metrics: None,
+            wire: None,
+        }
+    }
+
+    fn add_bytes(&self, bytes: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes(bytes);
+        }
+    }
+
+    // 1行（途中の {n} のリテラルも含めて）を読む
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            let n = self.inner.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.add_bytes(n);
+            match literal_length(&line) {
+                Some(length) => {
+                    let start = line.len();
+                    line.resize(start + length, 0);
+                    self.inner.read_exact(&mut line[start..])?;
+                    self.add_bytes(length);
+                }
+                None => {
+                    if let Some(wire) = &mut self.wire {
+                        wire.received(&line);
+                    }
+                    return Ok(line);
+                }
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Read for CaptureStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.position);
+                buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+                self.position += n;
+                return Ok(n);
+            }
+            let capturing = self
+                .capture
+                .lock()
+                .is_ok_and(|x| !x.names.is_empty() || x.record_codes);
+            if !capturing {
+                let n = self.inner.read(buf)?;
+                self.add_bytes(n);
+                if let Some(wire) = &mut self.wire {
+                    wire.received(&buf[..n]);
+                }
+                return Ok(n);
+            }
+
+            let line = self.read_line()?;
+            let mut capture = self
+                .capture
+                .lock()
+                .map_err(|_| io::Error::other("capture lock poisoned"))?;
+            if capture.record_codes {
+                capture.codes.extend(response_code(&line));
+            }
+            if is_captured(&line, &capture.names) {
+                capture.lines.push(line);
+            } else {
+                self.pending = line;
+                self.position = 0;
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for CaptureStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut capture = self
+            .capture
+            .lock()
+            .map_err(|_| io::Error::other("capture lock poisoned"))?;
+        if !capture.fake_login {
+            let n = self.inner.get_mut().write(buf)?;
+            if let Some(metrics) = &self.metrics {
+                if self.line_start && crate::metrics::is_command(&buf[..n]) {
+                    metrics.add_command();
+                }
+            }
+            self.line_start = buf[..n].ends_with(b"\r\n");
+            if let Some(wire) = &mut self.wire {
+                wire.sent(&buf[..n]);
+            }
+            return Ok(n);
+        }
+        // 「a1 LOGIN ...」の行が揃ったら、送らずに「a1 OK」を返す
+        self.written.extend_from_slice(buf);
+        if self.written.ends_with(b"\r\n") {
+            let tag = self
+                .written
+                .split(|&x| x == b' ')
+                .next()
+                .unwrap_or_default();
+            let mut response = tag.to_vec();
+            response.extend_from_slice(b" OK PREAUTH\r\n");
+            self.pending = response;
+            self.position = 0;
+            self.written.clear();
+            capture.fake_login = false;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.get_mut().flush()
+    }
+}
+
+impl SetReadTimeout for CaptureStream<Transport> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+        Ok(self.inner.get_mut().set_read_timeout(timeout)?)
+    }
+}
+
+// imap::Session にそのまま使えるうえ、拡張のコマンドも送れる
+pub(crate) struct MySession {
+    session: imap::Session<CaptureStream<Transport>>,
+    capture: Arc<Mutex<Capture>>,
+    // 最初に CAPABILITY を送ったときの結果
+    capabilities: Option<Capabilities>,
+    metrics: Option<Metrics>,
+    throttle: Throttle,
+}
+
+impl Deref for MySession {
+    type Target = imap::Session<CaptureStream<Transport>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.session
+    }
+}
+
+impl DerefMut for MySession {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.session
+    }
+}
+
+impl MySession {
+    fn new(
+        session: imap::Session<CaptureStream<Transport>>,
+        capture: Arc<Mutex<Capture>>,
+        metrics: Option<Metrics>,
+        throttle: Throttle,
+    ) -> Self {
+        Self {
+            session,
+            capture,
+            capabilities: None,
+            metrics,
+            throttle,
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    pub(crate) fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    pub(crate) fn capability_set(&mut self) -> Result<&Capabilities, Box<dyn Error>> {
+        if self.capabilities.is_none() {
+            let lines = self.run_extension("CAPABILITY", &["CAPABILITY"])?;
+            let names = lines
+                .iter()
+                .flat_map(|x| split_capabilities(x))
+                .collect::<Vec<_>>();
+            self.capabilities = Some(Capabilities::from_names(names.iter().map(String::as_str)));
+        }
+        Ok(self.capabilities.get_or_insert_with(Capabilities::default))
+    }
+
+    pub(crate) fn supports(&mut self, capability: Capability) -> Result<bool, Box<dyn Error>> {
+        Ok(self.capability_set()?.has(&capability))
+    }
+    // APPEND する（LITERAL+ が使えれば、サーバーの「+」を待たずに中身も続けて送る）
+    // date を指定すると、それを受信日時（INTERNALDATE）にする
+    // UIDPLUS に対応していれば、追加したメールの UID を返す
+    pub(crate) fn append_message(
+        &mut self,
+        folder: &str,
+        content: &[u8],
+        flags: &[imap::types::Flag],
+        date: Option<DateTime<FixedOffset>>,
+    ) -> Result<Option<u32>, Box<dyn Error>> {
+        let literal_plus = self.supports(Capability::LiteralPlus)?;
+        let ((), codes) = self.with_response_codes(|imap_session| {
+            match std::str::from_utf8(content) {
+                // imap クレートのコマンドは文字列なので、UTF-8 でないものは従来どおり送る
+                Ok(text) if literal_plus => {
+                    // \Recent はサーバーが付けるもので、APPEND では指定できない
+                    let flags = flags
+                        .iter()
+                        .filter(|x| **x != imap::types::Flag::Recent)
+                        .map(|x| x.to_string())
+                        .collect::<Vec<_>>();
+                    let date = date.map_or(String::new(), |x| {
+                        format!(" \"{}\"", x.format("%d-%b-%Y %H:%M:%S %z"))
+                    });
+                    imap_session.run_command_and_check_ok(format!(
+                        "APPEND {} ({}){} {}",
+                        crate::imap_quote(folder),
+                        flags.join(" "),
+                        date,
+                        literal(text)
+                    ))?;
+                }
+                _ => imap_session.append_with_flags_and_date(folder, content, flags, date)?,
+            }
+            Ok(())
+        })?;
+        Ok(codes
+            .iter()
+            .find_map(|x| crate::uidplus::parse_appenduid(x)))
+    }
+
+    // command を送り、応答のうち名前が responses のいずれかであるもの（「* QUOTA ...」など）を返す
+    // 返す行は「* 」と名前を除いた残りで、末尾の改行も除く
+    pub(crate) fn run_extension(
+        &mut self,
+        command: &str,
+        responses: &[&str],
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        self.set_capture(responses.iter().map(|x| x.to_ascii_uppercase()).collect())?;
+        let result = self.session.run_command_and_check_ok(command);
+        let lines = self.set_capture(Vec::new())?;
+        result?;
+        Ok(lines
+            .into_iter()
+            .map(|line| {
+                let line = line.strip_suffix(b"\r\n").unwrap_or(&line);
+                let rest = &line[2..];
+                let name = rest.iter().position(|&x| x == b' ').unwrap_or(rest.len());
+                rest[(name + 1).min(rest.len())..].to_vec()
+            })
+            .collect())
+    }
+
+    // imap クレートのメソッドを呼び、その間に受け取った OK 応答の応答コードも返す
+    pub(crate) fn with_response_codes<T, F>(
+        &mut self,
+        f: F,
+    ) -> Result<(T, Vec<ResponseCode>), Box<dyn Error>>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Box<dyn Error>>,
+    {
+        self.set_record_codes(true)?;
+        let result = f(self);
+        let codes = self.set_record_codes(false)?;
+        Ok((result?, codes))
+    }
+
+    fn set_record_codes(&self, record: bool) -> Result<Vec<ResponseCode>, Box<dyn Error>> {
+        let mut capture = self.capture.lock().map_err(|_| "capture lock poisoned")?;
+        capture.record_codes = record;
+        Ok(std::mem::take(&mut capture.codes))
+    }
+
+    fn set_capture(&self, names: Vec<String>) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let mut capture = self.capture.lock().map_err(|_| "capture lock poisoned")?;
+        capture.names = names;
+        Ok(std::mem::take(&mut capture.lines))
+    }
+}
+
+// ログイン前の接続
+pub(crate) struct MyClient {
+    client: imap::Client<CaptureStream<Transport>>,
+    capture: Arc<Mutex<Capture>>,
+    // ログイン前のケーパビリティ（AUTH= を見てログインの方法を選ぶ）
+    capabilities: Vec<String>,
+    preauth: bool,
+    // 実際に接続できた（ポート, 方法）
+    negotiated: (u16, Security),
+    metrics: Option<Metrics>,
+    throttle: Throttle,
+}
+
+impl MyClient {
+    // 接続して、サーバーの挨拶とケーパビリティを読む
+    // 接続できないサーバーや、挨拶が OK・PREAUTH でない（BYE など）サーバーは飛ばして次を試す
+    pub(crate) fn connect(mailbox: &MyMailbox) -> Result<Self, Box<dyn Error>> {
+        let mut errors = Vec::new();
+        for (host, port) in mailbox.servers() {
+            for (port, security) in mailbox.security.attempts(port) {
+                match Self::connect_server(mailbox, host, port, security) {
+                    Ok(client) => return Ok(client),
+                    Err(e) => errors.push(format!("{}:{}: {}", host, port, e)),
+                }
+            }
+        }
+        Err(errors.join("; ").into())
+    }
+
+    fn connect_server(
+        mailbox: &MyMailbox,
+        host: &str,
+        port: u16,
+        security: Security,
+    ) -> Result<Self, Box<dyn Error>> {
+        if security == Security::StartTls && mailbox.tunnel.is_none() {
+            let transport = Transport::open_starttls(host, port)?;
+            Self::start(mailbox, transport, true, (port, security))
+        } else {
+            let transport = Transport::open(mailbox, host, port)?;
+            Self::start(mailbox, transport, false, (port, security))
+        }
+    }
+
+    // 接続したところから、挨拶とケーパビリティを読む
+    // starttls なら挨拶は STARTTLS の前に読んでいるので、ケーパビリティのない挨拶の代わりにする
+    pub(crate) fn start(
+        mailbox: &MyMailbox,
+        transport: Transport,
+        starttls: bool,
+        negotiated: (u16, Security),
+    ) -> Result<Self, Box<dyn Error>> {
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        let greeting = if starttls {
+            Some(b"* OK STARTTLS completed\r\n".to_vec())
+        } else {
+            None
+        };
+        let mut stream = CaptureStream::new(transport, capture.clone());
+        stream.metrics = mailbox.metrics.clone();
+        stream.wire = mailbox.wire_log.clone().map(WireTap::new);
+        let greeting = match greeting {
+            Some(greeting) => greeting,
+            None => stream.read_line()?,
+        };
+        if !is_available(&greeting) {
+            return Err(format!(
+                "server is not available: {}",
+                String::from_utf8_lossy(&greeting).trim_end()
+            )
+            .into());
+        }
+        // 「* PREAUTH」ならログイン済み（トンネルで IMAP サーバーを直接起動したときなど）
+        let preauth = greeting
+            .get(..10)
+            .is_some_and(|x| x.eq_ignore_ascii_case(b"* PREAUTH "));
+
+        // 挨拶に [CAPABILITY ...] がなければ、imap クレートに渡す前に自分で CAPABILITY を送る
+        let capabilities = match greeting_capabilities(&greeting) {
+            Some(capabilities) => capabilities,
+            None => {
+                stream.write_all(b"c0 CAPABILITY\r\n")?;
+                stream.flush()?;
+                let mut capabilities = Vec::new();
+                loop {
+                    let line = stream.read_line()?;
+                    if let Some(rest) = line.strip_prefix(b"* CAPABILITY ") {
+                        capabilities = split_capabilities(rest);
+                    } else if line.starts_with(b"c0 ") {
+                        break;
+                    }
+                }
+                capabilities
+            }
+        };
+
+        Ok(Self {
+            client: imap::Client::new(stream),
+            capture,
+            capabilities,
+            preauth,
+            negotiated,
+            metrics: mailbox.metrics.clone(),
+            throttle: mailbox.throttle.clone(),
+        })
+    }
+
+    pub(crate) fn negotiated(&self) -> (u16, Security) {
+        self.negotiated
+    }
+
+    pub(crate) fn login(self, mailbox: &MyMailbox) -> Result<MySession, Box<dyn Error>> {
+        // imap クレートにはログインせずに Session を作る方法がないので、LOGIN を空振りさせる
+        if self.preauth {
+            self.capture
+                .lock()
+                .map_err(|_| "capture lock poisoned")?
+                .fake_login = true;
+            let session = self.client.login("", "").map_err(|e| e.0)?;
+            return Ok(MySession::new(
+                session,
+                self.capture,
+                self.metrics,
+                self.throttle,
+            ));
+        }
+
+        let (user, password) = (mailbox.user, mailbox.password);
+        let method = mailbox
+            .auth
+            .resolve(&self.capabilities, mailbox.forbid_plaintext)?;
+        let session = match method.mechanism() {
+            None => self.client.login(user, password).map_err(|e| e.0)?,
+            Some(mechanism) => {
+                let authenticator = Authenticator {
+                    method,
+                    user,
+                    password,
+                };
+                self.client
+                    .authenticate(mechanism, &authenticator)
+                    .map_err(|e| e.0)?
+            }
+        };
+        Ok(MySession::new(
+            session,
+            self.capture,
+            self.metrics,
+            self.throttle,
+        ))
+    }
+}
+
+// 拡張の応答に出てくる値
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Nil,
+    // atom（数値も含む）
+    Atom(String),
+    // quoted string かリテラル
+    Str(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    // atom・文字列のどちらでも中身を返す
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Atom(x) | Value::Str(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_list(&self) -> &[Value] {
+        match self {
+            Value::List(x) => x,
+            _ => &[],
+        }
+    }
+}
+
+// run_extension が返した行を値の並びにする
+pub(crate) fn parse_values(line: &[u8]) -> Vec<Value> {
+    let mut position = 0;
+    parse_list(line, &mut position)
+}
+
+fn parse_list(line: &[u8], position: &mut usize) -> Vec<Value> {
+    let mut values = Vec::new();
+    while *position < line.len() {
+        match line[*position] {
+            b' ' | b'\r' | b'\n' => *position += 1,
+            b')' => {
+                *position += 1;
+                break;
+            }
+            b'(' => {
+                *position += 1;
+                values.push(Value::List(parse_list(line, position)));
+            }
+            b'"' => {
+                let mut text = Vec::new();
+                *position += 1;
+                while *position < line.len() && line[*position] != b'"' {
+                    if line[*position] == b'\\' {
+                        *position += 1;
+                    }
+                    if let Some(&x) = line.get(*position) {
+                        text.push(x);
+                    }
+                    *position += 1;
+                }
+                *position += 1;
+                values.push(Value::Str(String::from_utf8_lossy(&text).into_owned()));
+            }
+            b'{' => {
+                let close = line[*position..].iter().position(|&x| x == b'}');
+                let length = close.and_then(|close| {
+                    let digits = &line[*position + 1..*position + close];
+                    let digits = digits.strip_suffix(b"+").unwrap_or(digits);
+                    std::str::from_utf8(digits).ok()?.parse::<usize>().ok()
+                });
+                match (close, length) {
+                    (Some(close), Some(length)) => {
+                        // 「}」の後の改行を飛ばす
+                        let start = (*position + close + 3).min(line.len());
+                        let end = (start + length).min(line.len());
+                        values.push(Value::Str(
+                            String::from_utf8_lossy(&line[start..end]).into_owned(),
+                        ));
+                        *position = end;
+                    }
+                    _ => *position = line.len(),
+                }
+            }
+            _ => {
+                let start = *position;
+                while *position < line.len() && !b" ()\r\n".contains(&line[*position]) {
+                    *position += 1;
+                }
+                let atom = String::from_utf8_lossy(&line[start..*position]).into_owned();
+                values.push(if atom.eq_ignore_ascii_case("NIL") {
+                    Value::Nil
+                } else {
+                    Value::Atom(atom)
+                });
+            }
+        }
+    }
+    values
+}
+
+// 「* OK [COPYUID 38505 304 3956] Done」や「a5 OK [APPENDUID 38505 3955] ...」の [] の中
+fn response_code(line: &[u8]) -> Option<Vec<u8>> {
+    let mut words = line.splitn(3, |&x| x == b' ');
+    words.next()?;
+    if !words.next()?.eq_ignore_ascii_case(b"OK") {
+        return None;
+    }
+    let rest = words.next()?.strip_prefix(b"[")?;
+    let end = rest.iter().position(|&x| x == b']')?;
+    Some(rest[..end].to_vec())
+}
+
+// 非同期リテラル（RFC 7888）
+pub(crate) fn literal(value: &str) -> String {
+    format!("{{{}+}}\r\n{}", value.len(), value)
+}
+
+// 「* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] ready」の [CAPABILITY ...]
+// 「* OK」か「* PREAUTH」で始まる挨拶なら使える（「* BYE」はメンテナンス中など）
+fn is_available(greeting: &[u8]) -> bool {
+    [&b"* OK"[..], b"* PREAUTH"].iter().any(|prefix| {
+        greeting
+            .get(..prefix.len())
+            .is_some_and(|x| x.eq_ignore_ascii_case(prefix))
+    })
+}
+
+fn greeting_capabilities(greeting: &[u8]) -> Option<Vec<String>> {
+    let start = greeting
+        .windows(12)
+        .position(|x| x.eq_ignore_ascii_case(b"[CAPABILITY "))?;
+    let rest = &greeting[start + 12..];
+    let end = rest.iter().position(|&x| x == b']')?;
+    Some(split_capabilities(&rest[..end]))
+}
+
+fn split_capabilities(text: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(text)
+        .split_whitespace()
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
+
+fn is_captured(line: &[u8], names: &[String]) -> bool {
+    let rest = match line.strip_prefix(b"* ") {
+        Some(rest) => rest,
+        None => return false,
+    };
+    names.iter().any(|name| {
+        rest.len() > name.len()
+            && rest[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+            && matches!(rest[name.len()], b' ' | b'\r' | b'\n')
+    })
+}
+
+// 行末が「{123}\r\n」（または LITERAL+ の「{123+}」）ならその長さ
+fn literal_length(line: &[u8]) -> Option<usize> {
+    let line = line
+        .strip_suffix(b"\r\n")
+        .or_else(|| line.strip_suffix(b"\n"))?;
+    let line = line.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|&x| x == b'{')?;
+    let digits = &line[start + 1..];
+    let digits = digits.strip_suffix(b"+").unwrap_or(digits);
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // 読み込みは用意した応答、書き込みは捨てる
+    struct MockStream(Cursor<Vec<u8>>);
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture_extension_responses() {
+        let response = "* QUOTAROOT INBOX \"\"\r\n\
+                        * QUOTA \"\" (STORAGE 10 512)\r\n\
+                        * ID (\"name\" {5}\r\nDove\n)\r\n\
+                        * 3 EXISTS\r\n\
+                        a1 OK done\r\n";
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        capture.lock().unwrap().names = vec!["QUOTA".to_string(), "ID".to_string()];
+        let mut stream = CaptureStream::new(
+            MockStream(Cursor::new(response.as_bytes().to_vec())),
+            capture.clone(),
+        );
+
+        let mut reader = BufReader::new(&mut stream);
+        let mut passed = String::new();
+        for _ in 0..3 {
+            reader.read_line(&mut passed).unwrap();
+        }
+        assert_eq!(
+            passed,
+            "* QUOTAROOT INBOX \"\"\r\n* 3 EXISTS\r\na1 OK done\r\n"
+        );
+        let lines = &capture.lock().unwrap().lines;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], b"* QUOTA \"\" (STORAGE 10 512)\r\n");
+        assert_eq!(lines[1], b"* ID (\"name\" {5}\r\nDove\n)\r\n");
+    }
+
+    #[test]
+    fn count_commands_and_bytes() {
+        let response = "* ID (\"name\" {4}\r\nDove)\r\na1 OK done\r\n";
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        let mut stream = CaptureStream::new(
+            MockStream(Cursor::new(response.as_bytes().to_vec())),
+            capture.clone(),
+        );
+        let metrics = Metrics::new();
+        stream.metrics = Some(metrics.clone());
+
+        stream.write_all(b"a1 APPEND INBOX {20}\r\n").unwrap();
+        stream.write_all(b"Subject: hi\r\n\r\nbody").unwrap();
+        stream.write_all(b"\r\n").unwrap();
+        stream.write_all(b"a2 NOOP\r\n").unwrap();
+        assert_eq!(metrics.commands_sent(), 2);
+
+        // 横取りするときも、しないときも数える
+        capture.lock().unwrap().names = vec!["ID".to_string()];
+        let mut passed = String::new();
+        BufReader::new(&mut stream)
+            .read_to_string(&mut passed)
+            .unwrap_or_default();
+        assert_eq!(metrics.bytes_downloaded(), response.len() as u64);
+    }
+
+    #[test]
+    fn read_response_code() {
+        assert_eq!(
+            response_code(b"a5 OK [APPENDUID 38505 3955] APPEND completed\r\n"),
+            Some(b"APPENDUID 38505 3955".to_vec())
+        );
+        assert_eq!(
+            response_code(b"* OK [COPYUID 38505 304,319:320 3956:3958] Moved\r\n"),
+            Some(b"COPYUID 38505 304,319:320 3956:3958".to_vec())
+        );
+        assert_eq!(response_code(b"* 3 EXISTS\r\n"), None);
+    }
+
+    #[test]
+    fn non_synchronizing_literal() {
+        assert_eq!(literal("請求書"), "{9+}\r\n請求書");
+    }
+
+    #[test]
+    fn read_greeting_capabilities() {
+        assert_eq!(
+            greeting_capabilities(
+                b"* OK [CAPABILITY IMAP4rev1 auth=PLAIN LOGINDISABLED] ready\r\n"
+            ),
+            Some(vec![
+                "IMAP4REV1".to_string(),
+                "AUTH=PLAIN".to_string(),
+                "LOGINDISABLED".to_string(),
+            ])
+        );
+        assert_eq!(greeting_capabilities(b"* OK ready\r\n"), None);
+    }
+
+    #[test]
+    fn check_greeting() {
+        assert!(is_available(b"* OK IMAP4rev1 ready\r\n"));
+        assert!(is_available(b"* preauth logged in\r\n"));
+        assert!(!is_available(b"* BYE maintenance\r\n"));
+        assert!(!is_available(b""));
+    }
+
+    #[test]
+    fn parse_response_values() {
+        let values = parse_values(b"\"\" (STORAGE 10 512) NIL {4}\r\na\"b) \"q\\\"x\"");
+        assert_eq!(
+            values,
+            [
+                Value::Str(String::new()),
+                Value::List(vec![
+                    Value::Atom("STORAGE".to_string()),
+                    Value::Atom("10".to_string()),
+                    Value::Atom("512".to_string()),
+                ]),
+                Value::Nil,
+                Value::Str("a\"b)".to_string()),
+                Value::Str("q\"x".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+// 本文末尾の署名を取り除く
+
+// 区切り線がこの行数より前にある場合は署名とみなさない
+const MAX_SIGNATURE_LINES: usize = 15;
+
+// 署名の前までを、改行（CRLF か LF）を変えずに返す
+pub(crate) fn strip_signature(body: &str) -> String {
+    // 各行の先頭の位置と、改行を除いた中身
+    let mut offset = 0;
+    let lines = body
+        .split_inclusive('\n')
+        .map(|x| {
+            let start = offset;
+            offset += x.len();
+            (start, x.trim_end_matches(['\r', '\n']))
+        })
+        .collect::<Vec<_>>();
+
+    // 「-- 」（RFC 3676 の署名区切り）は最後のものを使う
+    let start = lines
+        .iter()
+        .rposition(|&(_, x)| x == "-- " || x == "--")
+        .or_else(|| {
+            // それ以外の区切り線や「Sent from my iPhone」などは、末尾の数行の中だけを見る
+            let window = lines.len().saturating_sub(MAX_SIGNATURE_LINES);
+            lines
+                .iter()
+                .enumerate()
+                .skip(window)
+                .find(|(_, (_, x))| is_rule(x) || is_sent_from(x))
+                .map(|(i, _)| i)
+        })
+        .unwrap_or(lines.len());
+
+    // 署名の前の空行と、最後の行の改行は含めない
+    let end = lines[..start]
+        .iter()
+        .rev()
+        .find(|(_, x)| !x.trim().is_empty())
+        .map_or(0, |(i, x)| i + x.len());
+    body[..end].to_string()
+}
+
+// 「━━━━━━━━━━」「==========」「*-*-*-*-*-」のような飾り罫線
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.chars().count() >= 10
+        && line
+            .chars()
+            .all(|x| "-=_*~+#━─═".contains(x) || x.is_whitespace())
+}
+
+// 携帯やメールアプリが自動で付ける一文
+fn is_sent_from(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("Sent from my ")
+        || line.starts_with("Get Outlook for ")
+        || line.ends_with("から送信")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_standard_delimiter() {
+        let body = "Hello,\n-- not a delimiter\n\nBye.\n\n-- \nTaro Yamada\nExample Inc.";
+        assert_eq!(strip_signature(body), "Hello,\n-- not a delimiter\n\nBye.");
+    }
+
+    #[test]
+    fn strip_heuristic_signature() {
+        let body = "ご確認ください。\n\n━━━━━━━━━━━━\n山田 太郎\ntaro@example.com\n━━━━━━━━━━━━";
+        assert_eq!(strip_signature(body), "ご確認ください。");
+
+        let body = "OK\n\niPhoneから送信";
+        assert_eq!(strip_signature(body), "OK");
+
+        let body = "no signature here";
+        assert_eq!(strip_signature(body), "no signature here");
+        assert_eq!(strip_signature(""), "");
+    }
+
+    #[test]
+    fn keep_line_endings() {
+        let body = "Hello,\r\nsee you.\r\n\r\n-- \r\nTaro";
+        assert_eq!(strip_signature(body), "Hello,\r\nsee you.");
+
+        let body = "no signature here\r\n\r\n";
+        assert_eq!(strip_signature(body), "no signature here");
+    }
+}
@@ -0,0 +1,126 @@
+// 取得したメールを手元で並べ替える（ReadOptions::sort_by）
+// SORT（RFC 5256）に対応していないサーバーでも、UID 順ではなく決まった順で受け取れる
+
+use std::cmp::Ordering;
+
+use crate::MyMessage;
+
+// 並べ替えに使う値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    // Date ヘッダーの日時（ないメールは最も古いものとして扱う）
+    Date,
+    // 差出アドレス（大文字・小文字は区別しない）
+    From,
+    // 返信・転送の接頭辞を除いた件名（大文字・小文字は区別しない）
+    Subject,
+    // サーバーから取得したままのメールの大きさ
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+// 値が同じメールは、もとの順（フォルダーごとの UID 順）のまま残す
+pub(crate) fn sort(messages: &mut [MyMessage], key: SortKey, order: Order) {
+    sort_by_message(messages, |x| x, key, order);
+}
+
+// MyMessage を包んだ値（AccountMessage など）を、中のメールで並べ替える
+pub(crate) fn sort_by_message<T, F>(items: &mut [T], message: F, key: SortKey, order: Order)
+where
+    F: Fn(&T) -> &MyMessage,
+{
+    match key {
+        SortKey::Date => {
+            items.sort_by(|a, b| ordered(message(a).date.cmp(&message(b).date), order))
+        }
+        SortKey::From => items.sort_by_cached_key(|x| Keyed(message(x).from.to_lowercase(), order)),
+        SortKey::Subject => items
+            .sort_by_cached_key(|x| Keyed(message(x).normalized_subject().to_lowercase(), order)),
+        SortKey::Size => {
+            items.sort_by(|a, b| ordered(message(a).raw.len().cmp(&message(b).raw.len()), order))
+        }
+    }
+}
+
+fn ordered(ordering: Ordering, order: Order) -> Ordering {
+    match order {
+        Order::Ascending => ordering,
+        Order::Descending => ordering.reverse(),
+    }
+}
+
+// sort_by_cached_key で、order に従って比べる
+#[derive(PartialEq, Eq)]
+struct Keyed(String, Order);
+
+impl PartialOrd for Keyed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Keyed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        ordered(self.0.cmp(&other.0), self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReadOptions;
+
+    fn message(uid: u32, from: &str, subject: &str, date: &str) -> MyMessage {
+        let raw = format!(
+            "From: {}\r\nSubject: {}\r\nDate: {}\r\n\r\nbody\r\n",
+            from, subject, date
+        );
+        let mut message = crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap();
+        message.uid = uid;
+        message
+    }
+
+    #[test]
+    fn sort_messages() {
+        let mut messages = vec![
+            message(
+                1,
+                "jiro@example.com",
+                "Re: beta",
+                "Tue, 2 Jan 2024 09:00:00 +0900",
+            ),
+            message(
+                2,
+                "Taro@example.com",
+                "Alpha",
+                "Mon, 1 Jan 2024 09:00:00 +0900",
+            ),
+            message(
+                3,
+                "hanako@example.com",
+                "gamma",
+                "Mon, 1 Jan 2024 09:00:00 +0900",
+            ),
+        ];
+        let uids = |messages: &[MyMessage]| messages.iter().map(|x| x.uid).collect::<Vec<_>>();
+
+        sort(&mut messages, SortKey::Date, Order::Ascending);
+        assert_eq!(uids(&messages), [2, 3, 1]);
+        sort(&mut messages, SortKey::Date, Order::Descending);
+        assert_eq!(uids(&messages), [1, 2, 3]);
+        sort(&mut messages, SortKey::From, Order::Ascending);
+        assert_eq!(uids(&messages), [3, 1, 2]);
+        sort(&mut messages, SortKey::Subject, Order::Descending);
+        assert_eq!(uids(&messages), [3, 1, 2]);
+        sort(&mut messages, SortKey::Subject, Order::Ascending);
+        assert_eq!(uids(&messages), [2, 1, 3]);
+        sort(&mut messages, SortKey::Size, Order::Descending);
+        assert_eq!(uids(&messages), [1, 3, 2]);
+    }
+}
@@ -0,0 +1,131 @@
+// 特別な用途のフォルダー（SPECIAL-USE 拡張、RFC 6154）
+// 「ゴミ箱」「Gelöschte Elemente」のように言語によって名前が違っても、ゴミ箱や送信済みを見つけられる
+// SPECIAL-USE がなければ Gmail の XLIST を使う
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::session::{self, Value};
+use crate::{Capability, MyMailbox, MySession};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecialUse {
+    // すべてのメール（Gmail の「すべてのメール」）
+    All,
+    Archive,
+    Drafts,
+    // スター付き
+    Flagged,
+    // 迷惑メール
+    Junk,
+    Sent,
+    Trash,
+}
+
+impl SpecialUse {
+    // LIST の属性（XLIST の独自の名前も含む）から
+    fn from_attribute(attribute: &str) -> Option<Self> {
+        match attribute.to_ascii_lowercase().as_str() {
+            "\\all" | "\\allmail" => Some(SpecialUse::All),
+            "\\archive" => Some(SpecialUse::Archive),
+            "\\drafts" => Some(SpecialUse::Drafts),
+            "\\flagged" | "\\starred" => Some(SpecialUse::Flagged),
+            "\\junk" | "\\spam" => Some(SpecialUse::Junk),
+            "\\sent" => Some(SpecialUse::Sent),
+            "\\trash" => Some(SpecialUse::Trash),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpecialFolders {
+    folders: HashMap<SpecialUse, String>,
+}
+
+impl SpecialFolders {
+    // SELECT などにそのまま使えるフォルダー名
+    pub fn get(&self, special_use: SpecialUse) -> Option<&str> {
+        self.folders.get(&special_use).map(String::as_str)
+    }
+
+    pub fn trash(&self) -> Option<&str> {
+        self.get(SpecialUse::Trash)
+    }
+
+    pub fn sent(&self) -> Option<&str> {
+        self.get(SpecialUse::Sent)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SpecialUse, &str)> {
+        self.folders.iter().map(|(&k, v)| (k, v.as_str()))
+    }
+}
+
+pub fn special_folders(mailbox: &MyMailbox) -> Result<SpecialFolders, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    let folders = find_special_folders(&mut imap_session)?;
+    imap_session.logout()?;
+    Ok(folders)
+}
+
+pub(crate) fn find_special_folders(
+    imap_session: &mut MySession,
+) -> Result<SpecialFolders, Box<dyn Error>> {
+    let capabilities = imap_session.capability_set()?;
+    let command =
+        if !capabilities.has(&Capability::SpecialUse) && capabilities.has(&Capability::Xlist) {
+            "XLIST"
+        } else {
+            "LIST"
+        };
+
+    let lines = imap_session.run_extension(&format!("{} \"\" \"*\"", command), &[command])?;
+    let mut folders = SpecialFolders::default();
+    for line in &lines {
+        if let Some((special_use, name)) = parse_list(&session::parse_values(line)) {
+            // 同じ用途のフォルダーが複数あれば最初のもの
+            folders.folders.entry(special_use).or_insert(name);
+        }
+    }
+    Ok(folders)
+}
+
+// 「* LIST (\HasNoChildren \Trash) "/" "Trash"」の「* LIST」より後
+fn parse_list(values: &[Value]) -> Option<(SpecialUse, String)> {
+    match values {
+        [attributes, _, name, ..] => {
+            let special_use = attributes
+                .as_list()
+                .iter()
+                .filter_map(Value::as_str)
+                .find_map(SpecialUse::from_attribute)?;
+            Some((special_use, name.as_str()?.to_string()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_special_use() {
+        let values = session::parse_values(b"(\\HasNoChildren \\Trash) \"/\" \"&MLQw33ux-\"");
+        assert_eq!(
+            parse_list(&values),
+            Some((SpecialUse::Trash, "&MLQw33ux-".to_string()))
+        );
+
+        // Gmail の XLIST
+        let values = session::parse_values(b"(\\HasNoChildren \\Spam) \"/\" \"[Gmail]/Spam\"");
+        assert_eq!(
+            parse_list(&values),
+            Some((SpecialUse::Junk, "[Gmail]/Spam".to_string()))
+        );
+
+        let values = session::parse_values(b"(\\HasNoChildren) \"/\" INBOX");
+        assert_eq!(parse_list(&values), None);
+    }
+}
@@ -0,0 +1,575 @@
+// 大きなパートをメモリに読み込まずに、ファイルへ書き出す（ReadOptions::large_parts_to_disk）
+// BODYSTRUCTURE でパートの大きさを調べ、しきい値を超える添付ファイルのパートは「BODY.PEEK[2]<0.1048576>」のように
+// 少しずつ取得して、Content-Transfer-Encoding を解除しながらファイルに書く
+// 本文のパート（添付ファイルでないもの）は大きくてもメモリに読み込む
+// それ以外のパートは1つずつ取得して、大きなパートの中身を除いたメールを組み立て直して解析する
+// （組み立て直したパートには、書き出したファイルを示す X-Read-Mail-Spilled を付ける）
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use imap_proto::types::{BodyParams, BodyStructure, ContentEncoding, MessageSection, SectionPath};
+use sha2::{Digest, Sha256};
+
+use crate::session::MySession;
+use crate::MyMessage;
+
+pub(crate) const SPILLED_HEADER: &str = "X-Read-Mail-Spilled";
+
+// 一度に取得する大きさ
+const CHUNK: usize = 1024 * 1024;
+
+// BODYSTRUCTURE のうち、取得に使う部分
+#[derive(Debug, PartialEq)]
+enum Part {
+    Multipart {
+        boundary: String,
+        parts: Vec<Part>,
+    },
+    Leaf {
+        octets: usize,
+        encoding: Encoding,
+        attachment: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Base64,
+    QuotedPrintable,
+    Raw,
+}
+
+impl Part {
+    fn new(structure: &BodyStructure) -> Self {
+        let (common, other) = match structure {
+            BodyStructure::Multipart { common, bodies, .. } => {
+                let boundary = common
+                    .ty
+                    .params
+                    .iter()
+                    .flatten()
+                    .find(|x| x.0.eq_ignore_ascii_case("boundary"))
+                    .map(|x| x.1.to_string())
+                    .unwrap_or_default();
+                return Part::Multipart {
+                    boundary,
+                    parts: bodies.iter().map(Part::new).collect(),
+                };
+            }
+            BodyStructure::Basic { common, other, .. }
+            | BodyStructure::Text { common, other, .. }
+            | BodyStructure::Message { common, other, .. } => (common, other),
+        };
+        let encoding = match other.transfer_encoding {
+            ContentEncoding::Base64 => Encoding::Base64,
+            ContentEncoding::QuotedPrintable => Encoding::QuotedPrintable,
+            _ => Encoding::Raw,
+        };
+        // 本文の選び方（is_body_part）と同じく、attachment か、ファイル名があれば添付ファイル
+        let has_param = |params: &BodyParams, name: &str| {
+            params
+                .iter()
+                .flatten()
+                .any(|x| x.0.eq_ignore_ascii_case(name))
+        };
+        let attachment = common.disposition.as_ref().is_some_and(|x| {
+            x.ty.eq_ignore_ascii_case("attachment") || has_param(&x.params, "filename")
+        }) || has_param(&common.ty.params, "name");
+        Part::Leaf {
+            octets: other.octets as usize,
+            encoding,
+            attachment,
+        }
+    }
+
+    // 書き出すパート
+    fn is_spilled(&self, threshold: usize) -> bool {
+        match self {
+            Part::Multipart { .. } => false,
+            Part::Leaf {
+                octets, attachment, ..
+            } => *attachment && *octets > threshold,
+        }
+    }
+
+    fn is_large(&self, threshold: usize) -> bool {
+        match self {
+            Part::Multipart { parts, .. } => parts.iter().any(|x| x.is_large(threshold)),
+            Part::Leaf { .. } => self.is_spilled(threshold),
+        }
+    }
+}
+
+// 大きなパートの中身を除いて組み立て直したメールと、書き出したファイル
+// header はサーバーから取得したままのヘッダー（MyMessage::raw にはこちらを入れる）
+pub(crate) struct Spilled {
+    pub(crate) raw: Vec<u8>,
+    pub(crate) header: Vec<u8>,
+    pub(crate) paths: Vec<PathBuf>,
+}
+
+struct Spiller<'a> {
+    imap_session: &'a mut MySession,
+    folder: &'a str,
+    uid: u32,
+    threshold: usize,
+    dir: &'a Path,
+    raw: Vec<u8>,
+    header: Vec<u8>,
+    paths: Vec<PathBuf>,
+}
+
+// しきい値を超えるパートがあれば、書き出したうえで組み立て直したメールを返す
+// （なければ None を返すので、いつもどおり BODY.PEEK[] で取得する）
+pub(crate) fn fetch(
+    imap_session: &mut MySession,
+    folder: &str,
+    uid: u32,
+    threshold: usize,
+    dir: &Path,
+) -> Result<Option<Spilled>, Box<dyn Error>> {
+    let throttle = imap_session.throttle().clone();
+    let fetches =
+        throttle.run(|| imap_session.uid_fetch(uid.to_string(), "(RFC822.SIZE BODYSTRUCTURE)"))?;
+    let fetch = fetches.iter().next().ok_or("no message")?;
+    if fetch.size.unwrap_or_default() as usize <= threshold {
+        return Ok(None);
+    }
+    let root = match fetch.bodystructure() {
+        Some(structure) => Part::new(structure),
+        None => return Ok(None),
+    };
+    if !root.is_large(threshold) {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut spiller = Spiller {
+        imap_session,
+        folder,
+        uid,
+        threshold,
+        dir,
+        raw: Vec::new(),
+        header: Vec::new(),
+        paths: Vec::new(),
+    };
+    let result = spiller.part(&root, &[], Some(MessageSection::Header));
+    if let Err(e) = result {
+        remove(&spiller.paths);
+        return Err(e);
+    }
+    Ok(Some(Spilled {
+        raw: spiller.raw,
+        header: spiller.header,
+        paths: spiller.paths,
+    }))
+}
+
+impl Spiller<'_> {
+    // header（トップレベルなら HEADER、それ以外は MIME）に続けて、パートの中身を組み立てる
+    fn part(
+        &mut self,
+        part: &Part,
+        path: &[u32],
+        header: Option<MessageSection>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut fields = self.section(path, header)?;
+        if path.is_empty() {
+            self.header = fields.clone();
+        }
+        match part {
+            Part::Multipart { boundary, parts } => {
+                self.raw.extend(fields);
+                for (i, child) in parts.iter().enumerate() {
+                    let mut child_path = path.to_vec();
+                    child_path.push(i as u32 + 1);
+                    self.raw.extend(format!("--{}\r\n", boundary).into_bytes());
+                    self.part(child, &child_path, Some(MessageSection::Mime))?;
+                    self.raw.extend(b"\r\n");
+                }
+                self.raw
+                    .extend(format!("--{}--\r\n", boundary).into_bytes());
+            }
+            Part::Leaf { encoding, .. } if part.is_spilled(self.threshold) => {
+                let file = self.dir.join(file_name(self.folder, self.uid, path));
+                self.paths.push(file.clone());
+                mark(&mut fields, &file);
+                self.raw.extend(fields);
+                self.stream(path, *encoding, &file)?;
+            }
+            Part::Leaf { .. } => {
+                self.raw.extend(fields);
+                let body = self.section(path, None)?;
+                self.raw.extend(body);
+            }
+        }
+        Ok(())
+    }
+
+    fn section(
+        &mut self,
+        path: &[u32],
+        section: Option<MessageSection>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (name, section_path) = section_path(path, section);
+        let throttle = self.imap_session.throttle().clone();
+        let fetches = throttle.run(|| {
+            self.imap_session
+                .uid_fetch(self.uid.to_string(), format!("BODY.PEEK[{}]", name))
+        })?;
+        let fetch = fetches.iter().next().ok_or("no message")?;
+        Ok(fetch.section(&section_path).unwrap_or_default().to_vec())
+    }
+
+    fn stream(
+        &mut self,
+        path: &[u32],
+        encoding: Encoding,
+        file: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let (name, section_path) = section_path(path, None);
+        let mut writer = BufWriter::new(File::create(file)?);
+        let mut decoder = Decoder::new(encoding);
+        let throttle = self.imap_session.throttle().clone();
+        let mut offset = 0;
+        loop {
+            let fetches = throttle.run(|| {
+                self.imap_session.uid_fetch(
+                    self.uid.to_string(),
+                    format!("BODY.PEEK[{}]<{}.{}>", name, offset, CHUNK),
+                )
+            })?;
+            let fetch = fetches.iter().next().ok_or("no message")?;
+            let data = fetch.section(&section_path).unwrap_or_default();
+            decoder.write(data, &mut writer)?;
+            offset += data.len();
+            if data.len() < CHUNK {
+                break;
+            }
+        }
+        decoder.finish(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// 書き出したファイルのうち、添付ファイルにならなかった（または隔離された）ものを消す
+pub(crate) fn clean_up(paths: &[PathBuf], message: Option<&MyMessage>) {
+    let unused = paths
+        .iter()
+        .filter(|path| {
+            !message.is_some_and(|x| x.attachments().iter().any(|x| x.path() == Some(path)))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    remove(&unused);
+}
+
+fn remove(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// 「2.1」「2.1.MIME」「HEADER」「TEXT」
+fn section_path(path: &[u32], section: Option<MessageSection>) -> (String, SectionPath) {
+    let numbers = path
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(".");
+    match (path.is_empty(), section) {
+        (true, Some(MessageSection::Header)) => (
+            "HEADER".to_string(),
+            SectionPath::Full(MessageSection::Header),
+        ),
+        (true, _) => ("TEXT".to_string(), SectionPath::Full(MessageSection::Text)),
+        (false, Some(MessageSection::Mime)) => (
+            format!("{}.MIME", numbers),
+            SectionPath::Part(path.to_vec(), Some(MessageSection::Mime)),
+        ),
+        (false, _) => (numbers, SectionPath::Part(path.to_vec(), None)),
+    }
+}
+
+// 「INBOX-1a2b3c4d-4312-2.1.part」（フォルダー名の記号は「_」にする）
+// 「INBOX/a」と「INBOX.a」が同じ名前にならないように、フォルダー名の SHA-256 の先頭 4 バイト（16進）も入れる
+fn file_name(folder: &str, uid: u32, path: &[u32]) -> String {
+    let hash = Sha256::digest(folder.as_bytes())[..4]
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    let folder = folder
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let section = if path.is_empty() {
+        "1".to_string()
+    } else {
+        section_path(path, None).0
+    };
+    format!("{}-{}-{}-{}.part", folder, hash, uid, section)
+}
+
+// ヘッダーの最後（空行の前）に X-Read-Mail-Spilled を加える
+fn mark(fields: &mut Vec<u8>, file: &Path) {
+    let line = format!("{}: {}\r\n", SPILLED_HEADER, file.display()).into_bytes();
+    let end = if fields.ends_with(b"\r\n\r\n") {
+        fields.len() - 2
+    } else if fields.ends_with(b"\n\n") {
+        fields.len() - 1
+    } else {
+        if !fields.is_empty() && !fields.ends_with(b"\n") {
+            fields.extend(b"\r\n");
+        }
+        fields.extend(b"\r\n");
+        fields.len() - 2
+    };
+    fields.splice(end..end, line);
+}
+
+// 少しずつ届く中身の Content-Transfer-Encoding を解除する
+enum Decoder {
+    // 4文字にそろわずに残った文字
+    Base64(Vec<u8>),
+    // 改行で終わっていない最後の行
+    QuotedPrintable(Vec<u8>),
+    Raw,
+}
+
+impl Decoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Base64 => Decoder::Base64(Vec::new()),
+            Encoding::QuotedPrintable => Decoder::QuotedPrintable(Vec::new()),
+            Encoding::Raw => Decoder::Raw,
+        }
+    }
+
+    fn write<W: Write>(&mut self, data: &[u8], out: &mut W) -> Result<(), Box<dyn Error>> {
+        match self {
+            Decoder::Base64(pending) => {
+                pending.extend(data.iter().filter(|x| !x.is_ascii_whitespace()));
+                let end = pending.len() / 4 * 4;
+                out.write_all(&STANDARD.decode(&pending[..end])?)?;
+                pending.drain(..end);
+            }
+            Decoder::QuotedPrintable(pending) => {
+                pending.extend(data);
+                let end = pending
+                    .iter()
+                    .rposition(|&x| x == b'\n')
+                    .map_or(0, |x| x + 1);
+                out.write_all(&decode_quoted_printable(&pending[..end]))?;
+                pending.drain(..end);
+            }
+            Decoder::Raw => out.write_all(data)?,
+        }
+        Ok(())
+    }
+
+    fn finish<W: Write>(self, out: &mut W) -> Result<(), Box<dyn Error>> {
+        match self {
+            Decoder::Base64(pending) => {
+                let end = pending
+                    .iter()
+                    .rposition(|&x| x != b'=')
+                    .map_or(0, |x| x + 1);
+                out.write_all(&STANDARD_NO_PAD.decode(&pending[..end])?)?;
+            }
+            Decoder::QuotedPrintable(pending) => {
+                out.write_all(&decode_quoted_printable(&pending))?;
+            }
+            Decoder::Raw => {}
+        }
+        Ok(())
+    }
+}
+
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let hex = |x: u8| (x as char).to_digit(16).map(|x| x as u8);
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != b'=' {
+            decoded.push(data[i]);
+            i += 1;
+            continue;
+        }
+        // 「=」で終わる行は、次の行に続く
+        if data[i + 1..].starts_with(b"\r\n") {
+            i += 3;
+        } else if data[i + 1..].starts_with(b"\n") {
+            i += 2;
+        } else {
+            match (
+                data.get(i + 1).and_then(|&x| hex(x)),
+                data.get(i + 2).and_then(|&x| hex(x)),
+            ) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                }
+                _ => {
+                    decoded.push(b'=');
+                    i += 1;
+                }
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(encoding: Encoding, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut decoder = Decoder::new(encoding);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            decoder.write(chunk, &mut out).unwrap();
+        }
+        decoder.finish(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        // 「Hello, world!」
+        assert_eq!(
+            decode(Encoding::Base64, &[b"SGVsbG8", b"sIHdv\r\ncmxk", b"IQ=="]),
+            b"Hello, world!"
+        );
+        assert_eq!(decode(Encoding::Base64, &[b"SGk"]), b"Hi");
+        assert_eq!(
+            decode(
+                Encoding::QuotedPrintable,
+                &[b"caf=C3=A9 =", b"\r\nlatte=3D", b"1\r\nend="]
+            ),
+            "café latte=1\r\nend=".as_bytes()
+        );
+        assert_eq!(decode(Encoding::Raw, &[b"ab", b"c"]), b"abc");
+    }
+
+    fn part(bodystructure: &str) -> Part {
+        let response = format!("* 1 FETCH (BODYSTRUCTURE {})\r\n", bodystructure);
+        match imap_proto::parse_response(response.as_bytes()).unwrap().1 {
+            imap_proto::Response::Fetch(_, attributes) => match &attributes[0] {
+                imap_proto::AttributeValue::BodyStructure(x) => Part::new(x),
+                x => panic!("{:?}", x),
+            },
+            x => panic!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn spill_only_attachments() {
+        let body =
+            r#"("text" "plain" ("charset" "utf-8") NIL NIL "7bit" 5000 100 NIL NIL NIL NIL)"#;
+        let html = r#"("text" "html" NIL NIL NIL "base64" 8000 100 NIL ("inline" NIL) NIL NIL)"#;
+        let pdf = r#"("application" "pdf" NIL NIL NIL "base64" 9000 NIL ("attachment" ("filename" "a.pdf")) NIL NIL)"#;
+        let named = r#"("application" "octet-stream" ("name" "a.bin") NIL NIL "base64" 9000 NIL NIL NIL NIL)"#;
+
+        // 大きな本文はメモリに読み込む
+        let root = part(&format!(
+            r#"({}{} "alternative" ("boundary" "b") NIL NIL NIL)"#,
+            body, html
+        ));
+        assert!(!root.is_large(1000));
+        assert!(!part(body).is_large(1000));
+
+        let root = part(&format!(
+            r#"({}{} "mixed" ("boundary" "b") NIL NIL NIL)"#,
+            body, pdf
+        ));
+        assert!(root.is_large(1000));
+        assert!(!root.is_large(10000));
+        match &root {
+            Part::Multipart { boundary, parts } => {
+                assert_eq!(boundary, "b");
+                assert!(!parts[0].is_spilled(1000));
+                assert!(parts[1].is_spilled(1000));
+            }
+            x => panic!("{:?}", x),
+        }
+        assert!(part(named).is_spilled(1000));
+    }
+
+    #[test]
+    fn name_sections() {
+        assert_eq!(section_path(&[], Some(MessageSection::Header)).0, "HEADER");
+        assert_eq!(section_path(&[], None).0, "TEXT");
+        assert_eq!(
+            section_path(&[2, 1], Some(MessageSection::Mime)),
+            (
+                "2.1.MIME".to_string(),
+                SectionPath::Part(vec![2, 1], Some(MessageSection::Mime))
+            )
+        );
+        let name = file_name("Archive/2024", 7, &[2, 1]);
+        assert!(name.starts_with("Archive_2024-"));
+        assert!(name.ends_with("-7-2.1.part"));
+        assert_eq!(name.len(), "Archive_2024-12345678-7-2.1.part".len());
+        // 記号だけが違うフォルダーは別の名前にする
+        assert_ne!(name, file_name("Archive.2024", 7, &[2, 1]));
+    }
+
+    #[test]
+    fn parse_spilled_attachment() {
+        let dir = std::env::temp_dir().join(format!("read-mail-spill-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("INBOX-1-2.part");
+        fs::write(&file, b"%PDF-1.7 large").unwrap();
+
+        let raw = format!(
+            "From: taro@example.com\r\nSubject: report\r\n\
+             Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\nContent-Type: text/plain\r\n\r\nsee attached\r\n\
+             --b\r\nContent-Type: application/pdf; name=report.pdf\r\n\
+             Content-Transfer-Encoding: base64\r\n{}: {}\r\n\r\n\r\n\
+             --b--\r\n",
+            SPILLED_HEADER,
+            file.display()
+        );
+        let options = crate::ReadOptions {
+            spilled: vec![file.clone()],
+            ..Default::default()
+        }
+        .attachment_md5(true);
+        let message = crate::parse(raw.as_bytes(), &options).unwrap();
+        let attachment = &message.attachments()[0];
+        assert_eq!(attachment.path(), Some(file.as_path()));
+        assert_eq!(attachment.size(), 14);
+        assert!(attachment.data().is_empty());
+        assert_eq!(
+            attachment.sha256(),
+            &<[u8; 32]>::from(sha2::Sha256::digest(b"%PDF-1.7 large"))
+        );
+        assert!(attachment.md5().is_some());
+
+        // 書き出していないファイルを示すヘッダーは信用しない
+        let message = crate::parse(raw.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.attachments()[0].path(), None);
+
+        clean_up(std::slice::from_ref(&file), None);
+        assert!(!file.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mark_spilled_part() {
+        let mut fields = b"Content-Type: application/pdf\r\n\r\n".to_vec();
+        mark(&mut fields, Path::new("/tmp/a.part"));
+        assert_eq!(
+            fields,
+            b"Content-Type: application/pdf\r\nX-Read-Mail-Spilled: /tmp/a.part\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+// フォルダーの利用状況（差出人ごとの数・日ごとの数・平均サイズ・添付ファイルの割合）
+// 本文はダウンロードせず、ヘッダーとサイズ・受信日時だけを取得する
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use chrono::NaiveDate;
+use mailparse::{addrparse_header, parse_headers, MailAddr, MailHeaderMap};
+
+use crate::MyMailbox;
+
+// 一度に FETCH する UID の数
+const CHUNK: usize = 500;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MailboxStats {
+    messages: usize,
+    total_size: u64,
+    with_attachments: usize,
+    senders: HashMap<String, usize>,
+    per_day: BTreeMap<NaiveDate, usize>,
+}
+
+impl MailboxStats {
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    // RFC822.SIZE の合計（バイト）
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub fn average_size(&self) -> f64 {
+        if self.messages == 0 {
+            return 0.0;
+        }
+        self.total_size as f64 / self.messages as f64
+    }
+
+    // 添付ファイルがありそうな（Content-Type が multipart/mixed などの）メールの割合
+    pub fn attachment_ratio(&self) -> f64 {
+        if self.messages == 0 {
+            return 0.0;
+        }
+        self.with_attachments as f64 / self.messages as f64
+    }
+
+    // 差出人（小文字のメールアドレス）ごとの数を、多い順に
+    pub fn senders(&self) -> Vec<(&str, usize)> {
+        let mut senders = self
+            .senders
+            .iter()
+            .map(|(x, &n)| (x.as_str(), n))
+            .collect::<Vec<_>>();
+        senders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        senders
+    }
+
+    // 受信日（INTERNALDATE のサーバーでの日付）ごとの数
+    pub fn per_day(&self) -> &BTreeMap<NaiveDate, usize> {
+        &self.per_day
+    }
+
+    fn add(&mut self, header: &[u8], size: u32, date: Option<NaiveDate>) {
+        self.messages += 1;
+        self.total_size += size as u64;
+        let headers = parse_headers(header).map(|x| x.0).unwrap_or_default();
+
+        let from = headers
+            .get_first_header("From")
+            .and_then(|x| addrparse_header(x).ok())
+            .and_then(|x| match x.first() {
+                Some(MailAddr::Single(info)) => Some(info.addr.to_lowercase()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        *self.senders.entry(from).or_default() += 1;
+
+        if let Some(date) = date {
+            *self.per_day.entry(date).or_default() += 1;
+        }
+
+        let content_type = headers
+            .get_first_value("Content-Type")
+            .map(|x| mailparse::parse_content_type(&x).mimetype)
+            .unwrap_or_default();
+        if has_attachments(&content_type) {
+            self.with_attachments += 1;
+        }
+    }
+}
+
+// 本文だけのメールは text/* か multipart/alternative・multipart/related になる
+fn has_attachments(mimetype: &str) -> bool {
+    match mimetype.split('/').next() {
+        Some("multipart") => !matches!(mimetype, "multipart/alternative" | "multipart/related"),
+        Some("text") | Some("") | None => false,
+        _ => true,
+    }
+}
+
+// folder のうち、受信日が since 以降・before より前のメールについて数える（None なら制限なし）
+pub fn stats(
+    mailbox: &MyMailbox,
+    folder: &str,
+    since: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+) -> Result<MailboxStats, Box<dyn Error>> {
+    let mut imap_session = crate::connect(mailbox)?;
+    imap_session.examine(folder)?;
+    let uids = crate::esearch::search(&mut imap_session, &date_query(since, before), "ALL")?.uids();
+
+    let mut stats = MailboxStats::default();
+    for chunk in uids.chunks(CHUNK) {
+        let throttle = imap_session.throttle().clone();
+        let fetches = throttle.run(|| {
+            imap_session.uid_fetch(
+                crate::uid_set(chunk),
+                "(RFC822.SIZE INTERNALDATE BODY.PEEK[HEADER])",
+            )
+        })?;
+        for fetch in fetches.iter() {
+            let date = fetch.internal_date().map(|x| x.date_naive());
+            stats.add(
+                fetch.header().unwrap_or_default(),
+                fetch.size.unwrap_or_default(),
+                date,
+            );
+        }
+    }
+    imap_session.logout()?;
+    Ok(stats)
+}
+
+fn date_query(since: Option<NaiveDate>, before: Option<NaiveDate>) -> String {
+    let criteria = since
+        .map(|x| format!("SINCE {}", x.format("%-d-%b-%Y")))
+        .into_iter()
+        .chain(before.map(|x| format!("BEFORE {}", x.format("%-d-%b-%Y"))))
+        .collect::<Vec<_>>();
+    if criteria.is_empty() {
+        "ALL".to_string()
+    } else {
+        criteria.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_date_query() {
+        let date = |d| NaiveDate::from_ymd_opt(2024, 1, d);
+        assert_eq!(date_query(None, None), "ALL");
+        assert_eq!(
+            date_query(date(1), date(31)),
+            "SINCE 1-Jan-2024 BEFORE 31-Jan-2024"
+        );
+        assert_eq!(date_query(None, date(5)), "BEFORE 5-Jan-2024");
+    }
+
+    #[test]
+    fn summarize_headers() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d);
+        let mut stats = MailboxStats::default();
+        stats.add(b"From: Taro <taro@example.com>\r\n\r\n", 1000, day(1));
+        stats.add(
+            b"From: jiro@example.com\r\nContent-Type: multipart/mixed; boundary=x\r\n\r\n",
+            5000,
+            day(1),
+        );
+        stats.add(
+            b"From: TARO@example.com\r\nContent-Type: multipart/alternative; boundary=x\r\n\r\n",
+            3000,
+            day(2),
+        );
+
+        assert_eq!(stats.messages(), 3);
+        assert_eq!(stats.average_size(), 3000.0);
+        assert!((stats.attachment_ratio() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            stats.senders(),
+            [("taro@example.com", 2), ("jiro@example.com", 1)]
+        );
+        assert_eq!(
+            stats.per_day().values().copied().collect::<Vec<_>>(),
+            [2, 1]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+// 件名から返信・転送の接頭辞（Re: Fwd: AW: 回答: など）とメーリングリストのタグ（[ml:123] など）を取り除く
+// 同じ話題のメールを件名でまとめたり、数えたりするときに使う
+
+// 接頭辞（小文字、後ろに「:」か「：」が続くもの）
+const PREFIXES: [&str; 17] = [
+    "re", "fw", "fwd", "aw", "wg", "sv", "vs", "antw", "tr", "rif", "res", "enc", "回答", "返信",
+    "転送", "回复", "转发",
+];
+
+pub(crate) fn normalize(subject: &str) -> String {
+    let mut rest = subject.trim();
+    loop {
+        let stripped = strip_tag(rest).or_else(|| strip_prefix(rest));
+        match stripped {
+            Some(x) => rest = x.trim_start(),
+            None => break,
+        }
+    }
+    rest.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// 先頭の「[...]」か「【...】」
+fn strip_tag(subject: &str) -> Option<&str> {
+    let close = match subject.chars().next()? {
+        '[' => ']',
+        '【' => '】',
+        _ => return None,
+    };
+    let end = subject.find(close)?;
+    // 件名がタグだけなら残す
+    let rest = &subject[end + close.len_utf8()..];
+    if rest.trim().is_empty() {
+        return None;
+    }
+    Some(rest)
+}
+
+// 先頭の「Re:」「RE[2]:」「Re^2:」「Fwd：」など
+fn strip_prefix(subject: &str) -> Option<&str> {
+    let lower = subject.to_lowercase();
+    // to_lowercase で長さが変わる文字があると位置がずれるので、そのときは取り除かない
+    if lower.len() != subject.len() {
+        return None;
+    }
+    // 「re」と「res」のように重なるものがあるので、すべて試す
+    PREFIXES
+        .iter()
+        .filter(|x| lower.starts_with(*x))
+        .find_map(|prefix| {
+            let rest = &subject[prefix.len()..];
+            // 「[2]」「(2)」「^2」「*2」のような回数
+            let rest = strip_count(rest).unwrap_or(rest).trim_start();
+            rest.strip_prefix(':').or_else(|| rest.strip_prefix('：'))
+        })
+}
+
+// subject が prefix（「re:」「fwd:」など ASCII のもの）で始まるか（大文字・小文字は区別しない）
+// 「AB日本」のように prefix の長さの位置が文字の途中になることがあるので、get で切り出す
+pub(crate) fn starts_with_prefix(subject: &str, prefix: &str) -> bool {
+    subject
+        .get(..prefix.len())
+        .is_some_and(|x| x.eq_ignore_ascii_case(prefix))
+}
+
+fn strip_count(rest: &str) -> Option<&str> {
+    let (open, close) = match rest.chars().next()? {
+        '[' => ('[', Some(']')),
+        '(' => ('(', Some(')')),
+        '^' => ('^', None),
+        '*' => ('*', None),
+        _ => return None,
+    };
+    let digits = &rest[open.len_utf8()..];
+    let end = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    if end == 0 {
+        return None;
+    }
+    let after = &digits[end..];
+    match close {
+        Some(close) => after.strip_prefix(close),
+        None => Some(after),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_prefixes_and_tags() {
+        assert_eq!(normalize("Re: Fwd: Meeting"), "Meeting");
+        assert_eq!(normalize("RE[2]: AW: Angebot"), "Angebot");
+        assert_eq!(
+            normalize("Re^3:  [dev-list] Build   failed"),
+            "Build failed"
+        );
+        assert_eq!(normalize("回答: 【重要】 返信：請求書の件"), "請求書の件");
+        assert_eq!(normalize("[ml:123] Re: Release"), "Release");
+        assert_eq!(normalize("RES: Proposta"), "Proposta");
+        // 接頭辞に似た単語やタグだけの件名はそのまま
+        assert_eq!(normalize("Reminder: lunch"), "Reminder: lunch");
+        assert_eq!(normalize("Tree planting"), "Tree planting");
+        assert_eq!(normalize("[SPAM]"), "[SPAM]");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn check_ascii_prefix() {
+        assert!(starts_with_prefix("FWD: Meeting", "fwd:"));
+        assert!(!starts_with_prefix("AB日本", "fwd:"));
+        assert!(!starts_with_prefix("Re", "re:"));
+    }
+}
@@ -0,0 +1,296 @@
+// 差分同期の状態（フォルダーごとの最後の UID・MODSEQ・UIDVALIDITY）の保存
+// プロセスを再起動しても、前回の続きから新しいメールだけを読めるようにする
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::{
+    Capability, DeadlineExceeded, MyMailbox, MyMessage, MySession, ReadOptions, ResumeToken,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SyncState {
+    uid_validity: u32,
+    last_uid: u32,
+    modseq: Option<u64>,
+}
+
+impl SyncState {
+    pub fn new(uid_validity: u32, last_uid: u32, modseq: Option<u64>) -> Self {
+        Self {
+            uid_validity,
+            last_uid,
+            modseq,
+        }
+    }
+
+    pub fn uid_validity(&self) -> u32 {
+        self.uid_validity
+    }
+
+    // 前回までに読んだ最後の UID
+    pub fn last_uid(&self) -> u32 {
+        self.last_uid
+    }
+
+    // サーバーが CONDSTORE に対応していれば HIGHESTMODSEQ
+    pub fn modseq(&self) -> Option<u64> {
+        self.modseq
+    }
+}
+
+pub trait SyncStore {
+    fn load(&mut self, folder: &str) -> Result<Option<SyncState>, Box<dyn Error>>;
+    fn save(&mut self, folder: &str, state: &SyncState) -> Result<(), Box<dyn Error>>;
+}
+
+// プロセスの中だけで保持する（テストや常駐プロセス用）
+#[derive(Debug, Default)]
+pub struct MemorySyncStore {
+    states: HashMap<String, SyncState>,
+}
+
+impl MemorySyncStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SyncStore for MemorySyncStore {
+    fn load(&mut self, folder: &str) -> Result<Option<SyncState>, Box<dyn Error>> {
+        Ok(self.states.get(folder).copied())
+    }
+
+    fn save(&mut self, folder: &str, state: &SyncState) -> Result<(), Box<dyn Error>> {
+        self.states.insert(folder.to_string(), *state);
+        Ok(())
+    }
+}
+
+// 1行に1フォルダー「UIDVALIDITY<TAB>最後のUID<TAB>MODSEQ<TAB>フォルダー名」のテキストファイル
+#[derive(Debug)]
+pub struct FileSyncStore {
+    path: PathBuf,
+}
+
+impl FileSyncStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn read_all(&self) -> Result<Vec<(String, SyncState)>, Box<dyn Error>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut states = Vec::new();
+        for line in text.lines().filter(|x| !x.is_empty()) {
+            let fields = line.splitn(4, '\t').collect::<Vec<_>>();
+            if fields.len() != 4 {
+                return Err(format!("invalid sync state line: {}", line).into());
+            }
+            let modseq = match fields[2] {
+                "-" => None,
+                x => Some(x.parse()?),
+            };
+            states.push((
+                fields[3].to_string(),
+                SyncState::new(fields[0].parse()?, fields[1].parse()?, modseq),
+            ));
+        }
+        Ok(states)
+    }
+}
+
+impl SyncStore for FileSyncStore {
+    fn load(&mut self, folder: &str) -> Result<Option<SyncState>, Box<dyn Error>> {
+        Ok(self
+            .read_all()?
+            .into_iter()
+            .find(|(x, _)| x == folder)
+            .map(|(_, state)| state))
+    }
+
+    fn save(&mut self, folder: &str, state: &SyncState) -> Result<(), Box<dyn Error>> {
+        let mut states = self.read_all()?;
+        match states.iter_mut().find(|(x, _)| x == folder) {
+            Some((_, x)) => *x = *state,
+            None => states.push((folder.to_string(), *state)),
+        }
+
+        let text = states
+            .iter()
+            .map(|(folder, x)| {
+                let modseq = x.modseq.map_or("-".to_string(), |x| x.to_string());
+                format!(
+                    "{}\t{}\t{}\t{}\n",
+                    x.uid_validity, x.last_uid, modseq, folder
+                )
+            })
+            .collect::<String>();
+
+        // 書き込み途中で落ちても壊れないように、一時ファイルに書いてから置き換える
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+// 前回の同期以降に届いたメールだけを読み、状態を store に保存する
+// UIDVALIDITY が変わっていたら最初から読み直す
+pub fn read_new_mail(
+    mailbox: &MyMailbox,
+    options: &ReadOptions,
+    store: &mut dyn SyncStore,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    crate::read_folders(mailbox, options, |imap_session, folder| {
+        fetch_new(imap_session, folder, options, store)
+    })
+}
+
+pub(crate) fn fetch_new(
+    imap_session: &mut MySession,
+    folder: &str,
+    options: &ReadOptions,
+    store: &mut dyn SyncStore,
+) -> Result<Vec<MyMessage>, Box<dyn Error>> {
+    let selected = imap_session.select(folder)?;
+    let uid_validity = selected.uid_validity.ok_or("no UIDVALIDITY")?;
+    let last_uid = match store.load(folder)? {
+        Some(state) if state.uid_validity == uid_validity => state.last_uid,
+        _ => 0,
+    };
+
+    // 「n:*」は n より大きい UID がなくても最後のメールを返すので、自分でも絞り込む
+    let mut uids = imap_session
+        .uid_search(format!("UID {}:*", last_uid + 1))?
+        .into_iter()
+        .filter(|&x| x > last_uid)
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+
+    let mut messages = Vec::new();
+    let mut fetched_uid = last_uid;
+    for &uid in &uids {
+        // 締め切りを過ぎたら、読んだところまでを保存して止める
+        // （フラグの変化はまだ読んでいないので、MODSEQ は保存しない）
+        if crate::deadline::is_exceeded(options) {
+            store.save(folder, &SyncState::new(uid_validity, fetched_uid, None))?;
+            let resume = ResumeToken::new(folder, uid_validity, fetched_uid);
+            return Err(DeadlineExceeded::new(messages, resume).into());
+        }
+        messages.push(crate::fetch_message(imap_session, folder, uid, options)?);
+        fetched_uid = uid;
+    }
+
+    let modseq = highest_modseq(imap_session, folder)?;
+    let last_uid = uids.last().copied().unwrap_or(last_uid);
+    store.save(folder, &SyncState::new(uid_validity, last_uid, modseq))?;
+
+    Ok(messages)
+}
+
+// 前回の同期で持っていた UID（known_uids）のうち、その後サーバーから消えたものを返す
+// ローカルのキャッシュやデータベースから消すのに使う
+// UIDVALIDITY が state と変わっていれば、すべて消えたものとみなす
+pub fn diff_with_server(
+    mailbox: &MyMailbox,
+    folder: &str,
+    state: &SyncState,
+    known_uids: &[u32],
+) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut known = known_uids.to_vec();
+    known.sort_unstable();
+    known.dedup();
+    if known.is_empty() {
+        return Ok(known);
+    }
+
+    let mut imap_session = crate::connect(mailbox)?;
+    let selected = imap_session.examine(folder)?;
+    if selected.uid_validity != Some(state.uid_validity) {
+        imap_session.logout()?;
+        return Ok(known);
+    }
+    // 持っている UID だけに絞って検索し、返ってこなかったものが消えたもの
+    let query = format!("UID {}", crate::uid_set(&known));
+    let present = crate::esearch::search(&mut imap_session, &query, "ALL")?.uids();
+    imap_session.logout()?;
+    Ok(vanished(&known, &present))
+}
+
+fn vanished(known: &[u32], present: &[u32]) -> Vec<u32> {
+    known
+        .iter()
+        .copied()
+        .filter(|x| present.binary_search(x).is_err())
+        .collect()
+}
+
+// CONDSTORE（RFC 7162）に対応していれば STATUS で HIGHESTMODSEQ を取る
+fn highest_modseq(
+    imap_session: &mut MySession,
+    folder: &str,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    if !imap_session.supports(Capability::Condstore)? {
+        return Ok(None);
+    }
+    let response = imap_session.run_command_and_read_response(format!(
+        "STATUS {} (HIGHESTMODSEQ)",
+        crate::imap_quote(folder)
+    ))?;
+    let response = String::from_utf8_lossy(&response);
+    Ok(response
+        .split("HIGHESTMODSEQ")
+        .nth(1)
+        .and_then(|x| x.trim_start().split(|c: char| !c.is_ascii_digit()).next())
+        .and_then(|x| x.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("read-mail-sync-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileSyncStore::new(&path);
+        assert_eq!(store.load("INBOX").unwrap(), None);
+
+        store
+            .save("INBOX", &SyncState::new(1, 10, Some(100)))
+            .unwrap();
+        store
+            .save("[Gmail]/すべてのメール", &SyncState::new(2, 20, None))
+            .unwrap();
+        store
+            .save("INBOX", &SyncState::new(1, 11, Some(101)))
+            .unwrap();
+
+        // 別のインスタンス（再起動後）から読む
+        let mut store = FileSyncStore::new(&path);
+        assert_eq!(
+            store.load("INBOX").unwrap(),
+            Some(SyncState::new(1, 11, Some(101)))
+        );
+        assert_eq!(
+            store.load("[Gmail]/すべてのメール").unwrap(),
+            Some(SyncState::new(2, 20, None))
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_vanished_uids() {
+        assert_eq!(vanished(&[1, 2, 3, 5, 8], &[2, 3, 8]), [1, 5]);
+        assert_eq!(vanished(&[1, 2], &[1, 2]), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,236 @@
+// 送りすぎで制限されたときの待ち方
+// Gmail の「Account exceeded command or bandwidth limits」や Outlook の
+// 「Request is throttled. Suggested Backoff Time: 29868 milliseconds」のような NO が返ったら、
+// エラーにせずしばらく待ってから同じコマンドを送り直す
+//
+// 待って送り直すのは、コマンドへの NO・BAD だけ
+// Gmail の「* BYE [THROTTLED]」「* BYE [UNAVAILABLE]」のように BYE で切断されたときは、
+// imap クレートが BYE の内容を返さず ConnectionLost（接続が切れた）になるので、
+// ほかの理由で切れたときと見分けられず、待たずにそのままエラーにする
+// つなぎ直して続きから読む仕組みはないので、呼び出した側でつなぎ直す
+// （Watcher は max_backoff まで待ってつなぎ直す。read_mail などは最初から読み直すので、
+// 大きなフォルダーは ReadOptions::deadline で区切り、resume_from で続きから読むとやり直す量が少ない）
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleEvent {
+    // 制限されたので delay だけ待つ（attempt は 1 から）
+    Paused {
+        reason: String,
+        delay: Duration,
+        attempt: u32,
+    },
+    // 待ったあと、送り直したコマンドが通った
+    Resumed,
+}
+
+type Notify = Arc<dyn Fn(&ThrottleEvent) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Throttle {
+    max_retries: u32,
+    // 1回目に待つ時間（サーバーが待つ時間を示さなければ、送り直すたびに倍にする）
+    delay: Duration,
+    max_delay: Duration,
+    notify: Option<Notify>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(600),
+            notify: None,
+        }
+    }
+}
+
+impl fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Throttle")
+            .field("max_retries", &self.max_retries)
+            .field("delay", &self.delay)
+            .field("max_delay", &self.max_delay)
+            .finish()
+    }
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 0 なら待たずにエラーにする
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    // 待ち始めるとき・再開したときに呼ばれる
+    pub fn notify<F: Fn(&ThrottleEvent) + Send + Sync + 'static>(mut self, notify: F) -> Self {
+        self.notify = Some(Arc::new(notify));
+        self
+    }
+
+    // command を送り、制限されていれば待って送り直す
+    // BYE で切断されたとき（ConnectionLost）は送り直さない（モジュールの先頭を参照）
+    pub(crate) fn run<T, F>(&self, mut command: F) -> Result<T, Box<dyn Error>>
+    where
+        F: FnMut() -> imap::error::Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            let reason = match command() {
+                Ok(value) => {
+                    if attempt > 0 {
+                        self.send(&ThrottleEvent::Resumed);
+                    }
+                    return Ok(value);
+                }
+                Err(imap::error::Error::No(text)) | Err(imap::error::Error::Bad(text))
+                    if is_throttled(&text) && attempt < self.max_retries =>
+                {
+                    text
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let delay = suggested_delay(&reason)
+                .unwrap_or_else(|| self.delay * 2u32.saturating_pow(attempt))
+                .min(self.max_delay);
+            attempt += 1;
+            self.send(&ThrottleEvent::Paused {
+                reason,
+                delay,
+                attempt,
+            });
+            thread::sleep(delay);
+        }
+    }
+
+    fn send(&self, event: &ThrottleEvent) {
+        if let Some(notify) = &self.notify {
+            notify(event);
+        }
+    }
+}
+
+// 制限されたことを示す応答か
+pub(crate) fn is_throttled(text: &str) -> bool {
+    let text = text.to_ascii_lowercase();
+    [
+        "throttl",
+        "bandwidth limit",
+        "exceeded command",
+        "too many",
+        "rate limit",
+        "try again later",
+    ]
+    .iter()
+    .any(|x| text.contains(x))
+}
+
+// 「Suggested Backoff Time: 29868 milliseconds」（Outlook）
+fn suggested_delay(text: &str) -> Option<Duration> {
+    let lower = text.to_ascii_lowercase();
+    let rest = &lower[lower.find("backoff time:")? + "backoff time:".len()..];
+    let number = rest
+        .trim_start()
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_millis(number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn detect_throttling() {
+        assert!(is_throttled(
+            "Account exceeded command or bandwidth limits."
+        ));
+        assert!(is_throttled(
+            "Request is throttled. Suggested Backoff Time: 29868 milliseconds"
+        ));
+        assert!(!is_throttled("Mailbox does not exist"));
+        assert_eq!(
+            suggested_delay("Request is throttled. Suggested Backoff Time: 29868 milliseconds"),
+            Some(Duration::from_millis(29868))
+        );
+        assert_eq!(suggested_delay("Account exceeded bandwidth limits"), None);
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let throttle = {
+            let events = events.clone();
+            Throttle::new()
+                .delay(Duration::from_millis(1))
+                .notify(move |x| events.lock().unwrap().push(x.clone()))
+        };
+
+        let mut responses = vec![
+            Ok(7),
+            Err(imap::error::Error::No(
+                "Too many simultaneous connections".to_string(),
+            )),
+        ];
+        let value = throttle.run(|| responses.pop().unwrap()).unwrap();
+        assert_eq!(value, 7);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ThrottleEvent::Paused {
+                    reason: "Too many simultaneous connections".to_string(),
+                    delay: Duration::from_millis(1),
+                    attempt: 1,
+                },
+                ThrottleEvent::Resumed,
+            ]
+        );
+
+        // 制限以外の NO と、回数を超えたときはエラー
+        let result = throttle.run(|| -> imap::error::Result<()> {
+            Err(imap::error::Error::No("Mailbox does not exist".to_string()))
+        });
+        assert!(result.is_err());
+        let result = throttle
+            .clone()
+            .max_retries(0)
+            .run(|| -> imap::error::Result<()> {
+                Err(imap::error::Error::No(
+                    "Too many simultaneous connections".to_string(),
+                ))
+            });
+        assert!(result.is_err());
+
+        // BYE で切断されたときは送り直さない
+        let mut calls = 0;
+        let result = throttle.run(|| -> imap::error::Result<()> {
+            calls += 1;
+            Err(imap::error::Error::ConnectionLost)
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,382 @@
+// application/ms-tnef（winmail.dat）のデコード
+// https://docs.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxtnef/
+// https://docs.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxrtfcp/
+
+use std::convert::TryInto;
+use std::error::Error;
+
+use crate::AttachmentInfo;
+
+const TNEF_SIGNATURE: u32 = 0x223E_9F78;
+
+const LVL_ATTACHMENT: u8 = 0x02;
+
+const ATT_ATTACH_REND_DATA: u32 = 0x0006_9002;
+const ATT_ATTACH_TITLE: u32 = 0x0001_8010;
+const ATT_ATTACH_DATA: u32 = 0x0006_800F;
+const ATT_ATTACHMENT: u32 = 0x0006_9005;
+const ATT_MAPI_PROPS: u32 = 0x0006_9003;
+
+const PR_RTF_COMPRESSED: u16 = 0x1009;
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+const PR_ATTACH_MIME_TAG: u16 = 0x370E;
+
+// 圧縮した RTF を展開するときに、先に確保しておく大きさの上限
+const MAX_RTF_PREALLOC: usize = 1024 * 1024;
+
+// TNEF から取り出した中身
+pub(crate) struct Tnef {
+    pub(crate) attachments: Vec<AttachmentInfo>,
+    pub(crate) rtf_body: Option<String>,
+}
+
+// 添付ファイル1つ分（ファイル名などは複数の属性に分かれて入っている）
+#[derive(Default)]
+struct TnefAttachment {
+    title: Option<String>,
+    long_filename: Option<String>,
+    mimetype: Option<String>,
+    data: Vec<u8>,
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<Tnef, Box<dyn Error>> {
+    let mut reader = Reader::new(data);
+    if reader.u32()? != TNEF_SIGNATURE {
+        return Err("invalid TNEF signature".into());
+    }
+    // LegacyKey
+    reader.u16()?;
+
+    let mut attachments: Vec<TnefAttachment> = Vec::new();
+    let mut rtf_body = None;
+
+    while !reader.is_empty() {
+        let level = reader.u8()?;
+        let id = reader.u32()?;
+        let length = reader.u32()? as usize;
+        let value = reader.bytes(length)?;
+        // checksum
+        reader.u16()?;
+
+        match (level, id) {
+            (LVL_ATTACHMENT, ATT_ATTACH_REND_DATA) => attachments.push(TnefAttachment::default()),
+            (LVL_ATTACHMENT, ATT_ATTACH_TITLE) => {
+                if let Some(attachment) = attachments.last_mut() {
+                    attachment.title = Some(c_string(value));
+                }
+            }
+            (LVL_ATTACHMENT, ATT_ATTACH_DATA) => {
+                if let Some(attachment) = attachments.last_mut() {
+                    attachment.data = value.to_vec();
+                }
+            }
+            (LVL_ATTACHMENT, ATT_ATTACHMENT) => {
+                if let Some(attachment) = attachments.last_mut() {
+                    for (prop_id, value) in mapi_props(value)? {
+                        match prop_id {
+                            PR_ATTACH_LONG_FILENAME => {
+                                attachment.long_filename = Some(c_string(&value))
+                            }
+                            PR_ATTACH_MIME_TAG => attachment.mimetype = Some(c_string(&value)),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            (_, ATT_MAPI_PROPS) => {
+                for (prop_id, value) in mapi_props(value)? {
+                    if prop_id == PR_RTF_COMPRESSED {
+                        let rtf = decompress_rtf(&value)?;
+                        rtf_body = Some(String::from_utf8_lossy(&rtf).into_owned());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let attachments = attachments
+        .into_iter()
+        .map(|x| {
+            AttachmentInfo::new(
+                x.long_filename.or(x.title),
+                x.mimetype
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                x.data,
+            )
+        })
+        .collect();
+
+    Ok(Tnef {
+        attachments,
+        rtf_body,
+    })
+}
+
+// (プロパティID, 値)
+type MapiProp = (u16, Vec<u8>);
+
+// MAPI プロパティ一覧を読む
+// 値が可変長のもの（文字列・バイナリ）は1つ目の値だけを返す
+fn mapi_props(data: &[u8]) -> Result<Vec<MapiProp>, Box<dyn Error>> {
+    let mut reader = Reader::new(data);
+    let count = reader.u32()?;
+    let mut props = Vec::new();
+
+    for _ in 0..count {
+        let prop_type = reader.u16()?;
+        let prop_id = reader.u16()?;
+
+        // 名前付きプロパティ
+        if prop_id >= 0x8000 {
+            reader.bytes(16)?;
+            if reader.u32()? == 0 {
+                reader.u32()?;
+            } else {
+                let length = reader.u32()? as usize;
+                reader.padded_bytes(length)?;
+            }
+        }
+
+        let multi_valued = prop_type & 0x1000 != 0;
+        let value_count = if multi_valued { reader.u32()? } else { 1 };
+        let mut first = None;
+        for _ in 0..value_count {
+            let value = match prop_type & !0x1000 {
+                // PT_SHORT, PT_LONG, PT_FLOAT, PT_ERROR, PT_BOOLEAN
+                0x0002 | 0x0003 | 0x0004 | 0x000A | 0x000B => reader.bytes(4)?.to_vec(),
+                // PT_DOUBLE, PT_CURRENCY, PT_APPTIME, PT_I8, PT_SYSTIME
+                0x0005 | 0x0006 | 0x0007 | 0x0014 | 0x0040 => reader.bytes(8)?.to_vec(),
+                // PT_CLSID
+                0x0048 => reader.bytes(16)?.to_vec(),
+                // PT_OBJECT, PT_STRING8, PT_UNICODE, PT_BINARY
+                0x000D | 0x001E | 0x001F | 0x0102 => {
+                    let mut values = Vec::new();
+                    let inner_count = if multi_valued { 1 } else { reader.u32()? };
+                    for _ in 0..inner_count {
+                        let length = reader.u32()? as usize;
+                        values.push(reader.padded_bytes(length)?.to_vec());
+                    }
+                    let mut values = values.into_iter();
+                    let value = values.next().unwrap_or_default();
+                    if prop_type & !0x1000 == 0x001F {
+                        utf16_to_utf8(&value)
+                    } else {
+                        value
+                    }
+                }
+                _ => return Err(format!("unknown MAPI property type: {:#06x}", prop_type).into()),
+            };
+            first.get_or_insert(value);
+        }
+        props.push((prop_id, first.unwrap_or_default()));
+    }
+
+    Ok(props)
+}
+
+// 圧縮 RTF（LZFu）の展開
+fn decompress_rtf(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    const PREBUF: &[u8] = b"{\\rtf1\\ansi\\mac\\deff0\\deftab720{\\fonttbl;}\
+{\\f0\\fnil \\froman \\fswiss \\fmodern \\fscript \\fdecor MS Sans SerifSymbolArialTimes New RomanCourier\
+{\\colortbl\\red0\\green0\\blue0\r\n\\par \\pard\\plain\\f0\\fs20\\b\\i\\u\\tab\\tx";
+    const COMPRESSED: u32 = 0x7546_5A4C; // "LZFu"
+    const UNCOMPRESSED: u32 = 0x414C_454D; // "MELA"
+
+    let mut reader = Reader::new(data);
+    let comp_size = reader.u32()? as usize;
+    let raw_size = reader.u32()? as usize;
+    let comp_type = reader.u32()?;
+    // CRC
+    reader.u32()?;
+    let body = reader.bytes(
+        comp_size
+            .saturating_sub(12)
+            .min(data.len().saturating_sub(16)),
+    )?;
+
+    match comp_type {
+        UNCOMPRESSED => Ok(body[..raw_size.min(body.len())].to_vec()),
+        COMPRESSED => {
+            let mut dict = [0u8; 4096];
+            dict[..PREBUF.len()].copy_from_slice(PREBUF);
+            let mut write_pos = PREBUF.len();
+            // raw_size は添付ファイルに書かれた値なので、そのまま確保しない
+            // （参照 1 つは 2 バイトで最大 17 バイトになるので、中身の 9 倍までしか増えない）
+            let mut output = Vec::with_capacity(raw_size.min(body.len() * 9).min(MAX_RTF_PREALLOC));
+            let mut reader = Reader::new(body);
+
+            'outer: while !reader.is_empty() {
+                let control = reader.u8()?;
+                for bit in 0..8 {
+                    if reader.is_empty() || output.len() >= raw_size {
+                        break 'outer;
+                    }
+                    if control & (1 << bit) == 0 {
+                        let c = reader.u8()?;
+                        output.push(c);
+                        dict[write_pos] = c;
+                        write_pos = (write_pos + 1) % dict.len();
+                    } else {
+                        let reference = u16::from_be_bytes([reader.u8()?, reader.u8()?]);
+                        let mut offset = (reference >> 4) as usize;
+                        let length = (reference & 0x0F) as usize + 2;
+                        if offset == write_pos {
+                            break 'outer;
+                        }
+                        for _ in 0..length {
+                            let c = dict[offset];
+                            output.push(c);
+                            dict[write_pos] = c;
+                            offset = (offset + 1) % dict.len();
+                            write_pos = (write_pos + 1) % dict.len();
+                        }
+                    }
+                }
+            }
+            output.truncate(raw_size);
+            Ok(output)
+        }
+        _ => Err("unknown compressed RTF type".into()),
+    }
+}
+
+// NUL 終端の文字列（attAttachTitle など）
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn utf16_to_utf8(data: &[u8]) -> Vec<u8> {
+    let units = data
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .take_while(|&x| x != 0)
+        .collect::<Vec<_>>();
+    String::from_utf16_lossy(&units).into_bytes()
+}
+
+// リトルエンディアンのバイト列を先頭から読む
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.data.len() < length {
+            return Err("truncated TNEF data".into());
+        }
+        let (head, tail) = self.data.split_at(length);
+        self.data = tail;
+        Ok(head)
+    }
+
+    // 4 バイト境界まで詰め物がある値
+    fn padded_bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let value = self.bytes(length)?;
+        self.bytes((4 - length % 4) % 4)?;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(level: u8, id: u32, value: &[u8]) -> Vec<u8> {
+        let mut data = vec![level];
+        data.extend(&id.to_le_bytes());
+        data.extend(&(value.len() as u32).to_le_bytes());
+        data.extend(value);
+        let checksum = value
+            .iter()
+            .fold(0u16, |sum, &x| sum.wrapping_add(x as u16));
+        data.extend(&checksum.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn decompress_lzfu() {
+        // MS-OXRTFCP 4.1 の例
+        let compressed = [
+            0x2d, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00, 0x4c, 0x5a, 0x46, 0x75, 0xf1, 0xc5,
+            0xc7, 0xa7, 0x03, 0x00, 0x0a, 0x00, 0x72, 0x63, 0x70, 0x67, 0x31, 0x32, 0x35, 0x42,
+            0x32, 0x0a, 0xf3, 0x20, 0x68, 0x65, 0x6c, 0x09, 0x00, 0x20, 0x62, 0x77, 0x05, 0xb0,
+            0x6c, 0x64, 0x7d, 0x0a, 0x80, 0x0f, 0xa0,
+        ];
+        assert_eq!(
+            decompress_rtf(&compressed).unwrap(),
+            b"{\\rtf1\\ansi\\ansicpg1252\\pard hello world}\r\n"
+        );
+    }
+
+    #[test]
+    fn limit_rtf_size() {
+        // 展開後の大きさに 4 GiB 近くを書いた、中身が少しだけのもの
+        let mut compressed = Vec::new();
+        compressed.extend(&17u32.to_le_bytes());
+        compressed.extend(&u32::MAX.to_le_bytes());
+        compressed.extend(b"LZFu");
+        compressed.extend(&0u32.to_le_bytes());
+        compressed.extend(&[0x00, b'a', b'b', b'c', b'd']);
+        assert_eq!(decompress_rtf(&compressed).unwrap(), b"abcd");
+
+        // raw_size まで展開したら止める
+        compressed[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(decompress_rtf(&compressed).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn decode_attachments_and_rtf() {
+        let rtf = b"{\\rtf1 hello}";
+        let mut compressed_rtf = Vec::new();
+        compressed_rtf.extend(&(rtf.len() as u32 + 12).to_le_bytes());
+        compressed_rtf.extend(&(rtf.len() as u32).to_le_bytes());
+        compressed_rtf.extend(b"MELA");
+        compressed_rtf.extend(&0u32.to_le_bytes());
+        compressed_rtf.extend(rtf);
+
+        let mut props = Vec::new();
+        props.extend(&1u32.to_le_bytes());
+        props.extend(&0x0102u16.to_le_bytes());
+        props.extend(&PR_RTF_COMPRESSED.to_le_bytes());
+        props.extend(&1u32.to_le_bytes());
+        props.extend(&(compressed_rtf.len() as u32).to_le_bytes());
+        props.extend(&compressed_rtf);
+        props.resize(props.len() + (4 - compressed_rtf.len() % 4) % 4, 0);
+
+        let mut data = Vec::new();
+        data.extend(&TNEF_SIGNATURE.to_le_bytes());
+        data.extend(&0u16.to_le_bytes());
+        data.extend(attribute(0x01, ATT_MAPI_PROPS, &props));
+        data.extend(attribute(LVL_ATTACHMENT, ATT_ATTACH_REND_DATA, &[0; 14]));
+        data.extend(attribute(LVL_ATTACHMENT, ATT_ATTACH_TITLE, b"report.pdf\0"));
+        data.extend(attribute(LVL_ATTACHMENT, ATT_ATTACH_DATA, b"%PDF-1.4"));
+
+        let tnef = decode(&data).unwrap();
+        assert_eq!(tnef.rtf_body.as_deref(), Some("{\\rtf1 hello}"));
+        assert_eq!(tnef.attachments.len(), 1);
+        assert_eq!(tnef.attachments[0].filename(), Some("report.pdf"));
+        assert_eq!(tnef.attachments[0].data(), b"%PDF-1.4");
+    }
+}
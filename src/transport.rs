@@ -0,0 +1,169 @@
+// サーバーとの接続
+// 普通は TLS（最初から TLS か STARTTLS）、tunnel を指定したときはコマンド
+// （「ssh host dovecot --exec-mail imap」など）の標準入出力を使う
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use native_tls::TlsStream;
+
+use crate::MyMailbox;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Security {
+    // 最初から TLS（普通はポート 993）
+    Tls,
+    // 平文で接続してから STARTTLS（普通はポート 143）
+    StartTls,
+    // 993 の TLS、だめなら 143 の STARTTLS を試す（MyMailbox の port は使わない）
+    Auto,
+}
+
+impl Security {
+    // 試す（ポート, 方法）の順
+    pub(crate) fn attempts(self, port: u16) -> Vec<(u16, Security)> {
+        match self {
+            Security::Auto => vec![(993, Security::Tls), (143, Security::StartTls)],
+            x => vec![(port, x)],
+        }
+    }
+}
+
+pub(crate) enum Transport {
+    Tls(TlsStream<TcpStream>),
+    Tunnel(Child),
+}
+
+impl Transport {
+    // host・port に接続する（tunnel を指定したときは host・port は使わない）
+    pub(crate) fn open(mailbox: &MyMailbox, host: &str, port: u16) -> Result<Self, Box<dyn Error>> {
+        match mailbox.tunnel {
+            Some(command) => {
+                let child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                Ok(Transport::Tunnel(child))
+            }
+            None => {
+                let tcp = TcpStream::connect((host, port))?;
+                Ok(Transport::Tls(tls_connect(host, tcp)?))
+            }
+        }
+    }
+
+    // 平文で接続し、挨拶を読んでから STARTTLS で TLS にする
+    // STARTTLS の前に受け取ったケーパビリティは使えない（RFC 3501）ので、挨拶は捨てる
+    pub(crate) fn open_starttls(host: &str, port: u16) -> Result<Self, Box<dyn Error>> {
+        let tcp = TcpStream::connect((host, port))?;
+        starttls(&tcp)?;
+        Ok(Transport::Tls(tls_connect(host, tcp)?))
+    }
+
+    // トンネルでは読み込みのタイムアウトを設定できない（IDLE は次の通知まで待つ）
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Transport::Tls(stream) => stream.get_ref().set_read_timeout(timeout),
+            Transport::Tunnel(_) => Ok(()),
+        }
+    }
+}
+
+// 平文の接続で挨拶を読み、STARTTLS を送って OK を待つ
+pub(crate) fn starttls(tcp: &TcpStream) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(tcp.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("* OK") {
+        return Err(format!("unexpected greeting: {}", line.trim_end()).into());
+    }
+
+    let mut writer = tcp;
+    writer.write_all(b"s0 STARTTLS\r\n")?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("connection closed during STARTTLS".into());
+        }
+        if let Some(result) = line.strip_prefix("s0 ") {
+            if !result.starts_with("OK") {
+                return Err(format!("STARTTLS failed: {}", result.trim_end()).into());
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+// TLS のハンドシェイクをする
+pub(crate) fn tls_connect(
+    host: &str,
+    tcp: TcpStream,
+) -> Result<TlsStream<TcpStream>, Box<dyn Error>> {
+    let tls = native_tls::TlsConnector::builder().build()?;
+    Ok(tls.connect(host, tcp)?)
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "tunnel is closed")
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tls(stream) => stream.read(buf),
+            Transport::Tunnel(child) => child.stdout.as_mut().ok_or_else(closed)?.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tls(stream) => stream.write(buf),
+            Transport::Tunnel(child) => child.stdin.as_mut().ok_or_else(closed)?.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tls(stream) => stream.flush(),
+            Transport::Tunnel(child) => child.stdin.as_mut().ok_or_else(closed)?.flush(),
+        }
+    }
+}
+
+impl Drop for Transport {
+    // ログアウトしないまま終わったときもトンネルのプロセスを残さない
+    fn drop(&mut self) {
+        if let Transport::Tunnel(child) = self {
+            drop(child.stdin.take());
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn security_attempts() {
+        assert_eq!(
+            Security::Auto.attempts(10993),
+            [(993, Security::Tls), (143, Security::StartTls)]
+        );
+        assert_eq!(
+            Security::StartTls.attempts(1143),
+            [(1143, Security::StartTls)]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// コピー・移動・追加したメールの、コピー先での UID（UIDPLUS 拡張、RFC 4315）
+// 検索し直さなくても、どのメールがどこへ行ったかを追える
+
+use std::error::Error;
+
+use crate::{MyMailbox, MySession};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UidMapping {
+    uid_validity: u32,
+    pairs: Vec<(u32, u32)>,
+}
+
+impl UidMapping {
+    // コピー先のフォルダーの UIDVALIDITY
+    pub fn uid_validity(&self) -> u32 {
+        self.uid_validity
+    }
+
+    // （コピー元の UID, コピー先の UID）
+    pub fn pairs(&self) -> &[(u32, u32)] {
+        &self.pairs
+    }
+
+    pub fn destination(&self, source: u32) -> Option<u32> {
+        self.pairs.iter().find(|x| x.0 == source).map(|x| x.1)
+    }
+}
+
+// mailbox.selection のメールを to にコピーする
+// サーバーが UIDPLUS に対応していなければ None
+pub fn copy_messages(
+    mailbox: &MyMailbox,
+    uids: &[u32],
+    to: &str,
+) -> Result<Option<UidMapping>, Box<dyn Error>> {
+    transfer(mailbox, uids, to, copy_uids)
+}
+
+// mailbox.selection のメールを to に移動する
+pub fn move_messages(
+    mailbox: &MyMailbox,
+    uids: &[u32],
+    to: &str,
+) -> Result<Option<UidMapping>, Box<dyn Error>> {
+    transfer(mailbox, uids, to, crate::move_uids)
+}
+
+fn transfer<F>(
+    mailbox: &MyMailbox,
+    uids: &[u32],
+    to: &str,
+    f: F,
+) -> Result<Option<UidMapping>, Box<dyn Error>>
+where
+    F: FnOnce(&mut MySession, &str, &str) -> Result<Option<UidMapping>, Box<dyn Error>>,
+{
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    uids.dedup();
+    if uids.is_empty() {
+        return Ok(None);
+    }
+    let mut imap_session = crate::connect(mailbox)?;
+    imap_session.select(mailbox.selection)?;
+    let mapping = f(&mut imap_session, &crate::uid_set(&uids), to)?;
+    imap_session.logout()?;
+    Ok(mapping)
+}
+
+pub(crate) fn copy_uids(
+    imap_session: &mut MySession,
+    uid_set: &str,
+    to: &str,
+) -> Result<Option<UidMapping>, Box<dyn Error>> {
+    let ((), codes) = imap_session.with_response_codes(|imap_session| {
+        imap_session.uid_copy(uid_set, to)?;
+        Ok(())
+    })?;
+    Ok(codes.iter().find_map(|x| parse_copyuid(x)))
+}
+
+// 「COPYUID 38505 304,319:320 3956:3958」
+pub(crate) fn parse_copyuid(code: &[u8]) -> Option<UidMapping> {
+    let code = std::str::from_utf8(code).ok()?;
+    let mut words = code.split(' ');
+    if !words.next()?.eq_ignore_ascii_case("COPYUID") {
+        return None;
+    }
+    let uid_validity = words.next()?.parse().ok()?;
+    let sources = crate::esearch::expand_uid_set(words.next()?);
+    let destinations = crate::esearch::expand_uid_set(words.next()?);
+    if sources.len() != destinations.len() {
+        return None;
+    }
+    Some(UidMapping {
+        uid_validity,
+        pairs: sources.into_iter().zip(destinations).collect(),
+    })
+}
+
+// 「APPENDUID 38505 3955」の UID
+pub(crate) fn parse_appenduid(code: &[u8]) -> Option<u32> {
+    let code = std::str::from_utf8(code).ok()?;
+    let mut words = code.split(' ');
+    if !words.next()?.eq_ignore_ascii_case("APPENDUID") {
+        return None;
+    }
+    words.next()?;
+    crate::esearch::expand_uid_set(words.next()?)
+        .first()
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_uidplus_codes() {
+        let mapping = parse_copyuid(b"COPYUID 38505 304,319:320 3956:3958").unwrap();
+        assert_eq!(mapping.uid_validity(), 38505);
+        assert_eq!(mapping.pairs(), [(304, 3956), (319, 3957), (320, 3958)]);
+        assert_eq!(mapping.destination(319), Some(3957));
+        assert_eq!(parse_copyuid(b"COPYUID 1 1:3 5"), None);
+
+        assert_eq!(parse_appenduid(b"APPENDUID 38505 3955"), Some(3955));
+        assert_eq!(parse_appenduid(b"READ-WRITE"), None);
+    }
+}
@@ -0,0 +1,338 @@
+// 手元の mbox や Maildir のメールを、IMAP のフォルダーに APPEND する（プロバイダーの移行用）
+// 受信日時とフラグ（mbox の Status・X-Status、Maildir のファイル名の「:2,RS」など）も引き継ぐ
+// 追加したメールは進み具合のファイルに1行ずつ記録し、途中で失敗しても続きから追加し直せる
+//
+// <mbox>.read-mail-upload       追加したメールの、mbox の中での位置（バイト）
+// <Maildir>/.read-mail-upload   追加したメールのファイル名（「:2,」より前）
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use imap::types::Flag;
+use mailparse::{parse_headers, MailHeaderMap};
+
+use crate::MyMailbox;
+
+const PROGRESS: &str = ".read-mail-upload";
+
+#[derive(Debug, PartialEq)]
+struct LocalMessage {
+    // 進み具合のファイルに書く、メールを見分ける値
+    key: String,
+    content: Vec<u8>,
+    flags: Vec<Flag<'static>>,
+    date: Option<DateTime<FixedOffset>>,
+}
+
+// source（mbox のファイルか Maildir のディレクトリ）のメールを folder に追加し、追加したメールの数を返す
+// 前回途中で失敗していれば、追加済みのメールは飛ばす（最初からやり直すには進み具合のファイルを消す）
+pub fn upload<P: AsRef<Path>>(
+    source: P,
+    mailbox: &MyMailbox,
+    folder: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let source = source.as_ref();
+    let progress_path = if source.is_dir() {
+        source.join(PROGRESS)
+    } else {
+        let mut name = source.as_os_str().to_os_string();
+        name.push(PROGRESS);
+        PathBuf::from(name)
+    };
+    let done = match fs::read_to_string(&progress_path) {
+        Ok(text) => text.lines().map(str::to_string).collect::<HashSet<_>>(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut imap_session = crate::connect(mailbox)?;
+    let (existing, _) = crate::archive::list_folders(&mut imap_session)?;
+    if !existing.contains(folder) && !folder.eq_ignore_ascii_case("INBOX") {
+        imap_session.create(folder)?;
+    }
+
+    let mut progress = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&progress_path)?;
+    let messages: Box<dyn Iterator<Item = io::Result<LocalMessage>>> = if source.is_dir() {
+        Box::new(maildir_files(source)?.into_iter().map(|x| read_maildir(&x)))
+    } else {
+        Box::new(Mbox::new(BufReader::new(File::open(source)?)))
+    };
+    let mut count = 0;
+    for message in messages {
+        let message = message?;
+        if done.contains(&message.key) {
+            continue;
+        }
+        let date = message.date.or_else(|| header_date(&message.content));
+        imap_session.append_message(folder, &message.content, &message.flags, date)?;
+        writeln!(progress, "{}", message.key)?;
+        progress.flush()?;
+        count += 1;
+    }
+    imap_session.logout()?;
+    Ok(count)
+}
+
+// mbox のメールを1通ずつ読む（「From 」で始まる行で区切る、mboxrd の「>From 」も元に戻す）
+struct Mbox<R: BufRead> {
+    reader: R,
+    // 読んだバイト数
+    position: u64,
+    // 次のメールの「From 」の行と、その位置
+    next: Option<(Vec<u8>, u64)>,
+}
+
+impl<R: BufRead> Mbox<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            position: 0,
+            next: None,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        let n = self.reader.read_until(b'\n', &mut line)?;
+        self.position += n as u64;
+        Ok(if n == 0 { None } else { Some(line) })
+    }
+}
+
+impl<R: BufRead> Iterator for Mbox<R> {
+    type Item = io::Result<LocalMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (from_line, start) = match self.next.take() {
+            Some(next) => next,
+            // 最初の「From 」の行を探す
+            None => loop {
+                let start = self.position;
+                match self.read_line() {
+                    Ok(Some(line)) if line.starts_with(b"From ") => break (line, start),
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return None,
+                    Err(e) => return Some(Err(e)),
+                }
+            },
+        };
+
+        let mut content = Vec::new();
+        loop {
+            let start = self.position;
+            match self.read_line() {
+                Ok(Some(line)) if line.starts_with(b"From ") => {
+                    self.next = Some((line, start));
+                    break;
+                }
+                Ok(Some(line)) => content.extend(unescape_from(&line)),
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        // 区切りの前の空行はメールに含めない
+        if content.ends_with(b"\r\n\r\n") {
+            content.truncate(content.len() - 2);
+        } else if content.ends_with(b"\n\n") {
+            content.truncate(content.len() - 1);
+        }
+
+        Some(Ok(LocalMessage {
+            key: start.to_string(),
+            flags: mbox_flags(&content),
+            date: envelope_date(&from_line),
+            content: crlf(&content),
+        }))
+    }
+}
+
+// 「>From 」「>>From 」の「>」を1つ取る
+fn unescape_from(line: &[u8]) -> &[u8] {
+    let quotes = line.iter().take_while(|&&x| x == b'>').count();
+    if quotes > 0 && line[quotes..].starts_with(b"From ") {
+        &line[1..]
+    } else {
+        line
+    }
+}
+
+// 「From taro@example.com Mon Jan  1 10:00:00 2024」の日時（UTC とみなす）
+fn envelope_date(line: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let line = String::from_utf8_lossy(line);
+    let fields = line.split_whitespace().skip(2).take(5).collect::<Vec<_>>();
+    let date = NaiveDateTime::parse_from_str(&fields.join(" "), "%a %b %e %H:%M:%S %Y").ok()?;
+    Some(date.and_utc().fixed_offset())
+}
+
+// mutt や Thunderbird が書く Status（R は既読）と X-Status（A 返信済み、F フラグ、T 下書き、D 削除）
+fn mbox_flags(content: &[u8]) -> Vec<Flag<'static>> {
+    let headers = match parse_headers(content) {
+        Ok((headers, _)) => headers,
+        Err(_) => return Vec::new(),
+    };
+    let status = headers.get_first_value("Status").unwrap_or_default()
+        + &headers.get_first_value("X-Status").unwrap_or_default();
+    let mut flags = Vec::new();
+    for (c, flag) in [
+        ('R', Flag::Seen),
+        ('A', Flag::Answered),
+        ('F', Flag::Flagged),
+        ('T', Flag::Draft),
+        ('D', Flag::Deleted),
+    ] {
+        if status.contains(c) {
+            flags.push(flag);
+        }
+    }
+    flags
+}
+
+// Maildir の cur と new のメールのファイル（ファイル名の順）
+fn maildir_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for sub in ["cur", "new"].iter() {
+        let sub_dir = dir.join(sub);
+        if !sub_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(sub_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(files)
+}
+
+// 受信日時はファイルの更新日時（Dovecot などは受信日時にしている）
+fn read_maildir(path: &Path) -> io::Result<LocalMessage> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (key, info) = match name.find(':') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (&name[..], ""),
+    };
+    let date = fs::metadata(path)?
+        .modified()
+        .ok()
+        .map(|x| DateTime::<chrono::Utc>::from(x).fixed_offset());
+    Ok(LocalMessage {
+        key: key.to_string(),
+        flags: maildir_flags(info),
+        date,
+        content: crlf(&fs::read(path)?),
+    })
+}
+
+// 「2,FRS」のようなファイル名の後ろの部分（P 転送済みは IMAP にないので使わない）
+fn maildir_flags(info: &str) -> Vec<Flag<'static>> {
+    let letters = match info.strip_prefix("2,") {
+        Some(letters) => letters,
+        None => return Vec::new(),
+    };
+    let mut flags = Vec::new();
+    for c in letters.chars() {
+        let flag = match c {
+            'S' => Flag::Seen,
+            'R' => Flag::Answered,
+            'F' => Flag::Flagged,
+            'D' => Flag::Draft,
+            'T' => Flag::Deleted,
+            _ => continue,
+        };
+        flags.push(flag);
+    }
+    flags
+}
+
+// 改行を IMAP の CRLF にそろえる
+fn crlf(content: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(content.len() + content.len() / 32);
+    for (i, &x) in content.iter().enumerate() {
+        if x == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(x);
+    }
+    converted
+}
+
+fn header_date(content: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let (headers, _) = parse_headers(content).ok()?;
+    DateTime::parse_from_rfc2822(headers.get_first_value("Date")?.trim()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_mbox() {
+        let mbox = b"From taro@example.com Mon Jan  1 10:00:00 2024\n\
+                     From: taro@example.com\nSubject: one\nStatus: RO\nX-Status: A\n\n\
+                     >From the start\n>>From here\n\n\
+                     From jiro@example.com Tue Jan  2 11:30:00 2024\n\
+                     From: jiro@example.com\nSubject: two\n\nbody\n";
+        let messages = Mbox::new(&mbox[..])
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+
+        assert_eq!(messages[0].key, "0");
+        assert_eq!(
+            String::from_utf8_lossy(&messages[0].content),
+            "From: taro@example.com\r\nSubject: one\r\nStatus: RO\r\nX-Status: A\r\n\r\n\
+             From the start\r\n>From here\r\n"
+        );
+        assert_eq!(messages[0].flags, [Flag::Seen, Flag::Answered]);
+        assert_eq!(
+            messages[0].date.unwrap().to_rfc3339(),
+            "2024-01-01T10:00:00+00:00"
+        );
+
+        assert_eq!(messages[1].key, "136");
+        assert_eq!(messages[1].flags, []);
+        assert!(messages[1].content.ends_with(b"\r\n\r\nbody\r\n"));
+        assert_eq!(
+            messages[1].date.unwrap().to_rfc3339(),
+            "2024-01-02T11:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn read_maildir_flags() {
+        assert_eq!(
+            maildir_flags("2,DFRS"),
+            [Flag::Draft, Flag::Flagged, Flag::Answered, Flag::Seen]
+        );
+        assert_eq!(maildir_flags("2,P"), []);
+        assert_eq!(maildir_flags(""), []);
+
+        let dir = std::env::temp_dir().join(format!("read-mail-maildir-{}", std::process::id()));
+        for sub in ["cur", "new", "tmp"].iter() {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("cur/1700000000.1.host:2,S"), "Subject: a\n\nx\n").unwrap();
+        fs::write(dir.join("new/1700000001.2.host"), "Subject: b\n\ny\n").unwrap();
+        let messages = maildir_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|x| read_maildir(x).unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let keys = messages.iter().map(|x| x.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, ["1700000000.1.host", "1700000001.2.host"]);
+        assert_eq!(messages[0].flags, [Flag::Seen]);
+        assert_eq!(messages[1].content, b"Subject: b\r\n\r\ny\r\n");
+        assert!(messages[0].date.is_some());
+    }
+}
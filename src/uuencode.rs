@@ -0,0 +1,145 @@
+// 本文に埋め込まれた uuencode（「begin 644 ファイル名」から「end」まで）を添付ファイルとして取り出す
+// 古いメールソフトや、今も一部の業務システムが MIME を使わずにこの形で送ってくる
+
+use crate::AttachmentInfo;
+
+// 取り出した部分を除いた本文と、取り出したファイル
+// 最後まで正しく読めないもの（「begin」で始まるだけの文など）は本文に残す
+pub(crate) fn extract(body: &str) -> (String, Vec<AttachmentInfo>) {
+    let lines = body.split_inclusive('\n').collect::<Vec<_>>();
+    let mut text = String::with_capacity(body.len());
+    let mut attachments = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(filename) = begin_line(lines[i]) {
+            if let Some((data, read)) = decode(&lines[i + 1..]) {
+                attachments.push(AttachmentInfo::new(
+                    Some(filename.to_string()),
+                    "application/octet-stream".to_string(),
+                    data,
+                ));
+                i += 1 + read;
+                continue;
+            }
+        }
+        text.push_str(lines[i]);
+        i += 1;
+    }
+    (text, attachments)
+}
+
+// 「begin 644 report.csv」のファイル名
+fn begin_line(line: &str) -> Option<&str> {
+    let (mode, filename) = line.trim_end().strip_prefix("begin ")?.split_once(' ')?;
+    let is_mode = (3..=4).contains(&mode.len()) && mode.bytes().all(|x| (b'0'..=b'7').contains(&x));
+    let filename = filename.trim();
+    if is_mode && !filename.is_empty() {
+        Some(filename)
+    } else {
+        None
+    }
+}
+
+// begin の次の行から end の行までを読む（読めたら、データと end までの行数）
+fn decode(lines: &[&str]) -> Option<(Vec<u8>, usize)> {
+    let mut data = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        // 長さ0の行は「`」か空白1つ（空白は消されていることもある）なので、空白は残しておく
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim_end() == "end" {
+            return Some((data, i + 1));
+        }
+        decode_line(line.as_bytes(), &mut data)?;
+    }
+    None
+}
+
+// 1文字目が長さ、残りは4文字で3バイト
+// 行末の空白が消されていれば、足りない文字は空白（0）とみなす
+fn decode_line(line: &[u8], data: &mut Vec<u8>) -> Option<()> {
+    let (&first, rest) = match line.split_first() {
+        Some(x) => x,
+        None => return Some(()),
+    };
+    let len = sextet(first)? as usize;
+    let mut sextets = rest.iter().map(|&x| sextet(x));
+    let start = data.len();
+    for _ in 0..len.div_ceil(3) {
+        let mut group = [0; 4];
+        for x in &mut group {
+            *x = sextets.next().unwrap_or(Some(0))?;
+        }
+        data.push((group[0] << 2) | (group[1] >> 4));
+        data.push((group[1] << 4) | (group[2] >> 2));
+        data.push((group[2] << 6) | group[3]);
+    }
+    data.truncate(start + len);
+    Some(())
+}
+
+fn sextet(c: u8) -> Option<u8> {
+    if (b' '..=b'`').contains(&c) {
+        Some((c - b' ') & 0x3f)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_uuencoded_files() {
+        let body = "Daily report attached.\r\n\
+                    \r\n\
+                    begin 644 report.csv\r\n\
+                    /:60L=&5M< HQ+#(S+C4*\r\n\
+                    `\r\n\
+                    end\r\n\
+                    begin 600 bytes.bin\r\n\
+                    %+2XO,#$\r\n\
+                    \r\n\
+                    end\r\n\
+                    -- \r\n\
+                    begin 644 is not a file\r\n\
+                    plant 3\r\n";
+        let (text, attachments) = extract(body);
+        assert_eq!(
+            text,
+            "Daily report attached.\r\n\r\n-- \r\nbegin 644 is not a file\r\nplant 3\r\n"
+        );
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].filename(), Some("report.csv"));
+        assert_eq!(attachments[0].data(), b"id,temp\n1,23.5\n");
+        // 行末の空白が消されている
+        assert_eq!(attachments[1].filename(), Some("bytes.bin"));
+        assert_eq!(attachments[1].data(), [45, 46, 47, 48, 49]);
+
+        // end がなければ本文に残す
+        let body = "begin 644 a.txt\n#86)C\n";
+        assert_eq!(extract(body).0, body);
+        assert!(extract(body).1.is_empty());
+    }
+
+    #[test]
+    fn parse_uuencoded_body() {
+        let raw = "From: plc@factory.example.com\r\n\
+                   Subject: log\r\n\
+                   \r\n\
+                   Line 2 stopped.\r\n\
+                   begin 644 report.csv\r\n\
+                   /:60L=&5M< HQ+#(S+C4*\r\n\
+                   `\r\n\
+                   end\r\n";
+        let message = crate::parse(raw.as_bytes(), &crate::ReadOptions::default()).unwrap();
+        assert_eq!(message.body(), "Line 2 stopped.");
+        assert_eq!(message.attachments().len(), 1);
+        assert_eq!(message.attachments()[0].filename(), Some("report.csv"));
+
+        let options = crate::ReadOptions::default().attachments_matching(&["*.pdf"]);
+        let message = crate::parse(raw.as_bytes(), &options).unwrap();
+        assert_eq!(message.body(), "Line 2 stopped.");
+        assert!(message.attachments().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+// text/vcard（vCard 3.0 / 4.0）の最低限のパーサー
+// https://tools.ietf.org/html/rfc6350
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VCard {
+    name: Option<String>,
+    emails: Vec<String>,
+    phones: Vec<String>,
+}
+
+impl VCard {
+    // 表示名（FN がなければ N から組み立てる）
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn emails(&self) -> &[String] {
+        &self.emails
+    }
+
+    pub fn phones(&self) -> &[String] {
+        &self.phones
+    }
+}
+
+// 1つのパートに複数の vCard が入っていることもあるので Vec で返す
+pub(crate) fn parse_vcards(text: &str) -> Vec<VCard> {
+    let mut cards = Vec::new();
+    let mut current: Option<(VCard, Option<String>)> = None;
+
+    for line in unfold(text) {
+        let (name, value) = match split_property(&line) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        match name.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                current = Some((
+                    VCard {
+                        name: None,
+                        emails: Vec::new(),
+                        phones: Vec::new(),
+                    },
+                    None,
+                ));
+            }
+            "END" if value.eq_ignore_ascii_case("VCARD") => {
+                if let Some((mut card, n)) = current.take() {
+                    // FN がない場合は N（姓;名;...）で代用する
+                    if card.name.is_none() {
+                        card.name = n;
+                    }
+                    cards.push(card);
+                }
+            }
+            _ => {
+                let (card, n) = match current.as_mut() {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let value = unescape(value);
+                if value.is_empty() {
+                    continue;
+                }
+                match name.as_str() {
+                    "FN" => card.name = Some(value),
+                    "N" => {
+                        let parts = value
+                            .split(';')
+                            .filter(|x| !x.is_empty())
+                            .collect::<Vec<_>>();
+                        if !parts.is_empty() {
+                            *n = Some(parts.join(" "));
+                        }
+                    }
+                    "EMAIL" => card.emails.push(value),
+                    // vCard 4.0 では「tel:+81-3-...」の URI 形式もある
+                    "TEL" => card
+                        .phones
+                        .push(value.trim_start_matches("tel:").to_string()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    cards
+}
+
+// 行の折り返し（CRLF + 空白）を元に戻す
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.chars().next(), lines.last_mut()) {
+            (Some(' '), Some(last)) | (Some('\t'), Some(last)) => last.push_str(&line[1..]),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// 「item1.EMAIL;TYPE=work:foo@example.com」を ("EMAIL", "foo@example.com") に分解する
+fn split_property(line: &str) -> Option<(String, &str)> {
+    let colon = line.find(':')?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let name = head.split(';').next()?;
+    let name = name.rsplit('.').next()?;
+    Some((name.to_ascii_uppercase(), value.trim()))
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => result.push('\n'),
+                Some(x) => result.push(x),
+                None => {}
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vcard() {
+        let text = "BEGIN:VCARD\r\n\
+                    VERSION:3.0\r\n\
+                    N:山田;太郎;;;\r\n\
+                    FN:山田 太郎\r\n\
+                    item1.EMAIL;TYPE=INTERNET,WORK:taro@example.com\r\n\
+                    EMAIL;TYPE=HOME:taro@exam\r\n ple.net\r\n\
+                    TEL;TYPE=CELL:090-1234-5678\r\n\
+                    END:VCARD\r\n\
+                    BEGIN:VCARD\r\n\
+                    VERSION:4.0\r\n\
+                    N:Doe;John;;;\r\n\
+                    TEL;VALUE=uri:tel:+1-555-0100\r\n\
+                    END:VCARD\r\n";
+
+        let cards = parse_vcards(text);
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].name(), Some("山田 太郎"));
+        assert_eq!(cards[0].emails(), ["taro@example.com", "taro@example.net"]);
+        assert_eq!(cards[0].phones(), ["090-1234-5678"]);
+        assert_eq!(cards[1].name(), Some("Doe John"));
+        assert!(cards[1].emails().is_empty());
+        assert_eq!(cards[1].phones(), ["+1-555-0100"]);
+    }
+}
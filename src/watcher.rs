@@ -0,0 +1,291 @@
+// 新着メールの監視
+// IDLE（RFC 2177）が使えれば IDLE で、使えなければ一定間隔で新しいメールを確認し、
+// 登録したコールバックを呼び出す
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::events::{self, FolderSnapshot};
+use crate::sync::{self, MemorySyncStore, SyncState, SyncStore};
+use crate::{Capability, MailboxEvent, MyMailbox, MyMessage, MySession, ReadOptions};
+
+type MessageHook<'a> = Box<dyn FnMut(&MyMessage) + 'a>;
+type ErrorHook<'a> = Box<dyn FnMut(&dyn Error) + 'a>;
+
+pub struct Watcher<'a> {
+    mailbox: &'a MyMailbox<'a>,
+    options: ReadOptions,
+    interval: Duration,
+    max_backoff: Duration,
+    // 続けて失敗した回数
+    failures: u32,
+    store: MemorySyncStore,
+    on_new_message: Vec<MessageHook<'a>>,
+    on_error: Vec<ErrorHook<'a>>,
+    events: Vec<Sender<MailboxEvent>>,
+    snapshots: HashMap<String, FolderSnapshot>,
+    #[cfg(feature = "webhook")]
+    webhooks: Vec<crate::Webhook>,
+    stop: Arc<AtomicBool>,
+}
+
+impl<'a> Watcher<'a> {
+    // 監視を始めた時点ですでにあるメールは「新着」として扱わない
+    pub fn new(mailbox: &'a MyMailbox<'a>, options: ReadOptions) -> Self {
+        Self {
+            mailbox,
+            options,
+            interval: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(600),
+            failures: 0,
+            store: MemorySyncStore::new(),
+            on_new_message: Vec::new(),
+            on_error: Vec::new(),
+            events: Vec::new(),
+            snapshots: HashMap::new(),
+            #[cfg(feature = "webhook")]
+            webhooks: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // 確認の間隔（IDLE のときは待ち時間の上限）
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // 接続や取得に失敗したら、interval から倍ずつ延ばしてこの時間まで待ってからつなぎ直す
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn on_new_message<F: FnMut(&MyMessage) + 'a>(&mut self, hook: F) -> &mut Self {
+        self.on_new_message.push(Box::new(hook));
+        self
+    }
+
+    // 接続や取得に失敗したとき（失敗しても監視は続ける）
+    pub fn on_error<F: FnMut(&dyn Error) + 'a>(&mut self, hook: F) -> &mut Self {
+        self.on_error.push(Box::new(hook));
+        self
+    }
+
+    // 新着・削除・フラグの変化をイベントとして受け取る
+    // 削除・フラグの変化は、確認のたびに全メールのフラグを取得して前回と比べる
+    pub fn events(&mut self) -> Receiver<MailboxEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.events.push(sender);
+        receiver
+    }
+
+    // 新着メールを Webhook に送る（送れなかったときは on_error に渡す）
+    #[cfg(feature = "webhook")]
+    pub fn webhook(&mut self, webhook: crate::Webhook) -> &mut Self {
+        self.webhooks.push(webhook);
+        self
+    }
+
+    // 別のスレッドから true にすると run が終わる
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    // stop_handle で止めるまで監視を続ける
+    pub fn run(&mut self) {
+        while !self.stopped() {
+            if let Err(e) = self.watch() {
+                self.emit_error(e.as_ref());
+                self.failures += 1;
+                self.sleep(backoff(self.interval, self.max_backoff, self.failures));
+            }
+        }
+    }
+
+    // 1回だけ確認して、新着メールの数を返す
+    pub fn poll_once(&mut self) -> Result<usize, Box<dyn Error>> {
+        let mut imap_session = crate::connect(self.mailbox)?;
+        let count = self.check(&mut imap_session)?;
+        imap_session.logout()?;
+        Ok(count)
+    }
+
+    // 接続してから、エラーになるか止められるまで確認を繰り返す
+    fn watch(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut imap_session = crate::connect(self.mailbox)?;
+        let folders = self.folders();
+        let idle = folders.len() == 1 && imap_session.supports(Capability::Idle)?;
+
+        while !self.stopped() {
+            self.check(&mut imap_session)?;
+            self.failures = 0;
+            if idle {
+                // check で最後に選択したフォルダー（= 監視対象）の変化を待つ
+                imap_session.idle()?.wait_with_timeout(self.interval)?;
+            } else {
+                self.sleep(self.interval);
+            }
+        }
+
+        imap_session.logout()?;
+        Ok(())
+    }
+
+    fn check(&mut self, imap_session: &mut MySession) -> Result<usize, Box<dyn Error>> {
+        let mut count = 0;
+        for folder in self.folders() {
+            if self.store.load(&folder)?.is_none() {
+                self.start_from_now(imap_session, &folder)?;
+            }
+            let fetched = sync::fetch_new(imap_session, &folder, &self.options, &mut self.store)?;
+            let messages = self.deliver(fetched);
+            count += messages.len();
+
+            if !self.events.is_empty() {
+                // fetch_new でこのフォルダーを選択している
+                let flags = events::fetch_flags(imap_session)?;
+                let mut changes = messages
+                    .into_iter()
+                    .map(|x| MailboxEvent::MessageAdded(Box::new(x)))
+                    .collect::<Vec<_>>();
+                changes.extend(self.snapshots.entry(folder).or_default().update(flags));
+                self.send_events(changes);
+            }
+        }
+        Ok(count)
+    }
+
+    // filter_body や stage を通ったメールだけをコールバックと Webhook に渡して返す
+    fn deliver(&mut self, fetched: Vec<MyMessage>) -> Vec<MyMessage> {
+        let messages = fetched
+            .into_iter()
+            .filter_map(|x| self.options.process(x))
+            .collect::<Vec<_>>();
+        for message in &messages {
+            for hook in self.on_new_message.iter_mut() {
+                hook(message);
+            }
+            #[cfg(feature = "webhook")]
+            for i in 0..self.webhooks.len() {
+                if let Err(e) = self.webhooks[i].deliver(message) {
+                    self.emit_error(e.as_ref());
+                }
+            }
+        }
+        messages
+    }
+
+    // 受け取る側がいなくなったチャンネルには、それ以降送らない
+    fn send_events(&mut self, changes: Vec<MailboxEvent>) {
+        for change in changes {
+            self.events.retain(|x| x.send(change.clone()).is_ok());
+        }
+    }
+
+    // いまある最後の UID を記録しておく
+    fn start_from_now(
+        &mut self,
+        imap_session: &mut MySession,
+        folder: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let selected = imap_session.select(folder)?;
+        let uid_validity = selected.uid_validity.ok_or("no UIDVALIDITY")?;
+        let last_uid = match selected.uid_next {
+            Some(uid_next) => uid_next.saturating_sub(1),
+            None => crate::search_uids(imap_session)?
+                .last()
+                .copied()
+                .unwrap_or(0),
+        };
+        self.store
+            .save(folder, &SyncState::new(uid_validity, last_uid, None))
+    }
+
+    fn folders(&self) -> Vec<String> {
+        if self.options.folders.is_empty() {
+            vec![self.mailbox.selection.to_string()]
+        } else {
+            self.options.folders.clone()
+        }
+    }
+
+    fn emit_error(&mut self, error: &dyn Error) {
+        for hook in self.on_error.iter_mut() {
+            hook(error);
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    // 止められたらすぐに戻れるように、1秒ずつ区切って待つ
+    fn sleep(&self, duration: Duration) {
+        let mut waited = Duration::from_secs(0);
+        while waited < duration && !self.stopped() {
+            let step = Duration::from_secs(1).min(duration - waited);
+            thread::sleep(step);
+            waited += step;
+        }
+    }
+}
+
+// failures 回続けて失敗したあとに待つ時間
+fn backoff(interval: Duration, max: Duration, failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    interval.saturating_mul(factor).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let interval = Duration::from_secs(60);
+        let max = Duration::from_secs(600);
+        let delays = (1..=6)
+            .map(|x| backoff(interval, max, x).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [60, 120, 240, 480, 600, 600]);
+        assert_eq!(backoff(interval, max, 100), max);
+    }
+
+    #[test]
+    fn deliver_processed_messages() {
+        let mailbox = MyMailbox::default();
+        let options = ReadOptions::default().stage(|x| {
+            if x.subject().contains("spam") {
+                None
+            } else {
+                Some(x)
+            }
+        });
+        let fetched = ["hello", "spam"]
+            .iter()
+            .map(|subject| {
+                let raw = format!(
+                    "From: taro@example.com\r\nSubject: {}\r\n\r\nbody\r\n",
+                    subject
+                );
+                crate::parse(raw.as_bytes(), &ReadOptions::default()).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut subjects = Vec::new();
+        let messages = {
+            let mut watcher = Watcher::new(&mailbox, options);
+            watcher.on_new_message(|x| subjects.push(x.subject().to_string()));
+            watcher.deliver(fetched)
+        };
+        // stage が None を返したメールはコールバックに渡さない
+        assert_eq!(subjects, ["hello"]);
+        assert_eq!(messages.len(), 1);
+    }
+}
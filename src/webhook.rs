@@ -0,0 +1,175 @@
+// 新着メールを JSON にして Webhook（HTTP POST）で送る
+// secret を指定すると、本文の HMAC-SHA256 を X-Signature-256 ヘッダーに付ける（GitHub と同じ形式）
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::MyMessage;
+
+#[derive(Clone)]
+pub struct Webhook {
+    url: String,
+    secret: Option<Vec<u8>>,
+    retries: u32,
+    backoff: Duration,
+    timeout: Duration,
+}
+
+// secret は Debug に出さない（SecretString と同じ）
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook")
+            .field("url", &self.url)
+            .field("secret", &self.secret.as_ref().map(|_| "***"))
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            secret: None,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    // 署名に使う共有の秘密鍵
+    pub fn secret(mut self, secret: &[u8]) -> Self {
+        self.secret = Some(secret.to_vec());
+        self
+    }
+
+    // 失敗したときに再送する回数（待ち時間は backoff から倍々に増やす）
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // 接続エラーと 5xx・429 は再送し、それ以外の 4xx はすぐにエラーにする
+    pub fn deliver(&self, message: &MyMessage) -> Result<(), Box<dyn Error>> {
+        let body = to_json(message);
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(native_tls::TlsConnector::new()?))
+            .timeout(self.timeout)
+            .build();
+
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let mut request = agent
+                .post(&self.url)
+                .set("Content-Type", "application/json");
+            if let Some(secret) = &self.secret {
+                request = request.set("X-Signature-256", &sign(secret, body.as_bytes()));
+            }
+
+            let error = match request.send_string(&body) {
+                Ok(_) => return Ok(()),
+                Err(ureq::Error::Status(code, _)) if code != 429 && code < 500 => {
+                    return Err(format!("webhook rejected with status {}", code).into())
+                }
+                Err(e) => e,
+            };
+            if attempt >= self.retries {
+                return Err(error.into());
+            }
+            thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+pub(crate) fn to_json(message: &MyMessage) -> String {
+    let attachments = message
+        .attachments()
+        .iter()
+        .map(|x| {
+            json!({
+                "filename": x.filename(),
+                "mimetype": x.mimetype(),
+                "size": x.size(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "folder": message.folder(),
+        "uid": message.uid(),
+        "message_id": message.message_id(),
+        "from": message.from(),
+        "subject": message.subject(),
+        "body": message.body(),
+        "html": message.html(),
+        "attachments": attachments,
+    })
+    .to_string()
+}
+
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    // HMAC はどんな長さの鍵でも受け付けるので失敗しない
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex = digest
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_signature() {
+        // RFC 4231 Test Case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn hide_secret() {
+        let webhook = Webhook::new("https://example.com/hook").secret(b"hunter2");
+        let debug = format!("{:?}", webhook);
+        assert!(debug.contains("https://example.com/hook"));
+        assert!(debug.contains("***"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("104"));
+    }
+
+    #[test]
+    fn serialize_message() {
+        let raw = "From: taro@example.com\r\nSubject: \"hi\"\r\n\r\nline1\r\nline2\r\n";
+        let message =
+            crate::parse_fetched(raw.as_bytes(), "INBOX", 7, &crate::ReadOptions::default())
+                .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&to_json(&message)).unwrap();
+        assert_eq!(value["folder"], "INBOX");
+        assert_eq!(value["uid"], 7);
+        assert_eq!(value["subject"], "\"hi\"");
+        assert_eq!(value["body"], "line1\r\nline2");
+        assert!(value["html"].is_null());
+    }
+}
@@ -0,0 +1,158 @@
+// サーバーとのやり取りを1行ずつ記録する（変わった応答をするサーバーの調査用）
+// LOGIN のパスワードや AUTHENTICATE の中身は「***」に置き換える
+
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    // こちらから送った行
+    Sent,
+    // サーバーから受け取った行
+    Received,
+}
+
+type Sink = Arc<dyn Fn(Direction, &str) + Send + Sync>;
+
+// 記録先（tracing や log に渡すなら、その呼び出しを sink にする）
+#[derive(Clone)]
+pub struct WireLog {
+    sink: Sink,
+}
+
+impl fmt::Debug for WireLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WireLog(..)")
+    }
+}
+
+impl WireLog {
+    // 行は末尾の改行を除いて渡す
+    pub fn new<F: Fn(Direction, &str) + Send + Sync + 'static>(sink: F) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+
+    // 「C: a1 SELECT INBOX」「S: * 3 EXISTS」のように標準エラーに出す
+    pub fn stderr() -> Self {
+        Self::new(|direction, line| {
+            let prefix = match direction {
+                Direction::Sent => "C",
+                Direction::Received => "S",
+            };
+            eprintln!("{}: {}", prefix, line);
+        })
+    }
+}
+
+// 1つの接続について、行に揃えてから記録する
+pub(crate) struct WireTap {
+    log: WireLog,
+    sent: Vec<u8>,
+    received: Vec<u8>,
+    // AUTHENTICATE の途中（サーバーの「+」に返す行は隠す）
+    authenticating: bool,
+}
+
+impl WireTap {
+    pub(crate) fn new(log: WireLog) -> Self {
+        Self {
+            log,
+            sent: Vec::new(),
+            received: Vec::new(),
+            authenticating: false,
+        }
+    }
+
+    pub(crate) fn sent(&mut self, buf: &[u8]) {
+        self.sent.extend_from_slice(buf);
+        while let Some(line) = take_line(&mut self.sent) {
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            let line = self.redact(line);
+            (self.log.sink)(Direction::Sent, &line);
+        }
+    }
+
+    pub(crate) fn received(&mut self, buf: &[u8]) {
+        self.received.extend_from_slice(buf);
+        while let Some(line) = take_line(&mut self.received) {
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            // タグ付きの応答が来たら AUTHENTICATE は終わり
+            if self.authenticating && !line.starts_with('+') && !line.starts_with('*') {
+                self.authenticating = false;
+            }
+            (self.log.sink)(Direction::Received, line);
+        }
+    }
+
+    fn redact(&mut self, line: &str) -> String {
+        if self.authenticating {
+            return "***".to_string();
+        }
+        let mut words = line.splitn(3, ' ');
+        let (tag, command) = (words.next().unwrap_or_default(), words.next());
+        match command.map(str::to_ascii_uppercase).as_deref() {
+            Some("LOGIN") => format!("{} LOGIN ***", tag),
+            Some("AUTHENTICATE") => {
+                self.authenticating = true;
+                // 機構の名前は残し、続けて送る初期応答は隠す
+                let rest = words.next().unwrap_or_default();
+                match rest.split_once(' ') {
+                    Some((mechanism, _)) => format!("{} AUTHENTICATE {} ***", tag, mechanism),
+                    None => line.to_string(),
+                }
+            }
+            _ => line.to_string(),
+        }
+    }
+}
+
+fn take_line(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let end = buf.iter().position(|&x| x == b'\n')?;
+    let rest = buf.split_off(end + 1);
+    Some(std::mem::replace(buf, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn redact_credentials() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let log = {
+            let lines = lines.clone();
+            WireLog::new(move |direction, line| {
+                lines.lock().unwrap().push((direction, line.to_string()))
+            })
+        };
+        let mut tap = WireTap::new(log);
+
+        tap.sent(b"a1 LOGIN \"taro\" ");
+        tap.sent(b"\"secret\"\r\na2 AUTHENTICATE XOAUTH2\r\n");
+        tap.received(b"+ \r\n");
+        tap.sent(b"dXNlcj10YXJv\r\n");
+        tap.received(b"a2 OK done\r\n* 3 EX");
+        tap.received(b"ISTS\r\n");
+        tap.sent(b"a3 SELECT INBOX\r\n");
+
+        let lines = lines.lock().unwrap();
+        let expected = [
+            (Direction::Sent, "a1 LOGIN ***"),
+            (Direction::Sent, "a2 AUTHENTICATE XOAUTH2"),
+            (Direction::Received, "+"),
+            (Direction::Sent, "***"),
+            (Direction::Received, "a2 OK done"),
+            (Direction::Received, "* 3 EXISTS"),
+            (Direction::Sent, "a3 SELECT INBOX"),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        for ((direction, line), (x, y)) in lines.iter().zip(expected.iter()) {
+            assert_eq!((direction, line.as_str()), (x, *y));
+        }
+    }
+}